@@ -0,0 +1,345 @@
+use crate::lex::Token;
+
+// Identifies expressions that refer to a binding so later passes can attach
+// information to them without mutating the tree.
+pub type NodeId = usize;
+
+static NEXT_NODE_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// Ids are unique for the life of the process, so trees produced by separate
+// parses (REPL lines, imported files) never collide.
+pub fn next_id() -> NodeId {
+    NEXT_NODE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type", content = "value"))]
+pub enum Literal {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type"))]
+pub enum Expr {
+    Literal {
+        value: Literal,
+        token: Token,
+    },
+    Grouping {
+        expression: Box<Expr>,
+    },
+    Unary {
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    // `and` / `or`, kept apart from Binary because they short circuit
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Variable {
+        id: NodeId,
+        name: Token,
+    },
+    Assign {
+        id: NodeId,
+        name: Token,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        id: NodeId,
+        keyword: Token,
+    },
+    Super {
+        id: NodeId,
+        keyword: Token,
+        method: Token,
+    },
+    List {
+        bracket: Token,
+        elements: Vec<Expr>,
+    },
+    // `{key: value}`, keys are expressions evaluated in order
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    // `object[index]` on lists and maps, negative list indices count from the end
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    IndexSet {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    // `object[start:end]`, a missing bound means the start or end of the list
+    Slice {
+        object: Box<Expr>,
+        bracket: Token,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    // `start..end` counts up to but not including end, `start..=end` includes it
+    Range {
+        start: Box<Expr>,
+        operator: Token,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
+    // Anonymous function, `keyword` is the `fun` or `->` that introduced it
+    Lambda {
+        keyword: Token,
+        function: std::rc::Rc<Function>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Function {
+    // None for lambdas
+    pub name: Option<Token>,
+    pub params: Vec<Token>,
+    // One per parameter, None where it has no annotation
+    pub param_types: Vec<Option<TypeExpr>>,
+    pub return_type: Option<TypeExpr>,
+    pub body: Vec<Stmt>,
+}
+
+// A type annotation, only read by the type checker
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type"))]
+pub enum TypeExpr {
+    // `Number`, `String`, a class name, ...
+    Named {
+        name: Token,
+    },
+    // `T?`, short for `T | Nil`
+    Optional {
+        inner: Box<TypeExpr>,
+        question: Token,
+    },
+    // `A | B | C`, at least two members
+    Union {
+        types: Vec<TypeExpr>,
+    },
+}
+
+impl TypeExpr {
+    pub fn first_token(&self) -> &Token {
+        match self {
+            TypeExpr::Named { name } => name,
+            TypeExpr::Optional { inner, .. } => inner.first_token(),
+            TypeExpr::Union { types } => types[0].first_token(),
+        }
+    }
+}
+
+// `name: Type;` in a class body. Fields are still created by assigning them,
+// the declaration only gives their type.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Field {
+    pub name: Token,
+    pub annotation: TypeExpr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type"))]
+pub enum Stmt {
+    Expression {
+        expression: Expr,
+    },
+    Print {
+        keyword: Token,
+        expression: Expr,
+    },
+    Var {
+        name: Token,
+        annotation: Option<TypeExpr>,
+        initializer: Option<Expr>,
+    },
+    Block {
+        statements: Vec<Stmt>,
+    },
+    If {
+        keyword: Token,
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        keyword: Token,
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    // C-style loop, not desugared so `continue` still runs the increment
+    For {
+        keyword: Token,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    // `for (variable in iterable)` over lists, map keys, string characters,
+    // ranges, or instances implementing `iter()` / `next()`. Each iteration
+    // binds a fresh variable so closures capture that iteration's value.
+    ForIn {
+        keyword: Token,
+        variable: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    // Shared so runtime closures can hold on to the declaration cheaply
+    Function {
+        function: std::rc::Rc<Function>,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
+    },
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        fields: Vec<Field>,
+        methods: Vec<std::rc::Rc<Function>>,
+    },
+    // `test "name" { ... }` at the top level. Skipped when the program runs,
+    // `miette test` runs each one after it.
+    Test {
+        keyword: Token,
+        // A string literal
+        name: Token,
+        body: Vec<Stmt>,
+    },
+    // `import "path" as name;` at the top level, binding the module's
+    // exports to `name`
+    Import {
+        keyword: Token,
+        // A string literal
+        path: Token,
+        name: Token,
+    },
+    // A top-level var, fun or class that importing modules can see
+    Export {
+        keyword: Token,
+        declaration: Box<Stmt>,
+    },
+    // `throw value;`, any value can be thrown
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    // `try { ... }` followed by a catch clause, a finally block, or both
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Stmt>>,
+    },
+}
+
+// `catch (name) { ... }`, binding the thrown value to `name`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Catch {
+    pub name: Token,
+    pub body: Vec<Stmt>,
+}
+
+impl Expr {
+    // Token to point diagnostics at, the leftmost one except for arrow lambdas
+    pub fn first_token(&self) -> &Token {
+        match self {
+            Expr::Literal { token, .. } => token,
+            Expr::Grouping { expression } => expression.first_token(),
+            Expr::Unary { operator, .. } => operator,
+            Expr::Binary { left, .. } | Expr::Logical { left, .. } => left.first_token(),
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => name,
+            Expr::Call { callee, .. } => callee.first_token(),
+            Expr::Get { object, .. }
+            | Expr::Set { object, .. }
+            | Expr::Index { object, .. }
+            | Expr::IndexSet { object, .. }
+            | Expr::Slice { object, .. } => object.first_token(),
+            Expr::This { keyword, .. } | Expr::Super { keyword, .. } => keyword,
+            Expr::List { bracket, .. } => bracket,
+            Expr::Map { brace, .. } => brace,
+            Expr::Range { start, .. } => start.first_token(),
+            Expr::Lambda { keyword, .. } => keyword,
+        }
+    }
+}
+
+// The paren of a call stretched over the whole call, for errors about the
+// call itself to point at
+pub fn call_site(callee: &Expr, paren: &Token) -> Token {
+    Token::new(
+        paren.id(),
+        paren.kind().clone(),
+        paren.lexeme().to_string(),
+        paren.line(),
+        callee.first_token().span().to(paren.span()),
+    )
+}
+
+impl Stmt {
+    // Leftmost token of the statement, None only for an empty block
+    pub fn first_token(&self) -> Option<&Token> {
+        match self {
+            Stmt::Expression { expression } => Some(expression.first_token()),
+            Stmt::Print { keyword, .. }
+            | Stmt::If { keyword, .. }
+            | Stmt::While { keyword, .. }
+            | Stmt::For { keyword, .. }
+            | Stmt::ForIn { keyword, .. }
+            | Stmt::Return { keyword, .. }
+            | Stmt::Break { keyword }
+            | Stmt::Continue { keyword }
+            | Stmt::Test { keyword, .. }
+            | Stmt::Import { keyword, .. }
+            | Stmt::Export { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Try { keyword, .. } => Some(keyword),
+            Stmt::Var { name, .. } | Stmt::Class { name, .. } => Some(name),
+            Stmt::Block { statements } => statements.first()?.first_token(),
+            Stmt::Function { function } => function.name.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,825 @@
+// The `.mtc` file format, compiled bytecode that runs without the source.
+//
+// All integers are big-endian, like the operands inside a chunk.
+//
+//   magic      b"MTC\0"
+//   version    u16
+//   strings    u32 count, then u32 length and UTF-8 bytes for each
+//   functions  u32 count, then each function (see below)
+//   exports    u32 count, then a string index each
+//   imports    u32 count, then string indexes of the path as written and of
+//              the .mtc file it loads, relative to this one's directory
+//   checksum   u32 FNV-1a of every byte before it
+//
+// A function is its name (string index), arity (u8), upvalue count (u16),
+// code (u32 length and bytes), line table (u32 run count, then u32 length,
+// line, span start and span end per run) and constants (u32 count, then a tag
+// byte and payload each). Functions are written children first, so a function
+// constant always refers to one already read, and the script comes last.
+//
+// A program built from a single file exports nothing and imports nothing. A
+// project's modules each get a file, and running one loads those it imports.
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::lex::Span;
+use crate::module;
+
+pub const MAGIC: &[u8; 4] = b"MTC\0";
+pub const VERSION: u16 = 2;
+pub const EXTENSION: &str = "mtc";
+
+const TAG_NUMBER: u8 = 0;
+const TAG_TEXT: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    // Byte offset where the file ended and what was being read there
+    Truncated {
+        offset: usize,
+        reading: &'static str,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    BadString {
+        index: u32,
+        count: usize,
+    },
+    // Function constants may only refer to functions earlier in the file
+    BadFunction {
+        index: u32,
+        count: usize,
+    },
+    BadConstantTag {
+        offset: usize,
+        tag: u8,
+    },
+    NoFunctions,
+    LineTableMismatch {
+        function: String,
+        lines: usize,
+        code: usize,
+    },
+    InvalidCode {
+        function: String,
+        offset: usize,
+        message: String,
+    },
+    ChecksumMismatch {
+        stored: u32,
+        computed: u32,
+    },
+    TrailingBytes {
+        offset: usize,
+    },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "Not a miette bytecode file."),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported bytecode version {}, expected {}.",
+                version, VERSION
+            ),
+            LoadError::Truncated { offset, reading } => {
+                write!(f, "File ends at byte {} while reading {}.", offset, reading)
+            }
+            LoadError::InvalidUtf8 { offset } => {
+                write!(f, "String at byte {} is not valid UTF-8.", offset)
+            }
+            LoadError::BadString { index, count } => write!(
+                f,
+                "String index {} out of range for {} strings.",
+                index, count
+            ),
+            LoadError::BadFunction { index, count } => write!(
+                f,
+                "Function index {} out of range for {} functions read so far.",
+                index, count
+            ),
+            LoadError::BadConstantTag { offset, tag } => {
+                write!(f, "Unknown constant tag {} at byte {}.", tag, offset)
+            }
+            LoadError::NoFunctions => write!(f, "File contains no functions."),
+            LoadError::LineTableMismatch {
+                function,
+                lines,
+                code,
+            } => write!(
+                f,
+                "Line table of {} covers {} bytes but the code has {}.",
+                function, lines, code
+            ),
+            LoadError::InvalidCode {
+                function,
+                offset,
+                message,
+            } => write!(
+                f,
+                "Invalid code in {} at {:04}: {}",
+                function, offset, message
+            ),
+            LoadError::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "Checksum mismatch, file says {:08x} but contents hash to {:08x}.",
+                stored, computed
+            ),
+            LoadError::TrailingBytes { offset } => {
+                write!(f, "Unexpected data after the checksum at byte {}.", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// What a .mtc file holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub script: std::rc::Rc<FunctionProto>,
+    pub exports: Vec<String>,
+    // Each import's path as written, and the .mtc file it loads relative to
+    // the directory of this one
+    pub imports: Vec<(String, String)>,
+}
+
+// A module ready for the VM, the way module::Module is for one compiled from
+// source
+#[derive(Debug, Clone)]
+pub struct Linked {
+    pub name: String,
+    pub script: std::rc::Rc<FunctionProto>,
+    // Each import's path as written, and the module it loads
+    pub imports: std::collections::HashMap<String, usize>,
+    pub exports: Vec<String>,
+}
+
+pub fn write(script: &FunctionProto) -> Vec<u8> {
+    encode(script, &[], &[])
+}
+
+pub fn write_artifact(artifact: &Artifact) -> Vec<u8> {
+    encode(&artifact.script, &artifact.exports, &artifact.imports)
+}
+
+fn encode(script: &FunctionProto, exports: &[String], imports: &[(String, String)]) -> Vec<u8> {
+    let mut writer: Writer = Writer::default();
+    writer.function(script);
+    let exports: Vec<usize> = exports.iter().map(|name| writer.string(name)).collect();
+    let imports: Vec<(usize, usize)> = imports
+        .iter()
+        .map(|(path, file)| (writer.string(path), writer.string(file)))
+        .collect();
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_be_bytes());
+    put_u32(&mut out, writer.strings.len());
+    for string in writer.strings.iter() {
+        put_u32(&mut out, string.len());
+        out.extend_from_slice(string.as_bytes());
+    }
+    put_u32(&mut out, writer.function_count);
+    out.extend_from_slice(&writer.functions);
+    put_u32(&mut out, exports.len());
+    for name in exports {
+        put_u32(&mut out, name);
+    }
+    put_u32(&mut out, imports.len());
+    for (path, file) in imports {
+        put_u32(&mut out, path);
+        put_u32(&mut out, file);
+    }
+
+    let checksum: u32 = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_be_bytes());
+    out
+}
+
+#[derive(Default)]
+struct Writer {
+    strings: Vec<String>,
+    string_indexes: std::collections::HashMap<String, usize>,
+    functions: Vec<u8>,
+    function_count: usize,
+}
+
+impl Writer {
+    // Writes nested functions first and returns the index of this one
+    fn function(&mut self, function: &FunctionProto) -> usize {
+        let nested: Vec<Option<usize>> = function
+            .chunk
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::Function(inner) => Some(self.function(inner)),
+                _ => None,
+            })
+            .collect();
+
+        let name: usize = self.string(&function.name);
+        let chunk: &Chunk = &function.chunk;
+        let mut out: Vec<u8> = Vec::new();
+        put_u32(&mut out, name);
+        out.push(function.arity as u8);
+        out.extend_from_slice(&(function.upvalue_count as u16).to_be_bytes());
+        put_u32(&mut out, chunk.code.len());
+        out.extend_from_slice(&chunk.code);
+
+        let runs: Vec<(usize, usize, Span)> = line_runs(chunk);
+        put_u32(&mut out, runs.len());
+        for (length, line, span) in runs {
+            put_u32(&mut out, length);
+            put_u32(&mut out, line);
+            put_u32(&mut out, span.start);
+            put_u32(&mut out, span.end);
+        }
+
+        put_u32(&mut out, chunk.constants.len());
+        for (constant, nested) in chunk.constants.iter().zip(nested) {
+            match constant {
+                Constant::Number(n) => {
+                    out.push(TAG_NUMBER);
+                    out.extend_from_slice(&n.to_bits().to_be_bytes());
+                }
+                Constant::Text(s) => {
+                    out.push(TAG_TEXT);
+                    let index: usize = self.string(s);
+                    put_u32(&mut out, index);
+                }
+                Constant::Function(_) => {
+                    out.push(TAG_FUNCTION);
+                    put_u32(&mut out, nested.unwrap_or_default());
+                }
+            }
+        }
+
+        self.functions.extend_from_slice(&out);
+        self.function_count += 1;
+        self.function_count - 1
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(index) = self.string_indexes.get(s) {
+            return *index;
+        }
+        self.strings.push(s.to_string());
+        self.string_indexes
+            .insert(s.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+// Consecutive bytes from the same line and span share one entry
+fn line_runs(chunk: &Chunk) -> Vec<(usize, usize, Span)> {
+    let mut runs: Vec<(usize, usize, Span)> = Vec::new();
+    for (line, span) in chunk.lines.iter().zip(chunk.spans.iter()) {
+        match runs.last_mut() {
+            Some((length, last_line, last_span)) if last_line == line && last_span == span => {
+                *length += 1;
+            }
+            _ => runs.push((1, *line, *span)),
+        }
+    }
+    runs
+}
+
+fn put_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+// Reads a whole file, checking every index and instruction so a loaded
+// script can't send the VM outside its chunks or its stack
+pub fn load(bytes: &[u8]) -> Result<std::rc::Rc<FunctionProto>, LoadError> {
+    load_artifact(bytes).map(|artifact| artifact.script)
+}
+
+pub fn load_artifact(bytes: &[u8]) -> Result<Artifact, LoadError> {
+    let mut reader: Reader = Reader { bytes, offset: 0 };
+
+    if reader.take(MAGIC.len(), "the header").ok() != Some(MAGIC.as_slice()) {
+        return Err(LoadError::BadMagic);
+    }
+    let version: u16 = reader.u16("the version")?;
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let string_count: usize = reader.u32("the string count")? as usize;
+    let mut strings: Vec<std::rc::Rc<str>> = Vec::new();
+    for _ in 0..string_count {
+        let length: usize = reader.u32("a string length")? as usize;
+        let start: usize = reader.offset;
+        let raw: &[u8] = reader.take(length, "a string")?;
+        let text: &str =
+            std::str::from_utf8(raw).map_err(|_| LoadError::InvalidUtf8 { offset: start })?;
+        strings.push(std::rc::Rc::from(text));
+    }
+
+    let function_count: usize = reader.u32("the function count")? as usize;
+    if function_count == 0 {
+        return Err(LoadError::NoFunctions);
+    }
+    let mut functions: Vec<std::rc::Rc<FunctionProto>> = Vec::new();
+    for _ in 0..function_count {
+        let function: FunctionProto = reader.function(&strings, &functions)?;
+        verify(&function)?;
+        functions.push(std::rc::Rc::new(function));
+    }
+
+    let export_count: usize = reader.u32("the export count")? as usize;
+    let mut exports: Vec<String> = Vec::new();
+    for _ in 0..export_count {
+        exports.push(reader.string(&strings, "an export")?.to_string());
+    }
+    let import_count: usize = reader.u32("the import count")? as usize;
+    let mut imports: Vec<(String, String)> = Vec::new();
+    for _ in 0..import_count {
+        let path: std::rc::Rc<str> = reader.string(&strings, "an import")?;
+        let file: std::rc::Rc<str> = reader.string(&strings, "an import")?;
+        imports.push((path.to_string(), file.to_string()));
+    }
+
+    let computed: u32 = fnv1a(&bytes[..reader.offset]);
+    let stored: u32 = reader.u32("the checksum")?;
+    if stored != computed {
+        return Err(LoadError::ChecksumMismatch { stored, computed });
+    }
+    if reader.offset != bytes.len() {
+        return Err(LoadError::TrailingBytes {
+            offset: reader.offset,
+        });
+    }
+
+    let script: std::rc::Rc<FunctionProto> = functions.pop().ok_or(LoadError::NoFunctions)?;
+    Ok(Artifact {
+        script,
+        exports,
+        imports,
+    })
+}
+
+// Loads the .mtc file read from `path` along with the ones it imports, each
+// after those it imports, so the file itself comes last
+pub fn link(path: &std::path::Path, bytes: &[u8]) -> Result<Vec<Linked>, String> {
+    let mut linker: Linker = Linker::default();
+    linker.module(path, bytes)?;
+    Ok(linker.modules)
+}
+
+#[derive(Default)]
+struct Linker {
+    modules: Vec<Linked>,
+    // Keyed by canonical path, so each file is loaded once
+    loaded: std::collections::HashMap<std::path::PathBuf, usize>,
+    // The chain of imports being loaded, to catch cycles
+    loading: Vec<std::path::PathBuf>,
+}
+
+impl Linker {
+    fn module(&mut self, path: &std::path::Path, bytes: &[u8]) -> Result<usize, String> {
+        let artifact: Artifact =
+            load_artifact(bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir: &std::path::Path = path.parent().unwrap_or(std::path::Path::new(""));
+
+        self.loading.push(module::canonical(path));
+        let mut imports: std::collections::HashMap<String, usize> =
+            std::collections::HashMap::new();
+        for (written, file) in artifact.imports.iter() {
+            let imported: std::path::PathBuf = dir.join(file);
+            let key: std::path::PathBuf = module::canonical(&imported);
+            let index: usize = match self.loaded.get(&key) {
+                Some(index) => *index,
+                None if self.loading.contains(&key) => {
+                    return Err(format!(
+                        "{}: import cycle through {}",
+                        path.display(),
+                        imported.display()
+                    ));
+                }
+                None => {
+                    let bytes: Vec<u8> = std::fs::read(&imported)
+                        .map_err(|e| format!("{}: {}", imported.display(), e))?;
+                    self.module(&imported, &bytes)?
+                }
+            };
+            imports.insert(written.clone(), index);
+        }
+        let key: std::path::PathBuf = self.loading.pop().unwrap_or_default();
+
+        self.modules.push(Linked {
+            name: path
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+            script: artifact.script,
+            imports,
+            exports: artifact.exports,
+        });
+        self.loaded.insert(key, self.modules.len() - 1);
+        Ok(self.modules.len() - 1)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize, reading: &'static str) -> Result<&'a [u8], LoadError> {
+        let end: Option<usize> = self.offset.checked_add(length);
+        match end.and_then(|end| self.bytes.get(self.offset..end)) {
+            Some(bytes) => {
+                self.offset += length;
+                Ok(bytes)
+            }
+            None => Err(LoadError::Truncated {
+                offset: self.bytes.len(),
+                reading,
+            }),
+        }
+    }
+
+    fn u8(&mut self, reading: &'static str) -> Result<u8, LoadError> {
+        Ok(self.take(1, reading)?[0])
+    }
+
+    fn u16(&mut self, reading: &'static str) -> Result<u16, LoadError> {
+        let bytes: &[u8] = self.take(2, reading)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, reading: &'static str) -> Result<u32, LoadError> {
+        let bytes: &[u8] = self.take(4, reading)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self, reading: &'static str) -> Result<u64, LoadError> {
+        let bytes: &[u8] = self.take(8, reading)?;
+        let mut array: [u8; 8] = [0; 8];
+        array.copy_from_slice(bytes);
+        Ok(u64::from_be_bytes(array))
+    }
+
+    fn string(
+        &mut self,
+        strings: &[std::rc::Rc<str>],
+        reading: &'static str,
+    ) -> Result<std::rc::Rc<str>, LoadError> {
+        let index: u32 = self.u32(reading)?;
+        strings
+            .get(index as usize)
+            .cloned()
+            .ok_or(LoadError::BadString {
+                index,
+                count: strings.len(),
+            })
+    }
+
+    fn function(
+        &mut self,
+        strings: &[std::rc::Rc<str>],
+        functions: &[std::rc::Rc<FunctionProto>],
+    ) -> Result<FunctionProto, LoadError> {
+        let name: std::rc::Rc<str> = self.string(strings, "a function name")?;
+        let arity: usize = self.u8("a function arity")? as usize;
+        let upvalue_count: usize = self.u16("an upvalue count")? as usize;
+
+        let mut chunk: Chunk = Chunk::new();
+        let code_length: usize = self.u32("a code length")? as usize;
+        chunk.code = self.take(code_length, "function code")?.to_vec();
+
+        let run_count: usize = self.u32("a line table length")? as usize;
+        for _ in 0..run_count {
+            let length: usize = self.u32("a line table entry")? as usize;
+            let line: usize = self.u32("a line table entry")? as usize;
+            let start: usize = self.u32("a line table entry")? as usize;
+            let end: usize = self.u32("a line table entry")? as usize;
+            // Cap the run so a corrupt length can't allocate without bound
+            if chunk.lines.len() + length > code_length {
+                return Err(LoadError::LineTableMismatch {
+                    function: name.to_string(),
+                    lines: chunk.lines.len() + length,
+                    code: code_length,
+                });
+            }
+            chunk.lines.extend(std::iter::repeat_n(line, length));
+            chunk
+                .spans
+                .extend(std::iter::repeat_n(Span::new(start, end), length));
+        }
+        if chunk.lines.len() != code_length {
+            return Err(LoadError::LineTableMismatch {
+                function: name.to_string(),
+                lines: chunk.lines.len(),
+                code: code_length,
+            });
+        }
+
+        let constant_count: usize = self.u32("a constant count")? as usize;
+        for _ in 0..constant_count {
+            let tag_offset: usize = self.offset;
+            let constant: Constant = match self.u8("a constant tag")? {
+                TAG_NUMBER => Constant::Number(f64::from_bits(self.u64("a number constant")?)),
+                TAG_TEXT => Constant::Text(self.string(strings, "a string constant")?),
+                TAG_FUNCTION => {
+                    let index: u32 = self.u32("a function constant")?;
+                    let function: std::rc::Rc<FunctionProto> = functions
+                        .get(index as usize)
+                        .cloned()
+                        .ok_or(LoadError::BadFunction {
+                            index,
+                            count: functions.len(),
+                        })?;
+                    Constant::Function(function)
+                }
+                tag => {
+                    return Err(LoadError::BadConstantTag {
+                        offset: tag_offset,
+                        tag,
+                    });
+                }
+            };
+            chunk.constants.push(constant);
+        }
+
+        Ok(FunctionProto {
+            name: name.to_string(),
+            arity,
+            upvalue_count,
+            chunk,
+        })
+    }
+}
+
+// Walks every instruction checking opcodes, operand indexes and jump
+// targets, then how each path uses the stack
+fn verify(function: &FunctionProto) -> Result<(), LoadError> {
+    let chunk: &Chunk = &function.chunk;
+    let invalid = |offset: usize, message: String| LoadError::InvalidCode {
+        function: function.name.clone(),
+        offset,
+        message,
+    };
+
+    let mut starts: Vec<bool> = vec![false; chunk.code.len()];
+    let mut targets: Vec<(usize, usize)> = Vec::new();
+    let mut last: Option<OpCode> = None;
+    let mut offset: usize = 0;
+    while offset < chunk.code.len() {
+        starts[offset] = true;
+        let byte: u8 = chunk.code[offset];
+        let op: OpCode = OpCode::from_byte(byte)
+            .ok_or_else(|| invalid(offset, format!("unknown opcode {}.", byte)))?;
+        let width: usize = chunk.operand_width(op, offset);
+        let next: usize = offset + 1 + width;
+        if next > chunk.code.len() {
+            return Err(invalid(offset, format!("{:?} is cut short.", op)));
+        }
+
+        match op {
+            OpCode::Constant => {
+                let index: usize = chunk.read_u16(offset + 1) as usize;
+                match chunk.constants.get(index) {
+                    Some(Constant::Number(_) | Constant::Text(_)) => {}
+                    _ => return Err(invalid(offset, format!("no value constant {}.", index))),
+                }
+            }
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::Import => {
+                let index: usize = chunk.read_u16(offset + 1) as usize;
+                if !matches!(chunk.constants.get(index), Some(Constant::Text(_))) {
+                    return Err(invalid(offset, format!("no name constant {}.", index)));
+                }
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                let index: usize = chunk.code[offset + 1] as usize;
+                if index >= function.upvalue_count {
+                    return Err(invalid(offset, format!("no upvalue {}.", index)));
+                }
+            }
+            OpCode::Closure => {
+                let index: usize = chunk.read_u16(offset + 1) as usize;
+                if !matches!(chunk.constants.get(index), Some(Constant::Function(_))) {
+                    return Err(invalid(offset, format!("no function constant {}.", index)));
+                }
+                let mut at: usize = offset + 3;
+                while at < next {
+                    let is_local: u8 = chunk.code[at];
+                    let index: usize = chunk.code[at + 1] as usize;
+                    if is_local > 1 || (is_local == 0 && index >= function.upvalue_count) {
+                        return Err(invalid(at, format!("bad upvalue capture {}.", index)));
+                    }
+                    at += 2;
+                }
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Try | OpCode::Loop | OpCode::IterNext => {
+                let target: usize = jump_target(chunk, op, offset, next)
+                    .ok_or_else(|| invalid(offset, "loop jumps before the start.".to_string()))?;
+                targets.push((offset, target));
+            }
+            _ => {}
+        }
+
+        last = Some(op);
+        offset = next;
+    }
+
+    // Falling off the end of a chunk would read past the code
+    if last != Some(OpCode::Return) {
+        return Err(invalid(
+            chunk.code.len(),
+            "code does not end with a return.".to_string(),
+        ));
+    }
+    for (offset, target) in targets {
+        if !starts.get(target).copied().unwrap_or(false) {
+            return Err(invalid(
+                offset,
+                format!("jump to {:04} is not an instruction.", target),
+            ));
+        }
+    }
+    verify_stack(function)
+}
+
+// Where a jump instruction goes, None for a loop back past the start
+fn jump_target(chunk: &Chunk, op: OpCode, offset: usize, next: usize) -> Option<usize> {
+    match op {
+        OpCode::Loop => next.checked_sub(chunk.read_u16(offset + 1) as usize),
+        OpCode::IterNext => Some(next + chunk.read_u16(offset + 2) as usize),
+        _ => Some(next + chunk.read_u16(offset + 1) as usize),
+    }
+}
+
+// Follows the stack depth along every path through already checked code, so
+// no instruction pops below its frame or reads a slot that isn't there yet.
+// Where paths meet, the smaller depth is the one that counts.
+fn verify_stack(function: &FunctionProto) -> Result<(), LoadError> {
+    let chunk: &Chunk = &function.chunk;
+    let invalid = |offset: usize, message: String| LoadError::InvalidCode {
+        function: function.name.clone(),
+        offset,
+        message,
+    };
+
+    let mut depths: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    // Slot 0 holds the function being called, then come its arguments
+    depths[0] = Some(1 + function.arity);
+    let mut pending: Vec<usize> = vec![0];
+    while let Some(offset) = pending.pop() {
+        let depth: usize = depths[offset].expect("depth of a pending instruction");
+        let op: OpCode = OpCode::from_byte(chunk.code[offset]).expect("verified opcode");
+        let next: usize = offset + 1 + chunk.operand_width(op, offset);
+
+        let mut slots: Vec<(usize, usize)> = Vec::new();
+        match op {
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::IterNext => {
+                slots.push((offset, chunk.code[offset + 1] as usize));
+            }
+            OpCode::Closure => {
+                let mut at: usize = offset + 3;
+                while at < next {
+                    if chunk.code[at] == 1 {
+                        slots.push((at, chunk.code[at + 1] as usize));
+                    }
+                    at += 2;
+                }
+            }
+            _ => {}
+        }
+        for (at, slot) in slots {
+            if slot >= depth {
+                return Err(invalid(
+                    at,
+                    format!("local slot {} but the stack holds {}.", slot, depth),
+                ));
+            }
+        }
+
+        let (needs, pushes): (usize, usize) = stack_effect(chunk, op, offset);
+        if needs > depth {
+            return Err(invalid(
+                offset,
+                format!(
+                    "{:?} needs {} on the stack, which holds {}.",
+                    op, needs, depth
+                ),
+            ));
+        }
+        let after: usize = depth - needs + pushes;
+
+        let mut successors: Vec<(usize, usize)> = Vec::new();
+        match op {
+            OpCode::Return | OpCode::Throw => {}
+            OpCode::Jump | OpCode::Loop => {
+                successors.push((jump_target(chunk, op, offset, next).expect("target"), after));
+            }
+            OpCode::JumpIfFalse => {
+                successors.push((next, after));
+                successors.push((jump_target(chunk, op, offset, next).expect("target"), after));
+            }
+            // The handler starts with the thrown value on the stack
+            OpCode::Try => {
+                successors.push((next, after));
+                successors.push((
+                    jump_target(chunk, op, offset, next).expect("target"),
+                    after + 1,
+                ));
+            }
+            // The item is only pushed while there is one
+            OpCode::IterNext => {
+                successors.push((next, after));
+                successors.push((jump_target(chunk, op, offset, next).expect("target"), depth));
+            }
+            _ => successors.push((next, after)),
+        }
+        for (target, depth) in successors {
+            // The final return is checked to be last, so only jumps can get
+            // here and their targets are instructions
+            if depths[target].is_none_or(|known| depth < known) {
+                depths[target] = Some(depth);
+                pending.push(target);
+            }
+        }
+    }
+    Ok(())
+}
+
+// How many values an instruction takes off the stack, counting those it only
+// looks at, and how many it leaves in their place
+fn stack_effect(chunk: &Chunk, op: OpCode, offset: usize) -> (usize, usize) {
+    match op {
+        OpCode::Constant
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetLocal
+        | OpCode::GetGlobal
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::Class
+        | OpCode::BuildMap
+        | OpCode::Import
+        | OpCode::IterNext => (0, 1),
+        OpCode::Jump | OpCode::Loop | OpCode::Try | OpCode::EndTry => (0, 0),
+        OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::CloseUpvalue
+        | OpCode::Print
+        | OpCode::Return
+        | OpCode::Throw => (1, 0),
+        OpCode::SetLocal
+        | OpCode::SetGlobal
+        | OpCode::SetUpvalue
+        | OpCode::GetProperty
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse
+        | OpCode::IterInit => (1, 1),
+        OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Index
+        | OpCode::Range
+        | OpCode::Inherit
+        | OpCode::Method => (2, 1),
+        OpCode::IndexSet | OpCode::MapInsert => (3, 1),
+        OpCode::Call => (chunk.code[offset + 1] as usize + 1, 1),
+        OpCode::BuildList => (chunk.read_u16(offset + 1) as usize, 1),
+        OpCode::Slice => {
+            let bounds: u8 = chunk.code[offset + 1];
+            (1 + (bounds & 1) as usize + (bounds >> 1 & 1) as usize, 1)
+        }
+    }
+}
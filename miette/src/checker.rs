@@ -0,0 +1,1676 @@
+// Gradual type checking. Annotations are optional:
+//
+//     var count: Number = 0;
+//     fun greet(name: String, title: String?): String { ... }
+//     class Point { x: Number; y: Number; init(x, y) { ... } }
+//
+// Anything without one is `Any` and goes unchecked, so only mismatches with a
+// type written down somewhere are reported. That includes operands, callees
+// and argument counts whose type comes from an annotation, but never what is
+// only inferred about plain code. `T?` is short for `T | Nil`, and must be
+// narrowed before it can be used as a `T`.
+//
+// Reads see what the code before them implies: a local takes the type of the
+// value it was last given, and `x != nil` in a condition narrows `x` in the
+// branch it guards. Branches merge into a union where they meet again, and a
+// loop or catch forgets what its body assigns, since that may or may not
+// have happened. Inside a function, variables from outside it have their
+// declared type, as the function may run at any time.
+
+use crate::ast::{Expr, Function, Literal, Stmt, TypeExpr};
+use crate::error::Diagnostic;
+use crate::lex::{Span, Token, TokenKind};
+use crate::stdlib;
+use crate::visit::{self, Visitor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    // No annotation, so anything goes
+    Any,
+    Nil,
+    Bool,
+    Number,
+    String,
+    List,
+    Map,
+    Range,
+    Error,
+    Module,
+    // Any function, or one whose signature is known
+    Function(Option<std::rc::Rc<Signature>>),
+    // A class itself, which makes instances when called
+    Class(String),
+    Instance(String),
+    // Flat and without duplicates, never holding Any
+    Union(Vec<Type>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    // None for built-ins, whose arguments go unchecked
+    pub params: Option<Vec<(String, Type)>>,
+    pub result: Type,
+    // Whether any of it was written down, as calls are only checked then
+    pub annotated: bool,
+}
+
+impl Type {
+    pub fn union(self, other: Type) -> Type {
+        if self == Type::Any || other == Type::Any {
+            return Type::Any;
+        }
+        let mut members: Vec<Type> = Vec::new();
+        for member in self.members().iter().chain(other.members()) {
+            if !members.contains(member) {
+                members.push(member.clone());
+            }
+        }
+        if members.len() == 1 {
+            members.remove(0)
+        } else {
+            Type::Union(members)
+        }
+    }
+
+    fn members(&self) -> &[Type] {
+        match self {
+            Type::Union(members) => members,
+            other => std::slice::from_ref(other),
+        }
+    }
+
+    fn admits_nil(&self) -> bool {
+        matches!(self, Type::Any | Type::Nil) || self.members().contains(&Type::Nil)
+    }
+
+    // What is left once `!= nil` is known
+    fn without_nil(&self) -> Type {
+        let Type::Union(members) = self else {
+            return self.clone();
+        };
+        members
+            .iter()
+            .filter(|member| **member != Type::Nil)
+            .cloned()
+            .reduce(Type::union)
+            .unwrap_or(Type::Nil)
+    }
+
+    // What is left once `== nil` is known, unchanged if it can't be nil
+    fn only_nil(&self) -> Type {
+        if self.admits_nil() {
+            Type::Nil
+        } else {
+            self.clone()
+        }
+    }
+
+    // The members `and` can hand back without evaluating its right operand
+    fn falsy(&self) -> Option<Type> {
+        if *self == Type::Any {
+            return Some(Type::Any);
+        }
+        self.members()
+            .iter()
+            .filter(|member| matches!(member, Type::Nil | Type::Bool))
+            .cloned()
+            .reduce(Type::union)
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nil => write!(f, "Nil"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::List => write!(f, "List"),
+            Type::Map => write!(f, "Map"),
+            Type::Range => write!(f, "Range"),
+            Type::Error => write!(f, "Error"),
+            Type::Module => write!(f, "Module"),
+            Type::Function(_) => write!(f, "Function"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+            Type::Union(members) => match members.as_slice() {
+                [member, Type::Nil] | [Type::Nil, member] => write!(f, "{}?", member),
+                _ => {
+                    for (index, member) in members.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, " | ")?;
+                        }
+                        write!(f, "{}", member)?;
+                    }
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+// What the built-ins return, where it is always the same
+fn native_result(name: &str) -> Type {
+    match name {
+        "clock" | "len" | "sqrt" | "floor" | "pow" | "random" => Type::Number,
+        "str" | "type" | "trim" | "upper" | "lower" | "replace" | "substr" => Type::String,
+        "num" => Type::Number.union(Type::Nil),
+        "input" => Type::String.union(Type::Nil),
+        "keys" | "values" | "split" => Type::List,
+        "contains" => Type::Bool,
+        "push" | "insert" | "assert" | "assert_eq" => Type::Nil,
+        "error" => Type::Error,
+        _ => Type::Any,
+    }
+}
+
+// Variables and fields with the type a condition narrowed them to
+type Facts = Vec<(String, Type)>;
+
+#[derive(Debug, Clone)]
+struct Binding {
+    // What reading it gives at this point
+    current: Type,
+    // What it may hold, None without an annotation
+    declared: Option<Type>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    // Variables, and `x.field` or `this.field` for fields a condition
+    // narrowed
+    bindings: std::collections::HashMap<String, Binding>,
+    // The parameters of a function
+    function: bool,
+}
+
+// The function whose body is being checked
+struct Context {
+    name: String,
+    // None without an annotation
+    result: Option<Type>,
+    initializer: bool,
+}
+
+// What a class body declares, with what it inherits left to its superclass
+struct ClassInfo {
+    superclass: Option<String>,
+    fields: std::collections::HashMap<String, Type>,
+    methods: std::collections::HashMap<String, std::rc::Rc<Signature>>,
+}
+
+pub struct Checker {
+    // Innermost last, the globals and built-ins first
+    scopes: Vec<Scope>,
+    // By name, None where several classes share one and their members can't
+    // be told apart
+    classes: std::collections::HashMap<String, Option<ClassInfo>>,
+    // Variables assigned anywhere after being declared
+    reassigned: std::collections::HashSet<String>,
+    functions: Vec<Context>,
+    // The class of `this`, innermost last
+    class: Vec<String>,
+    strict: bool,
+    errors: Vec<Diagnostic>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            scopes: Vec::new(),
+            classes: std::collections::HashMap::new(),
+            reassigned: std::collections::HashSet::new(),
+            functions: Vec::new(),
+            class: Vec::new(),
+            strict: false,
+            errors: Vec::new(),
+        }
+    }
+
+    // Exported functions and methods must annotate every parameter and what
+    // they return
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn check(&mut self, statements: &[Stmt]) -> Result<(), Vec<Diagnostic>> {
+        let mut assigned: Assigned = Assigned::default();
+        visit::walk_stmts(&mut assigned, statements);
+        self.reassigned = assigned.names;
+        let mut declared: Vec<&Stmt> = Vec::new();
+        classes(statements, &mut declared);
+        self.declare_classes(&declared);
+
+        let mut globals: Scope = Scope::default();
+        for name in stdlib::NAMES {
+            let signature: Signature = Signature {
+                name: name.to_string(),
+                params: None,
+                result: native_result(name),
+                annotated: false,
+            };
+            let binding: Binding = Binding {
+                current: Type::Function(Some(std::rc::Rc::new(signature))),
+                declared: None,
+            };
+            globals.bindings.insert(name.to_string(), binding);
+        }
+        self.scopes = vec![globals];
+
+        self.statements(statements);
+        self.scopes.clear();
+        self.errors
+            .sort_by_key(|error| (error.line, error.span.start));
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    // Every class is known up front, so annotations can name one declared
+    // further down
+    fn declare_classes(&mut self, classes: &[&Stmt]) {
+        let mut counts: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+        for class in classes {
+            if let Stmt::Class { name, .. } = class {
+                *counts.entry(name.lexeme()).or_default() += 1;
+            }
+        }
+        self.classes = counts.keys().map(|name| (name.to_string(), None)).collect();
+
+        for class in classes {
+            let Stmt::Class {
+                name,
+                superclass,
+                fields,
+                methods,
+            } = class
+            else {
+                continue;
+            };
+            if counts[name.lexeme()] > 1 {
+                continue;
+            }
+            // Unknown types are reported where the class is checked
+            let mut unknown: Vec<Token> = Vec::new();
+            let info: ClassInfo = ClassInfo {
+                superclass: match superclass {
+                    Some(Expr::Variable { name, .. }) => Some(name.lexeme().to_string()),
+                    _ => None,
+                },
+                fields: fields
+                    .iter()
+                    .map(|field| {
+                        let field_type: Type = self.resolve(&field.annotation, &mut unknown);
+                        (field.name.lexeme().to_string(), field_type)
+                    })
+                    .collect(),
+                methods: methods
+                    .iter()
+                    .map(|method| {
+                        let signature: Signature =
+                            self.signature(method, Some(name.lexeme()), &mut unknown);
+                        (signature.name.clone(), std::rc::Rc::new(signature))
+                    })
+                    .collect(),
+            };
+            self.classes.insert(name.lexeme().to_string(), Some(info));
+        }
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        // Functions and classes can be used before they are declared, from
+        // the bodies of functions declared earlier
+        for statement in statements {
+            let declaration: &Stmt = match statement {
+                Stmt::Export { declaration, .. } => declaration,
+                other => other,
+            };
+            let (name, hoisted): (&Token, Type) = match declaration {
+                Stmt::Function { function } => {
+                    let Some(name) = &function.name else {
+                        continue;
+                    };
+                    let signature: Signature = self.signature(function, None, &mut Vec::new());
+                    (name, Type::Function(Some(std::rc::Rc::new(signature))))
+                }
+                Stmt::Class { name, .. } => (name, Type::Class(name.lexeme().to_string())),
+                _ => continue,
+            };
+            if !self.reassigned.contains(name.lexeme()) {
+                self.declare(name.lexeme(), hoisted, None);
+            }
+        }
+
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression { expression } | Stmt::Print { expression, .. } => {
+                self.expr(expression);
+            }
+            Stmt::Var {
+                name,
+                annotation,
+                initializer,
+            } => {
+                let declared: Option<Type> = annotation
+                    .as_ref()
+                    .map(|annotation| self.annotation(annotation));
+                let value: Type = match initializer {
+                    Some(initializer) => self.expr(initializer),
+                    None => Type::Nil,
+                };
+                let current: Type = match &declared {
+                    Some(declared) => {
+                        let (token, span): (&Token, Span) = match initializer {
+                            Some(initializer) => (initializer.first_token(), span(initializer)),
+                            None => (name, name.span()),
+                        };
+                        let message: String = format!(
+                            "Expected {} for '{}', found {}.",
+                            declared,
+                            name.lexeme(),
+                            value
+                        );
+                        self.narrowed(value, declared, token, span, message)
+                    }
+                    None => value,
+                };
+                self.declare(name.lexeme(), current, declared);
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope(false);
+                self.statements(statements);
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expr(condition);
+                let (when_true, when_false) = self.narrowing(condition);
+                let before: Vec<Scope> = self.scopes.clone();
+                self.narrow(when_true);
+                self.statement(then_branch);
+                let after_then: Vec<Scope> = std::mem::replace(&mut self.scopes, before);
+                self.narrow(when_false);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                let else_exits: bool = else_branch.as_deref().is_some_and(exits);
+                self.merge(after_then, exits(then_branch), else_exits);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.forget(&assigned(&[body], &[condition]));
+                let before: Vec<Scope> = self.scopes.clone();
+                self.expr(condition);
+                let (when_true, when_false) = self.narrowing(condition);
+                self.narrow(when_true);
+                self.statement(body);
+                self.scopes = before;
+                self.narrow(when_false);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope(false);
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                let clauses: Vec<&Expr> = condition.iter().chain(increment.iter()).collect();
+                self.forget(&assigned(&[body], &clauses));
+                let before: Vec<Scope> = self.scopes.clone();
+                let mut when_false: Facts = Vec::new();
+                if let Some(condition) = condition {
+                    self.expr(condition);
+                    let (when_true, otherwise) = self.narrowing(condition);
+                    self.narrow(when_true);
+                    when_false = otherwise;
+                }
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.scopes = before;
+                self.narrow(when_false);
+                self.end_scope();
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                let element: Type = match self.expr(iterable) {
+                    Type::Range => Type::Number,
+                    Type::String => Type::String,
+                    _ => Type::Any,
+                };
+                self.forget(&assigned(&[body], &[]));
+                let before: Vec<Scope> = self.scopes.clone();
+                self.begin_scope(false);
+                self.declare(variable.lexeme(), element, None);
+                self.statement(body);
+                self.end_scope();
+                self.scopes = before;
+            }
+            Stmt::Function { function } => {
+                let signature: std::rc::Rc<Signature> =
+                    std::rc::Rc::new(self.declared_signature(function, None));
+                if let Some(name) = &function.name {
+                    self.declare(name.lexeme(), Type::Function(Some(signature.clone())), None);
+                }
+                self.body(function, &signature, false);
+            }
+            Stmt::Return { keyword, value } => {
+                let found: Type = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Nil,
+                };
+                let Some(context) = self.functions.last() else {
+                    return;
+                };
+                let Some(expected) = context.result.clone() else {
+                    return;
+                };
+                if context.initializer {
+                    return;
+                }
+                let message: String = format!(
+                    "Expected '{}' to return {}, found {}.",
+                    context.name, expected, found
+                );
+                match value {
+                    Some(value) => {
+                        self.expect(&found, &expected, value.first_token(), span(value), message)
+                    }
+                    None => self.expect(&found, &expected, keyword, keyword.span(), message),
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Class {
+                name,
+                superclass,
+                fields,
+                methods,
+            } => {
+                if let Some(superclass) = superclass {
+                    self.expr(superclass);
+                }
+                self.declare(name.lexeme(), Type::Class(name.lexeme().to_string()), None);
+                for field in fields {
+                    self.annotation(&field.annotation);
+                }
+
+                self.class.push(name.lexeme().to_string());
+                for method in methods {
+                    let signature: Signature = self.declared_signature(method, Some(name.lexeme()));
+                    let initializer: bool = signature.name == "init";
+                    self.body(method, &signature, initializer);
+                }
+                self.class.pop();
+            }
+            Stmt::Test { body, .. } => {
+                self.begin_scope(true);
+                self.functions.push(Context {
+                    name: "test".to_string(),
+                    result: None,
+                    initializer: false,
+                });
+                self.statements(body);
+                self.functions.pop();
+                self.end_scope();
+            }
+            Stmt::Import { name, .. } => self.declare(name.lexeme(), Type::Module, None),
+            Stmt::Export { declaration, .. } => {
+                if self.strict {
+                    self.require_annotations(declaration);
+                }
+                self.statement(declaration);
+            }
+            Stmt::Throw { value, .. } => {
+                self.expr(value);
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.begin_scope(false);
+                self.statements(body);
+                self.end_scope();
+                let body_exits: bool = body.iter().any(exits);
+
+                let body_assigned: Assigned = assigned(&body.iter().collect::<Vec<_>>(), &[]);
+                if let Some(catch) = catch {
+                    let after_body: Vec<Scope> = self.scopes.clone();
+                    // The error may have come from anywhere in the body
+                    self.forget(&body_assigned);
+                    self.begin_scope(false);
+                    self.declare(catch.name.lexeme(), Type::Any, None);
+                    self.statements(&catch.body);
+                    self.end_scope();
+                    let catch_exits: bool = catch.body.iter().any(exits);
+                    // The catch only runs when the body doesn't finish, so
+                    // it takes the place of the else branch of an if
+                    let after_catch: Vec<Scope> = std::mem::replace(&mut self.scopes, after_body);
+                    let merged: Vec<Scope> = std::mem::replace(&mut self.scopes, after_catch);
+                    self.merge(merged, body_exits, catch_exits);
+                }
+
+                if let Some(finally) = finally {
+                    self.forget(&body_assigned);
+                    if let Some(catch) = catch {
+                        self.forget(&assigned(&catch.body.iter().collect::<Vec<_>>(), &[]));
+                    }
+                    self.begin_scope(false);
+                    self.statements(finally);
+                    self.end_scope();
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal { value, .. } => match value {
+                Literal::Nil => Type::Nil,
+                Literal::Bool(_) => Type::Bool,
+                Literal::Number(_) => Type::Number,
+                Literal::Text(_) => Type::String,
+            },
+            Expr::Grouping { expression } => self.expr(expression),
+            Expr::Unary { operator, right } => {
+                let found: Type = self.expr(right);
+                match operator.kind() {
+                    TokenKind::Bang => Type::Bool,
+                    _ => {
+                        let found: Type = self.declared(right, found);
+                        self.operand(&found, &Type::Number, operator, right);
+                        Type::Number
+                    }
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left_type: Type = self.expr(left);
+                let right_type: Type = self.expr(right);
+                let left_declared: Type = self.declared(left, left_type.clone());
+                let right_declared: Type = self.declared(right, right_type.clone());
+                match operator.kind() {
+                    // Two strings or two numbers, or a runtime error. One
+                    // side known to be either decides what the other must be.
+                    TokenKind::Plus => {
+                        let expected: Type = match (&left_type, &right_type) {
+                            (Type::Number, _) | (_, Type::Number) => Type::Number,
+                            (Type::String, _) | (_, Type::String) => Type::String,
+                            _ => Type::Number.union(Type::String),
+                        };
+                        self.operand(&left_declared, &expected, operator, left);
+                        self.operand(&right_declared, &expected, operator, right);
+                        match (left_type, right_type) {
+                            (Type::String, _) | (_, Type::String) => Type::String,
+                            (Type::Number, _) | (_, Type::Number) => Type::Number,
+                            _ => Type::Number.union(Type::String),
+                        }
+                    }
+                    TokenKind::EqualEqual | TokenKind::BangEqual => Type::Bool,
+                    kind => {
+                        self.operand(&left_declared, &Type::Number, operator, left);
+                        self.operand(&right_declared, &Type::Number, operator, right);
+                        match kind {
+                            TokenKind::Minus | TokenKind::Star | TokenKind::Slash => Type::Number,
+                            _ => Type::Bool,
+                        }
+                    }
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left_type: Type = self.expr(left);
+                let (when_true, when_false) = self.narrowing(left);
+                let before: Vec<Scope> = self.scopes.clone();
+                if *operator.kind() == TokenKind::And {
+                    self.narrow(when_true);
+                } else {
+                    self.narrow(when_false);
+                }
+                let right: Type = self.expr(right);
+                self.scopes = before;
+
+                // `or` hands back its left operand when that is truthy and
+                // `and` when it is falsy
+                let left_type: Option<Type> = if *operator.kind() == TokenKind::And {
+                    left_type.falsy()
+                } else {
+                    Some(left_type.without_nil())
+                };
+                match left_type {
+                    Some(left_type) => left_type.union(right),
+                    None => right,
+                }
+            }
+            Expr::Variable { name, .. } => self.lookup(name.lexeme()),
+            Expr::Assign { name, value, .. } => {
+                let found: Type = self.expr(value);
+                self.assign(name, value, found.clone());
+                found
+            }
+            Expr::Call {
+                callee: callee_expr,
+                arguments,
+                ..
+            } => {
+                let callee: Type = self.expr(callee_expr);
+                let found: Vec<Type> = arguments
+                    .iter()
+                    .map(|argument| self.expr(argument))
+                    .collect();
+                // A call can change any object's fields
+                self.forget_fields(|_| true);
+
+                if !self
+                    .declared(callee_expr, callee.clone())
+                    .members()
+                    .iter()
+                    .all(|member| matches!(member, Type::Any | Type::Function(_) | Type::Class(_)))
+                {
+                    let message: String =
+                        format!("Can only call functions and classes, found {}.", callee);
+                    let mut error: Diagnostic = Diagnostic::at(callee_expr.first_token(), &message);
+                    error.span = span(callee_expr);
+                    self.errors.push(error);
+                }
+
+                let signature: Option<std::rc::Rc<Signature>> = match &callee {
+                    Type::Function(signature) => signature.clone(),
+                    Type::Class(name) => self.initializer(name),
+                    _ => None,
+                };
+                if let Some(signature) = &signature
+                    && let Some(params) = &signature.params
+                {
+                    if signature.annotated && params.len() != arguments.len() {
+                        let message: String = format!(
+                            "Expected {} arguments to '{}' but got {}.",
+                            params.len(),
+                            signature.name,
+                            arguments.len()
+                        );
+                        let mut error: Diagnostic =
+                            Diagnostic::at(callee_expr.first_token(), &message);
+                        error.span = span(expr);
+                        self.errors.push(error);
+                    }
+                    for ((argument, found), (param, expected)) in
+                        arguments.iter().zip(found.iter()).zip(params.iter())
+                    {
+                        let message: String = format!(
+                            "Expected {} for parameter '{}' of '{}', found {}.",
+                            expected, param, signature.name, found
+                        );
+                        self.expect(
+                            found,
+                            expected,
+                            argument.first_token(),
+                            span(argument),
+                            message,
+                        );
+                    }
+                }
+                match callee {
+                    Type::Class(name) => Type::Instance(name),
+                    _ => signature.map_or(Type::Any, |signature| signature.result.clone()),
+                }
+            }
+            Expr::Get { object, name } => {
+                let object_type: Type = self.expr(object);
+                if let Some(path) = path(object, name)
+                    && let Some(binding) = self.local(&path)
+                {
+                    return binding.current.clone();
+                }
+                self.property(&object_type, name.lexeme())
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object_type: Type = self.expr(object);
+                let found: Type = self.expr(value);
+                let declared: Option<Type> = match object_type.without_nil() {
+                    Type::Instance(class) => self.field(&class, name.lexeme()).map(|declared| {
+                        let message: String = format!(
+                            "Expected {} for field '{}' of {}, found {}.",
+                            declared,
+                            name.lexeme(),
+                            class,
+                            found
+                        );
+                        self.expect(&found, &declared, value.first_token(), span(value), message);
+                        declared
+                    }),
+                    _ => None,
+                };
+                self.forget_fields(|field| field == name.lexeme());
+                if let Some(path) = path(object, name) {
+                    let current: Type = match &declared {
+                        Some(declared) if !self.assignable(&found, declared) => declared.clone(),
+                        _ => found.clone(),
+                    };
+                    self.declare(&path, current, declared);
+                }
+                found
+            }
+            Expr::This { .. } => match self.class.last() {
+                Some(class) => Type::Instance(class.clone()),
+                None => Type::Any,
+            },
+            Expr::Super { method, .. } => {
+                let superclass: Option<String> = self.class.last().and_then(|class| {
+                    let info: &ClassInfo = self.classes.get(class)?.as_ref()?;
+                    info.superclass.clone()
+                });
+                superclass
+                    .and_then(|superclass| self.method(&superclass, method.lexeme()))
+                    .map_or(Type::Any, |signature| Type::Function(Some(signature)))
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.expr(element);
+                }
+                Type::List
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                Type::Map
+            }
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.expr(index);
+                Type::Any
+            }
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value)
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                let object: Type = self.expr(object);
+                for bound in start.iter().chain(end.iter()) {
+                    self.expr(bound);
+                }
+                match object {
+                    Type::List | Type::String => object,
+                    _ => Type::Any,
+                }
+            }
+            Expr::Range { start, end, .. } => {
+                self.expr(start);
+                self.expr(end);
+                Type::Range
+            }
+            Expr::Lambda { function, .. } => {
+                let signature: std::rc::Rc<Signature> =
+                    std::rc::Rc::new(self.declared_signature(function, None));
+                self.body(function, &signature, false);
+                Type::Function(Some(signature))
+            }
+        }
+    }
+
+    fn body(&mut self, function: &Function, signature: &Signature, initializer: bool) {
+        self.begin_scope(true);
+        for ((param, annotation), (_, param_type)) in function
+            .params
+            .iter()
+            .zip(function.param_types.iter())
+            .zip(signature.params.iter().flatten())
+        {
+            let declared: Option<Type> = annotation.as_ref().map(|_| param_type.clone());
+            self.declare(param.lexeme(), param_type.clone(), declared);
+        }
+        let result: Option<Type> = function
+            .return_type
+            .as_ref()
+            .map(|_| signature.result.clone());
+        self.functions.push(Context {
+            name: signature.name.clone(),
+            result: result.clone(),
+            initializer,
+        });
+
+        self.statements(&function.body);
+
+        if let (Some(result), Some(annotation)) = (result, &function.return_type)
+            && !initializer
+            && !result.admits_nil()
+            && !function.body.iter().any(exits)
+        {
+            let message: String = format!(
+                "'{}' can reach its end without returning {}.",
+                signature.name, result
+            );
+            self.errors
+                .push(Diagnostic::at(annotation.first_token(), &message));
+        }
+        self.functions.pop();
+        self.end_scope();
+    }
+
+    // The signature a function's annotations give it, reporting unknown types
+    fn declared_signature(&mut self, function: &Function, class: Option<&str>) -> Signature {
+        let mut unknown: Vec<Token> = Vec::new();
+        let signature: Signature = self.signature(function, class, &mut unknown);
+        self.unknown(unknown);
+        signature
+    }
+
+    fn signature(
+        &self,
+        function: &Function,
+        class: Option<&str>,
+        unknown: &mut Vec<Token>,
+    ) -> Signature {
+        let name: String = function
+            .name
+            .as_ref()
+            .map_or("lambda".to_string(), |name| name.lexeme().to_string());
+        let params: Vec<(String, Type)> = function
+            .params
+            .iter()
+            .zip(function.param_types.iter())
+            .map(|(param, annotation)| {
+                let param_type: Type = match annotation {
+                    Some(annotation) => self.resolve(annotation, unknown),
+                    None => Type::Any,
+                };
+                (param.lexeme().to_string(), param_type)
+            })
+            .collect();
+        let result: Type = match (&function.return_type, class) {
+            (Some(annotation), _) => self.resolve(annotation, unknown),
+            (None, Some(class)) if name == "init" => Type::Instance(class.to_string()),
+            (None, _) => Type::Any,
+        };
+        Signature {
+            name,
+            params: Some(params),
+            result,
+            annotated: function.return_type.is_some()
+                || function.param_types.iter().any(Option::is_some),
+        }
+    }
+
+    fn annotation(&mut self, annotation: &TypeExpr) -> Type {
+        let mut unknown: Vec<Token> = Vec::new();
+        let resolved: Type = self.resolve(annotation, &mut unknown);
+        self.unknown(unknown);
+        resolved
+    }
+
+    fn unknown(&mut self, names: Vec<Token>) {
+        for name in names {
+            let message: String = format!("Unknown type '{}'.", name.lexeme());
+            self.errors.push(Diagnostic::at(&name, &message));
+        }
+    }
+
+    fn resolve(&self, annotation: &TypeExpr, unknown: &mut Vec<Token>) -> Type {
+        match annotation {
+            TypeExpr::Named { name } => match name.lexeme() {
+                "Any" => Type::Any,
+                "Nil" => Type::Nil,
+                "Bool" => Type::Bool,
+                "Number" => Type::Number,
+                "String" => Type::String,
+                "List" => Type::List,
+                "Map" => Type::Map,
+                "Range" => Type::Range,
+                "Error" => Type::Error,
+                "Module" => Type::Module,
+                "Function" => Type::Function(None),
+                class if self.classes.contains_key(class) => Type::Instance(class.to_string()),
+                _ => {
+                    unknown.push(name.clone());
+                    Type::Any
+                }
+            },
+            TypeExpr::Optional { inner, .. } => self.resolve(inner, unknown).union(Type::Nil),
+            TypeExpr::Union { types } => types
+                .iter()
+                .map(|member| self.resolve(member, unknown))
+                .reduce(Type::union)
+                .unwrap_or(Type::Any),
+        }
+    }
+
+    fn require_annotations(&mut self, declaration: &Stmt) {
+        let (functions, kind): (Vec<&Function>, &str) = match declaration {
+            Stmt::Function { function } => (vec![function], "function"),
+            Stmt::Class { methods, .. } => (methods.iter().map(|m| &**m).collect(), "method"),
+            _ => return,
+        };
+        for function in functions {
+            let Some(name) = &function.name else {
+                continue;
+            };
+            for (param, annotation) in function.params.iter().zip(function.param_types.iter()) {
+                if annotation.is_none() {
+                    let message: String = format!(
+                        "Parameter '{}' of exported {} '{}' needs a type.",
+                        param.lexeme(),
+                        kind,
+                        name.lexeme()
+                    );
+                    self.errors.push(Diagnostic::at(param, &message));
+                }
+            }
+            // `init` always returns the instance
+            if function.return_type.is_none() && !(kind == "method" && name.lexeme() == "init") {
+                let message: String =
+                    format!("Exported {} '{}' needs a return type.", kind, name.lexeme());
+                self.errors.push(Diagnostic::at(name, &message));
+            }
+        }
+    }
+
+    // The type of an expression as far as annotations tell, or Any for what
+    // is only inferred, which plain code may well use in ways that work
+    fn declared(&self, expr: &Expr, found: Type) -> Type {
+        let annotated: bool = match expr {
+            Expr::Grouping { expression } => return self.declared(expression, found),
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => self
+                .binding(name.lexeme())
+                .is_some_and(|(binding, _)| binding.declared.is_some()),
+            Expr::Get { object, name } => {
+                if let Some(path) = path(object, name)
+                    && let Some(binding) = self.local(&path)
+                {
+                    binding.declared.is_some()
+                } else {
+                    // Fields only have a type when their class declares one
+                    let object_type: Type = match &**object {
+                        Expr::Variable { name, .. } => self.lookup(name.lexeme()),
+                        Expr::This { .. } => self
+                            .class
+                            .last()
+                            .map_or(Type::Any, |class| Type::Instance(class.clone())),
+                        _ => Type::Any,
+                    };
+                    object_type.without_nil().members().iter().any(|member| {
+                        matches!(member, Type::Instance(class)
+                            if self.field(class, name.lexeme()).is_some())
+                    })
+                }
+            }
+            Expr::Call { callee, .. } => matches!(
+                self.known(callee),
+                Some((_, Type::Function(Some(signature)))) if signature.annotated
+            ),
+            _ => false,
+        };
+        if annotated { found } else { Type::Any }
+    }
+
+    // Operators other than `==` and `!=` fail at runtime on the wrong types
+    fn operand(&mut self, found: &Type, expected: &Type, operator: &Token, operand: &Expr) {
+        let message: String = format!(
+            "Expected {} for operand of '{}', found {}.",
+            expected,
+            operator.lexeme(),
+            found
+        );
+        self.expect(
+            found,
+            expected,
+            operand.first_token(),
+            span(operand),
+            message,
+        );
+    }
+
+    fn expect(
+        &mut self,
+        found: &Type,
+        expected: &Type,
+        token: &Token,
+        span: Span,
+        message: String,
+    ) {
+        if self.assignable(found, expected) {
+            return;
+        }
+        let mut error: Diagnostic = Diagnostic::at(token, &message);
+        error.span = span;
+        self.errors.push(error);
+    }
+
+    // Checks a value given to something declared, and what reading it back
+    // gives: the value's own type when it fits, the declared one otherwise
+    fn narrowed(
+        &mut self,
+        found: Type,
+        declared: &Type,
+        token: &Token,
+        span: Span,
+        message: String,
+    ) -> Type {
+        self.expect(&found, declared, token, span, message);
+        if *declared == Type::Any || !self.assignable(&found, declared) {
+            declared.clone()
+        } else {
+            found
+        }
+    }
+
+    fn assignable(&self, found: &Type, expected: &Type) -> bool {
+        match (found, expected) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Union(members), _) => members
+                .iter()
+                .all(|member| self.assignable(member, expected)),
+            (_, Type::Union(members)) => {
+                members.iter().any(|member| self.assignable(found, member))
+            }
+            (Type::Function(_), Type::Function(_)) => true,
+            (Type::Instance(found), Type::Instance(expected)) => self.is_subclass(found, expected),
+            _ => found == expected,
+        }
+    }
+
+    // True when it can't be told, for classes sharing a name
+    fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut class: String = class.to_string();
+        // Bounded, a program may declare an inheritance cycle it never runs
+        for _ in 0..=self.classes.len() {
+            if class == ancestor {
+                return true;
+            }
+            let Some(Some(info)) = self.classes.get(&class) else {
+                return self.classes.contains_key(&class);
+            };
+            let Some(superclass) = &info.superclass else {
+                return false;
+            };
+            class = superclass.clone();
+        }
+        false
+    }
+
+    // Looks up a class member, searching the superclass chain
+    fn member<T>(&self, class: &str, find: impl Fn(&ClassInfo) -> Option<T>) -> Option<T> {
+        let mut class: String = class.to_string();
+        for _ in 0..=self.classes.len() {
+            let info: &ClassInfo = self.classes.get(&class)?.as_ref()?;
+            if let Some(found) = find(info) {
+                return Some(found);
+            }
+            class = info.superclass.clone()?;
+        }
+        None
+    }
+
+    fn method(&self, class: &str, name: &str) -> Option<std::rc::Rc<Signature>> {
+        self.member(class, |info| info.methods.get(name).cloned())
+    }
+
+    // What calling a class takes: its `init`, or no arguments at all when
+    // no class in the chain has one. None when that can't be told.
+    fn initializer(&self, class: &str) -> Option<std::rc::Rc<Signature>> {
+        let mut current: String = class.to_string();
+        for _ in 0..=self.classes.len() {
+            let info: &ClassInfo = self.classes.get(&current)?.as_ref()?;
+            if let Some(init) = info.methods.get("init") {
+                return Some(init.clone());
+            }
+            let Some(superclass) = &info.superclass else {
+                return Some(std::rc::Rc::new(Signature {
+                    name: class.to_string(),
+                    params: Some(Vec::new()),
+                    result: Type::Instance(class.to_string()),
+                    annotated: false,
+                }));
+            };
+            current = superclass.clone();
+        }
+        None
+    }
+
+    fn field(&self, class: &str, name: &str) -> Option<Type> {
+        self.member(class, |info| info.fields.get(name).cloned())
+    }
+
+    // Fields may hold anything without a declaration, since any can be set.
+    // Reading one from nil fails at runtime, so nil itself adds nothing.
+    fn property(&self, object: &Type, name: &str) -> Type {
+        let property = |member: &Type| -> Type {
+            match member {
+                Type::Instance(class) => self
+                    .field(class, name)
+                    .or_else(|| {
+                        self.method(class, name)
+                            .map(|signature| Type::Function(Some(signature)))
+                    })
+                    .unwrap_or(Type::Any),
+                Type::Error => match name {
+                    "message" | "kind" => Type::String,
+                    "trace" => Type::List,
+                    _ => Type::Any,
+                },
+                _ => Type::Any,
+            }
+        };
+        object
+            .without_nil()
+            .members()
+            .iter()
+            .map(property)
+            .reduce(Type::union)
+            .unwrap_or(Type::Any)
+    }
+
+    fn assign(&mut self, name: &Token, value: &Expr, found: Type) {
+        let Some((binding, crossed)) = self.binding(name.lexeme()) else {
+            return;
+        };
+        let declared: Option<Type> = binding.declared.clone();
+        let current: Type = match &declared {
+            Some(declared) => {
+                let message: String = format!(
+                    "Expected {} for '{}', found {}.",
+                    declared,
+                    name.lexeme(),
+                    found
+                );
+                self.narrowed(found, declared, value.first_token(), span(value), message)
+            }
+            None => found,
+        };
+        let prefix: String = format!("{}.", name.lexeme());
+        self.forget_paths(|path| path.starts_with(&prefix));
+        // Whether code from another function has run yet is unknown
+        if !crossed && let Some(binding) = self.binding_mut(name.lexeme()) {
+            binding.current = current;
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        let Some((binding, crossed)) = self.binding(name) else {
+            return Type::Any;
+        };
+        if !crossed {
+            return binding.current.clone();
+        }
+        match &binding.declared {
+            Some(declared) => declared.clone(),
+            None if self.reassigned.contains(name) => Type::Any,
+            None => binding.current.clone(),
+        }
+    }
+
+    // The innermost binding of a name, and whether it is outside the
+    // function being checked
+    fn binding(&self, name: &str) -> Option<(&Binding, bool)> {
+        let mut crossed: bool = false;
+        for scope in self.scopes.iter().rev() {
+            if let Some(binding) = scope.bindings.get(name) {
+                return Some((binding, crossed));
+            }
+            crossed = crossed || scope.function;
+        }
+        None
+    }
+
+    fn binding_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.bindings.get_mut(name))
+    }
+
+    // A binding of the function being checked
+    fn local(&self, name: &str) -> Option<&Binding> {
+        match self.binding(name) {
+            Some((binding, false)) => Some(binding),
+            _ => None,
+        }
+    }
+
+    fn declare(&mut self, name: &str, current: Type, declared: Option<Type>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope
+                .bindings
+                .insert(name.to_string(), Binding { current, declared });
+        }
+    }
+
+    fn begin_scope(&mut self, function: bool) {
+        self.scopes.push(Scope {
+            bindings: std::collections::HashMap::new(),
+            function,
+        });
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // What a condition being true, then false, says about the variables and
+    // fields it tests
+    fn narrowing(&self, condition: &Expr) -> (Facts, Facts) {
+        match condition {
+            Expr::Grouping { expression } => self.narrowing(expression),
+            Expr::Unary { operator, right } if *operator.kind() == TokenKind::Bang => {
+                let (when_true, when_false) = self.narrowing(right);
+                (when_false, when_true)
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if matches!(
+                operator.kind(),
+                TokenKind::EqualEqual | TokenKind::BangEqual
+            ) =>
+            {
+                let subject: &Expr = match (&**left, &**right) {
+                    (
+                        subject,
+                        Expr::Literal {
+                            value: Literal::Nil,
+                            ..
+                        },
+                    )
+                    | (
+                        Expr::Literal {
+                            value: Literal::Nil,
+                            ..
+                        },
+                        subject,
+                    ) => subject,
+                    _ => return (Vec::new(), Vec::new()),
+                };
+                let Some((key, known)) = self.known(subject) else {
+                    return (Vec::new(), Vec::new());
+                };
+                let is_nil: Facts = vec![(key.clone(), known.only_nil())];
+                let not_nil: Facts = vec![(key, not_nil(&known))];
+                if *operator.kind() == TokenKind::EqualEqual {
+                    (is_nil, not_nil)
+                } else {
+                    (not_nil, is_nil)
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let (left_true, left_false) = self.narrowing(left);
+                let (right_true, right_false) = self.narrowing(right);
+                if *operator.kind() == TokenKind::And {
+                    (
+                        left_true.into_iter().chain(right_true).collect(),
+                        Vec::new(),
+                    )
+                } else {
+                    (
+                        Vec::new(),
+                        left_false.into_iter().chain(right_false).collect(),
+                    )
+                }
+            }
+            // Truthy means not nil
+            subject => match self.known(subject) {
+                Some((key, known)) => (vec![(key, not_nil(&known))], Vec::new()),
+                None => (Vec::new(), Vec::new()),
+            },
+        }
+    }
+
+    // A variable, or a field of a variable or `this`, with its type now
+    fn known(&self, expr: &Expr) -> Option<(String, Type)> {
+        match expr {
+            Expr::Variable { name, .. } => {
+                self.local(name.lexeme())?;
+                Some((name.lexeme().to_string(), self.lookup(name.lexeme())))
+            }
+            Expr::Get { object, name } => {
+                let path: String = path(object, name)?;
+                if let Some(binding) = self.local(&path) {
+                    return Some((path, binding.current.clone()));
+                }
+                let object_type: Type = match &**object {
+                    Expr::Variable { name, .. } => self.lookup(name.lexeme()),
+                    _ => self
+                        .class
+                        .last()
+                        .map_or(Type::Any, |class| Type::Instance(class.clone())),
+                };
+                Some((path, self.property(&object_type, name.lexeme())))
+            }
+            _ => None,
+        }
+    }
+
+    fn narrow(&mut self, facts: Facts) {
+        for (key, narrowed) in facts {
+            if key.contains('.') {
+                let declared: Option<Type> = self.local(&key).and_then(|b| b.declared.clone());
+                self.declare(&key, narrowed, declared);
+            } else if self.local(&key).is_some()
+                && let Some(binding) = self.binding_mut(&key)
+            {
+                binding.current = narrowed;
+            }
+        }
+    }
+
+    // Joins the state after one branch with the state after the other, which
+    // is the current one. A branch that always leaves adds nothing.
+    fn merge(&mut self, other: Vec<Scope>, other_exits: bool, exits: bool) {
+        if exits && !other_exits {
+            self.scopes = other;
+            return;
+        }
+        if other_exits {
+            return;
+        }
+        for (scope, other) in self.scopes.iter_mut().zip(other) {
+            scope
+                .bindings
+                .retain(|key, binding| match other.bindings.get(key) {
+                    Some(other) => {
+                        binding.current = binding.current.clone().union(other.current.clone());
+                        true
+                    }
+                    None => !key.contains('.'),
+                });
+        }
+    }
+
+    // Back to what variables were declared as, for those a loop or try may
+    // or may not have assigned by now
+    fn forget(&mut self, assigned: &Assigned) {
+        for name in assigned.names.iter() {
+            if self.local(name).is_some()
+                && let Some(binding) = self.binding_mut(name)
+            {
+                binding.current = binding.declared.clone().unwrap_or(Type::Any);
+            }
+        }
+        self.forget_fields(|field| assigned.fields.contains(field));
+        self.forget_paths(|path| {
+            assigned.names.iter().any(|name| {
+                path.strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+            })
+        });
+    }
+
+    fn forget_fields(&mut self, field: impl Fn(&str) -> bool) {
+        self.forget_paths(|path| path.split_once('.').is_some_and(|(_, name)| field(name)));
+    }
+
+    fn forget_paths(&mut self, path: impl Fn(&str) -> bool) {
+        for scope in self.scopes.iter_mut() {
+            scope
+                .bindings
+                .retain(|key, _| !key.contains('.') || !path(key));
+        }
+    }
+}
+
+// Narrowing something known to be nil to not nil happens in code that never
+// runs, where nothing is worth reporting
+fn not_nil(known: &Type) -> Type {
+    match known {
+        Type::Nil => Type::Any,
+        other => other.without_nil(),
+    }
+}
+
+// `x.field` or `this.field`, the fields whose narrowing is tracked
+fn path(object: &Expr, name: &Token) -> Option<String> {
+    match object {
+        Expr::Variable { name: variable, .. } => {
+            Some(format!("{}.{}", variable.lexeme(), name.lexeme()))
+        }
+        Expr::This { .. } => Some(format!("this.{}", name.lexeme())),
+        _ => None,
+    }
+}
+
+// Whether a statement never finishes normally
+fn exits(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Return { .. } | Stmt::Throw { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {
+            true
+        }
+        Stmt::Block { statements } => statements.iter().any(exits),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => exits(then_branch) && exits(else_branch),
+        Stmt::While {
+            condition:
+                Expr::Literal {
+                    value: Literal::Bool(true),
+                    ..
+                },
+            body,
+            ..
+        }
+        | Stmt::For {
+            condition: None,
+            body,
+            ..
+        } => !breaks(body),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            finally
+                .as_ref()
+                .is_some_and(|finally| finally.iter().any(exits))
+                || (body.iter().any(exits)
+                    && catch
+                        .as_ref()
+                        .is_none_or(|catch| catch.body.iter().any(exits)))
+        }
+        _ => false,
+    }
+}
+
+// Whether a loop body can break out of that loop
+fn breaks(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Break { .. } => true,
+        Stmt::Block { statements } => statements.iter().any(breaks),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => breaks(then_branch) || else_branch.as_deref().is_some_and(breaks),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            body.iter().any(breaks)
+                || catch
+                    .as_ref()
+                    .is_some_and(|catch| catch.body.iter().any(breaks))
+                || finally
+                    .as_ref()
+                    .is_some_and(|finally| finally.iter().any(breaks))
+        }
+        _ => false,
+    }
+}
+
+// Closing brackets aren't kept in the tree, so spans stop at the last token
+// inside them
+fn span(expr: &Expr) -> Span {
+    expr.first_token().span().to(last_token(expr).span())
+}
+
+fn last_token(expr: &Expr) -> &Token {
+    match expr {
+        Expr::Literal { token, .. } => token,
+        Expr::Grouping { expression } => last_token(expression),
+        Expr::Unary { right, .. } | Expr::Binary { right, .. } | Expr::Logical { right, .. } => {
+            last_token(right)
+        }
+        Expr::Variable { name, .. } | Expr::Get { name, .. } => name,
+        Expr::Assign { value, .. } | Expr::Set { value, .. } | Expr::IndexSet { value, .. } => {
+            last_token(value)
+        }
+        Expr::Call { paren, .. } => paren,
+        Expr::This { keyword, .. } | Expr::Lambda { keyword, .. } => keyword,
+        Expr::Super { method, .. } => method,
+        Expr::List { bracket, elements } => elements.last().map_or(bracket, last_token),
+        Expr::Map { brace, entries } => {
+            entries.last().map_or(brace, |(_, value)| last_token(value))
+        }
+        Expr::Index { index, .. } => last_token(index),
+        Expr::Slice {
+            bracket,
+            start,
+            end,
+            ..
+        } => end
+            .as_deref()
+            .or(start.as_deref())
+            .map_or(bracket, last_token),
+        Expr::Range { end, .. } => last_token(end),
+    }
+}
+
+// Names assigned and fields set anywhere in some code
+#[derive(Default)]
+struct Assigned {
+    names: std::collections::HashSet<String>,
+    fields: std::collections::HashSet<String>,
+}
+
+impl Visitor for Assigned {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { name, .. } => {
+                self.names.insert(name.lexeme().to_string());
+            }
+            Expr::Set { name, .. } => {
+                self.fields.insert(name.lexeme().to_string());
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+fn assigned(statements: &[&Stmt], exprs: &[&Expr]) -> Assigned {
+    let mut assigned: Assigned = Assigned::default();
+    for statement in statements {
+        assigned.visit_stmt(statement);
+    }
+    for expr in exprs {
+        assigned.visit_expr(expr);
+    }
+    assigned
+}
+
+// Every class declared in some statements, including those nested in
+// blocks and function bodies
+fn classes<'a>(statements: &'a [Stmt], found: &mut Vec<&'a Stmt>) {
+    for statement in statements {
+        match statement {
+            Stmt::Class { methods, .. } => {
+                found.push(statement);
+                for method in methods {
+                    classes(&method.body, found);
+                }
+            }
+            Stmt::Block { statements }
+            | Stmt::Test {
+                body: statements, ..
+            } => classes(statements, found),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                classes(std::slice::from_ref(&**then_branch), found);
+                if let Some(else_branch) = else_branch {
+                    classes(std::slice::from_ref(&**else_branch), found);
+                }
+            }
+            Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::ForIn { body, .. } => {
+                classes(std::slice::from_ref(&**body), found)
+            }
+            Stmt::Function { function } => classes(&function.body, found),
+            Stmt::Export { declaration, .. } => {
+                classes(std::slice::from_ref(&**declaration), found)
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                classes(body, found);
+                if let Some(catch) = catch {
+                    classes(&catch.body, found);
+                }
+                if let Some(finally) = finally {
+                    classes(finally, found);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::lex::Span;
+
+// One byte per opcode, followed by its operands. Constant and name operands
+// are u16 indexes into the constant pool, jumps are u16 byte offsets, slots
+// and counts that are bounded by the language are single bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    // [constant: u16]
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    // [slot: u8]
+    GetLocal,
+    SetLocal,
+    // [name: u16]
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    // [index: u8]
+    GetUpvalue,
+    SetUpvalue,
+    // [name: u16]
+    GetProperty,
+    SetProperty,
+    // [name: u16], pops the superclass and the instance to bind
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    // [offset: u16] forwards
+    Jump,
+    // [offset: u16] forwards, leaves the condition on the stack
+    JumpIfFalse,
+    // [offset: u16] backwards
+    Loop,
+    // [argument count: u8]
+    Call,
+    // [function: u16] then [is_local: u8, index: u8] per upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    // [name: u16]
+    Class,
+    // Copies the superclass methods into the subclass
+    Inherit,
+    // [name: u16]
+    Method,
+    // [element count: u16]
+    BuildList,
+    // Pushes an empty map, entries are added one at a time by MapInsert
+    BuildMap,
+    // Pops a key and value into the map below them
+    MapInsert,
+    Index,
+    IndexSet,
+    // [bounds: u8] bit 0 set when there is a start, bit 1 for an end
+    Slice,
+    // [inclusive: u8]
+    Range,
+    // Replaces the value on top with an iterator over it
+    IterInit,
+    // [iterator slot: u8, exit offset: u16] pushes the next item, or jumps
+    // forward when the iterator is done
+    IterNext,
+    // [path: u16] pushes the module the path, as written, names
+    Import,
+    // [handler offset: u16] starts a try block, anything thrown before the
+    // matching EndTry unwinds to the handler with the thrown value pushed
+    Try,
+    EndTry,
+    // Pops a value and throws it
+    Throw,
+}
+
+// Must list every opcode in declaration order, decoding relies on it
+const OPCODES: &[OpCode] = &[
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::GetGlobal,
+    OpCode::DefineGlobal,
+    OpCode::SetGlobal,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::GetSuper,
+    OpCode::Equal,
+    OpCode::NotEqual,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Not,
+    OpCode::Negate,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Loop,
+    OpCode::Call,
+    OpCode::Closure,
+    OpCode::CloseUpvalue,
+    OpCode::Return,
+    OpCode::Class,
+    OpCode::Inherit,
+    OpCode::Method,
+    OpCode::BuildList,
+    OpCode::BuildMap,
+    OpCode::MapInsert,
+    OpCode::Index,
+    OpCode::IndexSet,
+    OpCode::Slice,
+    OpCode::Range,
+    OpCode::IterInit,
+    OpCode::IterNext,
+    OpCode::Import,
+    OpCode::Try,
+    OpCode::EndTry,
+    OpCode::Throw,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+// Constants are plain data with no ties to a running VM, so a chunk can be
+// shared, printed or written to disk as is
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    Text(std::rc::Rc<str>),
+    Function(std::rc::Rc<FunctionProto>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    // Source line and span of the instruction each byte belongs to
+    pub lines: Vec<usize>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Constant>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize, span: Span) {
+        self.code.push(byte);
+        self.lines.push(line);
+        self.spans.push(span);
+    }
+
+    // Numbers and strings already in the pool are reused
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let existing: Option<usize> = self.constants.iter().position(|c| match (c, &constant) {
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Text(a), Constant::Text(b)) => a == b,
+            _ => false,
+        });
+        if let Some(index) = existing {
+            return index;
+        }
+
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // Number of operand bytes following the opcode at `offset`. A closure has
+    // two more for each upvalue of the function it refers to.
+    pub fn operand_width(&self, op: OpCode, offset: usize) -> usize {
+        match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::Import
+            | OpCode::BuildList
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::Try => 2,
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call
+            | OpCode::Slice
+            | OpCode::Range => 1,
+            OpCode::IterNext => 3,
+            OpCode::Closure => {
+                let upvalues: usize = match self.code.get(offset + 1..offset + 3) {
+                    Some(&[high, low]) => {
+                        match self.constants.get(u16::from_be_bytes([high, low]) as usize) {
+                            Some(Constant::Function(function)) => function.upvalue_count,
+                            _ => 0,
+                        }
+                    }
+                    _ => 0,
+                };
+                2 + upvalues * 2
+            }
+            _ => 0,
+        }
+    }
+}
+
+// A compiled function. The top level script is one too, named "script".
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
@@ -0,0 +1,4 @@
+// Pieces of the `miette` binary that aren't part of the library: argument
+// parsing and printing problems for a person or a tool
+pub mod args;
+pub mod report;
@@ -0,0 +1,345 @@
+// Command line parsing. Flags may come before or after the file, and a flag
+// that takes a value accepts both `--flag value` and `--flag=value`.
+
+pub const USAGE: &str = "usage: miette <command> [options] <file|->
+       miette build [options] [dir]
+       miette test [options] [dir]
+       miette new <dir>
+       miette repl
+       miette lsp";
+
+pub const HELP: &str = "\
+usage: miette <command> [options] <file|->
+       miette build [options] [dir]
+       miette test [options] [dir]
+       miette new <dir>
+       miette repl
+       miette lsp
+
+Reads the program from <file>, or from stdin when it is `-`. Imports are
+found next to the importing file, then among the dependencies of the project
+holding it, then in each directory of $MIETTE_PATH. check, run and build also
+take a project directory, one with a Miette.toml, and start at its entry point.
+
+commands:
+  lex       print the tokens
+  parse     print the syntax tree
+  check     report errors without running the program
+  run       run a program, or a compiled .mtc file
+  build     compile to bytecode, next to the source unless -o is given, or
+            every module of the project in [dir] into its target directory,
+            skipping those that haven't changed
+  disasm    print the bytecode of a program or .mtc file
+  fmt       format a program in place, or to stdout when reading stdin
+  test      run the programs under [dir], the current one by default,
+            check them against their `// expect: ...` comments and run
+            their `test \"name\" { ... }` blocks
+  repl      run code as it is typed, see :help inside it
+  lsp       serve the Language Server Protocol over stdin and stdout
+  new       make a project in <dir> with a program that says hello
+
+options:
+  -o, --output <path>            write the output to <path> instead of stdout
+      --error-format <format>    human (default) or json
+      --color <when>             auto (default), always or never
+      --max-errors <n>           report at most <n> errors
+      --json                     print tokens and syntax trees as JSON
+      --vm                       run on the bytecode VM
+      --trace                    print each VM instruction to stderr, implies --vm
+      --strict                   with check, run and build, require type
+                                 annotations on exported functions
+      --check                    with fmt, print a diff instead of formatting and
+                                 fail if the program isn't formatted
+      --filter <text>            with test, only run tests with <text> in their path
+                                 or name
+  -j, --jobs <n>                 with test, run <n> tests at once, by default
+                                 one per CPU
+  -h, --help                     print this help
+      --version                  print the version
+
+exit codes:
+  0   success
+  1   fmt --check found a program that isn't formatted, a test failed, or
+      an LSP client exited without shutting the server down
+  64  the command line is wrong
+  65  the program has compile errors or isn't valid UTF-8
+  66  the input doesn't exist or can't be opened
+  70  the program failed at runtime
+  74  reading the input or writing the output failed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Lex,
+    Parse,
+    Check,
+    Run,
+    Build,
+    Disasm,
+    Fmt,
+    Test,
+    Repl,
+    Lsp,
+    New,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "lex" => Some(Command::Lex),
+            "parse" => Some(Command::Parse),
+            "check" => Some(Command::Check),
+            "run" => Some(Command::Run),
+            "build" => Some(Command::Build),
+            "disasm" => Some(Command::Disasm),
+            "fmt" => Some(Command::Fmt),
+            "test" => Some(Command::Test),
+            "repl" => Some(Command::Repl),
+            "lsp" => Some(Command::Lsp),
+            "new" => Some(Command::New),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Lex => "lex",
+            Command::Parse => "parse",
+            Command::Check => "check",
+            Command::Run => "run",
+            Command::Build => "build",
+            Command::Disasm => "disasm",
+            Command::Fmt => "fmt",
+            Command::Test => "test",
+            Command::Repl => "repl",
+            Command::Lsp => "lsp",
+            Command::New => "new",
+        }
+    }
+
+    // Whether the command produces something `-o` can redirect
+    fn has_output(&self) -> bool {
+        !matches!(
+            self,
+            Command::Check
+                | Command::Run
+                | Command::Test
+                | Command::Repl
+                | Command::Lsp
+                | Command::New
+        )
+    }
+
+    // The REPL reads its entries from stdin as they come, and the language
+    // server its messages
+    fn needs_input(&self) -> bool {
+        !matches!(self, Command::Repl | Command::Lsp)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Path(std::path::PathBuf),
+    Stdin,
+}
+
+impl Input {
+    // For messages about the input
+    pub fn name(&self) -> String {
+        match self {
+            Input::Path(path) => path.display().to_string(),
+            Input::Stdin => "<stdin>".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub input: Input,
+    pub output: Option<std::path::PathBuf>,
+    pub json: bool,
+    pub vm: bool,
+    pub trace: bool,
+    pub color: Color,
+    pub error_format: ErrorFormat,
+    pub max_errors: Option<usize>,
+    pub check: bool,
+    pub strict: bool,
+    pub filter: Option<String>,
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    Run(Options),
+    Help,
+    Version,
+}
+
+// The error is a message for the user, to be printed above the usage line
+pub fn parse(args: &[String]) -> Result<Parsed, String> {
+    let mut positional: Vec<&str> = Vec::new();
+    let mut output: Option<std::path::PathBuf> = None;
+    let mut json: bool = false;
+    let mut vm: bool = false;
+    let mut trace: bool = false;
+    let mut color: Color = Color::Auto;
+    let mut error_format: Option<ErrorFormat> = None;
+    let mut max_errors: Option<usize> = None;
+    let mut check: bool = false;
+    let mut strict: bool = false;
+    let mut filter: Option<String> = None;
+    let mut jobs: Option<usize> = None;
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        // A lone `-` is stdin, not a flag
+        if arg == "-" || !arg.starts_with('-') {
+            positional.push(arg);
+            continue;
+        }
+
+        let (flag, inline): (&str, Option<&str>) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value)),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| -> Result<String, String> {
+            match inline {
+                Some(value) => Ok(value.to_string()),
+                None => rest
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} needs a value", name)),
+            }
+        };
+
+        match flag {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "--version" => return Ok(Parsed::Version),
+            "--json" => json = true,
+            "--vm" => vm = true,
+            "--trace" => trace = true,
+            "--check" => check = true,
+            "--strict" => strict = true,
+            "-o" | "--output" => output = Some(std::path::PathBuf::from(value(flag)?)),
+            "--color" => {
+                color = match value(flag)?.as_str() {
+                    "auto" => Color::Auto,
+                    "always" => Color::Always,
+                    "never" => Color::Never,
+                    other => {
+                        return Err(format!(
+                            "--color must be auto, always or never, not '{}'",
+                            other
+                        ));
+                    }
+                }
+            }
+            "--error-format" => {
+                error_format = match value(flag)?.as_str() {
+                    "human" => Some(ErrorFormat::Human),
+                    "json" => Some(ErrorFormat::Json),
+                    other => {
+                        return Err(format!(
+                            "--error-format must be human or json, not '{}'",
+                            other
+                        ));
+                    }
+                }
+            }
+            "--max-errors" => max_errors = Some(positive(flag, &value(flag)?)?),
+            "--filter" => filter = Some(value(flag)?),
+            "-j" | "--jobs" => jobs = Some(positive(flag, &value(flag)?)?),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    let Some(name) = positional.first() else {
+        return Err("no command given".to_string());
+    };
+    let Some(command) = Command::from_name(name) else {
+        return Err(format!("unknown command '{}'", name));
+    };
+    let input: Input = match (command.needs_input(), &positional[1..]) {
+        // `test` looks for programs in a directory, the current one by default
+        (_, []) if command == Command::Test => Input::Path(std::path::PathBuf::from(".")),
+        (_, ["-"]) if command == Command::Test => {
+            return Err("`test` needs a directory, not stdin".to_string());
+        }
+        // `build` builds the project in the current directory by default
+        (_, []) if command == Command::Build => Input::Path(std::path::PathBuf::from(".")),
+        (_, [] | ["-"]) if command == Command::New => {
+            return Err("`new` needs a directory for the project".to_string());
+        }
+        (true, []) => return Err(format!("`{}` needs a file, or - for stdin", name)),
+        (true, ["-"]) | (false, []) => Input::Stdin,
+        (true, [path]) => Input::Path(std::path::PathBuf::from(path)),
+        (true, [_, extra, ..]) | (false, [extra, ..]) => {
+            return Err(format!("unexpected argument '{}'", extra));
+        }
+    };
+
+    if output.is_some() && !command.has_output() {
+        return Err(format!("`{}` has no output for -o", command.name()));
+    }
+    if check && command != Command::Fmt {
+        return Err("--check only applies to `fmt`".to_string());
+    }
+    if check && output.is_some() {
+        return Err("--check writes nothing for -o".to_string());
+    }
+    if strict && !matches!(command, Command::Check | Command::Run | Command::Build) {
+        return Err("--strict only applies to `check`, `run` and `build`".to_string());
+    }
+    if (filter.is_some() || jobs.is_some()) && command != Command::Test {
+        return Err("--filter and --jobs only apply to `test`".to_string());
+    }
+    if command == Command::Build && input == Input::Stdin && output.is_none() {
+        return Err("`build` from stdin needs -o".to_string());
+    }
+
+    Ok(Parsed::Run(Options {
+        command,
+        input,
+        output,
+        json,
+        // Only the VM can trace, so asking for a trace selects it
+        vm: vm || trace,
+        trace,
+        color,
+        // Asking for JSON output means a tool is reading, so errors follow
+        error_format: error_format.unwrap_or(if json {
+            ErrorFormat::Json
+        } else {
+            ErrorFormat::Human
+        }),
+        max_errors,
+        check,
+        strict,
+        filter,
+        jobs,
+    }))
+}
+
+fn positive(flag: &str, text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "{} must be a positive number, not '{}'",
+            flag, text
+        )),
+    }
+}
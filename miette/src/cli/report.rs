@@ -0,0 +1,132 @@
+// Prints compile errors, warnings and runtime errors to stderr, either as the
+// usual `[line N] Error...` lines or as one JSON document per report
+use super::args::{Color, ErrorFormat, Options};
+use miette::error::{Diagnostic, RuntimeError};
+use miette::lint::Warning;
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+pub struct Reporter {
+    format: ErrorFormat,
+    color: bool,
+    max_errors: Option<usize>,
+}
+
+impl Reporter {
+    pub fn new(options: &Options) -> Reporter {
+        // https://no-color.org asks for any non empty value to count
+        let color: bool = match options.color {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto => {
+                std::io::stderr().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        };
+
+        Reporter {
+            format: options.error_format,
+            color,
+            max_errors: options.max_errors,
+        }
+    }
+
+    // Prints nothing when there are neither errors nor warnings
+    pub fn problems(&self, errors: &[Diagnostic], warnings: &[Warning]) {
+        if errors.is_empty() && warnings.is_empty() {
+            return;
+        }
+
+        let shown: usize = self.max_errors.unwrap_or(usize::MAX).min(errors.len());
+        let omitted: usize = errors.len() - shown;
+        match self.format {
+            ErrorFormat::Json => {
+                eprintln!("{}", report_json(&errors[..shown], warnings, omitted))
+            }
+            ErrorFormat::Human => {
+                for warning in warnings.iter() {
+                    eprintln!(
+                        "[line {}] {}{}: {} [{}]",
+                        warning.line,
+                        self.paint("Warning", YELLOW),
+                        warning.location,
+                        warning.message,
+                        warning.lint.code()
+                    );
+                }
+                for error in errors[..shown].iter() {
+                    eprintln!(
+                        "[line {}] {}{}: {}",
+                        error.line,
+                        self.paint("Error", RED),
+                        error.location,
+                        error.message
+                    );
+                }
+                if omitted > 0 {
+                    let noun: &str = if omitted == 1 { "error" } else { "errors" };
+                    eprintln!("... and {} more {}", omitted, noun);
+                }
+            }
+        }
+    }
+
+    pub fn runtime_error(&self, error: &RuntimeError) {
+        match self.format {
+            ErrorFormat::Json => eprintln!("{}", runtime_error_json(error)),
+            ErrorFormat::Human => {
+                // The message is the first line, the trace follows it
+                let text: String = error.to_string();
+                let (message, trace) = text.split_once('\n').unwrap_or((&text, ""));
+                eprintln!("{}\n{}", self.paint(message, RED), trace);
+            }
+        }
+    }
+
+    // A failure with no place in the source, like an unreadable file
+    pub fn failure(&self, message: &str) {
+        match self.format {
+            ErrorFormat::Json => {
+                let diagnostic: Diagnostic = Diagnostic {
+                    message: message.to_string(),
+                    line: 0,
+                    span: Default::default(),
+                    location: String::new(),
+                };
+                eprintln!("{}", report_json(&[diagnostic], &[], 0));
+            }
+            ErrorFormat::Human => eprintln!("{}", message),
+        }
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn report_json(errors: &[Diagnostic], warnings: &[Warning], omitted: usize) -> String {
+    miette::json::report_to_json(errors, warnings, omitted)
+}
+
+#[cfg(feature = "json")]
+fn runtime_error_json(error: &RuntimeError) -> String {
+    miette::json::runtime_error_to_json(error)
+}
+
+#[cfg(not(feature = "json"))]
+fn report_json(_errors: &[Diagnostic], _warnings: &[Warning], _omitted: usize) -> String {
+    unreachable!("--error-format=json is rejected when the json feature is disabled")
+}
+
+#[cfg(not(feature = "json"))]
+fn runtime_error_json(_error: &RuntimeError) -> String {
+    unreachable!("--error-format=json is rejected when the json feature is disabled")
+}
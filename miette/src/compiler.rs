@@ -0,0 +1,1029 @@
+use crate::ast::{self, Catch, Expr, Function, Literal, Stmt};
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::error::Diagnostic;
+use crate::lex::{Token, TokenKind};
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Closed over by an inner function, so it must be moved off the stack
+    // when it goes out of scope
+    captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    // Whether it captures a local of the enclosing function rather than one
+    // of that function's own upvalues
+    is_local: bool,
+}
+
+struct Loop {
+    // Where `continue` jumps back to, None when it has to jump forwards to
+    // code that is not emitted yet
+    start: Option<usize>,
+    // Locals deeper than this belong to the loop and are dropped on a jump
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// A try block or a catch whose handler is active. Jumping out of one with
+// break, continue or return ends the handler and runs the finally block on
+// the way.
+#[derive(Clone)]
+struct Try {
+    // Loops open when it started, later ones are inside it
+    loops: usize,
+    finally: Option<Vec<Stmt>>,
+}
+
+// Per function state, one entry for every function being compiled
+struct FunctionState {
+    kind: FunctionKind,
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: &str) -> Self {
+        // Slot zero holds the callee, or `this` inside methods
+        let slot_zero: &str = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+
+        FunctionState {
+            kind,
+            name: name.to_string(),
+            arity: 0,
+            chunk: Chunk::new(),
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+        }
+    }
+}
+
+// Single pass from a resolved AST to bytecode. Scope errors are the
+// resolver's job, this only reports limits of the bytecode format.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    // Location of the statement being compiled, for errors with no better one
+    line: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: vec![FunctionState::new(FunctionKind::Script, "script")],
+            line: 1,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn compile(
+        mut self,
+        statements: &[Stmt],
+    ) -> Result<std::rc::Rc<FunctionProto>, Vec<Diagnostic>> {
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.emit_return(None);
+
+        let state: FunctionState = self.functions.pop().expect("script function state");
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        Ok(std::rc::Rc::new(FunctionProto {
+            name: state.name,
+            arity: 0,
+            upvalue_count: 0,
+            chunk: state.chunk,
+        }))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        if let Some(token) = stmt.first_token() {
+            self.line = token.line();
+        }
+
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.expression(expression);
+                self.emit_op(OpCode::Pop, expression.first_token());
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                self.expression(expression);
+                self.emit_op(OpCode::Print, keyword);
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit_op(OpCode::Nil, name),
+                }
+                self.define_variable(name);
+            }
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let then_jump: usize = self.emit_jump(OpCode::JumpIfFalse, keyword);
+                self.emit_op(OpCode::Pop, keyword);
+                self.statement(then_branch);
+
+                let else_jump: usize = self.emit_jump(OpCode::Jump, keyword);
+                self.patch_jump(then_jump, keyword);
+                self.emit_op(OpCode::Pop, keyword);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump, keyword);
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => {
+                let start: usize = self.current().chunk.code.len();
+                self.expression(condition);
+                let exit: usize = self.emit_jump(OpCode::JumpIfFalse, keyword);
+                self.emit_op(OpCode::Pop, keyword);
+
+                self.begin_loop(Some(start));
+                self.statement(body);
+                self.emit_loop(start, keyword);
+
+                self.patch_jump(exit, keyword);
+                self.emit_op(OpCode::Pop, keyword);
+                self.end_loop(keyword);
+            }
+            Stmt::For {
+                keyword,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+
+                let start: usize = self.current().chunk.code.len();
+                let exit: Option<usize> = condition.as_ref().map(|condition| {
+                    self.expression(condition);
+                    let exit: usize = self.emit_jump(OpCode::JumpIfFalse, keyword);
+                    self.emit_op(OpCode::Pop, keyword);
+                    exit
+                });
+
+                // `continue` has to run the increment, which comes after the body
+                self.begin_loop(None);
+                self.statement(body);
+
+                let continues: Vec<usize> = std::mem::take(
+                    &mut self
+                        .current_mut()
+                        .loops
+                        .last_mut()
+                        .expect("loop state")
+                        .continues,
+                );
+                for jump in continues {
+                    self.patch_jump(jump, keyword);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit_op(OpCode::Pop, keyword);
+                }
+                self.emit_loop(start, keyword);
+
+                if let Some(exit) = exit {
+                    self.patch_jump(exit, keyword);
+                    self.emit_op(OpCode::Pop, keyword);
+                }
+                self.end_loop(keyword);
+                self.end_scope();
+            }
+            Stmt::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.emit_op(OpCode::IterInit, keyword);
+
+                // The iterator lives in a hidden local for the whole loop
+                self.begin_scope();
+                self.add_local(" iterator", keyword);
+                let slot: u8 = (self.current().locals.len() - 1) as u8;
+
+                let start: usize = self.current().chunk.code.len();
+                self.emit_op(OpCode::IterNext, keyword);
+                self.emit_byte(slot, keyword);
+                let exit: usize = self.current().chunk.code.len();
+                self.emit_bytes(0xff, 0xff, keyword);
+
+                self.begin_loop(Some(start));
+                // A new scope per iteration gives closures a fresh variable
+                self.begin_scope();
+                self.add_local(variable.lexeme(), variable);
+                self.statement(body);
+                self.end_scope();
+                self.emit_loop(start, keyword);
+
+                self.patch_jump(exit, keyword);
+                self.end_loop(keyword);
+                self.end_scope();
+            }
+            Stmt::Function { function } => {
+                let Some(name) = &function.name else { return };
+                if self.current().scope_depth > 0 {
+                    // Declared first so the body can call itself
+                    self.add_local(name.lexeme(), name);
+                    self.function(function, FunctionKind::Function, name);
+                } else {
+                    self.function(function, FunctionKind::Function, name);
+                    self.define_variable(name);
+                }
+            }
+            Stmt::Return { keyword, value } => match value {
+                Some(value) => {
+                    self.expression(value);
+                    if !self.current().tries.is_empty() {
+                        // The value waits in a slot of its own while finally
+                        // blocks run
+                        self.add_local(" return", keyword);
+                        self.exit_tries(0, keyword);
+                        self.current_mut().locals.pop();
+                    }
+                    self.emit_op(OpCode::Return, keyword);
+                }
+                None => {
+                    self.exit_tries(0, keyword);
+                    self.emit_return(Some(keyword));
+                }
+            },
+            Stmt::Break { keyword } => {
+                let Some(depth) = self.current().loops.last().map(|l| l.scope_depth) else {
+                    return;
+                };
+                self.exit_tries(self.current().loops.len(), keyword);
+                self.discard_locals(depth, keyword);
+                let jump: usize = self.emit_jump(OpCode::Jump, keyword);
+                if let Some(current) = self.current_mut().loops.last_mut() {
+                    current.breaks.push(jump);
+                }
+            }
+            Stmt::Continue { keyword } => {
+                let Some((depth, start)) = self
+                    .current()
+                    .loops
+                    .last()
+                    .map(|l| (l.scope_depth, l.start))
+                else {
+                    return;
+                };
+                self.exit_tries(self.current().loops.len(), keyword);
+                self.discard_locals(depth, keyword);
+                match start {
+                    Some(start) => self.emit_loop(start, keyword),
+                    None => {
+                        let jump: usize = self.emit_jump(OpCode::Jump, keyword);
+                        if let Some(current) = self.current_mut().loops.last_mut() {
+                            current.continues.push(jump);
+                        }
+                    }
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => self.class(name, superclass, methods),
+            // Only `miette test` runs these
+            Stmt::Test { .. } => {}
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+            } => {
+                let TokenKind::Text(text) = path.kind() else {
+                    unreachable!("the parser only accepts a string as an import's path")
+                };
+                let constant: u16 =
+                    self.make_constant(Constant::Text(std::rc::Rc::from(text.as_str())), path);
+                self.emit_op(OpCode::Import, keyword);
+                self.emit_u16(constant, path);
+                self.define_variable(name);
+            }
+            Stmt::Export { declaration, .. } => self.statement(declaration),
+            Stmt::Throw { keyword, value } => {
+                self.expression(value);
+                self.emit_op(OpCode::Throw, keyword);
+            }
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => self.try_statement(keyword, body, catch, finally),
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.end_scope();
+    }
+
+    // The body runs under a handler that goes to the catch, or straight to
+    // the finally code when there is no catch. A catch with a finally runs
+    // under a handler of its own that goes there. On the way out without an
+    // error the finally block is compiled in place; the copy after the
+    // handlers runs it with the thrown value in a hidden local and throws
+    // that again.
+    fn try_statement(
+        &mut self,
+        keyword: &Token,
+        body: &[Stmt],
+        catch: &Option<Catch>,
+        finally: &Option<Vec<Stmt>>,
+    ) {
+        let handler: usize = self.emit_jump(OpCode::Try, keyword);
+        self.begin_try(finally);
+        self.block(body);
+        self.current_mut().tries.pop();
+        self.emit_op(OpCode::EndTry, keyword);
+        if let Some(finally) = finally {
+            self.block(finally);
+        }
+        let mut exits: Vec<usize> = vec![self.emit_jump(OpCode::Jump, keyword)];
+        self.patch_jump(handler, keyword);
+
+        if let Some(catch) = catch {
+            // The handler left the thrown value where the local goes
+            self.begin_scope();
+            self.add_local(catch.name.lexeme(), &catch.name);
+            let rethrow: Option<usize> = finally.as_ref().map(|_| {
+                let jump: usize = self.emit_jump(OpCode::Try, keyword);
+                self.begin_try(finally);
+                jump
+            });
+            for statement in catch.body.iter() {
+                self.statement(statement);
+            }
+            if rethrow.is_some() {
+                self.current_mut().tries.pop();
+                self.emit_op(OpCode::EndTry, keyword);
+            }
+            self.end_scope();
+            if let Some(finally) = finally {
+                self.block(finally);
+            }
+            exits.push(self.emit_jump(OpCode::Jump, keyword));
+            if let Some(rethrow) = rethrow {
+                self.patch_jump(rethrow, keyword);
+            }
+        }
+
+        if let Some(finally) = finally {
+            self.begin_scope();
+            // Coming from the catch's handler, the catch variable is still
+            // below the thrown value
+            if catch.is_some() {
+                self.add_local(" caught", keyword);
+            }
+            self.add_local(" error", keyword);
+            let slot: u8 = (self.current().locals.len() - 1) as u8;
+            for statement in finally.iter() {
+                self.statement(statement);
+            }
+            self.emit_op(OpCode::GetLocal, keyword);
+            self.emit_byte(slot, keyword);
+            self.emit_op(OpCode::Throw, keyword);
+            self.end_scope();
+        }
+
+        for exit in exits {
+            self.patch_jump(exit, keyword);
+        }
+    }
+
+    fn begin_try(&mut self, finally: &Option<Vec<Stmt>>) {
+        let loops: usize = self.current().loops.len();
+        self.current_mut().tries.push(Try {
+            loops,
+            finally: finally.clone(),
+        });
+    }
+
+    // Ends the handlers of the tries a jump leaves and runs their finally
+    // blocks, innermost first. Tries started with fewer than `loops` loops
+    // open stay, so a return passes 0 to leave them all.
+    fn exit_tries(&mut self, loops: usize, token: &Token) {
+        let tries: Vec<Try> = std::mem::take(&mut self.current_mut().tries);
+        for (index, left) in tries.iter().enumerate().rev() {
+            if left.loops < loops {
+                break;
+            }
+            self.emit_op(OpCode::EndTry, token);
+            if let Some(finally) = &left.finally {
+                // A jump inside the finally block leaves only the tries
+                // around this one
+                self.current_mut().tries = tries[..index].to_vec();
+                self.block(finally);
+            }
+        }
+        self.current_mut().tries = tries;
+    }
+
+    fn class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[std::rc::Rc<Function>],
+    ) {
+        let name_constant: u16 = self.name_constant(name);
+        self.emit_op(OpCode::Class, name);
+        self.emit_u16(name_constant, name);
+        if self.current().scope_depth > 0 {
+            self.add_local(name.lexeme(), name);
+        } else {
+            self.define_variable(name);
+        }
+
+        if let Some(superclass) = superclass {
+            let token: &Token = superclass.first_token();
+            self.expression(superclass);
+
+            self.begin_scope();
+            self.add_local("super", token);
+
+            self.named_variable(name, false);
+            self.emit_op(OpCode::Inherit, token);
+        }
+
+        self.named_variable(name, false);
+        for method in methods {
+            let Some(method_name) = &method.name else {
+                continue;
+            };
+            let kind: FunctionKind = if method_name.lexeme() == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind, method_name);
+
+            let constant: u16 = self.name_constant(method_name);
+            self.emit_op(OpCode::Method, method_name);
+            self.emit_u16(constant, method_name);
+        }
+        self.emit_op(OpCode::Pop, name);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    // Compiles a function body as its own chunk and leaves a closure over it
+    // on the stack
+    fn function(&mut self, function: &Function, kind: FunctionKind, token: &Token) {
+        let name: &str = function
+            .name
+            .as_ref()
+            .map_or("lambda", |name| name.lexeme());
+
+        let mut state: FunctionState = FunctionState::new(kind, name);
+        state.arity = function.params.len();
+        self.functions.push(state);
+
+        self.begin_scope();
+        for param in function.params.iter() {
+            self.add_local(param.lexeme(), param);
+        }
+        for statement in function.body.iter() {
+            self.statement(statement);
+        }
+        self.emit_return(None);
+
+        let state: FunctionState = self.functions.pop().expect("function state");
+        let upvalues: Vec<Upvalue> = state.upvalues;
+        let proto: FunctionProto = FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            upvalue_count: upvalues.len(),
+            chunk: state.chunk,
+        };
+
+        let constant: u16 = self.make_constant(Constant::Function(std::rc::Rc::new(proto)), token);
+        self.emit_op(OpCode::Closure, token);
+        self.emit_u16(constant, token);
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index, token);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal { value, token } => match value {
+                Literal::Nil => self.emit_op(OpCode::Nil, token),
+                Literal::Bool(true) => self.emit_op(OpCode::True, token),
+                Literal::Bool(false) => self.emit_op(OpCode::False, token),
+                Literal::Number(n) => self.emit_constant(Constant::Number(*n), token),
+                Literal::Text(s) => {
+                    self.emit_constant(Constant::Text(std::rc::Rc::from(s.as_str())), token)
+                }
+            },
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                match operator.kind() {
+                    TokenKind::Bang => self.emit_op(OpCode::Not, operator),
+                    _ => self.emit_op(OpCode::Negate, operator),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                let op: OpCode = match operator.kind() {
+                    TokenKind::EqualEqual => OpCode::Equal,
+                    TokenKind::BangEqual => OpCode::NotEqual,
+                    TokenKind::Greater => OpCode::Greater,
+                    TokenKind::GreaterEqual => OpCode::GreaterEqual,
+                    TokenKind::Less => OpCode::Less,
+                    TokenKind::LessEqual => OpCode::LessEqual,
+                    TokenKind::Plus => OpCode::Add,
+                    TokenKind::Minus => OpCode::Subtract,
+                    TokenKind::Star => OpCode::Multiply,
+                    _ => OpCode::Divide,
+                };
+                self.emit_op(op, operator);
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                if let TokenKind::Or = operator.kind() {
+                    let else_jump: usize = self.emit_jump(OpCode::JumpIfFalse, operator);
+                    let end_jump: usize = self.emit_jump(OpCode::Jump, operator);
+                    self.patch_jump(else_jump, operator);
+                    self.emit_op(OpCode::Pop, operator);
+                    self.expression(right);
+                    self.patch_jump(end_jump, operator);
+                } else {
+                    let end_jump: usize = self.emit_jump(OpCode::JumpIfFalse, operator);
+                    self.emit_op(OpCode::Pop, operator);
+                    self.expression(right);
+                    self.patch_jump(end_jump, operator);
+                }
+            }
+            Expr::Variable { name, .. } => self.named_variable(name, false),
+            Expr::Assign { name, value, .. } => {
+                self.expression(value);
+                self.named_variable(name, true);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                let site: Token = ast::call_site(callee, paren);
+                self.emit_op(OpCode::Call, &site);
+                self.emit_byte(arguments.len() as u8, &site);
+            }
+            Expr::Get { object, name } => {
+                self.expression(object);
+                let constant: u16 = self.name_constant(name);
+                self.emit_op(OpCode::GetProperty, name);
+                self.emit_u16(constant, name);
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                let constant: u16 = self.name_constant(name);
+                self.emit_op(OpCode::SetProperty, name);
+                self.emit_u16(constant, name);
+            }
+            Expr::This { keyword, .. } => self.named_variable(keyword, false),
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                let this: Token = synthetic(keyword, "this");
+                let sup: Token = synthetic(keyword, "super");
+                self.named_variable(&this, false);
+                self.named_variable(&sup, false);
+
+                let constant: u16 = self.name_constant(method);
+                self.emit_op(OpCode::GetSuper, method);
+                self.emit_u16(constant, method);
+            }
+            Expr::List { bracket, elements } => {
+                if elements.len() > u16::MAX as usize {
+                    self.error(bracket, "Too many elements in a list literal.");
+                    return;
+                }
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit_op(OpCode::BuildList, bracket);
+                self.emit_u16(elements.len() as u16, bracket);
+            }
+            Expr::Map { brace, entries } => {
+                self.emit_op(OpCode::BuildMap, brace);
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                    self.emit_op(OpCode::MapInsert, brace);
+                }
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit_op(OpCode::Index, bracket);
+            }
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit_op(OpCode::IndexSet, bracket);
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                self.expression(object);
+                let mut bounds: u8 = 0;
+                if let Some(start) = start {
+                    self.expression(start);
+                    bounds |= 1;
+                }
+                if let Some(end) = end {
+                    self.expression(end);
+                    bounds |= 2;
+                }
+                self.emit_op(OpCode::Slice, bracket);
+                self.emit_byte(bounds, bracket);
+            }
+            Expr::Range {
+                start,
+                operator,
+                end,
+                inclusive,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                self.emit_op(OpCode::Range, operator);
+                self.emit_byte(*inclusive as u8, operator);
+            }
+            Expr::Lambda { keyword, function } => {
+                self.function(function, FunctionKind::Function, keyword)
+            }
+        }
+    }
+
+    // Loads or stores a variable, whichever of local, upvalue or global it is
+    fn named_variable(&mut self, name: &Token, assign: bool) {
+        let top: usize = self.functions.len() - 1;
+        let (get, set, operand): (OpCode, OpCode, u16) =
+            if let Some(slot) = self.resolve_local(top, name.lexeme()) {
+                (OpCode::GetLocal, OpCode::SetLocal, slot as u16)
+            } else if let Some(index) = self.resolve_upvalue(top, name) {
+                (OpCode::GetUpvalue, OpCode::SetUpvalue, index as u16)
+            } else {
+                let constant: u16 = self.name_constant(name);
+                (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+            };
+
+        self.emit_op(if assign { set } else { get }, name);
+        match get {
+            OpCode::GetGlobal => self.emit_u16(operand, name),
+            _ => self.emit_byte(operand as u8, name),
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<usize> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Option<usize> {
+        if function == 0 {
+            return None;
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, name.lexeme()) {
+            self.functions[function - 1].locals[slot].captured = true;
+            return self.add_upvalue(function, slot as u8, true, name);
+        }
+
+        let index: usize = self.resolve_upvalue(function - 1, name)?;
+        self.add_upvalue(function, index as u8, false, name)
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+        name: &Token,
+    ) -> Option<usize> {
+        let upvalue: Upvalue = Upvalue { index, is_local };
+        if let Some(existing) = self.functions[function]
+            .upvalues
+            .iter()
+            .position(|u| *u == upvalue)
+        {
+            return Some(existing);
+        }
+
+        if self.functions[function].upvalues.len() >= MAX_UPVALUES {
+            self.error(name, "Too many closure variables in function.");
+            return None;
+        }
+        self.functions[function].upvalues.push(upvalue);
+        Some(self.functions[function].upvalues.len() - 1)
+    }
+
+    fn define_variable(&mut self, name: &Token) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name.lexeme(), name);
+            return;
+        }
+
+        let constant: u16 = self.name_constant(name);
+        self.emit_op(OpCode::DefineGlobal, name);
+        self.emit_u16(constant, name);
+    }
+
+    // The value for the local is already on top of the stack
+    fn add_local(&mut self, name: &str, token: &Token) {
+        if self.current().locals.len() >= MAX_LOCALS {
+            self.error(token, "Too many local variables in function.");
+            return;
+        }
+
+        let depth: usize = self.current().scope_depth;
+        self.current_mut().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+        let depth: usize = self.current().scope_depth;
+        let line: usize = self.line;
+
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op: OpCode = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.current_mut()
+                .chunk
+                .write(op as u8, line, Default::default());
+            self.current_mut().locals.pop();
+        }
+    }
+
+    // Drops the locals a break or continue jumps out of, without forgetting
+    // them since the code after the jump is still in their scope
+    fn discard_locals(&mut self, depth: usize, token: &Token) {
+        let ops: Vec<OpCode> = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit_op(op, token);
+        }
+    }
+
+    fn begin_loop(&mut self, start: Option<usize>) {
+        let scope_depth: usize = self.current().scope_depth;
+        self.current_mut().loops.push(Loop {
+            start,
+            scope_depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    fn end_loop(&mut self, token: &Token) {
+        let Some(finished) = self.current_mut().loops.pop() else {
+            return;
+        };
+        for jump in finished.breaks {
+            self.patch_jump(jump, token);
+        }
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().expect("function state")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("function state")
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) {
+        self.current_mut()
+            .chunk
+            .write(byte, token.line(), token.span());
+    }
+
+    fn emit_bytes(&mut self, first: u8, second: u8, token: &Token) {
+        self.emit_byte(first, token);
+        self.emit_byte(second, token);
+    }
+
+    fn emit_op(&mut self, op: OpCode, token: &Token) {
+        self.emit_byte(op as u8, token);
+    }
+
+    fn emit_u16(&mut self, value: u16, token: &Token) {
+        let [high, low] = value.to_be_bytes();
+        self.emit_bytes(high, low, token);
+    }
+
+    // Functions return nil when they fall off the end, initializers `this`
+    fn emit_return(&mut self, token: Option<&Token>) {
+        let token: Token = match token {
+            Some(token) => token.clone(),
+            None => synthetic_at(self.line, ""),
+        };
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal, &token);
+            self.emit_byte(0, &token);
+        } else {
+            self.emit_op(OpCode::Nil, &token);
+        }
+        self.emit_op(OpCode::Return, &token);
+    }
+
+    fn make_constant(&mut self, constant: Constant, token: &Token) -> u16 {
+        let index: usize = self.current_mut().chunk.add_constant(constant);
+        if index > u16::MAX as usize {
+            self.error(token, "Too many constants in one chunk.");
+            return 0;
+        }
+        index as u16
+    }
+
+    fn name_constant(&mut self, name: &Token) -> u16 {
+        self.make_constant(Constant::Text(std::rc::Rc::from(name.lexeme())), name)
+    }
+
+    fn emit_constant(&mut self, constant: Constant, token: &Token) {
+        let index: u16 = self.make_constant(constant, token);
+        self.emit_op(OpCode::Constant, token);
+        self.emit_u16(index, token);
+    }
+
+    // Emits a jump with a placeholder offset, returning where to patch it
+    fn emit_jump(&mut self, op: OpCode, token: &Token) -> usize {
+        self.emit_op(op, token);
+        self.emit_bytes(0xff, 0xff, token);
+        self.current().chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize, token: &Token) {
+        let jump: usize = self.current().chunk.code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error(token, "Too much code to jump over.");
+            return;
+        }
+
+        let [high, low] = (jump as u16).to_be_bytes();
+        let code: &mut Vec<u8> = &mut self.current_mut().chunk.code;
+        code[offset] = high;
+        code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, start: usize, token: &Token) {
+        self.emit_op(OpCode::Loop, token);
+        let offset: usize = self.current().chunk.code.len() - start + 2;
+        if offset > u16::MAX as usize {
+            self.error(token, "Loop body too large.");
+        }
+        self.emit_u16(offset as u16, token);
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(Diagnostic::at(token, message));
+    }
+}
+
+// An identifier token for names the language refers to implicitly
+fn synthetic(at: &Token, name: &str) -> Token {
+    Token::new(
+        at.id(),
+        TokenKind::Identifier(name.to_string()),
+        name.to_string(),
+        at.line(),
+        at.span(),
+    )
+}
+
+fn synthetic_at(line: usize, name: &str) -> Token {
+    Token::new(
+        0,
+        TokenKind::Identifier(name.to_string()),
+        name.to_string(),
+        line,
+        Default::default(),
+    )
+}
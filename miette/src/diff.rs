@@ -0,0 +1,151 @@
+// Line diffs in the unified format, for showing how output differs from what
+// was expected
+
+// Unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+// Beyond this many cells the longest common subsequence table costs more than
+// a readable diff is worth, and the differing middle is shown as replaced
+const MAX_TABLE: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    // Line indexes in the old and new text
+    Same(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// Empty when the texts are equal
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    // Lines keep their endings, so a missing final newline is a difference
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits: Vec<Edit> = edits(&old_lines, &new_lines);
+    if edits.iter().all(|edit| matches!(edit, Edit::Same(..))) {
+        return String::new();
+    }
+
+    let mut out: String = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|&index| !matches!(edits[index], Edit::Same(..)))
+        .collect();
+
+    // Changes close enough for their context to touch share a hunk
+    let mut first: usize = 0;
+    while first < changes.len() {
+        let mut last: usize = first;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+        let start: usize = changes[first].saturating_sub(CONTEXT);
+        let end: usize = (changes[last] + CONTEXT + 1).min(edits.len());
+        let before: (usize, usize) =
+            edits[..start]
+                .iter()
+                .fold((0, 0), |(o, n), edit| match edit {
+                    Edit::Same(..) => (o + 1, n + 1),
+                    Edit::Delete(_) => (o + 1, n),
+                    Edit::Insert(_) => (o, n + 1),
+                });
+        hunk(&edits[start..end], before, &old_lines, &new_lines, &mut out);
+        first = last + 1;
+    }
+    out
+}
+
+// `start` is how many lines of each text come before the hunk
+fn hunk(edits: &[Edit], start: (usize, usize), old: &[&str], new: &[&str], out: &mut String) {
+    let (mut old_count, mut new_count): (usize, usize) = (0, 0);
+    let mut body: String = String::new();
+    for edit in edits.iter() {
+        match *edit {
+            Edit::Same(i, _) => {
+                old_count += 1;
+                new_count += 1;
+                push_line(&mut body, ' ', old[i]);
+            }
+            Edit::Delete(i) => {
+                old_count += 1;
+                push_line(&mut body, '-', old[i]);
+            }
+            Edit::Insert(j) => {
+                new_count += 1;
+                push_line(&mut body, '+', new[j]);
+            }
+        }
+    }
+
+    // An empty side is numbered by the line before it, as diff does
+    let old_from: usize = start.0 + usize::from(old_count > 0);
+    let new_from: usize = start.1 + usize::from(new_count > 0);
+    out.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n{}",
+        old_from, old_count, new_from, new_count, body
+    ));
+}
+
+fn push_line(out: &mut String, marker: char, line: &str) {
+    out.push(marker);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+// A shortest edit script through the longest common subsequence, deletions
+// before insertions where both are possible
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix: usize = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix: usize = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle: &[&str] = &old[prefix..old.len() - suffix];
+    let new_middle: &[&str] = &new[prefix..new.len() - suffix];
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    let (n, m): (usize, usize) = (old_middle.len(), new_middle.len());
+    if (n + 1) * (m + 1) > MAX_TABLE {
+        edits.extend((0..n).map(|i| Edit::Delete(prefix + i)));
+        edits.extend((0..m).map(|j| Edit::Insert(prefix + j)));
+    } else {
+        // common[i][j] is the LCS length of old_middle[i..] and new_middle[j..]
+        let mut common: Vec<Vec<usize>> = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                common[i][j] = if old_middle[i] == new_middle[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j): (usize, usize) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_middle[i] == new_middle[j] {
+                edits.push(Edit::Same(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || common[i + 1][j] >= common[i][j + 1]) {
+                edits.push(Edit::Delete(prefix + i));
+                i += 1;
+            } else {
+                edits.push(Edit::Insert(prefix + j));
+                j += 1;
+            }
+        }
+    }
+
+    let old_tail: usize = old.len() - suffix;
+    let new_tail: usize = new.len() - suffix;
+    edits.extend((0..suffix).map(|k| Edit::Same(old_tail + k, new_tail + k)));
+    edits
+}
@@ -0,0 +1,146 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+
+// Prints a function followed by every function nested in it, depth first.
+// Each instruction shows its offset, source line ("|" when unchanged from the
+// previous instruction), opcode and decoded operands.
+pub fn disassemble(function: &FunctionProto) -> String {
+    let mut out: String = String::new();
+    write_function(function, &mut out);
+    out
+}
+
+fn write_function(function: &FunctionProto, out: &mut String) {
+    out.push_str(&format!("== {} ==\n", function.name));
+
+    let chunk: &Chunk = &function.chunk;
+    let mut offset: usize = 0;
+    while offset < chunk.code.len() {
+        let (text, next) = disassemble_instruction(chunk, offset);
+        out.push_str(&text);
+        out.push('\n');
+        offset = next;
+    }
+
+    for constant in chunk.constants.iter() {
+        if let Constant::Function(nested) = constant {
+            out.push('\n');
+            write_function(nested, out);
+        }
+    }
+}
+
+// Returns the instruction at `offset` as one or more lines, without the final
+// newline, along with the offset of the next instruction
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let line: String = if offset > 0 && chunk.lines.get(offset) == chunk.lines.get(offset - 1) {
+        "   |".to_string()
+    } else {
+        format!("{:4}", chunk.lines.get(offset).copied().unwrap_or(0))
+    };
+    let prefix: String = format!("{:04} {} ", offset, line);
+
+    let byte: u8 = chunk.code[offset];
+    let Some(op) = OpCode::from_byte(byte) else {
+        return (format!("{}Unknown opcode {}", prefix, byte), offset + 1);
+    };
+    let name: String = format!("{:?}", op);
+
+    // Bail out on instructions cut short instead of reading past the end
+    let width: usize = chunk.operand_width(op, offset);
+    if offset + width >= chunk.code.len() {
+        return (format!("{}{} <truncated>", prefix, name), chunk.code.len());
+    }
+
+    let text: String = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::Import => {
+            let index: u16 = chunk.read_u16(offset + 1);
+            format!(
+                "{}{:<16} {:4} {}",
+                prefix,
+                name,
+                index,
+                constant_text(chunk, index as usize)
+            )
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => format!("{}{:<16} {:4}", prefix, name, chunk.code[offset + 1]),
+        OpCode::BuildList => format!("{}{:<16} {:4}", prefix, name, chunk.read_u16(offset + 1)),
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Try => {
+            let target: usize = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            format!("{}{:<16} -> {:04}", prefix, name, target)
+        }
+        OpCode::Loop => {
+            let target: usize = (offset + 3).saturating_sub(chunk.read_u16(offset + 1) as usize);
+            format!("{}{:<16} -> {:04}", prefix, name, target)
+        }
+        OpCode::IterNext => {
+            let slot: u8 = chunk.code[offset + 1];
+            let target: usize = offset + 4 + chunk.read_u16(offset + 2) as usize;
+            format!("{}{:<16} {:4} exit {:04}", prefix, name, slot, target)
+        }
+        OpCode::Slice => {
+            let bounds: u8 = chunk.code[offset + 1];
+            let start: &str = if bounds & 1 != 0 { "start" } else { "" };
+            let end: &str = if bounds & 2 != 0 { "end" } else { "" };
+            format!("{}{:<16} [{}:{}]", prefix, name, start, end)
+        }
+        OpCode::Range => {
+            let operator: &str = if chunk.code[offset + 1] == 1 {
+                "..="
+            } else {
+                ".."
+            };
+            format!("{}{:<16} {}", prefix, name, operator)
+        }
+        OpCode::Closure => {
+            let index: u16 = chunk.read_u16(offset + 1);
+            let mut text: String = format!(
+                "{}{:<16} {:4} {}",
+                prefix,
+                name,
+                index,
+                constant_text(chunk, index as usize)
+            );
+            let mut at: usize = offset + 3;
+            while at + 1 < offset + 1 + width {
+                let kind: &str = if chunk.code[at] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                text.push_str(&format!(
+                    "\n{:04}    |   {:<14} {:4}",
+                    at,
+                    kind,
+                    chunk.code[at + 1]
+                ));
+                at += 2;
+            }
+            text
+        }
+        _ => format!("{}{}", prefix, name),
+    };
+
+    (text, offset + 1 + width)
+}
+
+fn constant_text(chunk: &Chunk, index: usize) -> String {
+    match chunk.constants.get(index) {
+        Some(Constant::Number(n)) => format!("'{}'", n),
+        Some(Constant::Text(s)) => format!("{:?}", s),
+        Some(Constant::Function(function)) => format!("<fn {}>", function.name),
+        None => "<missing constant>".to_string(),
+    }
+}
@@ -1,3 +1,4 @@
+use crate::lex::{Span, Token, TokenKind};
 use std::error::Error;
 use std::fmt;
 
@@ -23,3 +24,143 @@ impl Error for MietteError {
         &self.details
     }
 }
+
+// A compile time problem tied to a location in the source
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+    pub span: Span,
+    // " at 'x'" or " at end", empty when there is no token to point at
+    pub location: String,
+}
+
+impl Diagnostic {
+    pub fn at(token: &Token, message: &str) -> Diagnostic {
+        let location: String = match token.kind() {
+            TokenKind::EOF => " at end".to_string(),
+            _ => format!(" at '{}'", token.lexeme()),
+        };
+
+        Diagnostic {
+            message: message.to_string(),
+            line: token.line(),
+            span: token.span(),
+            location,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+impl Error for Diagnostic {}
+
+// One line of a runtime stack trace, innermost call first
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct TraceFrame {
+    // "script" for top level code
+    pub function: String,
+    pub line: usize,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.function == "script" {
+            write!(f, "[line {}] in script", self.line)
+        } else {
+            write!(f, "[line {}] in {}()", self.line, self.function)
+        }
+    }
+}
+
+// What went wrong at runtime, visible to programs as the `kind` of a caught
+// error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum ErrorKind {
+    // Thrown by the program itself, or made with `error()`
+    Error,
+    TypeError,
+    NameError,
+    PropertyError,
+    ArityError,
+    IndexError,
+    KeyError,
+    ZeroDivisionError,
+    StackOverflowError,
+    // A native function given arguments it can't use
+    ArgumentError,
+    AssertionError,
+    IoError,
+    ImportError,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// An error raised while running a program
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub line: usize,
+    pub span: Span,
+    pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, token: &Token, message: &str) -> RuntimeError {
+        RuntimeError {
+            kind,
+            message: message.to_string(),
+            line: token.line(),
+            span: token.span(),
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if self.trace.is_empty() {
+            return write!(f, "\n[line {}]", self.line);
+        }
+        // Deep recursion repeats a frame thousands of times, so a long run
+        // of the same one is shown once with a count
+        let mut rest: &[TraceFrame] = &self.trace;
+        while let Some(frame) = rest.first() {
+            let run: usize = rest.iter().take_while(|other| *other == frame).count();
+            if run > 3 {
+                write!(
+                    f,
+                    "\n{}\n[previous line repeated {} more times]",
+                    frame,
+                    run - 1
+                )?;
+            } else {
+                for frame in &rest[..run] {
+                    write!(f, "\n{}", frame)?;
+                }
+            }
+            rest = &rest[run..];
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
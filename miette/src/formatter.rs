@@ -0,0 +1,799 @@
+// `miette fmt`: prints a program in the one canonical layout. Four space
+// indentation, one statement per line, spaces around binary operators, and
+// call arguments, parameters, lists and maps kept on one line when they fit
+// in WIDTH columns and one per line otherwise.
+//
+// Comments are not part of the syntax tree, so they are placed by position.
+// A comment on a line of its own stays on its own line before the next
+// statement, a comment after code stays at the end of that statement's last
+// line. Inside call arguments, parameters, lists and maps the same goes for
+// the items, and a comment there spreads them one per line. Single blank
+// lines between statements are kept, runs of them shrink to one.
+mod doc;
+
+use crate::ast::{Expr, Field, Function, Stmt, TypeExpr};
+use crate::error::Diagnostic;
+use crate::lex::{self, Comment, Token, TokenKind};
+use crate::parser::Parser;
+use doc::{Doc, concat, group, indent, text};
+
+pub const WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+// Formats a whole program. Programs that don't parse are returned as their
+// errors, since there is no tree to lay out.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, comments) = lex::scan_source_with_comments(source).map_err(|e| {
+        vec![match e.downcast::<Diagnostic>() {
+            Ok(diagnostic) => *diagnostic,
+            Err(e) => Diagnostic {
+                message: e.to_string(),
+                line: 0,
+                span: Default::default(),
+                location: String::new(),
+            },
+        }]
+    })?;
+    let statements: Vec<Stmt> = Parser::new(tokens.clone()).parse()?;
+
+    let mut formatter: Formatter = Formatter::new(source, tokens.into(), comments);
+    let body: Doc = formatter.statements(&statements, source.len(), false);
+    let mut out: String = doc::render(&body, WIDTH, INDENT);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    // Source order, taken from the front as they are placed
+    comments: std::collections::VecDeque<Comment>,
+    // Offset of each `{` to the offset of its `}`
+    closers: std::collections::HashMap<usize, usize>,
+    // Everything before this offset has been printed. Statements are laid out
+    // in source order, so the first token past it starts whatever is next.
+    cursor: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, tokens: Vec<Token>, comments: Vec<Comment>) -> Self {
+        let mut closers: std::collections::HashMap<usize, usize> = std::collections::HashMap::new();
+        let mut open: Vec<usize> = Vec::new();
+        for token in tokens.iter() {
+            match token.kind() {
+                TokenKind::LeftBracket => open.push(token.span().start),
+                TokenKind::RightBracket => {
+                    if let Some(start) = open.pop() {
+                        closers.insert(start, token.span().start);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Formatter {
+            source,
+            tokens,
+            comments: comments.into(),
+            closers,
+            cursor: 0,
+        }
+    }
+
+    // The first token not printed yet that satisfies `wanted`
+    fn next_token(&self, wanted: impl Fn(&TokenKind) -> bool) -> Option<&Token> {
+        let from: usize = self
+            .tokens
+            .partition_point(|token| token.span().start < self.cursor);
+        self.tokens[from..]
+            .iter()
+            .find(|token| wanted(token.kind()))
+    }
+
+    fn token(&mut self, token: &Token) -> Doc {
+        self.cursor = self.cursor.max(token.span().end);
+        text(token.lexeme())
+    }
+
+    // Skips past the `;` that ends what was just printed
+    fn semicolon(&mut self) -> Doc {
+        if let Some(end) = self
+            .next_token(|kind| *kind == TokenKind::SemiColon)
+            .map(|token| token.span().end)
+        {
+            self.cursor = end;
+        }
+        text(";")
+    }
+
+    // Whether a blank line comes right before `offset`
+    fn blank_line_before(&self, offset: usize) -> bool {
+        let before: &str = &self.source[..offset];
+        let gap: &str = &before[before.trim_end().len()..];
+        gap.matches('\n').count() >= 2
+    }
+
+    // Lays out statements one per line, with the comments among them and
+    // those before `end`. With `leading`, a line break comes before the first.
+    fn statements(&mut self, statements: &[Stmt], end: usize, leading: bool) -> Doc {
+        self.lines(statements, end, leading, |formatter, statement| {
+            formatter.statement(statement)
+        })
+    }
+
+    fn lines<T>(
+        &mut self,
+        items: &[T],
+        end: usize,
+        leading: bool,
+        mut print: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Doc {
+        let mut lines: Lines = Lines {
+            parts: Vec::new(),
+            leading,
+            empty: true,
+        };
+        for item in items.iter() {
+            let start: usize = self
+                .next_token(|kind| *kind != TokenKind::EOF)
+                .map_or(end, |token| token.span().start);
+            self.comments_before(start, &mut lines);
+            self.line_break(&mut lines, start);
+            lines.parts.push(print(self, item));
+        }
+        self.comments_before(end, &mut lines);
+        concat(lines.parts)
+    }
+
+    // Starts a line for something at `offset`, after a blank one if the
+    // source has one there
+    fn line_break(&self, lines: &mut Lines, offset: usize) {
+        if !lines.empty || lines.leading {
+            lines.parts.push(Doc::HardLine);
+        }
+        if !lines.empty && self.blank_line_before(offset) {
+            lines.parts.push(Doc::HardLine);
+        }
+        lines.empty = false;
+    }
+
+    fn comments_before(&mut self, offset: usize, lines: &mut Lines) {
+        while let Some(comment) = self.comments.front() {
+            if comment.span.start >= offset {
+                break;
+            }
+            let comment: Comment = self.comments.pop_front().expect("checked above");
+            let line: String = format!("//{}", comment.text.trim_end());
+            // A trailing comment as the first line of a file has nothing
+            // before it to trail
+            if comment.trailing && (lines.leading || !lines.empty) {
+                lines.parts.push(Doc::LineSuffix(format!(" {}", line)));
+            } else {
+                self.line_break(lines, comment.span.start);
+                lines.parts.push(text(line));
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Doc {
+        self.braces(|formatter, close| formatter.statements(statements, close, true))
+    }
+
+    // `{ ... }` whose `{` is the next one in the source. `body` lays out what
+    // is inside given the offset of the `}`.
+    fn braces(&mut self, body: impl FnOnce(&mut Self, usize) -> Doc) -> Doc {
+        let open: Option<usize> = self
+            .next_token(|kind| *kind == TokenKind::LeftBracket)
+            .map(|token| token.span().start);
+        let close: usize = open
+            .and_then(|open| self.closers.get(&open).copied())
+            .unwrap_or(self.source.len());
+        if let Some(open) = open {
+            self.cursor = open + 1;
+        }
+
+        let body: Doc = body(self, close);
+        self.cursor = self.cursor.max(close + 1);
+        if body == concat(Vec::new()) {
+            return text("{}");
+        }
+        concat(vec![text("{"), indent(body), Doc::HardLine, text("}")])
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Doc {
+        match statement {
+            Stmt::Expression { expression } => {
+                concat(vec![self.expr(expression), self.semicolon()])
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => concat(vec![
+                self.token(keyword),
+                text(" "),
+                self.expr(expression),
+                self.semicolon(),
+            ]),
+            Stmt::Var { .. } => concat(vec![self.clause(statement), self.semicolon()]),
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let mut parts: Vec<Doc> = vec![
+                    self.token(keyword),
+                    text(" ("),
+                    self.expr(condition),
+                    text(")"),
+                    self.body(then_branch),
+                ];
+                if let Some(else_branch) = else_branch {
+                    // `} else` after a block, else on a line of its own
+                    parts.push(if matches!(**then_branch, Stmt::Block { .. }) {
+                        text(" ")
+                    } else {
+                        Doc::HardLine
+                    });
+                    parts.push(text("else"));
+                    if let Some(keyword) = self.next_token(|kind| *kind == TokenKind::Else) {
+                        self.cursor = keyword.span().end;
+                    }
+                    parts.push(match **else_branch {
+                        Stmt::If { .. } => concat(vec![text(" "), self.statement(else_branch)]),
+                        _ => self.body(else_branch),
+                    });
+                }
+                concat(parts)
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => concat(vec![
+                self.token(keyword),
+                text(" ("),
+                self.expr(condition),
+                text(")"),
+                self.body(body),
+            ]),
+            Stmt::For {
+                keyword,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                let mut parts: Vec<Doc> = vec![self.token(keyword), text(" (")];
+                if let Some(initializer) = initializer {
+                    parts.push(self.clause(initializer));
+                }
+                parts.push(self.semicolon());
+                if let Some(condition) = condition {
+                    parts.push(text(" "));
+                    parts.push(self.expr(condition));
+                }
+                parts.push(self.semicolon());
+                if let Some(increment) = increment {
+                    parts.push(text(" "));
+                    parts.push(self.expr(increment));
+                }
+                parts.push(text(")"));
+                parts.push(self.body(body));
+                concat(parts)
+            }
+            Stmt::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => concat(vec![
+                self.token(keyword),
+                text(" ("),
+                self.token(variable),
+                text(" in "),
+                self.expr(iterable),
+                text(")"),
+                self.body(body),
+            ]),
+            Stmt::Function { function } => concat(vec![text("fun "), self.function(function)]),
+            Stmt::Return { keyword, value } => {
+                let mut parts: Vec<Doc> = vec![self.token(keyword)];
+                if let Some(value) = value {
+                    parts.push(text(" "));
+                    parts.push(self.expr(value));
+                }
+                parts.push(self.semicolon());
+                concat(parts)
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                concat(vec![self.token(keyword), self.semicolon()])
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                fields,
+                methods,
+            } => {
+                let mut parts: Vec<Doc> = vec![text("class "), self.token(name)];
+                if let Some(superclass) = superclass {
+                    parts.push(text(" < "));
+                    parts.push(self.expr(superclass));
+                }
+                parts.push(text(" "));
+                // Fields and methods are kept apart in the tree, so put them
+                // back in source order
+                let mut members: Vec<Member> = fields
+                    .iter()
+                    .map(Member::Field)
+                    .chain(methods.iter().map(|method| Member::Method(method)))
+                    .collect();
+                members.sort_by_key(|member| member.start());
+                parts.push(self.braces(|formatter, close| {
+                    formatter.lines(&members, close, true, |formatter, member| match member {
+                        Member::Field(field) => concat(vec![
+                            formatter.token(&field.name),
+                            text(": "),
+                            formatter.annotation(&field.annotation),
+                            formatter.semicolon(),
+                        ]),
+                        Member::Method(method) => formatter.function(method),
+                    })
+                }));
+                concat(parts)
+            }
+            Stmt::Test {
+                keyword,
+                name,
+                body,
+            } => concat(vec![
+                self.token(keyword),
+                text(" "),
+                self.token(name),
+                text(" "),
+                self.block(body),
+            ]),
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+            } => concat(vec![
+                self.token(keyword),
+                text(" "),
+                self.token(path),
+                text(" as "),
+                self.token(name),
+                self.semicolon(),
+            ]),
+            Stmt::Export {
+                keyword,
+                declaration,
+            } => concat(vec![
+                self.token(keyword),
+                text(" "),
+                self.statement(declaration),
+            ]),
+            Stmt::Throw { keyword, value } => concat(vec![
+                self.token(keyword),
+                text(" "),
+                self.expr(value),
+                self.semicolon(),
+            ]),
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => {
+                let mut parts: Vec<Doc> = vec![self.token(keyword), text(" "), self.block(body)];
+                if let Some(catch) = catch {
+                    parts.push(text(" catch ("));
+                    if let Some(keyword) = self.next_token(|kind| *kind == TokenKind::Catch) {
+                        self.cursor = keyword.span().end;
+                    }
+                    parts.push(self.token(&catch.name));
+                    parts.push(text(") "));
+                    parts.push(self.block(&catch.body));
+                }
+                if let Some(finally) = finally {
+                    parts.push(text(" finally "));
+                    if let Some(keyword) = self.next_token(|kind| *kind == TokenKind::Finally) {
+                        self.cursor = keyword.span().end;
+                    }
+                    parts.push(self.block(finally));
+                }
+                concat(parts)
+            }
+        }
+    }
+
+    // The body of an if, loop or else. A block follows on the same line, a
+    // single statement too unless it doesn't fit.
+    fn body(&mut self, body: &Stmt) -> Doc {
+        if let Stmt::Block { statements } = body {
+            return concat(vec![text(" "), self.block(statements)]);
+        }
+        let statement: Doc = self.statement(body);
+        // One that spans lines anyway, like a call taking a lambda, reads
+        // better starting on the same line
+        if doc::has_hard_line(&statement) {
+            return concat(vec![text(" "), statement]);
+        }
+        group(indent(concat(vec![Doc::Line, statement])))
+    }
+
+    // A variable declaration or expression without its semicolon, as in the
+    // first clause of a for loop
+    fn clause(&mut self, statement: &Stmt) -> Doc {
+        match statement {
+            Stmt::Var {
+                name,
+                annotation,
+                initializer,
+            } => {
+                let mut parts: Vec<Doc> = vec![text("var "), self.token(name)];
+                if let Some(annotation) = annotation {
+                    parts.push(text(": "));
+                    parts.push(self.annotation(annotation));
+                }
+                if let Some(initializer) = initializer {
+                    parts.push(text(" = "));
+                    parts.push(self.expr(initializer));
+                }
+                concat(parts)
+            }
+            Stmt::Expression { expression } => self.expr(expression),
+            // The parser allows nothing else there
+            other => self.statement(other),
+        }
+    }
+
+    // Name, parameters and body of a declared function or method
+    fn function(&mut self, function: &Function) -> Doc {
+        let name: Doc = match &function.name {
+            Some(name) => self.token(name),
+            None => text(""),
+        };
+        let mut parts: Vec<Doc> = vec![name, self.parameters(function)];
+        if let Some(return_type) = &function.return_type {
+            parts.push(text(": "));
+            parts.push(self.annotation(return_type));
+        }
+        parts.push(text(" "));
+        parts.push(self.block(&function.body));
+        concat(parts)
+    }
+
+    fn parameters(&mut self, function: &Function) -> Doc {
+        let params: Vec<(&Token, &Option<TypeExpr>)> = function
+            .params
+            .iter()
+            .zip(function.param_types.iter())
+            .collect();
+        let params: Commented = self.commented(
+            &params,
+            &TokenKind::RightParen,
+            |formatter, (param, annotation)| {
+                let param: Doc = formatter.token(param);
+                match annotation {
+                    Some(annotation) => {
+                        concat(vec![param, text(": "), formatter.annotation(annotation)])
+                    }
+                    None => param,
+                }
+            },
+        );
+        delimited("(", params, ")", false)
+    }
+
+    // Prints the items of a bracketed list closed by the next `close`. A
+    // comment on a line of its own goes with the item after it, one after
+    // code with the item or bracket before it.
+    fn commented<T>(
+        &mut self,
+        items: &[T],
+        close: &TokenKind,
+        mut print: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Commented {
+        let mut commented: Commented = Commented::default();
+        for item in items.iter() {
+            // Past the comma after the item before
+            let start: usize = self
+                .next_token(|kind| !matches!(kind, TokenKind::Comma | TokenKind::EOF))
+                .map_or(self.source.len(), |token| token.span().start);
+            let before: Vec<String> = self.item_comments(start, &mut commented);
+            let mut parts: Vec<Doc> = Vec::new();
+            for comment in before {
+                parts.push(text(comment));
+                parts.push(Doc::HardLine);
+            }
+            parts.push(print(self, item));
+            commented.items.push(concat(parts));
+        }
+        let end: usize = self
+            .next_token(|kind| kind == close)
+            .map_or(self.source.len(), |token| token.span().start);
+        commented.closing = self.item_comments(end, &mut commented);
+        commented
+    }
+
+    // Takes the comments before `offset`, attaching those after code to the
+    // last item, or the opening bracket, and returning the rest
+    fn item_comments(&mut self, offset: usize, commented: &mut Commented) -> Vec<String> {
+        let mut own_lines: Vec<String> = Vec::new();
+        while let Some(comment) = self.comments.front() {
+            if comment.span.start >= offset {
+                break;
+            }
+            let comment: Comment = self.comments.pop_front().expect("checked above");
+            let line: String = format!("//{}", comment.text.trim_end());
+            commented.broken = true;
+            if !comment.trailing || !own_lines.is_empty() {
+                own_lines.push(line);
+            } else if let Some(last) = commented.items.last_mut() {
+                *last = concat(vec![last.clone(), Doc::LineSuffix(format!(" {}", line))]);
+            } else {
+                commented.opening = Some(line);
+            }
+        }
+        own_lines
+    }
+
+    fn annotation(&mut self, annotation: &TypeExpr) -> Doc {
+        match annotation {
+            TypeExpr::Named { name } => self.token(name),
+            TypeExpr::Optional { inner, question } => {
+                concat(vec![self.annotation(inner), self.token(question)])
+            }
+            TypeExpr::Union { types } => {
+                let mut parts: Vec<Doc> = Vec::new();
+                for (index, member) in types.iter().enumerate() {
+                    if index > 0 {
+                        parts.push(text(" | "));
+                    }
+                    parts.push(self.annotation(member));
+                }
+                concat(parts)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Doc {
+        match expr {
+            Expr::Literal { token, .. } => self.token(token),
+            Expr::Grouping { expression } => {
+                concat(vec![text("("), self.expr(expression), text(")")])
+            }
+            Expr::Unary { operator, right } => concat(vec![self.token(operator), self.expr(right)]),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+            } => concat(vec![
+                self.expr(left),
+                text(" "),
+                self.token(operator),
+                text(" "),
+                self.expr(right),
+            ]),
+            Expr::Variable { name, .. } => self.token(name),
+            Expr::Assign { name, value, .. } => {
+                concat(vec![self.token(name), text(" = "), self.expr(value)])
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                // A `fun` lambda last stays on the call's line, its body is
+                // what spreads over lines. Calls take no trailing comma.
+                let callee: Doc = self.expr(callee);
+                let hug: bool = matches!(
+                    arguments.last(),
+                    Some(Expr::Lambda { keyword, .. }) if *keyword.kind() == TokenKind::Fun
+                );
+                let arguments: Commented =
+                    self.commented(arguments, &TokenKind::RightParen, |formatter, argument| {
+                        formatter.expr(argument)
+                    });
+                self.token(paren);
+                if hug && !arguments.broken {
+                    let mut parts: Vec<Doc> = vec![callee, text("(")];
+                    for (index, argument) in arguments.items.into_iter().enumerate() {
+                        if index > 0 {
+                            parts.push(text(", "));
+                        }
+                        parts.push(argument);
+                    }
+                    parts.push(text(")"));
+                    return concat(parts);
+                }
+                concat(vec![callee, delimited("(", arguments, ")", false)])
+            }
+            Expr::Get { object, name } => {
+                concat(vec![self.expr(object), text("."), self.token(name)])
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => concat(vec![
+                self.expr(object),
+                text("."),
+                self.token(name),
+                text(" = "),
+                self.expr(value),
+            ]),
+            Expr::This { keyword, .. } => self.token(keyword),
+            Expr::Super {
+                keyword, method, ..
+            } => concat(vec![self.token(keyword), text("."), self.token(method)]),
+            Expr::List { bracket, elements } => {
+                self.token(bracket);
+                let elements: Commented =
+                    self.commented(elements, &TokenKind::RightSBracket, |formatter, element| {
+                        formatter.expr(element)
+                    });
+                delimited("[", elements, "]", true)
+            }
+            Expr::Map { brace, entries } => {
+                self.token(brace);
+                let entries: Commented = self.commented(
+                    entries,
+                    &TokenKind::RightBracket,
+                    |formatter, (key, value)| {
+                        concat(vec![formatter.expr(key), text(": "), formatter.expr(value)])
+                    },
+                );
+                delimited("{", entries, "}", true)
+            }
+            Expr::Index { object, index, .. } => concat(vec![
+                self.expr(object),
+                text("["),
+                self.expr(index),
+                text("]"),
+            ]),
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => concat(vec![
+                self.expr(object),
+                text("["),
+                self.expr(index),
+                text("] = "),
+                self.expr(value),
+            ]),
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                let mut parts: Vec<Doc> = vec![self.expr(object), text("[")];
+                if let Some(start) = start {
+                    parts.push(self.expr(start));
+                }
+                parts.push(text(":"));
+                if let Some(end) = end {
+                    parts.push(self.expr(end));
+                }
+                parts.push(text("]"));
+                concat(parts)
+            }
+            Expr::Range {
+                start,
+                operator,
+                end,
+                ..
+            } => concat(vec![self.expr(start), self.token(operator), self.expr(end)]),
+            Expr::Lambda { keyword, function } => {
+                if *keyword.kind() == TokenKind::Arrow {
+                    let params: Doc = self.parameters(function);
+                    let arrow: Doc = self.token(keyword);
+                    let value: Doc = match function.body.as_slice() {
+                        [
+                            Stmt::Return {
+                                value: Some(value), ..
+                            },
+                        ] => self.expr(value),
+                        // Arrow lambdas are always parsed into a single return
+                        _ => text("nil"),
+                    };
+                    concat(vec![params, text(" "), arrow, text(" "), value])
+                } else {
+                    concat(vec![
+                        self.token(keyword),
+                        text(" "),
+                        self.function(function),
+                    ])
+                }
+            }
+        }
+    }
+}
+
+// A member of a class body
+enum Member<'a> {
+    Field(&'a Field),
+    Method(&'a Function),
+}
+
+impl Member<'_> {
+    fn start(&self) -> usize {
+        match self {
+            Member::Field(field) => field.name.span().start,
+            Member::Method(method) => method.name.as_ref().map_or(0, |name| name.span().start),
+        }
+    }
+}
+
+// Lines being laid out by Formatter::lines
+struct Lines {
+    parts: Vec<Doc>,
+    // Whether the first line needs a break before it, as inside a block
+    leading: bool,
+    // Nothing placed yet
+    empty: bool,
+}
+
+// The items of a bracketed list with the comments among them, from
+// Formatter::commented
+#[derive(Default)]
+struct Commented {
+    items: Vec<Doc>,
+    // After the opening bracket on its line
+    opening: Option<String>,
+    // On lines of their own after the last item
+    closing: Vec<String>,
+    // Whether there are any comments, which keep the list from one line
+    broken: bool,
+}
+
+// `open` items separated by commas `close`, all on one line when they fit and
+// one per line otherwise, with a trailing comma if `trailing_comma` allows it
+fn delimited(open: &str, list: Commented, close: &str, trailing_comma: bool) -> Doc {
+    if list.items.is_empty() && !list.broken {
+        return text(format!("{}{}", open, close));
+    }
+
+    let mut inner: Vec<Doc> = Vec::new();
+    if let Some(comment) = list.opening {
+        inner.push(Doc::LineSuffix(format!(" {}", comment)));
+    }
+    inner.push(if list.broken {
+        Doc::HardLine
+    } else {
+        Doc::SoftLine
+    });
+    let count: usize = list.items.len();
+    for (index, item) in list.items.into_iter().enumerate() {
+        inner.push(item);
+        if index + 1 < count {
+            inner.push(text(","));
+            inner.push(Doc::Line);
+        }
+    }
+    if trailing_comma && count > 0 {
+        inner.push(Doc::IfBreak(",".to_string()));
+    }
+    for (index, comment) in list.closing.into_iter().enumerate() {
+        if index > 0 || count > 0 {
+            inner.push(Doc::HardLine);
+        }
+        inner.push(text(comment));
+    }
+
+    group(concat(vec![
+        text(open),
+        indent(concat(inner)),
+        Doc::SoftLine,
+        text(close),
+    ]))
+}
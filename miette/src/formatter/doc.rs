@@ -0,0 +1,158 @@
+// Layout documents for the formatter: text, plus the places where a line may
+// break. A group is printed flat when it fits in the remaining width and
+// broken at every one of its lines otherwise, in the style of Wadler's
+// "prettier printer".
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Doc {
+    Text(String),
+    // A space in a flat group, a newline in a broken one
+    Line,
+    // Nothing in a flat group, a newline in a broken one
+    SoftLine,
+    // Always a newline. Every group containing one breaks.
+    HardLine,
+    // Only printed when the enclosing group breaks, for trailing commas
+    IfBreak(String),
+    // Lines inside are indented one more level
+    Indent(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+    // Held back until the end of the line and not counted in its width, for
+    // comments after code
+    LineSuffix(String),
+}
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+pub fn indent(doc: Doc) -> Doc {
+    Doc::Indent(Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+// What is left to print: indentation level, mode and document
+type Command<'a> = (usize, Mode, &'a Doc);
+
+pub fn render(doc: &Doc, width: usize, indent_width: usize) -> String {
+    let mut out: String = String::new();
+    let mut column: usize = 0;
+    let mut suffixes: Vec<&str> = Vec::new();
+    // Innermost last, so the next command to print is at the end
+    let mut stack: Vec<Command> = vec![(0, Mode::Break, doc)];
+
+    while let Some((level, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if *doc == Doc::Line {
+                    out.push(' ');
+                    column += 1;
+                }
+            }
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                for suffix in suffixes.drain(..) {
+                    out.push_str(suffix);
+                }
+                // Blank lines carry no indentation
+                let trimmed: usize = out.trim_end_matches(' ').len();
+                out.truncate(trimmed);
+                out.push('\n');
+                out.push_str(&" ".repeat(level * indent_width));
+                column = level * indent_width;
+            }
+            Doc::IfBreak(s) => {
+                if mode == Mode::Break {
+                    out.push_str(s);
+                    column += s.chars().count();
+                }
+            }
+            Doc::Indent(inner) => stack.push((level + 1, mode, inner)),
+            Doc::Group(inner) => {
+                let flat: Command = (level, Mode::Flat, inner);
+                let fits: bool = mode == Mode::Flat
+                    || (!has_hard_line(inner) && fits(flat, &stack, width.saturating_sub(column)));
+                stack.push(if fits {
+                    flat
+                } else {
+                    (level, Mode::Break, inner)
+                });
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((level, mode, doc));
+                }
+            }
+            Doc::LineSuffix(s) => suffixes.push(s),
+        }
+    }
+
+    for suffix in suffixes {
+        out.push_str(suffix);
+    }
+    out
+}
+
+// Whether `next` fits in `width` columns, along with whatever follows it up
+// to the next line break
+fn fits(next: Command, rest: &[Command], width: usize) -> bool {
+    let mut remaining: isize = width as isize;
+    let mut stack: Vec<Command> = vec![next];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let Some((level, mode, doc)) = stack.pop().or_else(|| rest.next().copied()) else {
+            return true;
+        };
+        match doc {
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if *doc == Doc::Line {
+                    remaining -= 1;
+                }
+            }
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::IfBreak(s) => {
+                if mode == Mode::Break {
+                    remaining -= s.chars().count() as isize;
+                }
+            }
+            Doc::Indent(inner) | Doc::Group(inner) => stack.push((level, mode, inner)),
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((level, mode, doc));
+                }
+            }
+            Doc::LineSuffix(_) => {}
+        }
+        if remaining < 0 {
+            return false;
+        }
+    }
+}
+
+pub fn has_hard_line(doc: &Doc) -> bool {
+    match doc {
+        Doc::HardLine => true,
+        Doc::Indent(inner) | Doc::Group(inner) => has_hard_line(inner),
+        Doc::Concat(docs) => docs.iter().any(has_hard_line),
+        _ => false,
+    }
+}
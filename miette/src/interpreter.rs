@@ -0,0 +1,1290 @@
+pub mod environment;
+pub mod natives;
+pub mod value;
+
+use crate::ast::{self, Expr, Function, Literal, NodeId, Stmt};
+use crate::error::{ErrorKind, RuntimeError, TraceFrame};
+use crate::lex::{Token, TokenKind};
+use crate::list::List;
+use crate::map::{Map, MapError};
+use crate::module::Program;
+use crate::range::Range;
+use crate::resolver::Locals;
+use crate::stdlib;
+use environment::Environment;
+use value::{Class, Closure, Instance, Module, NativeFunction, Value};
+
+// Matches the VM's frame limit; each level costs several host stack frames
+const MAX_CALL_DEPTH: usize = 4096;
+
+// Enough host stack for MAX_CALL_DEPTH calls even in a debug build, where
+// each takes tens of kilobytes; run the interpreter on a thread this size
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// How a statement finished, so loops and calls know whether to keep going
+pub enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+// A call in progress, kept for stack traces
+struct Frame {
+    function: String,
+    // Line of the call expression in the caller
+    call_line: usize,
+}
+
+pub struct Interpreter {
+    globals: std::rc::Rc<std::cell::RefCell<Environment>>,
+    environment: std::rc::Rc<std::cell::RefCell<Environment>>,
+    // Scope distances from the resolver, anything missing is a global
+    locals: Locals,
+    frames: Vec<Frame>,
+    // Built-ins, so each module's globals can start out with them
+    natives: Vec<(String, Value)>,
+    // The modules the running module imports, by path as written
+    imports: std::collections::HashMap<String, Value>,
+    // The value a `throw` is unwinding with, next to the error carrying it up
+    // the call stack, so a catch can hand back what was thrown
+    thrown: Option<(Value, RuntimeError)>,
+    out: Box<dyn std::io::Write>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::with_output(Box::new(std::io::stdout()))
+    }
+
+    // `print` writes to `out` instead of stdout
+    pub fn with_output(out: Box<dyn std::io::Write>) -> Self {
+        let globals: std::rc::Rc<std::cell::RefCell<Environment>> =
+            std::rc::Rc::new(std::cell::RefCell::new(Environment::new(None)));
+
+        let mut interpreter: Interpreter = Interpreter {
+            environment: globals.clone(),
+            globals,
+            locals: Locals::new(),
+            frames: Vec::new(),
+            natives: Vec::new(),
+            imports: std::collections::HashMap::new(),
+            thrown: None,
+            out,
+        };
+        for (name, arity, function) in natives::natives() {
+            interpreter.define_native(name, arity, move |args: &[Value]| function(args));
+        }
+        interpreter
+    }
+
+    // Makes `function` callable from scripts as a global named `name`
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let native: Value = Value::Native(std::rc::Rc::new(NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: std::rc::Rc::new(function),
+        }));
+        self.globals.borrow_mut().define(name, native.clone());
+        self.natives.push((name.to_string(), native));
+    }
+
+    // Adds the resolver's results for a program about to be interpreted
+    pub fn resolve(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            self.execute(statement)?;
+        }
+        self.flush()
+    }
+
+    // Runs each module of a program in turn, the main one in the interpreter's
+    // own globals and the others in globals of their own
+    pub fn interpret_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let mut modules: Vec<Value> = Vec::new();
+        for (index, module) in program.modules.iter().enumerate() {
+            self.resolve(module.locals.clone());
+            let globals: std::rc::Rc<std::cell::RefCell<Environment>> =
+                if index + 1 == program.modules.len() {
+                    self.globals.clone()
+                } else {
+                    let mut environment: Environment = Environment::new(None);
+                    for (name, native) in self.natives.iter() {
+                        environment.define(name, native.clone());
+                    }
+                    std::rc::Rc::new(std::cell::RefCell::new(environment))
+                };
+            self.imports = module
+                .imports
+                .iter()
+                .map(|(path, index)| (path.clone(), modules[*index].clone()))
+                .collect();
+
+            let main: std::rc::Rc<std::cell::RefCell<Environment>> =
+                std::mem::replace(&mut self.globals, globals.clone());
+            self.environment = globals.clone();
+            let result: Result<(), RuntimeError> = module
+                .statements
+                .iter()
+                .try_for_each(|statement| self.execute(statement).map(|_| ()));
+            self.globals = main;
+            self.environment = self.globals.clone();
+            self.imports.clear();
+            result?;
+
+            modules.push(Value::Module(std::rc::Rc::new(Module {
+                name: module.name.clone(),
+                globals,
+                exports: module.exports.clone(),
+            })));
+        }
+        self.flush()
+    }
+
+    // Evaluates a top level expression for callers that want its value, like
+    // the REPL echoing results
+    pub fn interpret_expression(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        let value: Value = self.evaluate(expr)?;
+        self.flush()?;
+        Ok(value)
+    }
+
+    fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.out.flush().map_err(|e| RuntimeError {
+            kind: ErrorKind::IoError,
+            message: format!("Failed to write output: {}.", e),
+            line: 0,
+            span: crate::lex::Span::default(),
+            trace: Vec::new(),
+        })
+    }
+
+    pub fn globals(&self) -> std::rc::Rc<std::cell::RefCell<Environment>> {
+        self.globals.clone()
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, RuntimeError> {
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.evaluate(expression)?;
+                Ok(Flow::Normal)
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                let value: Value = self.evaluate(expression)?;
+                if let Err(e) = writeln!(self.out, "{}", value) {
+                    return Err(self.error(
+                        ErrorKind::IoError,
+                        keyword,
+                        &format!("Failed to print: {}.", e),
+                    ));
+                }
+                Ok(Flow::Normal)
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                let value: Value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(name.lexeme(), value);
+                Ok(Flow::Normal)
+            }
+            Stmt::Block { statements } => {
+                let environment: Environment = Environment::new(Some(self.environment.clone()));
+                self.execute_block(statements, environment)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)
+                } else {
+                    Ok(Flow::Normal)
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while self.evaluate(condition)?.is_truthy() {
+                    match self.execute(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                // The initializer gets its own scope that ends with the loop
+                let environment: Environment = Environment::new(Some(self.environment.clone()));
+                let previous = std::mem::replace(
+                    &mut self.environment,
+                    std::rc::Rc::new(std::cell::RefCell::new(environment)),
+                );
+                let result: Result<Flow, RuntimeError> =
+                    self.execute_for(initializer, condition, increment, body);
+                self.environment = previous;
+                result
+            }
+            Stmt::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => {
+                let iterable: Value = self.evaluate(iterable)?;
+                self.execute_for_in(keyword, variable, iterable, body)
+            }
+            Stmt::Function { function } => {
+                let closure: Value = self.closure(function);
+                let name: &str = function.name.as_ref().map_or("", |name| name.lexeme());
+                self.environment.borrow_mut().define(name, closure);
+                Ok(Flow::Normal)
+            }
+            Stmt::Return { value, .. } => {
+                let value: Value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                Ok(Flow::Return(value))
+            }
+            Stmt::Break { .. } => Ok(Flow::Break),
+            Stmt::Continue { .. } => Ok(Flow::Continue),
+            // Only `miette test` runs these
+            Stmt::Test { .. } => Ok(Flow::Normal),
+            Stmt::Import { path, name, .. } => {
+                let TokenKind::Text(text) = path.kind() else {
+                    unreachable!("the parser only accepts a string as an import's path")
+                };
+                let Some(module) = self.imports.get(text).cloned() else {
+                    return Err(self.error(
+                        ErrorKind::ImportError,
+                        path,
+                        "Can only import modules in a program run from a file.",
+                    ));
+                };
+                self.environment.borrow_mut().define(name.lexeme(), module);
+                Ok(Flow::Normal)
+            }
+            Stmt::Export { declaration, .. } => self.execute(declaration),
+            Stmt::Throw { keyword, value } => {
+                let value: Value = self.evaluate(value)?;
+                Err(self.throw(keyword, value))
+            }
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => {
+                let environment: Environment = Environment::new(Some(self.environment.clone()));
+                let mut result: Result<Flow, RuntimeError> = self.execute_block(body, environment);
+                if let Some(catch) = catch
+                    && let Err(error) = result
+                {
+                    let mut environment: Environment =
+                        Environment::new(Some(self.environment.clone()));
+                    environment.define(catch.name.lexeme(), self.caught(error));
+                    result = self.execute_block(&catch.body, environment);
+                }
+                let Some(finally) = finally else {
+                    return result;
+                };
+
+                // What is being thrown is set aside while the finally block
+                // runs, since it may throw and catch on its own
+                let pending: Result<Flow, Value> = result.map_err(|error| self.caught(error));
+                let environment: Environment = Environment::new(Some(self.environment.clone()));
+                match self.execute_block(finally, environment)? {
+                    Flow::Normal => {}
+                    // Leaving the finally block early drops what was pending
+                    flow => return Ok(flow),
+                }
+                pending.map_err(|value| self.throw(keyword, value))
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let superclass: Option<std::rc::Rc<Class>> = match superclass {
+                    Some(expr) => match self.evaluate(expr)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            let token: &Token = expr.first_token();
+                            return Err(self.error(
+                                ErrorKind::TypeError,
+                                token,
+                                "Superclass must be a class.",
+                            ));
+                        }
+                    },
+                    None => None,
+                };
+
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme(), Value::Nil);
+
+                // Methods of a subclass see `super` one scope out from `this`
+                let method_environment: std::rc::Rc<std::cell::RefCell<Environment>> =
+                    match &superclass {
+                        Some(superclass) => {
+                            let mut environment: Environment =
+                                Environment::new(Some(self.environment.clone()));
+                            environment.define("super", Value::Class(superclass.clone()));
+                            std::rc::Rc::new(std::cell::RefCell::new(environment))
+                        }
+                        None => self.environment.clone(),
+                    };
+
+                let mut class_methods: std::collections::HashMap<String, std::rc::Rc<Closure>> =
+                    std::collections::HashMap::new();
+                for method in methods {
+                    let method_name: &str = method.name.as_ref().map_or("", |name| name.lexeme());
+                    class_methods.insert(
+                        method_name.to_string(),
+                        std::rc::Rc::new(Closure {
+                            declaration: method.clone(),
+                            closure: method_environment.clone(),
+                            globals: self.globals.clone(),
+                            is_initializer: method_name == "init",
+                        }),
+                    );
+                }
+
+                let class: Value = Value::Class(std::rc::Rc::new(Class {
+                    name: name.lexeme().to_string(),
+                    superclass,
+                    methods: class_methods,
+                }));
+                self.environment.borrow_mut().assign(name.lexeme(), class);
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<Flow, RuntimeError> {
+        let previous = std::mem::replace(
+            &mut self.environment,
+            std::rc::Rc::new(std::cell::RefCell::new(environment)),
+        );
+
+        let mut result: Result<Flow, RuntimeError> = Ok(Flow::Normal);
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(Flow::Normal) => {}
+                other => {
+                    result = other;
+                    break;
+                }
+            }
+        }
+
+        self.environment = previous;
+        result
+    }
+
+    fn execute_for(
+        &mut self,
+        initializer: &Option<Box<Stmt>>,
+        condition: &Option<Expr>,
+        increment: &Option<Expr>,
+        body: &Stmt,
+    ) -> Result<Flow, RuntimeError> {
+        if let Some(initializer) = initializer {
+            self.execute(initializer)?;
+        }
+
+        loop {
+            if let Some(condition) = condition
+                && !self.evaluate(condition)?.is_truthy()
+            {
+                break;
+            }
+
+            match self.execute(body)? {
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => {}
+            }
+
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn execute_for_in(
+        &mut self,
+        keyword: &Token,
+        variable: &Token,
+        iterable: Value,
+        body: &Stmt,
+    ) -> Result<Flow, RuntimeError> {
+        match iterable {
+            Value::List(list) => {
+                // Indexed each time round so the body may grow or shrink the list
+                let mut index: usize = 0;
+                loop {
+                    let item: Option<Value> = list.borrow().items().get(index).cloned();
+                    let Some(item) = item else { break };
+                    index += 1;
+                    match self.run_iteration(variable, item, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Value::Map(map) => {
+                // Keys are taken up front, changing the map does not affect this loop
+                let keys: Vec<Value> = map.borrow().keys().map(Value::from_key).collect();
+                for key in keys {
+                    match self.run_iteration(variable, key, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Value::Text(text) => {
+                for c in text.chars() {
+                    let item: Value = Value::text(c.encode_utf8(&mut [0; 4]));
+                    match self.run_iteration(variable, item, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Value::Range(range) => {
+                for n in range.iter() {
+                    match self.run_iteration(variable, Value::Number(n), body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Value::Instance(ref instance) => {
+                let class: std::rc::Rc<Class> = instance.borrow().class.clone();
+
+                // An iterator is used directly, an iterable hands one out
+                if class.find_method("next").is_some() {
+                    return self.iterate_protocol(keyword, variable, iterable.clone(), body);
+                }
+                if class.find_method("iter").is_some() {
+                    let iterator: Value = self.invoke(keyword, &iterable, "iter", Vec::new())?;
+                    return self.execute_for_in(keyword, variable, iterator, body);
+                }
+                return Err(self.error(
+                    ErrorKind::TypeError,
+                    keyword,
+                    &format!(
+                        "{} instance is not iterable, it needs an iter() or next() method.",
+                        class.name
+                    ),
+                ));
+            }
+            other => {
+                return Err(self.error(
+                    ErrorKind::TypeError,
+                    keyword,
+                    &format!("Can't iterate over a {}.", other.type_name()),
+                ));
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    // Calls `next()` until it returns nil
+    fn iterate_protocol(
+        &mut self,
+        keyword: &Token,
+        variable: &Token,
+        iterator: Value,
+        body: &Stmt,
+    ) -> Result<Flow, RuntimeError> {
+        loop {
+            let item: Value = self.invoke(keyword, &iterator, "next", Vec::new())?;
+            if let Value::Nil = item {
+                break;
+            }
+            match self.run_iteration(variable, item, body)? {
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => {}
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Each iteration gets its own scope so closures see that iteration's value
+    fn run_iteration(
+        &mut self,
+        variable: &Token,
+        item: Value,
+        body: &Stmt,
+    ) -> Result<Flow, RuntimeError> {
+        let mut environment: Environment = Environment::new(Some(self.environment.clone()));
+        environment.define(variable.lexeme(), item);
+        self.execute_block(std::slice::from_ref(body), environment)
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match expr {
+            Expr::Literal { value, .. } => Ok(match value {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Number(n) => Value::Number(*n),
+                Literal::Text(s) => Value::text(s),
+            }),
+            Expr::Grouping { expression } => self.evaluate(expression),
+            Expr::Unary { operator, right } => {
+                let right: Value = self.evaluate(right)?;
+                match (operator.kind(), right) {
+                    (TokenKind::Bang, right) => Ok(Value::Bool(!right.is_truthy())),
+                    (TokenKind::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+                    _ => {
+                        Err(self.error(ErrorKind::TypeError, operator, "Operand must be a number."))
+                    }
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left: Value = self.evaluate(left)?;
+                let right: Value = self.evaluate(right)?;
+                self.binary(operator, left, right)
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left: Value = self.evaluate(left)?;
+                let short_circuit: bool = match operator.kind() {
+                    TokenKind::Or => left.is_truthy(),
+                    _ => !left.is_truthy(),
+                };
+                if short_circuit {
+                    Ok(left)
+                } else {
+                    self.evaluate(right)
+                }
+            }
+            Expr::Variable { id, name } => self.look_up(*id, name),
+            Expr::Assign { id, name, value } => {
+                let value: Value = self.evaluate(value)?;
+                let assigned: bool = match self.locals.get(id) {
+                    Some(distance) => self.environment.borrow_mut().assign_at(
+                        *distance,
+                        name.lexeme(),
+                        value.clone(),
+                    ),
+                    None => self
+                        .globals
+                        .borrow_mut()
+                        .assign(name.lexeme(), value.clone()),
+                };
+                if !assigned {
+                    return Err(self.error(
+                        ErrorKind::NameError,
+                        name,
+                        &format!("Undefined variable '{}'.", name.lexeme()),
+                    ));
+                }
+                Ok(value)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let site: Token = ast::call_site(callee, paren);
+                let callee: Value = self.evaluate(callee)?;
+                let mut values: Vec<Value> = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    values.push(self.evaluate(argument)?);
+                }
+                self.call(&site, callee, values)
+            }
+            Expr::Get { object, name } => {
+                let object: Value = self.evaluate(object)?;
+                self.get_property(name, &object)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object: Value = self.evaluate(object)?;
+                let value: Value = self.evaluate(value)?;
+                let Value::Instance(instance) = object else {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        name,
+                        "Only instances have fields.",
+                    ));
+                };
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert(name.lexeme().to_string(), value.clone());
+                Ok(value)
+            }
+            Expr::This { id, keyword } => self.look_up(*id, keyword),
+            Expr::Super {
+                id,
+                keyword,
+                method,
+            } => self.super_method(*id, keyword, method),
+            Expr::List { elements, .. } => {
+                let mut items: Vec<Value> = Vec::with_capacity(elements.len());
+                for element in elements {
+                    items.push(self.evaluate(element)?);
+                }
+                Ok(Value::list(items))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map: Map<Value> = Map::new();
+                for (key, value) in entries {
+                    let key: Value = self.evaluate(key)?;
+                    let value: Value = self.evaluate(value)?;
+                    let key = key
+                        .to_key()
+                        .map_err(|e| self.error(e.kind(), brace, &e.to_string()))?;
+                    map.insert(key, value);
+                }
+                Ok(Value::map(map))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object: Value = self.evaluate(object)?;
+                let index: Value = self.evaluate(index)?;
+                self.index(bracket, &object, &index)
+            }
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object: Value = self.evaluate(object)?;
+                let index: Value = self.evaluate(index)?;
+                let value: Value = self.evaluate(value)?;
+                self.index_set(bracket, &object, &index, value)
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                let object: Value = self.evaluate(object)?;
+                let start: Option<Value> = match start {
+                    Some(start) => Some(self.evaluate(start)?),
+                    None => None,
+                };
+                let end: Option<Value> = match end {
+                    Some(end) => Some(self.evaluate(end)?),
+                    None => None,
+                };
+                let start: Option<f64> = self.slice_bound(bracket, start)?;
+                let end: Option<f64> = self.slice_bound(bracket, end)?;
+                self.slice(bracket, &object, start, end)
+            }
+            Expr::Range {
+                start,
+                operator,
+                end,
+                inclusive,
+            } => {
+                let start: Value = self.evaluate(start)?;
+                let end: Value = self.evaluate(end)?;
+                let (Value::Number(start), Value::Number(end)) = (start, end) else {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        operator,
+                        "Range bounds must be numbers.",
+                    ));
+                };
+                let range: Range = Range::new(start, end, *inclusive)
+                    .map_err(|e| self.error(ErrorKind::TypeError, operator, &e.to_string()))?;
+                Ok(Value::Range(range))
+            }
+            Expr::Lambda { function, .. } => Ok(self.closure(function)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operator: &Token,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, RuntimeError> {
+        match operator.kind() {
+            TokenKind::EqualEqual => return Ok(Value::Bool(left.equals(&right))),
+            TokenKind::BangEqual => return Ok(Value::Bool(!left.equals(&right))),
+            TokenKind::Plus => {
+                if let (Value::Text(a), Value::Text(b)) = (&left, &right) {
+                    return Ok(Value::text(&format!("{}{}", a, b)));
+                }
+                if !matches!((&left, &right), (Value::Number(_), Value::Number(_))) {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        operator,
+                        "Operands must be two numbers or two strings.",
+                    ));
+                }
+            }
+            _ => {}
+        }
+
+        let (Value::Number(a), Value::Number(b)) = (left, right) else {
+            return Err(self.error(ErrorKind::TypeError, operator, "Operands must be numbers."));
+        };
+
+        match operator.kind() {
+            TokenKind::Plus => Ok(Value::Number(a + b)),
+            TokenKind::Minus => Ok(Value::Number(a - b)),
+            TokenKind::Star => Ok(Value::Number(a * b)),
+            TokenKind::Slash => {
+                if b == 0.0 {
+                    return Err(self.error(
+                        ErrorKind::ZeroDivisionError,
+                        operator,
+                        "Division by zero.",
+                    ));
+                }
+                Ok(Value::Number(a / b))
+            }
+            TokenKind::Greater => Ok(Value::Bool(a > b)),
+            TokenKind::GreaterEqual => Ok(Value::Bool(a >= b)),
+            TokenKind::Less => Ok(Value::Bool(a < b)),
+            TokenKind::LessEqual => Ok(Value::Bool(a <= b)),
+            _ => Err(self.error(ErrorKind::Error, operator, "Unknown binary operator.")),
+        }
+    }
+
+    fn look_up(&mut self, id: NodeId, name: &Token) -> Result<Value, RuntimeError> {
+        let value: Option<Value> = match self.locals.get(&id) {
+            Some(distance) => self.environment.borrow().get_at(*distance, name.lexeme()),
+            None => self.globals.borrow().get(name.lexeme()),
+        };
+        match value {
+            Some(value) => Ok(value),
+            None => Err(self.error(
+                ErrorKind::NameError,
+                name,
+                &format!("Undefined variable '{}'.", name.lexeme()),
+            )),
+        }
+    }
+
+    fn closure(&self, function: &std::rc::Rc<Function>) -> Value {
+        Value::Function(std::rc::Rc::new(Closure {
+            declaration: function.clone(),
+            closure: self.environment.clone(),
+            globals: self.globals.clone(),
+            is_initializer: false,
+        }))
+    }
+
+    // `super.method` starts the lookup in the superclass of the class that
+    // declared the running method, and binds the result to the current `this`
+    fn super_method(
+        &mut self,
+        id: NodeId,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let Some(distance) = self.locals.get(&id).copied() else {
+            return Err(self.error(
+                ErrorKind::Error,
+                keyword,
+                "Can't use 'super' outside of a class.",
+            ));
+        };
+
+        let superclass: Option<Value> = self.environment.borrow().get_at(distance, "super");
+        let instance: Option<Value> = self.environment.borrow().get_at(distance - 1, "this");
+        let (Some(Value::Class(superclass)), Some(instance)) = (superclass, instance) else {
+            return Err(self.error(
+                ErrorKind::Error,
+                keyword,
+                "Can't use 'super' outside of a class.",
+            ));
+        };
+
+        match superclass.find_method(method.lexeme()) {
+            Some(found) => Ok(Value::Function(std::rc::Rc::new(found.bind(instance)))),
+            None => Err(self.error(
+                ErrorKind::PropertyError,
+                method,
+                &format!(
+                    "Undefined property '{}' on superclass {}.",
+                    method.lexeme(),
+                    superclass.name
+                ),
+            )),
+        }
+    }
+
+    fn get_property(&mut self, name: &Token, object: &Value) -> Result<Value, RuntimeError> {
+        if let Value::Module(module) = object {
+            return match module.get(name.lexeme()) {
+                Some(value) => Ok(value),
+                None => Err(self.error(
+                    ErrorKind::PropertyError,
+                    name,
+                    &format!(
+                        "Module '{}' has no export '{}'.",
+                        module.name,
+                        name.lexeme()
+                    ),
+                )),
+            };
+        }
+        if let Value::Error(error) = object {
+            let error: RuntimeError = error.borrow().clone();
+            return match name.lexeme() {
+                "message" => Ok(Value::text(&error.message)),
+                "kind" => Ok(Value::text(&error.kind.to_string())),
+                "trace" => Ok(Value::list(
+                    error
+                        .trace
+                        .iter()
+                        .map(|frame| Value::text(&frame.to_string()))
+                        .collect(),
+                )),
+                _ => Err(self.error(
+                    ErrorKind::PropertyError,
+                    name,
+                    &format!("Undefined property '{}' on error.", name.lexeme()),
+                )),
+            };
+        }
+        let Value::Instance(instance) = object else {
+            return Err(self.error(
+                ErrorKind::TypeError,
+                name,
+                &format!(
+                    "Only instances have properties, got a {}.",
+                    object.type_name()
+                ),
+            ));
+        };
+
+        if let Some(value) = instance.borrow().fields.get(name.lexeme()) {
+            return Ok(value.clone());
+        }
+
+        let method: Option<std::rc::Rc<Closure>> =
+            instance.borrow().class.find_method(name.lexeme());
+        match method {
+            Some(method) => Ok(Value::Function(std::rc::Rc::new(
+                method.bind(object.clone()),
+            ))),
+            None => {
+                let class: String = instance.borrow().class.name.clone();
+                Err(self.error(
+                    ErrorKind::PropertyError,
+                    name,
+                    &format!(
+                        "Undefined property '{}' on {} instance.",
+                        name.lexeme(),
+                        class
+                    ),
+                ))
+            }
+        }
+    }
+
+    // Looks up and calls a method by name, used by the iteration protocol
+    fn invoke(
+        &mut self,
+        token: &Token,
+        object: &Value,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let method_token: Token = Token::new(
+            token.id(),
+            TokenKind::Identifier(name.to_string()),
+            name.to_string(),
+            token.line(),
+            token.span(),
+        );
+        let method: Value = self.get_property(&method_token, object)?;
+        self.call(token, method, arguments)
+    }
+
+    pub fn call(
+        &mut self,
+        paren: &Token,
+        callee: Value,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Function(closure) => {
+                self.check_arity(paren, closure.arity(), arguments.len())?;
+                self.call_closure(paren, &closure, arguments)
+            }
+            Value::Native(native) => {
+                self.check_arity(paren, native.arity, arguments.len())?;
+                if native.name == stdlib::ASSERT_THROWS {
+                    return self.assert_throws(paren, &arguments[0]);
+                }
+                (native.function)(&arguments).map_err(|message| {
+                    self.error(stdlib::error_kind(&native.name), paren, &message)
+                })
+            }
+            Value::Class(class) => {
+                self.check_arity(paren, class.arity(), arguments.len())?;
+                let instance: Value =
+                    Value::Instance(std::rc::Rc::new(std::cell::RefCell::new(Instance {
+                        class: class.clone(),
+                        fields: std::collections::HashMap::new(),
+                    })));
+                if let Some(init) = class.find_method("init") {
+                    self.call_closure(paren, &init.bind(instance.clone()), arguments)?;
+                }
+                Ok(instance)
+            }
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                paren,
+                &format!(
+                    "Can only call functions and classes, got a {}.",
+                    other.type_name()
+                ),
+            )),
+        }
+    }
+
+    // Calls `function` expecting it to fail, giving back the error's message
+    fn assert_throws(&mut self, paren: &Token, function: &Value) -> Result<Value, RuntimeError> {
+        let arity: usize = match function {
+            Value::Function(closure) => closure.arity(),
+            Value::Native(native) => native.arity,
+            other => {
+                let message: String =
+                    stdlib::expected(stdlib::ASSERT_THROWS, "a function", other.type_name());
+                return Err(self.error(stdlib::error_kind(stdlib::ASSERT_THROWS), paren, &message));
+            }
+        };
+        // Otherwise the arity error would pass for the one expected
+        self.check_arity(paren, arity, 0)?;
+
+        match self.call(paren, function.clone(), Vec::new()) {
+            Ok(value) => Err(self.error(
+                ErrorKind::AssertionError,
+                paren,
+                &stdlib::assert_throws_failed(&ValueRepr(&value).to_string()),
+            )),
+            Err(error) => Ok(match self.caught(error) {
+                Value::Error(error) => Value::text(&error.borrow().message),
+                thrown => thrown,
+            }),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        paren: &Token,
+        closure: &Closure,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(self.error(ErrorKind::StackOverflowError, paren, "Stack overflow."));
+        }
+
+        let mut environment: Environment = Environment::new(Some(closure.closure.clone()));
+        for (param, argument) in closure.declaration.params.iter().zip(arguments) {
+            environment.define(param.lexeme(), argument);
+        }
+
+        self.frames.push(Frame {
+            function: closure.name().to_string(),
+            call_line: paren.line(),
+        });
+        let caller: std::rc::Rc<std::cell::RefCell<Environment>> =
+            std::mem::replace(&mut self.globals, closure.globals.clone());
+        let result: Result<Flow, RuntimeError> =
+            self.execute_block(&closure.declaration.body, environment);
+        self.globals = caller;
+        self.frames.pop();
+
+        let value: Value = match result? {
+            Flow::Return(value) => value,
+            _ => Value::Nil,
+        };
+        if closure.is_initializer {
+            return Ok(closure.closure.borrow().get_at(0, "this").unwrap_or(value));
+        }
+        Ok(value)
+    }
+
+    fn check_arity(
+        &mut self,
+        paren: &Token,
+        arity: usize,
+        count: usize,
+    ) -> Result<(), RuntimeError> {
+        if arity != count {
+            return Err(self.error(
+                ErrorKind::ArityError,
+                paren,
+                &format!("Expected {} arguments but got {}.", arity, count),
+            ));
+        }
+        Ok(())
+    }
+
+    fn index(
+        &mut self,
+        bracket: &Token,
+        object: &Value,
+        index: &Value,
+    ) -> Result<Value, RuntimeError> {
+        match object {
+            Value::List(list) => {
+                let position: f64 = self.expect_index(bracket, index)?;
+                let item: Result<Value, String> = list
+                    .borrow()
+                    .get(position)
+                    .cloned()
+                    .map_err(|e| e.to_string());
+                item.map_err(|message| self.error(ErrorKind::IndexError, bracket, &message))
+            }
+            Value::Text(text) => {
+                let position: f64 = self.expect_index(bracket, index)?;
+                let chars: List<char> = List::new(text.chars().collect());
+                match chars.get(position) {
+                    Ok(c) => Ok(Value::text(c.encode_utf8(&mut [0; 4]))),
+                    Err(e) => Err(self.error(
+                        ErrorKind::IndexError,
+                        bracket,
+                        &e.to_string()
+                            .replace("List", "String")
+                            .replace("list", "string"),
+                    )),
+                }
+            }
+            Value::Map(map) => {
+                let key = index
+                    .to_key()
+                    .map_err(|e| self.error(e.kind(), bracket, &e.to_string()))?;
+                let value: Option<Value> = map.borrow().get(&key).cloned();
+                match value {
+                    Some(value) => Ok(value),
+                    None => {
+                        let missing: String = format!("{}", ValueRepr(index));
+                        Err(self.error(
+                            ErrorKind::KeyError,
+                            bracket,
+                            &MapError::MissingKey(missing).to_string(),
+                        ))
+                    }
+                }
+            }
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                bracket,
+                &format!("Can't index into a {}.", other.type_name()),
+            )),
+        }
+    }
+
+    fn index_set(
+        &mut self,
+        bracket: &Token,
+        object: &Value,
+        index: &Value,
+        value: Value,
+    ) -> Result<Value, RuntimeError> {
+        match object {
+            Value::List(list) => {
+                let position: f64 = self.expect_index(bracket, index)?;
+                let result = list.borrow_mut().set(position, value.clone());
+                result.map_err(|e| self.error(ErrorKind::IndexError, bracket, &e.to_string()))?;
+                Ok(value)
+            }
+            Value::Map(map) => {
+                let key = index
+                    .to_key()
+                    .map_err(|e| self.error(e.kind(), bracket, &e.to_string()))?;
+                map.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            }
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                bracket,
+                &format!("Can't assign into a {}.", other.type_name()),
+            )),
+        }
+    }
+
+    fn slice(
+        &mut self,
+        bracket: &Token,
+        object: &Value,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Value, RuntimeError> {
+        match object {
+            Value::List(list) => {
+                let sliced = list.borrow().slice(start, end);
+                match sliced {
+                    Ok(sliced) => Ok(Value::list(sliced.items().to_vec())),
+                    Err(e) => Err(self.error(ErrorKind::IndexError, bracket, &e.to_string())),
+                }
+            }
+            Value::Text(text) => {
+                let chars: List<char> = List::new(text.chars().collect());
+                match chars.slice(start, end) {
+                    Ok(sliced) => Ok(Value::text(&sliced.items().iter().collect::<String>())),
+                    Err(e) => Err(self.error(ErrorKind::IndexError, bracket, &e.to_string())),
+                }
+            }
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                bracket,
+                &format!("Can't slice a {}.", other.type_name()),
+            )),
+        }
+    }
+
+    fn slice_bound(
+        &mut self,
+        bracket: &Token,
+        bound: Option<Value>,
+    ) -> Result<Option<f64>, RuntimeError> {
+        match bound {
+            Some(bound) => Ok(Some(self.expect_index(bracket, &bound)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn expect_index(&mut self, bracket: &Token, index: &Value) -> Result<f64, RuntimeError> {
+        match index {
+            Value::Number(n) => Ok(*n),
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                bracket,
+                &format!("Index must be a number, got a {}.", other.type_name()),
+            )),
+        }
+    }
+
+    // Starts unwinding with `value`. An error made by `error()` takes on the
+    // place it is first thrown from, anything else is reported as uncaught
+    // if nothing catches it.
+    fn throw(&mut self, keyword: &Token, value: Value) -> RuntimeError {
+        let error: RuntimeError = match &value {
+            Value::Error(error) => {
+                if error.borrow().trace.is_empty() {
+                    let (kind, message): (ErrorKind, String) = {
+                        let error: std::cell::Ref<RuntimeError> = error.borrow();
+                        (error.kind, error.message.clone())
+                    };
+                    let raised: RuntimeError = self.error(kind, keyword, &message);
+                    *error.borrow_mut() = raised;
+                }
+                error.borrow().clone()
+            }
+            other => self.error(
+                ErrorKind::Error,
+                keyword,
+                &format!("Uncaught {}.", ValueRepr(other)),
+            ),
+        };
+        self.thrown = Some((value, error.clone()));
+        error
+    }
+
+    // The value a catch binds for `error`: what was thrown, or the runtime
+    // error itself
+    fn caught(&mut self, error: RuntimeError) -> Value {
+        match self.thrown.take() {
+            Some((value, thrown)) if thrown == error => value,
+            _ => Value::error(error),
+        }
+    }
+
+    // Builds an error at `token` with the current call stack attached
+    fn error(&self, kind: ErrorKind, token: &Token, message: &str) -> RuntimeError {
+        let mut error: RuntimeError = RuntimeError::new(kind, token, message);
+
+        let mut line: usize = token.line();
+        for frame in self.frames.iter().rev() {
+            error.trace.push(TraceFrame {
+                function: frame.function.clone(),
+                line,
+            });
+            line = frame.call_line;
+        }
+        error.trace.push(TraceFrame {
+            function: "script".to_string(),
+            line,
+        });
+
+        error
+    }
+}
+
+// Formats a value the way it appears inside a container, strings quoted
+struct ValueRepr<'a>(&'a Value);
+
+impl std::fmt::Display for ValueRepr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Text(s) => write!(f, "{:?}", s),
+            other => write!(f, "{}", other),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use super::value::Value;
+
+// One lexical scope. Lookups that miss fall through to the enclosing scope.
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: std::collections::HashMap<String, Value>,
+    enclosing: Option<std::rc::Rc<std::cell::RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Option<std::rc::Rc<std::cell::RefCell<Environment>>>) -> Self {
+        Environment {
+            values: std::collections::HashMap::new(),
+            enclosing,
+        }
+    }
+
+    // Declaring a name again in the same scope replaces it
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    // Fails when no enclosing scope declares the name
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = value;
+            return true;
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => false,
+        }
+    }
+
+    // Reads from the scope `distance` levels out, as computed by the resolver
+    pub fn get_at(&self, distance: usize, name: &str) -> Option<Value> {
+        if distance == 0 {
+            return self.values.get(name).cloned();
+        }
+        self.enclosing.as_ref()?.borrow().get_at(distance - 1, name)
+    }
+
+    pub fn assign_at(&mut self, distance: usize, name: &str, value: Value) -> bool {
+        if distance == 0 {
+            return match self.values.get_mut(name) {
+                Some(slot) => {
+                    *slot = value;
+                    true
+                }
+                None => false,
+            };
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(distance - 1, name, value),
+            None => false,
+        }
+    }
+}
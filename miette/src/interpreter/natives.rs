@@ -0,0 +1,343 @@
+use super::ValueRepr;
+use super::value::{NativeFn, Value};
+use crate::map::Map;
+use crate::stdlib::{self, Rng};
+
+type Builtin = fn(&[Value]) -> Result<Value, String>;
+
+// Built-in functions defined in the global scope of every program
+const NATIVES: &[(&str, usize, Builtin)] = &[
+    ("clock", 0, clock),
+    ("len", 1, len),
+    ("push", 2, push),
+    ("pop", 1, pop),
+    ("insert", 3, insert),
+    ("remove", 2, remove),
+    ("keys", 1, keys),
+    ("values", 1, values),
+    ("contains", 2, contains),
+    ("delete", 2, delete),
+    ("str", 1, str),
+    ("num", 1, num),
+    ("type", 1, type_of),
+    ("input", 0, input),
+    ("sqrt", 1, sqrt),
+    ("floor", 1, floor),
+    ("pow", 2, pow),
+    ("split", 2, split),
+    ("trim", 1, trim),
+    ("upper", 1, upper),
+    ("lower", 1, lower),
+    ("replace", 3, replace),
+    ("substr", 3, substr),
+    ("assert", 1, assert),
+    ("assert_eq", 2, assert_eq),
+    (stdlib::ASSERT_THROWS, 1, assert_throws),
+    ("error", 1, error),
+];
+
+// The table above plus `random` and `seed`, which share a generator
+pub fn natives() -> Vec<(&'static str, usize, NativeFn)> {
+    let mut natives: Vec<(&'static str, usize, NativeFn)> = NATIVES
+        .iter()
+        .map(|(name, arity, function)| (*name, *arity, std::rc::Rc::new(*function) as NativeFn))
+        .collect();
+
+    let rng: std::rc::Rc<std::cell::Cell<Rng>> =
+        std::rc::Rc::new(std::cell::Cell::new(Rng::from_clock()));
+    let state: std::rc::Rc<std::cell::Cell<Rng>> = rng.clone();
+    natives.push((
+        "random",
+        0,
+        std::rc::Rc::new(move |_args: &[Value]| {
+            let mut generator: Rng = state.get();
+            let n: f64 = generator.next_f64();
+            state.set(generator);
+            Ok(Value::Number(n))
+        }),
+    ));
+    natives.push((
+        "seed",
+        1,
+        std::rc::Rc::new(move |args: &[Value]| {
+            rng.set(stdlib::seed(expect_number(&args[0], "seed")?)?);
+            Ok(Value::Nil)
+        }),
+    ));
+    natives
+}
+
+fn clock(_args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(stdlib::clock()?))
+}
+
+fn len(args: &[Value]) -> Result<Value, String> {
+    let len: usize = match &args[0] {
+        Value::Text(s) => s.chars().count(),
+        Value::List(list) => list.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        Value::Range(range) => range.len(),
+        other => return Err(format!("Can't take the length of a {}.", other.type_name())),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+fn push(args: &[Value]) -> Result<Value, String> {
+    let list = expect_list(&args[0], "push")?;
+    list.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+fn pop(args: &[Value]) -> Result<Value, String> {
+    let list = expect_list(&args[0], "pop")?;
+    let value: Value = list.borrow_mut().pop().map_err(|e| e.to_string())?;
+    Ok(value)
+}
+
+fn insert(args: &[Value]) -> Result<Value, String> {
+    let list = expect_list(&args[0], "insert")?;
+    let index: f64 = expect_index(&args[1], "insert")?;
+    list.borrow_mut()
+        .insert(index, args[2].clone())
+        .map_err(|e| e.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn remove(args: &[Value]) -> Result<Value, String> {
+    let list = expect_list(&args[0], "remove")?;
+    let index: f64 = expect_index(&args[1], "remove")?;
+    let value: Value = list.borrow_mut().remove(index).map_err(|e| e.to_string())?;
+    Ok(value)
+}
+
+fn keys(args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0], "keys")?;
+    let keys: Vec<Value> = map.borrow().keys().map(Value::from_key).collect();
+    Ok(Value::list(keys))
+}
+
+fn values(args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0], "values")?;
+    let values: Vec<Value> = map.borrow().values().cloned().collect();
+    Ok(Value::list(values))
+}
+
+// Substrings of a string, elements of a list or keys of a map
+fn contains(args: &[Value]) -> Result<Value, String> {
+    let found: bool = match &args[0] {
+        Value::Text(s) => s.contains(&*expect_text(&args[1], "contains")?),
+        Value::List(list) => list
+            .borrow()
+            .items()
+            .iter()
+            .any(|item| item.equals(&args[1])),
+        Value::Map(map) => {
+            let key = args[1].to_key().map_err(|e| e.to_string())?;
+            map.borrow().contains(&key)
+        }
+        other => {
+            return Err(stdlib::expected(
+                "contains",
+                "a string, list or map",
+                other.type_name(),
+            ));
+        }
+    };
+    Ok(Value::Bool(found))
+}
+
+// Returns the removed value, or nil when the key was not there
+fn delete(args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0], "delete")?;
+    let key = args[1].to_key().map_err(|e| e.to_string())?;
+    let removed: Option<Value> = map.borrow_mut().remove(&key);
+    Ok(removed.unwrap_or(Value::Nil))
+}
+
+fn str(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(&args[0].to_string()))
+}
+
+// nil when the string isn't a number
+fn num(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(n) => Ok(Value::Number(*n)),
+        Value::Text(s) => Ok(stdlib::parse_number(s).map_or(Value::Nil, Value::Number)),
+        other => Err(stdlib::expected(
+            "num",
+            "a string or number",
+            other.type_name(),
+        )),
+    }
+}
+
+fn type_of(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(args[0].type_name()))
+}
+
+fn input(_args: &[Value]) -> Result<Value, String> {
+    Ok(stdlib::input()?.map_or(Value::Nil, |line| Value::text(&line)))
+}
+
+fn sqrt(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(stdlib::sqrt(expect_number(
+        &args[0], "sqrt",
+    )?)?))
+}
+
+fn floor(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(expect_number(&args[0], "floor")?.floor()))
+}
+
+fn pow(args: &[Value]) -> Result<Value, String> {
+    let base: f64 = expect_number(&args[0], "pow")?;
+    let exponent: f64 = expect_number(&args[1], "pow")?;
+    Ok(Value::Number(base.powf(exponent)))
+}
+
+fn split(args: &[Value]) -> Result<Value, String> {
+    let s = expect_text(&args[0], "split")?;
+    let separator = expect_text(&args[1], "split")?;
+    let parts: Vec<Value> = stdlib::split(&s, &separator)
+        .iter()
+        .map(|part| Value::text(part))
+        .collect();
+    Ok(Value::list(parts))
+}
+
+fn trim(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(expect_text(&args[0], "trim")?.trim()))
+}
+
+fn upper(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(&expect_text(&args[0], "upper")?.to_uppercase()))
+}
+
+fn lower(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(&expect_text(&args[0], "lower")?.to_lowercase()))
+}
+
+fn replace(args: &[Value]) -> Result<Value, String> {
+    let s = expect_text(&args[0], "replace")?;
+    let from = expect_text(&args[1], "replace")?;
+    let to = expect_text(&args[2], "replace")?;
+    Ok(Value::text(&stdlib::replace(&s, &from, &to)?))
+}
+
+fn substr(args: &[Value]) -> Result<Value, String> {
+    let s = expect_text(&args[0], "substr")?;
+    let start: f64 = expect_number(&args[1], "substr")?;
+    let length: f64 = expect_number(&args[2], "substr")?;
+    Ok(Value::text(&stdlib::substr(&s, start, length)?))
+}
+
+fn assert(args: &[Value]) -> Result<Value, String> {
+    if !args[0].is_truthy() {
+        return Err(stdlib::assert_failed(&ValueRepr(&args[0]).to_string()));
+    }
+    Ok(Value::Nil)
+}
+
+fn assert_eq(args: &[Value]) -> Result<Value, String> {
+    let (actual, expected) = (&args[0], &args[1]);
+    if !same(actual, expected, &mut Vec::new()) {
+        return Err(stdlib::assert_eq_failed(
+            &ValueRepr(actual).to_string(),
+            &ValueRepr(expected).to_string(),
+        ));
+    }
+    Ok(Value::Nil)
+}
+
+fn assert_throws(_args: &[Value]) -> Result<Value, String> {
+    unreachable!("the interpreter runs assert_throws() in its call path")
+}
+
+fn error(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::error(stdlib::error(&expect_text(
+        &args[0], "error",
+    )?)))
+}
+
+// Equality that looks inside lists and maps, where `==` compares identity.
+// Pairs already being compared count as equal, so cycles end.
+fn same(a: &Value, b: &Value, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+    match (a, b) {
+        (Value::List(a), Value::List(b)) => {
+            let pair: (*const (), *const ()) = (
+                std::rc::Rc::as_ptr(a) as *const (),
+                std::rc::Rc::as_ptr(b) as *const (),
+            );
+            if std::rc::Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+                return true;
+            }
+            comparing.push(pair);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal: bool = a.len() == b.len()
+                && a.items()
+                    .iter()
+                    .zip(b.items())
+                    .all(|(a, b)| same(a, b, comparing));
+            comparing.pop();
+            equal
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            let pair: (*const (), *const ()) = (
+                std::rc::Rc::as_ptr(a) as *const (),
+                std::rc::Rc::as_ptr(b) as *const (),
+            );
+            if std::rc::Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+                return true;
+            }
+            comparing.push(pair);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal: bool = a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same(a, b, comparing)));
+            comparing.pop();
+            equal
+        }
+        _ => a.equals(b),
+    }
+}
+
+fn expect_list(
+    value: &Value,
+    name: &str,
+) -> Result<std::rc::Rc<std::cell::RefCell<crate::list::List<Value>>>, String> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+        other => Err(stdlib::expected(name, "a list", other.type_name())),
+    }
+}
+
+fn expect_map(
+    value: &Value,
+    name: &str,
+) -> Result<std::rc::Rc<std::cell::RefCell<Map<Value>>>, String> {
+    match value {
+        Value::Map(map) => Ok(map.clone()),
+        other => Err(stdlib::expected(name, "a map", other.type_name())),
+    }
+}
+
+fn expect_text(value: &Value, name: &str) -> Result<std::rc::Rc<str>, String> {
+    match value {
+        Value::Text(s) => Ok(s.clone()),
+        other => Err(stdlib::expected(name, "a string", other.type_name())),
+    }
+}
+
+fn expect_number(value: &Value, name: &str) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        other => Err(stdlib::expected(name, "a number", other.type_name())),
+    }
+}
+
+fn expect_index(value: &Value, name: &str) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        other => Err(stdlib::expected(name, "a number index", other.type_name())),
+    }
+}
@@ -0,0 +1,269 @@
+use super::environment::Environment;
+use crate::ast::Function;
+use crate::error::RuntimeError;
+use crate::list::List;
+use crate::map::{Map, MapError, MapKey};
+use crate::range::Range;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Text(std::rc::Rc<str>),
+    List(std::rc::Rc<std::cell::RefCell<List<Value>>>),
+    Map(std::rc::Rc<std::cell::RefCell<Map<Value>>>),
+    Range(Range),
+    Function(std::rc::Rc<Closure>),
+    Native(std::rc::Rc<NativeFunction>),
+    Class(std::rc::Rc<Class>),
+    Instance(std::rc::Rc<std::cell::RefCell<Instance>>),
+    Module(std::rc::Rc<Module>),
+    // A caught runtime error or one made by `error()`, which `throw` fills
+    // in with where it was thrown
+    Error(std::rc::Rc<std::cell::RefCell<RuntimeError>>),
+}
+
+impl Value {
+    pub fn text(s: &str) -> Value {
+        Value::Text(std::rc::Rc::from(s))
+    }
+
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::List(std::rc::Rc::new(std::cell::RefCell::new(List::new(items))))
+    }
+
+    pub fn map(map: Map<Value>) -> Value {
+        Value::Map(std::rc::Rc::new(std::cell::RefCell::new(map)))
+    }
+
+    pub fn error(error: RuntimeError) -> Value {
+        Value::Error(std::rc::Rc::new(std::cell::RefCell::new(error)))
+    }
+
+    // nil and false are falsey, everything else is truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Text(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::Module(_) => "module",
+            Value::Error(_) => "error",
+        }
+    }
+
+    pub fn to_key(&self) -> Result<MapKey, MapError> {
+        match self {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Number(n) => MapKey::number(*n),
+            Value::Text(s) => Ok(MapKey::Text(s.to_string())),
+            other => Err(MapError::Unhashable(other.type_name().to_string())),
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> Value {
+        match key {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Number(_) => Value::Number(key.as_number().unwrap_or_default()),
+            MapKey::Text(s) => Value::text(s),
+        }
+    }
+
+    // Value types compare by value, objects by identity
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Text(a), Value::Text(b)) => a == b,
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::List(a), Value::List(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => std::rc::Rc::ptr_eq(a, b),
+            (Value::Error(a), Value::Error(b)) => std::rc::Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    // Strings nested in containers are quoted so `["1"]` and `[1]` differ
+    fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        nested: bool,
+        seen: &mut Vec<*const ()>,
+    ) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) if nested => write!(f, "{:?}", s),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Range(range) => write!(f, "{}", range),
+            Value::List(list) => {
+                let pointer: *const () = std::rc::Rc::as_ptr(list) as *const ();
+                if seen.contains(&pointer) {
+                    return write!(f, "[...]");
+                }
+                seen.push(pointer);
+                write!(f, "[")?;
+                for (i, item) in list.borrow().items().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.write(f, true, seen)?;
+                }
+                seen.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let pointer: *const () = std::rc::Rc::as_ptr(map) as *const ();
+                if seen.contains(&pointer) {
+                    return write!(f, "{{...}}");
+                }
+                seen.push(pointer);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from_key(key).write(f, true, seen)?;
+                    write!(f, ": ")?;
+                    value.write(f, true, seen)?;
+                }
+                seen.pop();
+                write!(f, "}}")
+            }
+            Value::Function(closure) => write!(f, "<fn {}>", closure.name()),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Error(error) => {
+                let error: std::cell::Ref<RuntimeError> = error.borrow();
+                write!(f, "{}: {}", error.kind, error.message)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, false, &mut Vec::new())
+    }
+}
+
+// A user function together with the scope it was declared in
+#[derive(Debug)]
+pub struct Closure {
+    pub declaration: std::rc::Rc<Function>,
+    pub closure: std::rc::Rc<std::cell::RefCell<Environment>>,
+    // The globals of the module declaring the function, which it keeps
+    // using when called from another module
+    pub globals: std::rc::Rc<std::cell::RefCell<Environment>>,
+    // `init` methods always hand back the instance
+    pub is_initializer: bool,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        match &self.declaration.name {
+            Some(name) => name.lexeme(),
+            None => "lambda",
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    // A copy of a method whose scope has `this` bound to the instance
+    pub fn bind(&self, instance: Value) -> Closure {
+        let mut environment: Environment = Environment::new(Some(self.closure.clone()));
+        environment.define("this", instance);
+
+        Closure {
+            declaration: self.declaration.clone(),
+            closure: std::rc::Rc::new(std::cell::RefCell::new(environment)),
+            globals: self.globals.clone(),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+// Any Rust closure can be a native, so built-ins can keep state between calls
+pub type NativeFn = std::rc::Rc<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<std::rc::Rc<Class>>,
+    pub methods: std::collections::HashMap<String, std::rc::Rc<Closure>>,
+}
+
+impl Class {
+    // Searches this class first, then up the superclass chain
+    pub fn find_method(&self, name: &str) -> Option<std::rc::Rc<Closure>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    // Calling a class runs `init`, so it takes the same arguments
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: std::rc::Rc<Class>,
+    pub fields: std::collections::HashMap<String, Value>,
+}
+
+// An imported file, whose exports are read from its globals when used so
+// they see later assignments
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub globals: std::rc::Rc<std::cell::RefCell<Environment>>,
+    pub exports: Vec<String>,
+}
+
+impl Module {
+    pub fn get(&self, name: &str) -> Option<Value> {
+        if !self.exports.iter().any(|export| export == name) {
+            return None;
+        }
+        self.globals.borrow().get(name)
+    }
+}
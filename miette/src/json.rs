@@ -0,0 +1,94 @@
+// JSON output for tools that consume tokens or syntax trees without linking
+// against this crate.
+//
+// Every document is an object with a `schema_version` and a `kind` of
+// "tokens", "ast", "diagnostics" or "runtime_error". Tokens carry `id`, `kind`, `lexeme`,
+// `line` and a byte `span` ({"start", "end"}); a token kind is
+// {"type": "Identifier", "value": "x"} or {"type": "LeftParen"}. Statements
+// and expressions are objects tagged by their `type` with one field per child
+// using the names in `ast`. SCHEMA_VERSION is bumped whenever an existing
+// field changes meaning or disappears; new node types and fields do not bump it.
+
+use crate::ast::Stmt;
+use crate::error::{Diagnostic, RuntimeError};
+use crate::lex::Token;
+use crate::lint::Warning;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(serde::Serialize)]
+struct Document<'a, T: serde::Serialize + ?Sized> {
+    schema_version: u32,
+    kind: &'static str,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+#[derive(serde::Serialize)]
+struct TokensBody<'a> {
+    tokens: &'a std::collections::VecDeque<Token>,
+}
+
+#[derive(serde::Serialize)]
+struct AstBody<'a> {
+    statements: &'a [Stmt],
+}
+
+#[derive(serde::Serialize)]
+struct DiagnosticsBody<'a> {
+    diagnostics: &'a [Diagnostic],
+    #[serde(skip_serializing_if = "<[Warning]>::is_empty")]
+    warnings: &'a [Warning],
+    // Errors left out because of a reporting limit
+    #[serde(skip_serializing_if = "is_zero")]
+    omitted: usize,
+}
+
+#[derive(serde::Serialize)]
+struct RuntimeErrorBody<'a> {
+    error: &'a RuntimeError,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn document<T: serde::Serialize + ?Sized>(kind: &'static str, body: &T) -> String {
+    let document: Document<'_, T> = Document {
+        schema_version: SCHEMA_VERSION,
+        kind,
+        body,
+    };
+
+    // Only fails for maps with non string keys, which none of these types have
+    serde_json::to_string_pretty(&document).expect("syntax trees always serialize")
+}
+
+pub fn tokens_to_json(tokens: &std::collections::VecDeque<Token>) -> String {
+    document("tokens", &TokensBody { tokens })
+}
+
+pub fn ast_to_json(statements: &[Stmt]) -> String {
+    document("ast", &AstBody { statements })
+}
+
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> String {
+    report_to_json(diagnostics, &[], 0)
+}
+
+// Diagnostics along with the warnings from the same run, and how many more
+// errors there were than `diagnostics` holds
+pub fn report_to_json(diagnostics: &[Diagnostic], warnings: &[Warning], omitted: usize) -> String {
+    document(
+        "diagnostics",
+        &DiagnosticsBody {
+            diagnostics,
+            warnings,
+            omitted,
+        },
+    )
+}
+
+pub fn runtime_error_to_json(error: &RuntimeError) -> String {
+    document("runtime_error", &RuntimeErrorBody { error })
+}
@@ -1,4 +1,4 @@
-use crate::error::MietteError;
+use crate::error::{Diagnostic, MietteError};
 
 pub struct Tokens {
     tokens: std::collections::VecDeque<Token>,
@@ -6,6 +6,12 @@ pub struct Tokens {
     current_line: usize,
     current_token: Option<char>,
     token_count: usize,
+    // Byte offset of the next unread char
+    offset: usize,
+    // Where the token currently being scanned started
+    start: usize,
+    start_line: usize,
+    comments: Vec<Comment>,
 }
 
 impl Tokens {
@@ -19,27 +25,33 @@ impl Tokens {
             }
         };
 
-        Ok(Tokens {
+        Ok(Tokens::from_source(&contents))
+    }
+
+    pub fn from_source(source: &str) -> Tokens {
+        Tokens {
             tokens: std::collections::VecDeque::new(),
-            contents: contents.chars().collect(),
-            current_line: 0,
+            contents: source.chars().collect(),
+            current_line: 1,
             current_token: None,
             token_count: 0,
-        })
+            offset: 0,
+            start: 0,
+            start_line: 1,
+            comments: Vec::new(),
+        }
     }
 
     pub fn advance(&mut self) -> Option<char> {
         self.current_token = self.contents.pop_front();
 
-        if self.current_token.is_some() {
+        if let Some(c) = self.current_token {
             self.token_count += 1;
+            self.offset += c.len_utf8();
 
-            if self.current_token.unwrap() == '\n' {
+            if c == '\n' {
                 self.current_line += 1;
-                self.advance();
             }
-
-            return self.current_token;
         }
 
         self.current_token
@@ -49,33 +61,117 @@ impl Tokens {
         let temp: Option<&char> = self.contents.front();
         temp
     }
+
+    pub fn peek_next(&self) -> Option<&char> {
+        self.contents.get(1)
+    }
+
+    // Consumes the next char only if it is the expected one
+    pub fn match_next(&mut self, expected: char) -> bool {
+        if self.contents.front() == Some(&expected) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn add_token(&mut self, kind: TokenKind, lexeme: String) {
+        let span: Span = Span::new(self.start, self.offset);
+        Token::add_token(&mut self.tokens, kind, lexeme, self.start_line, span);
+    }
+
+    // A Diagnostic so callers can downcast and report it like parse errors
+    fn error(&self, message: &str) -> Box<dyn std::error::Error> {
+        Box::new(Diagnostic {
+            message: message.to_string(),
+            line: self.current_line,
+            span: Span::new(self.start, self.offset),
+            location: String::new(),
+        })
+    }
+}
+
+// A `//` comment. The parser never sees these, they are kept for tools that
+// care about them: lint suppressions and the formatter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Comment {
+    // Text after the slashes, untrimmed
+    pub text: String,
+    pub line: usize,
+    pub span: Span,
+    // Whether code comes before it on the same line
+    pub trailing: bool,
+}
+
+// Byte range of a token in the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    // Smallest span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub struct Token {
     id: usize,
     kind: TokenKind,
     lexeme: String,
     line: usize,
+    span: Span,
 }
 
 impl Token {
-    pub fn new(id: usize, kind: TokenKind, lexeme: String, line: usize) -> Self {
+    pub fn new(id: usize, kind: TokenKind, lexeme: String, line: usize, span: Span) -> Self {
         Token {
             id,
             kind,
             lexeme,
             line,
+            span,
         }
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn add_token(
         tokens: &mut std::collections::VecDeque<Token>,
         kind: TokenKind,
         lexeme: String,
         line: usize,
+        span: Span,
     ) {
-        tokens.push_back(Token::new(tokens.len(), kind, lexeme, line))
+        tokens.push_back(Token::new(tokens.len(), kind, lexeme, line, span))
     }
 }
 
@@ -93,408 +189,330 @@ impl std::fmt::Display for Token {
 pub fn scan_tokens(
     file_name: String,
 ) -> Result<std::collections::VecDeque<Token>, Box<dyn std::error::Error>> {
-    let mut tokens: Tokens = Tokens::new(file_name)?;
+    let tokens: Tokens = Tokens::new(file_name)?;
+    scan(tokens)
+}
 
-    // Collects chars to be processes as a single token lexeme
-    let mut buffer: std::collections::VecDeque<char> = std::collections::VecDeque::new();
+// Same as scan_tokens but for source that is already in memory
+pub fn scan_source(
+    source: &str,
+) -> Result<std::collections::VecDeque<Token>, Box<dyn std::error::Error>> {
+    scan(Tokens::from_source(source))
+}
+
+// Same as scan_source but also returns the comments, in source order
+pub fn scan_source_with_comments(source: &str) -> Result<Scanned, Box<dyn std::error::Error>> {
+    scan_with_comments(Tokens::from_source(source))
+}
 
-    // Flag if currently iterating through a string "....."
-    let mut is_text: bool = false;
-    let mut is_number: bool = false;
-    let mut is_identifier: bool = false;
+const UNTERMINATED_STRING: &str = "Unterminated string.";
+const UNTERMINATED_ESCAPE: &str = "Expected character after escape character \\ but got nothing.";
+
+// Whether the source stops partway through something, so a REPL should read
+// another line before running it: an unclosed (, { or [, or an open string.
+// Other mistakes are complete input for the parser to report.
+pub fn is_incomplete(source: &str) -> bool {
+    let tokens: std::collections::VecDeque<Token> = match scan_source(source) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return e.downcast_ref::<Diagnostic>().is_some_and(|diagnostic| {
+                diagnostic.message == UNTERMINATED_STRING
+                    || diagnostic.message == UNTERMINATED_ESCAPE
+            });
+        }
+    };
+
+    let mut parens: isize = 0;
+    let mut brackets: isize = 0;
+    let mut square: isize = 0;
+    for token in tokens.iter() {
+        match token.kind() {
+            TokenKind::LeftParen => parens += 1,
+            TokenKind::RightParen => parens -= 1,
+            TokenKind::LeftBracket => brackets += 1,
+            TokenKind::RightBracket => brackets -= 1,
+            TokenKind::LeftSBracket => square += 1,
+            TokenKind::RightSBracket => square -= 1,
+            _ => {}
+        }
+    }
+    parens > 0 || brackets > 0 || square > 0
+}
+
+pub type Scanned = (std::collections::VecDeque<Token>, Vec<Comment>);
+
+fn scan(tokens: Tokens) -> Result<std::collections::VecDeque<Token>, Box<dyn std::error::Error>> {
+    let (tokens, _comments) = scan_with_comments(tokens)?;
+    Ok(tokens)
+}
+
+fn scan_with_comments(mut tokens: Tokens) -> Result<Scanned, Box<dyn std::error::Error>> {
+    // Collects chars to be processes as a single token lexeme
+    let mut buffer: String = String::new();
 
     while let Some(current) = tokens.advance() {
+        tokens.start = tokens.offset - current.len_utf8();
+        tokens.start_line = tokens.current_line;
+
         match current {
             '"' => {
-                if is_text {
-                    // end quote
-                    continue;
-                } else {
-                    // start quote
-                    is_text = true;
-                    buffer = std::collections::VecDeque::new();
-                    while let Some(peek) = tokens.peek() {
-                        // If not the closing " continue
-                        if *peek != '"' || *peek != '\\' {
-                            buffer.push_back(tokens.advance().unwrap());
-                        } else if *peek == '\\' {
-                            // ignore the escape char
-                            tokens.advance();
-                            let string_current = match tokens.advance() {
+                // Text literal, the lexeme keeps the quotes and escapes as written
+                let mut lexeme: String = String::from('"');
+                buffer.clear();
+                loop {
+                    let string_current: char = match tokens.advance() {
+                        Some(c) => c,
+                        None => return Err(tokens.error(UNTERMINATED_STRING)),
+                    };
+                    lexeme.push(string_current);
+
+                    match string_current {
+                        '"' => break,
+                        '\\' => {
+                            let escaped: char = match tokens.advance() {
                                 Some(c) => c,
                                 None => {
-                                    return Err(Box::new(MietteError::new("Tokenize Error: Expected token after escape character \\ but got nothing".to_string())));
+                                    return Err(tokens.error(UNTERMINATED_ESCAPE));
                                 }
                             };
-                            buffer.push_back(string_current);
-                        } else {
-                            // closing "
-                            is_text = false;
-                            let temp: String = buffer.clone().into_iter().collect();
-                            Token::add_token(
-                                &mut tokens.tokens,
-                                TokenKind::Text(temp.clone()),
-                                temp,
-                                tokens.current_line,
-                            );
-                            buffer.clear();
-                            break;
+                            lexeme.push(escaped);
+                            buffer.push(match escaped {
+                                'n' => '\n',
+                                't' => '\t',
+                                'r' => '\r',
+                                '0' => '\0',
+                                '"' => '"',
+                                '\\' => '\\',
+                                other => {
+                                    return Err(tokens.error(&format!(
+                                        "Unknown escape sequence '\\{}'.",
+                                        other
+                                    )));
+                                }
+                            });
                         }
+                        c => buffer.push(c),
                     }
-                    continue;
                 }
-            }
+                tokens.add_token(TokenKind::Text(buffer.clone()), lexeme);
+            } // end text
             '=' => {
-                let peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Equals with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after equals but got nothing".to_string(),
-                        )));
-                    }
-                };
-
-                if *peek == '=' {
+                if tokens.match_next('=') {
                     // Double equals '=='
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::EqualEqual,
-                        "==".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::EqualEqual, "==".to_string());
                 } else {
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::Equal,
-                        "=".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::Equal, "=".to_string());
                 }
             } // end equal '='
             '>' => {
-                let peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Gtreater with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after greater than token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-                if *peek == '=' {
+                if tokens.match_next('=') {
                     // Greater equals '>='
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::GreaterEqual,
-                        ">=".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::GreaterEqual, ">=".to_string());
                 } else {
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::Greater,
-                        ">".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::Greater, ">".to_string());
                 }
             } // end greater '>'
             '<' => {
-                let peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Less than with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after Less Than token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-                if *peek == '=' {
+                if tokens.match_next('=') {
                     // Less than equals '<='
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::LessEqual,
-                        "<=".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::LessEqual, "<=".to_string());
                 } else {
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::Less,
-                        "<".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::Less, "<".to_string());
                 }
             } // end less '<'
             '!' => {
-                let peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Bang than with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after bang token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-                if *peek == '=' {
-                    // bang than equals '!='
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::BangEqual,
-                        "!=".to_string(),
-                        tokens.current_line,
-                    );
+                if tokens.match_next('=') {
+                    // bang equals '!='
+                    tokens.add_token(TokenKind::BangEqual, "!=".to_string());
                 } else {
-                    Token::add_token(
-                        &mut tokens.tokens,
-                        TokenKind::Bang,
-                        "!".to_string(),
-                        tokens.current_line,
-                    );
+                    tokens.add_token(TokenKind::Bang, "!".to_string());
                 }
             } // end bang '!'
-            '\n' => {
-                let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // new line than with nothing after
-                        Token::add_token(
-                            &mut tokens.tokens,
-                            TokenKind::EOF,
-                            "EOF".to_string(),
-                            tokens.current_line,
-                        );
-                        continue;
-                    }
-                };
+            ' ' | '\r' | '\t' | '\n' => {
+                // Whitespace only separates tokens, advance already counts lines
                 continue;
-            } // end new line '\n'
-            c if c.is_ascii_digit() || c == '.' => {
-                if is_number {
-                } else {
-                    if is_text {
-                        // Part of a user string
+            } // end whitespace
+            c if c.is_ascii_digit() => {
+                buffer.clear();
+                buffer.push(c);
+                while let Some(peek) = tokens.peek() {
+                    if peek.is_ascii_digit() {
+                        buffer.push(tokens.advance().unwrap());
                     } else {
-                        // Can either be part of an identifier or number
-                        if is_identifier {
-                            buffer.push_back(c);
-                            continue;
+                        break;
+                    }
+                }
+
+                // A fraction needs a digit after the dot so `1.` stays a number then a dot
+                let has_fraction: bool = tokens.peek() == Some(&'.')
+                    && tokens.peek_next().is_some_and(|c| c.is_ascii_digit());
+                if has_fraction {
+                    buffer.push(tokens.advance().unwrap());
+                    while let Some(peek) = tokens.peek() {
+                        if peek.is_ascii_digit() {
+                            buffer.push(tokens.advance().unwrap());
                         } else {
-                            // Probably start of a number
+                            break;
                         }
                     }
                 }
-            } // end si digit
-            '{' => {
-                    let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Bracket with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after left bracket '{' token but got nothing"
-                                .to_string(),
-                        )));
-                    }
+
+                let value: f64 = match buffer.parse::<f64>() {
+                    Ok(n) => n,
+                    Err(_) => return Err(tokens.error(&format!("Invalid number '{}'.", buffer))),
                 };
+                tokens.add_token(TokenKind::Number(value), buffer.clone());
+            } // end number
+            c if c.is_alphabetic() || c == '_' => {
+                buffer.clear();
+                buffer.push(c);
+                while let Some(peek) = tokens.peek() {
+                    if peek.is_alphanumeric() || *peek == '_' {
+                        buffer.push(tokens.advance().unwrap());
+                    } else {
+                        break;
+                    }
+                }
 
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::LeftBracket,
-                    "{{".to_string(),
-                    tokens.current_line,
-                );
- 
-            }
+                let kind: TokenKind = match keyword(&buffer) {
+                    Some(kind) => kind,
+                    None => TokenKind::Identifier(buffer.clone()),
+                };
+                tokens.add_token(kind, buffer.clone());
+            } // end identifier
+            '{' => {
+                tokens.add_token(TokenKind::LeftBracket, "{".to_string());
+            } // end left bracket
             '}' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::RightBracket,
-                    "}}".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::RightBracket, "}".to_string());
             } // end right bracket
             '[' => {
-                    let _peek: &char = match tokens.peek() {
-                        Some(c) => c,
-                        None => {
-                            // Square Bracket with nothing after
-                            return Err(Box::new(MietteError::new(
-                                "Lexer Error: Expected token after left square bracket '[' token but got nothing"
-                                    .to_string(),
-                            )));
-                        }
-                    };
-
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::LeftSBracket,
-                    "[".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::LeftSBracket, "[".to_string());
             } // end left square bracket
             ']' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::RightSBracket,
-                    "]".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::RightSBracket, "]".to_string());
             } // end right square bracket
             '(' => {
-                    let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Parenthesis with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after left parenthesis '(' token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::LeftParen,
-                    "(".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::LeftParen, "(".to_string());
             } // end left parenthesis
             ')' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::RightParen,
-                    ")".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::RightParen, ")".to_string());
             } // end right parenthesis
             '+' => {
-                let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Plus with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after plus operator '+' token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Plus,
-                    "+".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::Plus, "+".to_string());
             } // end plus
             '-' => {
-                let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Minus with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after minus operator '-' token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Minus,
-                    "-".to_string(),
-                    tokens.current_line,
-                );
+                if tokens.match_next('>') {
+                    // Arrow '->' of a shorthand lambda
+                    tokens.add_token(TokenKind::Arrow, "->".to_string());
+                } else {
+                    tokens.add_token(TokenKind::Minus, "-".to_string());
+                }
             } // end minus
             '/' => {
-                let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // slash with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after slash operator '/' token but got nothing"
-                                .to_string(),
-                        )));
+                if tokens.match_next('/') {
+                    // Line comment, runs until the end of the line
+                    buffer.clear();
+                    while let Some(peek) = tokens.peek() {
+                        if *peek == '\n' {
+                            break;
+                        }
+                        buffer.push(*peek);
+                        tokens.advance();
                     }
-                };
-
 
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Slash,
-                    "/".to_string(),
-                    tokens.current_line,
-                );
+                    let trailing: bool = tokens
+                        .tokens
+                        .back()
+                        .is_some_and(|token| token.line() == tokens.start_line);
+                    tokens.comments.push(Comment {
+                        text: buffer.clone(),
+                        line: tokens.start_line,
+                        span: Span::new(tokens.start, tokens.offset),
+                        trailing,
+                    });
+                } else {
+                    tokens.add_token(TokenKind::Slash, "/".to_string());
+                }
             } // end slash
             '*' => {
-                let _peek: &char = match tokens.peek() {
-                    Some(c) => c,
-                    None => {
-                        // Star with nothing after
-                        return Err(Box::new(MietteError::new(
-                            "Lexer Error: Expected token after star operator '*' token but got nothing"
-                                .to_string(),
-                        )));
-                    }
-                };
-
-
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Star,
-                    ")".to_string(),
-                    tokens.current_line,
-                );
+                tokens.add_token(TokenKind::Star, "*".to_string());
             } // end star
             ',' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Comma,
-                    ",".to_string(),
-                    tokens.current_line,
-                );
-            } // end star
+                tokens.add_token(TokenKind::Comma, ",".to_string());
+            } // end comma
             ':' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Colon,
-                    ":".to_string(),
-                    tokens.current_line,
-                );
-            } // end star
+                tokens.add_token(TokenKind::Colon, ":".to_string());
+            } // end colon
+            '?' => {
+                // Nil-able type `T?`
+                tokens.add_token(TokenKind::Question, "?".to_string());
+            } // end question mark
+            '|' => {
+                // Union type `A | B`
+                tokens.add_token(TokenKind::Pipe, "|".to_string());
+            } // end pipe
             ';' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::SemiColon,
-                    ";".to_string(),
-                    tokens.current_line,
-                );
-            } // end semi colon 
+                tokens.add_token(TokenKind::SemiColon, ";".to_string());
+            } // end semi colon
             '.' => {
-                Token::add_token(
-                    &mut tokens.tokens,
-                    TokenKind::Dot,
-                    ".".to_string(),
-                    tokens.current_line,
-                );
-            } // end dot 
-
-            _ => {
-                continue;
+                if tokens.match_next('.') {
+                    if tokens.match_next('=') {
+                        // Inclusive range '..='
+                        tokens.add_token(TokenKind::DotDotEqual, "..=".to_string());
+                    } else {
+                        // Exclusive range '..'
+                        tokens.add_token(TokenKind::DotDot, "..".to_string());
+                    }
+                } else {
+                    tokens.add_token(TokenKind::Dot, ".".to_string());
+                }
+            } // end dot
+            c => {
+                return Err(tokens.error(&format!("Unexpected character '{}'.", c)));
             }
         };
     }
 
-    Ok(tokens.tokens)
+    tokens.start = tokens.offset;
+    tokens.start_line = tokens.current_line;
+    tokens.add_token(TokenKind::EOF, String::new());
+
+    Ok((tokens.tokens, tokens.comments))
+}
+
+fn keyword(text: &str) -> Option<TokenKind> {
+    match text {
+        "and" => Some(TokenKind::And),
+        "break" => Some(TokenKind::Break),
+        "catch" => Some(TokenKind::Catch),
+        "class" => Some(TokenKind::Class),
+        "continue" => Some(TokenKind::Continue),
+        "else" => Some(TokenKind::Else),
+        "false" => Some(TokenKind::False),
+        "finally" => Some(TokenKind::Finally),
+        "for" => Some(TokenKind::For),
+        "fun" => Some(TokenKind::Fun),
+        "if" => Some(TokenKind::If),
+        "in" => Some(TokenKind::In),
+        "nil" => Some(TokenKind::Nil),
+        "or" => Some(TokenKind::Or),
+        "print" => Some(TokenKind::Print),
+        "return" => Some(TokenKind::Return),
+        "super" => Some(TokenKind::Super),
+        "this" => Some(TokenKind::This),
+        "throw" => Some(TokenKind::Throw),
+        "true" => Some(TokenKind::True),
+        "try" => Some(TokenKind::Try),
+        "var" => Some(TokenKind::Var),
+        "while" => Some(TokenKind::While),
+        _ => None,
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type", content = "value"))]
 pub enum TokenKind {
     Identifier(String),
     Text(String),
@@ -514,6 +532,8 @@ pub enum TokenKind {
     Colon,
     SemiColon,
     Dot,
+    DotDot,
+    DotDotEqual,
     Bang,
     BangEqual,
     Equal,
@@ -522,6 +542,9 @@ pub enum TokenKind {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
+    Question,
+    Pipe,
 
     And,
     Continue,
@@ -533,6 +556,7 @@ pub enum TokenKind {
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -541,6 +565,10 @@ pub enum TokenKind {
     This,
     Var,
     While,
+    Throw,
+    Try,
+    Catch,
+    Finally,
 
     EOF,
 }
@@ -561,6 +589,9 @@ impl std::fmt::Display for TokenKind {
             TokenKind::BangEqual => write!(f, "!="),
             TokenKind::Less => write!(f, "<"),
             TokenKind::LessEqual => write!(f, "<="),
+            TokenKind::Arrow => write!(f, "->"),
+            TokenKind::Question => write!(f, "?"),
+            TokenKind::Pipe => write!(f, "|"),
             TokenKind::Greater => write!(f, ">"),
             TokenKind::GreaterEqual => write!(f, ">="),
             TokenKind::LeftParen => write!(f, "("),
@@ -573,9 +604,12 @@ impl std::fmt::Display for TokenKind {
             TokenKind::Colon => write!(f, ":"),
             TokenKind::SemiColon => write!(f, ";"),
             TokenKind::Dot => write!(f, "."),
+            TokenKind::DotDot => write!(f, ".."),
+            TokenKind::DotDotEqual => write!(f, "..="),
             TokenKind::And => write!(f, "and"),
             TokenKind::Or => write!(f, "or"),
             TokenKind::If => write!(f, "if"),
+            TokenKind::In => write!(f, "in"),
             TokenKind::Else => write!(f, "else"),
             TokenKind::Fun => write!(f, "fun"),
             TokenKind::Return => write!(f, "return"),
@@ -589,8 +623,12 @@ impl std::fmt::Display for TokenKind {
             TokenKind::True => write!(f, "true"),
             TokenKind::False => write!(f, "false"),
             TokenKind::Print => write!(f, "print"),
-            TokenKind::Break => write!(f, "continue"),
+            TokenKind::Break => write!(f, "break"),
             TokenKind::Continue => write!(f, "continue"),
+            TokenKind::Throw => write!(f, "throw"),
+            TokenKind::Try => write!(f, "try"),
+            TokenKind::Catch => write!(f, "catch"),
+            TokenKind::Finally => write!(f, "finally"),
             TokenKind::EOF => write!(f, "<EOF>"),
         }
     }
@@ -1,3 +1,29 @@
+pub mod ast;
+pub mod bytecode;
+pub mod checker;
+pub mod chunk;
+pub mod compiler;
+pub mod diff;
+pub mod disassembler;
 pub mod error;
+pub mod formatter;
+pub mod interpreter;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod lex;
+pub mod lint;
+pub mod list;
+#[cfg(feature = "json")]
+pub mod lsp;
+pub mod manifest;
+pub mod map;
+pub mod module;
 pub mod parser;
+pub mod project;
+pub mod range;
+pub mod repl;
+pub mod resolver;
+pub mod stdlib;
+pub mod test_runner;
+pub mod visit;
+pub mod vm;
@@ -0,0 +1,116 @@
+use crate::lex::{Comment, Span, Token, TokenKind};
+
+// Warnings the resolver can raise. Codes are stable, they are what users
+// write in `// miette: allow(code)` comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "snake_case"))]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    UnusedFunction,
+    UnreachableCode,
+    ShadowedName,
+    ConstantCondition,
+}
+
+pub const ALL_LINTS: &[Lint] = &[
+    Lint::UnusedVariable,
+    Lint::UnusedParameter,
+    Lint::UnusedFunction,
+    Lint::UnreachableCode,
+    Lint::ShadowedName,
+    Lint::ConstantCondition,
+];
+
+impl Lint {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused_variable",
+            Lint::UnusedParameter => "unused_parameter",
+            Lint::UnusedFunction => "unused_function",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::ShadowedName => "shadowed_name",
+            Lint::ConstantCondition => "constant_condition",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Lint> {
+        ALL_LINTS.iter().copied().find(|lint| lint.code() == code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: String,
+    pub line: usize,
+    pub span: Span,
+    // " at 'x'", same as Diagnostic
+    pub location: String,
+}
+
+impl Warning {
+    pub fn at(lint: Lint, token: &Token, message: &str) -> Warning {
+        let location: String = match token.kind() {
+            TokenKind::EOF => " at end".to_string(),
+            _ => format!(" at '{}'", token.lexeme()),
+        };
+
+        Warning {
+            lint,
+            message: message.to_string(),
+            line: token.line(),
+            span: token.span(),
+            location,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning{}: {} [{}]",
+            self.line,
+            self.location,
+            self.message,
+            self.lint.code()
+        )
+    }
+}
+
+// Drops warnings silenced by `// miette: allow(code, ...)`. A comment after
+// code covers its own line, a comment on a line by itself covers the next one.
+pub fn suppress(warnings: Vec<Warning>, comments: &[Comment]) -> Vec<Warning> {
+    let mut allowed: Vec<(usize, Lint)> = Vec::new();
+    for comment in comments {
+        let Some(codes) = comment
+            .text
+            .trim()
+            .strip_prefix("miette:")
+            .map(str::trim)
+            .and_then(|rest| rest.strip_prefix("allow("))
+            .and_then(|rest| rest.strip_suffix(')'))
+        else {
+            continue;
+        };
+
+        let line: usize = if comment.trailing {
+            comment.line
+        } else {
+            comment.line + 1
+        };
+        for code in codes.split(',') {
+            if let Some(lint) = Lint::from_code(code.trim()) {
+                allowed.push((line, lint));
+            }
+        }
+    }
+
+    warnings
+        .into_iter()
+        .filter(|warning| !allowed.contains(&(warning.line, warning.lint)))
+        .collect()
+}
@@ -0,0 +1,136 @@
+// The list object shared by every backend, so indexing rules are defined once.
+//
+// Indices are numbers with no fractional part. Negative indices count from the
+// end, -1 being the last element. Reading or writing outside the list is an
+// error; slices clamp their bounds to the list like most scripting languages
+// do, so `xs[1:100]` is everything after the first element.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListError {
+    NotAnInteger(f64),
+    OutOfRange { index: f64, len: usize },
+    Empty,
+}
+
+impl std::fmt::Display for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListError::NotAnInteger(index) => {
+                write!(f, "List index must be an integer, got {}.", index)
+            }
+            ListError::OutOfRange { index, len } => write!(
+                f,
+                "List index {} out of range for list of length {}.",
+                index, len
+            ),
+            ListError::Empty => write!(f, "Can't pop from an empty list."),
+        }
+    }
+}
+
+impl std::error::Error for ListError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct List<T> {
+    items: Vec<T>,
+}
+
+impl<T: Clone> List<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        List { items }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn get(&self, index: f64) -> Result<&T, ListError> {
+        let position: usize = self.position(index)?;
+        Ok(&self.items[position])
+    }
+
+    pub fn set(&mut self, index: f64, value: T) -> Result<(), ListError> {
+        let position: usize = self.position(index)?;
+        self.items[position] = value;
+        Ok(())
+    }
+
+    pub fn slice(&self, start: Option<f64>, end: Option<f64>) -> Result<List<T>, ListError> {
+        let start: usize = match start {
+            Some(start) => self.clamp(start)?,
+            None => 0,
+        };
+        let end: usize = match end {
+            Some(end) => self.clamp(end)?,
+            None => self.items.len(),
+        };
+
+        if start >= end {
+            return Ok(List::new(Vec::new()));
+        }
+        Ok(List::new(self.items[start..end].to_vec()))
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.items.push(value);
+    }
+
+    pub fn pop(&mut self) -> Result<T, ListError> {
+        self.items.pop().ok_or(ListError::Empty)
+    }
+
+    // Inserting at the length appends, negative positions count from the end
+    pub fn insert(&mut self, index: f64, value: T) -> Result<(), ListError> {
+        let position: usize = if index == self.items.len() as f64 {
+            self.items.len()
+        } else {
+            self.position(index)?
+        };
+        self.items.insert(position, value);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: f64) -> Result<T, ListError> {
+        let position: usize = self.position(index)?;
+        Ok(self.items.remove(position))
+    }
+
+    // Resolves an index that must land on an existing element
+    fn position(&self, index: f64) -> Result<usize, ListError> {
+        let offset: i64 = integer(index)?;
+        let len: i64 = self.items.len() as i64;
+        let position: i64 = if offset < 0 { len + offset } else { offset };
+
+        if position < 0 || position >= len {
+            return Err(ListError::OutOfRange {
+                index,
+                len: self.items.len(),
+            });
+        }
+        Ok(position as usize)
+    }
+
+    // Resolves a slice bound, anything past either end sticks to that end
+    fn clamp(&self, index: f64) -> Result<usize, ListError> {
+        let offset: i64 = integer(index)?;
+        let len: i64 = self.items.len() as i64;
+        let position: i64 = if offset < 0 { len + offset } else { offset };
+
+        Ok(position.clamp(0, len) as usize)
+    }
+}
+
+fn integer(index: f64) -> Result<i64, ListError> {
+    if index.fract() != 0.0 || !index.is_finite() {
+        return Err(ListError::NotAnInteger(index));
+    }
+    Ok(index as i64)
+}
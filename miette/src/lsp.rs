@@ -0,0 +1,715 @@
+// `miette lsp`: a Language Server Protocol server over stdin and stdout.
+//
+// Documents are synced whole on every change and re-analyzed with the same
+// front end as `miette check`, after which the server publishes their
+// diagnostics. Definitions, references and hovers come from the resolver's
+// record of which declaration each name refers to, so they only work in
+// documents that parse. Names after a `.` are looked up at runtime and have
+// no declaration to go to.
+mod document;
+pub mod rpc;
+
+use crate::ast::Stmt;
+use crate::formatter;
+use crate::lex::{Span, Token, TokenKind};
+use crate::resolver::{BindingKind, Reference};
+use crate::stdlib;
+use document::{Document, Position, Severity};
+
+// Semantic token types and modifiers, indexed by the numbers in the encoded
+// tokens
+const TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "string",
+    "number",
+    "operator",
+    "comment",
+    "variable",
+    "parameter",
+    "function",
+    "class",
+    "method",
+    "property",
+    "namespace",
+];
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "defaultLibrary"];
+
+// LSP's SymbolKind numbers
+const SYMBOL_CLASS: u32 = 5;
+const SYMBOL_METHOD: u32 = 6;
+const SYMBOL_FUNCTION: u32 = 12;
+
+// A result, or a JSON-RPC error code and message
+type Reply = Result<serde_json::Value, (i64, String)>;
+
+pub struct Server {
+    // By URI
+    documents: std::collections::HashMap<String, Document>,
+    initialized: bool,
+    shutdown: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            documents: std::collections::HashMap::new(),
+            initialized: false,
+            shutdown: false,
+        }
+    }
+
+    // Serves until the client sends `exit` or the input ends. True when the
+    // client asked for a shutdown first, as the exit code should report.
+    pub fn run(
+        &mut self,
+        input: &mut dyn std::io::BufRead,
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<bool> {
+        while let Some(body) = rpc::read_message(input)? {
+            let message: serde_json::Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error: serde_json::Value = rpc::error_response(
+                        serde_json::Value::Null,
+                        rpc::PARSE_ERROR,
+                        &e.to_string(),
+                    );
+                    rpc::write_message(output, &error)?;
+                    continue;
+                }
+            };
+            if message["method"] == "exit" {
+                return Ok(self.shutdown);
+            }
+            for reply in self.handle(&message) {
+                rpc::write_message(output, &reply)?;
+            }
+        }
+        Ok(false)
+    }
+
+    // What to send back for one message: a response for a request, any
+    // number of notifications for a notification
+    fn handle(&mut self, message: &serde_json::Value) -> Vec<serde_json::Value> {
+        let id: Option<serde_json::Value> = message.get("id").cloned();
+        let Some(method) = message["method"].as_str() else {
+            // The server sends no requests, so expects no responses either
+            return match id {
+                Some(id) if message.get("result").is_none() && message.get("error").is_none() => {
+                    vec![rpc::error_response(
+                        id,
+                        rpc::INVALID_REQUEST,
+                        "a request needs a method",
+                    )]
+                }
+                _ => Vec::new(),
+            };
+        };
+        let params: &serde_json::Value = &message["params"];
+
+        let Some(id) = id else {
+            return self.notify(method, params);
+        };
+        match self.request(method, params) {
+            Ok(result) => vec![rpc::response(id, result)],
+            Err((code, message)) => vec![rpc::error_response(id, code, &message)],
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &serde_json::Value) -> Reply {
+        if !self.initialized && method != "initialize" {
+            return Err((
+                rpc::SERVER_NOT_INITIALIZED,
+                "the server hasn't been initialized".to_string(),
+            ));
+        }
+        if self.shutdown {
+            return Err((
+                rpc::INVALID_REQUEST,
+                "the server is shutting down".to_string(),
+            ));
+        }
+
+        match method {
+            "initialize" => {
+                self.initialized = true;
+                Ok(capabilities())
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(serde_json::Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => Err((
+                rpc::METHOD_NOT_FOUND,
+                format!("unknown method '{}'", method),
+            )),
+        }
+    }
+
+    // Unknown notifications, and any before initialization, are dropped as
+    // the protocol asks
+    fn notify(&mut self, method: &str, params: &serde_json::Value) -> Vec<serde_json::Value> {
+        if !self.initialized {
+            return Vec::new();
+        }
+        let Some(uri) = params["textDocument"]["uri"].as_str() else {
+            return Vec::new();
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text: &str = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.to_string(), Document::new(text.to_string()));
+            }
+            // Full sync, so the last change holds the whole text
+            "textDocument/didChange" => {
+                let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return Vec::new();
+                };
+                self.documents
+                    .insert(uri.to_string(), Document::new(text.to_string()));
+            }
+            // Clears the closed document's diagnostics
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![rpc::notification(
+                    "textDocument/publishDiagnostics",
+                    serde_json::json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            _ => return Vec::new(),
+        }
+        vec![self.diagnostics(uri)]
+    }
+
+    fn diagnostics(&self, uri: &str) -> serde_json::Value {
+        let document: &Document = &self.documents[uri];
+        let diagnostics: Vec<serde_json::Value> = document
+            .problems
+            .iter()
+            .map(|problem| {
+                let mut diagnostic: serde_json::Value = serde_json::json!({
+                    "range": range(document, problem.span),
+                    "severity": match problem.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "source": "miette",
+                    "message": problem.message,
+                });
+                if let Some(lint) = problem.lint {
+                    diagnostic["code"] = lint.code().into();
+                }
+                diagnostic
+            })
+            .collect();
+        rpc::notification(
+            "textDocument/publishDiagnostics",
+            serde_json::json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    fn document<'a>(
+        &'a self,
+        params: &'a serde_json::Value,
+    ) -> Result<(&'a str, &'a Document), (i64, String)> {
+        let Some(uri) = params["textDocument"]["uri"].as_str() else {
+            return Err((rpc::INVALID_PARAMS, "missing textDocument.uri".to_string()));
+        };
+        match self.documents.get(uri) {
+            Some(document) => Ok((uri, document)),
+            None => Err((rpc::INVALID_PARAMS, format!("'{}' isn't open", uri))),
+        }
+    }
+
+    // The document and the byte offset of the position in the params
+    fn cursor<'a>(
+        &'a self,
+        params: &'a serde_json::Value,
+    ) -> Result<(&'a str, &'a Document, usize), (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let (Some(line), Some(character)) = (
+            params["position"]["line"].as_u64(),
+            params["position"]["character"].as_u64(),
+        ) else {
+            return Err((rpc::INVALID_PARAMS, "missing position".to_string()));
+        };
+        let offset: usize = document.offset(Position {
+            line: line as usize,
+            character: character as usize,
+        });
+        Ok((uri, document, offset))
+    }
+
+    fn definition(&self, params: &serde_json::Value) -> Reply {
+        let (uri, document, offset) = self.cursor(params)?;
+        let declaration: Option<&Token> = document
+            .reference_at(offset)
+            .and_then(|reference| reference.declaration.as_ref())
+            .map(|declaration| &declaration.name);
+        Ok(match declaration {
+            Some(name) => location(uri, document, name.span()),
+            None => serde_json::Value::Null,
+        })
+    }
+
+    // Uses of an undeclared global, usually a built-in, are matched by name
+    fn references(&self, params: &serde_json::Value) -> Reply {
+        let (uri, document, offset) = self.cursor(params)?;
+        let Some(target) = document.reference_at(offset) else {
+            return Ok(serde_json::Value::Null);
+        };
+        let include_declaration: bool = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+
+        let locations: Vec<serde_json::Value> = document
+            .references
+            .iter()
+            .filter(
+                |reference| match (&reference.declaration, &target.declaration) {
+                    (Some(declaration), Some(target)) => {
+                        declaration.name.span() == target.name.span()
+                            && (include_declaration || !is_declaration(reference))
+                    }
+                    (None, None) => reference.name.lexeme() == target.name.lexeme(),
+                    _ => false,
+                },
+            )
+            .map(|reference| location(uri, document, reference.name.span()))
+            .collect();
+        Ok(serde_json::Value::Array(locations))
+    }
+
+    // Shows the line the name was declared on
+    fn hover(&self, params: &serde_json::Value) -> Reply {
+        let (_, document, offset) = self.cursor(params)?;
+        let Some(reference) = document.reference_at(offset) else {
+            return Ok(serde_json::Value::Null);
+        };
+
+        let contents: String = match &reference.declaration {
+            Some(declaration) => {
+                let what: &str = match declaration.kind {
+                    BindingKind::Variable => "variable",
+                    BindingKind::Parameter => "parameter",
+                    BindingKind::Function => "function",
+                    BindingKind::Class => "class",
+                    BindingKind::Module => "module",
+                    BindingKind::Implicit => unreachable!("implicit names aren't references"),
+                };
+                format!(
+                    "```miette\n{}\n```\n{} declared on line {}",
+                    document.line_text(declaration.name.span().start).trim(),
+                    what,
+                    declaration.name.line()
+                )
+            }
+            None if stdlib::NAMES.contains(&reference.name.lexeme()) => {
+                format!("built-in function `{}`", reference.name.lexeme())
+            }
+            None => format!("`{}` isn't declared in this file", reference.name.lexeme()),
+        };
+        Ok(serde_json::json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(document, reference.name.span()),
+        }))
+    }
+
+    fn document_symbols(&self, params: &serde_json::Value) -> Reply {
+        let (_, document) = self.document(params)?;
+        Ok(serde_json::Value::Array(symbols(
+            document,
+            &document.statements,
+        )))
+    }
+
+    // Every token as [line delta, start delta, length, type, modifiers], with
+    // tokens spanning lines split into one per line
+    fn semantic_tokens(&self, params: &serde_json::Value) -> Reply {
+        let (_, document) = self.document(params)?;
+        let references: std::collections::HashMap<usize, &Reference> = document
+            .references
+            .iter()
+            .map(|reference| (reference.name.span().start, reference))
+            .collect();
+        let methods: std::collections::HashSet<usize> = method_names(&document.statements);
+
+        let mut classified: Vec<(Span, usize, u32)> = Vec::new();
+        for (index, token) in document.tokens.iter().enumerate() {
+            let previous: Option<&TokenKind> = index
+                .checked_sub(1)
+                .and_then(|previous| document.tokens.get(previous))
+                .map(Token::kind);
+            let next: Option<&TokenKind> = document.tokens.get(index + 1).map(Token::kind);
+            if let Some((kind, modifiers)) = classify(token, previous, next, &references, &methods)
+            {
+                classified.push((token.span(), kind, modifiers));
+            }
+        }
+        for comment in document.comments.iter() {
+            classified.push((comment.span, type_index("comment"), 0));
+        }
+        classified.sort_by_key(|(span, _, _)| span.start);
+
+        let mut data: Vec<usize> = Vec::new();
+        let mut last: Position = Position {
+            line: 0,
+            character: 0,
+        };
+        for (span, kind, modifiers) in classified {
+            let text: &str = &document.text[span.start..span.end];
+            let mut start: usize = span.start;
+            for piece in text.split_inclusive('\n') {
+                let length: usize = piece.trim_end_matches(['\r', '\n']).encode_utf16().count();
+                let position: Position = document.position(start);
+                start += piece.len();
+                if length == 0 {
+                    continue;
+                }
+                let character: usize = if position.line == last.line {
+                    position.character - last.character
+                } else {
+                    position.character
+                };
+                data.extend([
+                    position.line - last.line,
+                    character,
+                    length,
+                    kind,
+                    modifiers as usize,
+                ]);
+                last = position;
+            }
+        }
+        Ok(serde_json::json!({ "data": data }))
+    }
+
+    // One edit replacing the whole text, none when it's already formatted,
+    // and null when it doesn't parse
+    fn formatting(&self, params: &serde_json::Value) -> Reply {
+        let (_, document) = self.document(params)?;
+        let Ok(formatted) = formatter::format(&document.text) else {
+            return Ok(serde_json::Value::Null);
+        };
+        if formatted == document.text {
+            return Ok(serde_json::json!([]));
+        }
+        Ok(serde_json::json!([{
+            "range": range(document, Span::new(0, document.text.len())),
+            "newText": formatted,
+        }]))
+    }
+}
+
+fn capabilities() -> serde_json::Value {
+    serde_json::json!({
+        "capabilities": {
+            "positionEncoding": "utf-16",
+            "textDocumentSync": { "openClose": true, "change": 1 },
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "documentFormattingProvider": true,
+            "semanticTokensProvider": {
+                "legend": {
+                    "tokenTypes": TOKEN_TYPES,
+                    "tokenModifiers": TOKEN_MODIFIERS,
+                },
+                "full": true,
+            },
+        },
+        "serverInfo": { "name": "miette", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn position(position: Position) -> serde_json::Value {
+    serde_json::json!({ "line": position.line, "character": position.character })
+}
+
+fn range(document: &Document, span: Span) -> serde_json::Value {
+    serde_json::json!({
+        "start": position(document.position(span.start)),
+        "end": position(document.position(span.end)),
+    })
+}
+
+fn location(uri: &str, document: &Document, span: Span) -> serde_json::Value {
+    serde_json::json!({ "uri": uri, "range": range(document, span) })
+}
+
+fn is_declaration(reference: &Reference) -> bool {
+    reference
+        .declaration
+        .as_ref()
+        .is_some_and(|declaration| declaration.name.span() == reference.name.span())
+}
+
+fn type_index(name: &str) -> usize {
+    TOKEN_TYPES
+        .iter()
+        .position(|&kind| kind == name)
+        .expect("every classification is in the legend")
+}
+
+// The semantic token type and modifier bits for a token, None for
+// punctuation
+fn classify(
+    token: &Token,
+    previous: Option<&TokenKind>,
+    next: Option<&TokenKind>,
+    references: &std::collections::HashMap<usize, &Reference>,
+    methods: &std::collections::HashSet<usize>,
+) -> Option<(usize, u32)> {
+    const DECLARATION: u32 = 1;
+    const DEFAULT_LIBRARY: u32 = 2;
+
+    let kind: &str = match token.kind() {
+        TokenKind::Identifier(name) => {
+            // Contextual keywords, see the parser
+            let contextual: bool = match name.as_str() {
+                "test" | "import" => matches!(next, Some(TokenKind::Text(_))),
+                "as" => matches!(previous, Some(TokenKind::Text(_))),
+                "export" => matches!(
+                    next,
+                    Some(TokenKind::Var | TokenKind::Fun | TokenKind::Class)
+                ),
+                _ => false,
+            };
+            if contextual {
+                return Some((type_index("keyword"), 0));
+            }
+            let start: usize = token.span().start;
+            if methods.contains(&start) {
+                return Some((type_index("method"), DECLARATION));
+            }
+            if previous == Some(&TokenKind::Dot) {
+                let kind: &str = if next == Some(&TokenKind::LeftParen) {
+                    "method"
+                } else {
+                    "property"
+                };
+                return Some((type_index(kind), 0));
+            }
+
+            let reference: Option<&&Reference> = references.get(&start);
+            match reference.and_then(|reference| reference.declaration.as_ref()) {
+                Some(declaration) => {
+                    let kind: &str = match declaration.kind {
+                        BindingKind::Parameter => "parameter",
+                        BindingKind::Function => "function",
+                        BindingKind::Class => "class",
+                        BindingKind::Module => "namespace",
+                        BindingKind::Variable | BindingKind::Implicit => "variable",
+                    };
+                    let modifiers: u32 = if declaration.name.span() == token.span() {
+                        DECLARATION
+                    } else {
+                        0
+                    };
+                    return Some((type_index(kind), modifiers));
+                }
+                None if stdlib::NAMES.contains(&name.as_str()) => {
+                    return Some((type_index("function"), DEFAULT_LIBRARY));
+                }
+                None => "variable",
+            }
+        }
+        TokenKind::Text(_) => "string",
+        TokenKind::Number(_) => "number",
+        TokenKind::Plus
+        | TokenKind::Minus
+        | TokenKind::Star
+        | TokenKind::Slash
+        | TokenKind::Bang
+        | TokenKind::BangEqual
+        | TokenKind::Equal
+        | TokenKind::EqualEqual
+        | TokenKind::Greater
+        | TokenKind::GreaterEqual
+        | TokenKind::Less
+        | TokenKind::LessEqual
+        | TokenKind::Arrow
+        | TokenKind::DotDot
+        | TokenKind::DotDotEqual => "operator",
+        TokenKind::And
+        | TokenKind::Continue
+        | TokenKind::Break
+        | TokenKind::Class
+        | TokenKind::Else
+        | TokenKind::False
+        | TokenKind::True
+        | TokenKind::Fun
+        | TokenKind::For
+        | TokenKind::If
+        | TokenKind::In
+        | TokenKind::Nil
+        | TokenKind::Or
+        | TokenKind::Print
+        | TokenKind::Return
+        | TokenKind::Super
+        | TokenKind::This
+        | TokenKind::Var
+        | TokenKind::While
+        | TokenKind::Throw
+        | TokenKind::Try
+        | TokenKind::Catch
+        | TokenKind::Finally => "keyword",
+        TokenKind::LeftParen
+        | TokenKind::RightParen
+        | TokenKind::LeftBracket
+        | TokenKind::RightBracket
+        | TokenKind::LeftSBracket
+        | TokenKind::RightSBracket
+        | TokenKind::Comma
+        | TokenKind::Colon
+        | TokenKind::Question
+        | TokenKind::Pipe
+        | TokenKind::SemiColon
+        | TokenKind::Dot
+        | TokenKind::EOF => return None,
+    };
+    Some((type_index(kind), 0))
+}
+
+// Where the names of methods start, since methods aren't variables and the
+// resolver doesn't record them
+fn method_names(statements: &[Stmt]) -> std::collections::HashSet<usize> {
+    let mut starts: std::collections::HashSet<usize> = std::collections::HashSet::new();
+    for statement in statements {
+        match statement {
+            Stmt::Class { methods, .. } => {
+                for method in methods {
+                    if let Some(name) = &method.name {
+                        starts.insert(name.span().start);
+                    }
+                    starts.extend(method_names(&method.body));
+                }
+            }
+            Stmt::Function { function } => starts.extend(method_names(&function.body)),
+            Stmt::Block { statements }
+            | Stmt::Test {
+                body: statements, ..
+            } => starts.extend(method_names(statements)),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                starts.extend(method_names(body));
+                if let Some(catch) = catch {
+                    starts.extend(method_names(&catch.body));
+                }
+                if let Some(finally) = finally {
+                    starts.extend(method_names(finally));
+                }
+            }
+            _ => {}
+        }
+    }
+    starts
+}
+
+// Functions and classes, with methods and nested functions as children
+fn symbols(document: &Document, statements: &[Stmt]) -> Vec<serde_json::Value> {
+    let mut found: Vec<serde_json::Value> = Vec::new();
+    for statement in statements {
+        match statement {
+            Stmt::Function { function } => {
+                if let Some(name) = &function.name {
+                    let children: Vec<serde_json::Value> = symbols(document, &function.body);
+                    found.push(symbol(document, name, SYMBOL_FUNCTION, children));
+                }
+            }
+            Stmt::Class { name, methods, .. } => {
+                let children: Vec<serde_json::Value> = methods
+                    .iter()
+                    .filter_map(|method| {
+                        let name: &Token = method.name.as_ref()?;
+                        let children: Vec<serde_json::Value> = symbols(document, &method.body);
+                        Some(symbol(document, name, SYMBOL_METHOD, children))
+                    })
+                    .collect();
+                found.push(symbol(document, name, SYMBOL_CLASS, children));
+            }
+            Stmt::Block { statements }
+            | Stmt::Test {
+                body: statements, ..
+            } => found.extend(symbols(document, statements)),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                found.extend(symbols(document, body));
+                if let Some(catch) = catch {
+                    found.extend(symbols(document, &catch.body));
+                }
+                if let Some(finally) = finally {
+                    found.extend(symbols(document, finally));
+                }
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+fn symbol(
+    document: &Document,
+    name: &Token,
+    kind: u32,
+    children: Vec<serde_json::Value>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "name": name.lexeme(),
+        "kind": kind,
+        "range": range(document, extent(document, name)),
+        "selectionRange": range(document, name.span()),
+        "children": children,
+    })
+}
+
+// From the `fun` or `class` keyword, if there is one, to the brace closing
+// the body. The syntax tree has no end positions, so the braces are matched
+// in the tokens.
+fn extent(document: &Document, name: &Token) -> Span {
+    let tokens: &[Token] = &document.tokens;
+    let index: usize = tokens.partition_point(|token| token.span().start < name.span().start);
+
+    let start: Span = match index.checked_sub(1).map(|previous| tokens[previous].kind()) {
+        Some(TokenKind::Fun | TokenKind::Class) => tokens[index - 1].span(),
+        _ => name.span(),
+    };
+
+    let mut depth: usize = 0;
+    for token in tokens[index..].iter() {
+        match token.kind() {
+            TokenKind::LeftBracket => depth += 1,
+            TokenKind::RightBracket if depth == 1 => return start.to(token.span()),
+            TokenKind::RightBracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    start.to(name.span())
+}
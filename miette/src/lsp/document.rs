@@ -0,0 +1,186 @@
+// An open file and everything the front end found out about it. Each change
+// replaces the whole text and the analysis is redone from scratch, which is
+// quick enough for files of the size people write by hand.
+
+use crate::ast::Stmt;
+use crate::checker::Checker;
+use crate::compiler::Compiler;
+use crate::error::Diagnostic;
+use crate::lex::{self, Comment, Span, Token};
+use crate::lint::{self, Lint};
+use crate::parser::Parser;
+use crate::resolver::{Reference, Resolver};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// An error or warning from any stage of the front end
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub lint: Option<Lint>,
+}
+
+// A line and column as LSP counts them: both from zero, columns in UTF-16
+// code units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+pub struct Document {
+    pub text: String,
+    // Byte offset where each line starts
+    line_starts: Vec<usize>,
+    // Empty when the text doesn't lex
+    pub tokens: Vec<Token>,
+    pub comments: Vec<Comment>,
+    // Empty when the text doesn't parse
+    pub statements: Vec<Stmt>,
+    pub references: Vec<Reference>,
+    pub problems: Vec<Problem>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Document {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        let mut document: Document = Document {
+            text,
+            line_starts,
+            tokens: Vec::new(),
+            comments: Vec::new(),
+            statements: Vec::new(),
+            references: Vec::new(),
+            problems: Vec::new(),
+        };
+        document.analyze();
+        document
+    }
+
+    // Lexes, parses, resolves and compiles, stopping at the first stage with
+    // errors the way `miette check` does
+    fn analyze(&mut self) {
+        let (tokens, comments) = match lex::scan_source_with_comments(&self.text) {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                let span: Span = match e.downcast_ref::<Diagnostic>() {
+                    Some(diagnostic) => diagnostic.span,
+                    None => Span::default(),
+                };
+                self.error(span, &e.to_string());
+                return;
+            }
+        };
+        self.tokens = tokens.iter().cloned().collect();
+        self.comments = comments;
+
+        self.statements = match Parser::new(tokens).parse() {
+            Ok(statements) => statements,
+            Err(errors) => return self.errors(&errors),
+        };
+
+        let mut resolver: Resolver = Resolver::new();
+        let resolved: Result<_, Vec<Diagnostic>> = resolver.resolve(&self.statements);
+        self.references = resolver.references().to_vec();
+        for warning in lint::suppress(resolver.warnings().to_vec(), &self.comments) {
+            self.problems.push(Problem {
+                severity: Severity::Warning,
+                message: warning.message,
+                span: warning.span,
+                lint: Some(warning.lint),
+            });
+        }
+        if let Err(errors) = resolved {
+            return self.errors(&errors);
+        }
+
+        if let Err(errors) = Checker::new().check(&self.statements) {
+            return self.errors(&errors);
+        }
+
+        if let Err(errors) = Compiler::new().compile(&self.statements) {
+            self.errors(&errors);
+        }
+    }
+
+    fn errors(&mut self, errors: &[Diagnostic]) {
+        for error in errors {
+            self.error(error.span, &error.message);
+        }
+    }
+
+    fn error(&mut self, span: Span, message: &str) {
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            message: message.to_string(),
+            span,
+            lint: None,
+        });
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset: usize = offset.min(self.text.len());
+        let line: usize = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start: usize = self.line_starts[line];
+        let character: usize = self.text[start..offset].encode_utf16().count();
+        Position { line, character }
+    }
+
+    // Positions past the end of a line or the text clamp to its end
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line) else {
+            return self.text.len();
+        };
+        let end: usize = self
+            .line_starts
+            .get(position.line + 1)
+            .map_or(self.text.len(), |&next| next - 1);
+
+        let mut units: usize = 0;
+        for (index, c) in self.text[start..end].char_indices() {
+            if units >= position.character {
+                return start + index;
+            }
+            units += c.len_utf16();
+        }
+        end
+    }
+
+    // The token under the cursor. A cursor just after a token counts as on it,
+    // as editors put the cursor there after typing a name.
+    pub fn token_at(&self, offset: usize) -> Option<&Token> {
+        let index: usize = self
+            .tokens
+            .partition_point(|token| token.span().end < offset);
+        self.tokens
+            .get(index)
+            .filter(|token| token.span().start <= offset)
+    }
+
+    // The declaration or use of a variable under the cursor
+    pub fn reference_at(&self, offset: usize) -> Option<&Reference> {
+        let token: &Token = self.token_at(offset)?;
+        self.references
+            .iter()
+            .find(|reference| reference.name.span() == token.span())
+    }
+
+    // The text of the line holding `offset`, without its line break
+    pub fn line_text(&self, offset: usize) -> &str {
+        let line: usize = self.position(offset).line;
+        let start: usize = self.line_starts[line];
+        let end: usize = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+}
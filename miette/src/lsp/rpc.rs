@@ -0,0 +1,81 @@
+// JSON-RPC framing for LSP: each message is a JSON body after a header block
+// with its length in bytes, as in
+//
+//     Content-Length: 52\r\n
+//     \r\n
+//     {"jsonrpc":"2.0","id":1,"method":"shutdown"}
+//
+// Other headers are allowed and ignored.
+
+// Error codes from the JSON-RPC and LSP specifications
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+// The body of the next message, or None at the end of the input
+pub fn read_message(input: &mut dyn std::io::BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length: Option<usize> = None;
+    let mut started: bool = false;
+    loop {
+        let mut line: String = String::new();
+        if input.read_line(&mut line)? == 0 {
+            if started {
+                return Err(invalid("the input ended inside a header"));
+            }
+            return Ok(None);
+        }
+        started = true;
+
+        let line: &str = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(invalid(&format!("malformed header '{}'", line)));
+        };
+        if name.trim().eq_ignore_ascii_case("Content-Length") {
+            let value: &str = value.trim();
+            length = Some(value.parse::<usize>().map_err(|_| {
+                invalid(&format!("Content-Length must be a number, not '{}'", value))
+            })?);
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(invalid("a message has no Content-Length"));
+    };
+    let mut body: Vec<u8> = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn write_message(
+    output: &mut dyn std::io::Write,
+    message: &serde_json::Value,
+) -> std::io::Result<()> {
+    let body: String = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+pub fn response(id: serde_json::Value, result: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error_response(id: serde_json::Value, code: i64, message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+pub fn notification(method: &str, params: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
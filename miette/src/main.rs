@@ -1,3 +1,581 @@
-fn main() {
-    println!("Hello, world!");
+mod cli;
+
+use cli::args::{Command, ErrorFormat, Input, Options, Parsed};
+use cli::report::Reporter;
+use miette::ast::Stmt;
+use miette::bytecode;
+use miette::chunk::FunctionProto;
+use miette::diff;
+use miette::disassembler::disassemble;
+use miette::error::Diagnostic;
+use miette::formatter;
+use miette::interpreter::{self, Interpreter};
+use miette::lex;
+use miette::manifest;
+use miette::module::{Loader, Program};
+use miette::parser::Parser;
+use miette::project::{self, BuildError, Project};
+use miette::repl::Repl;
+use miette::test_runner::{self, Backend, Case, Outcome};
+use miette::vm::Vm;
+
+// Exit codes, from sysexits.h apart from the first
+const UNFORMATTED: u8 = 1;
+const TESTS_FAILED: u8 = 1;
+// An LSP client exited without asking for a shutdown first
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+const UNCLEAN_EXIT: u8 = 1;
+const USAGE_ERROR: u8 = 64;
+const COMPILE_ERROR: u8 = 65;
+const NO_INPUT: u8 = 66;
+const RUNTIME_ERROR: u8 = 70;
+const IO_ERROR: u8 = 74;
+
+fn main() -> std::process::ExitCode {
+    // The interpreter takes host stack for each call the program makes
+    std::thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(start)
+        .expect("the main thread can be spawned")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn start() -> std::process::ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options: Options = match cli::args::parse(&args) {
+        Ok(Parsed::Run(options)) => options,
+        Ok(Parsed::Help) => {
+            println!("{}", cli::args::HELP);
+            return std::process::ExitCode::SUCCESS;
+        }
+        Ok(Parsed::Version) => {
+            println!("miette {}", env!("CARGO_PKG_VERSION"));
+            return std::process::ExitCode::SUCCESS;
+        }
+        Err(message) => return usage_error(&message),
+    };
+
+    // The language server speaks JSON-RPC
+    let needs_json: bool = options.json
+        || options.error_format == ErrorFormat::Json
+        || options.command == Command::Lsp;
+    if needs_json && !cfg!(feature = "json") {
+        eprintln!("miette was built without the `json` feature");
+        return std::process::ExitCode::from(USAGE_ERROR);
+    }
+
+    match options.command {
+        Command::Repl => return repl(),
+        Command::Lsp => return lsp(),
+        Command::Test => return test(&options),
+        Command::New => return new(&options),
+        _ => {}
+    }
+
+    let reporter: Reporter = Reporter::new(&options);
+    if let Input::Path(path) = &options.input
+        && path.is_dir()
+        && matches!(
+            options.command,
+            Command::Check | Command::Run | Command::Build
+        )
+    {
+        return project_command(&options, &reporter, path);
+    }
+    let bytes: Vec<u8> = match read_input(&options.input) {
+        Ok(bytes) => bytes,
+        Err(e) => return read_failure(&reporter, &options.input.name(), &e),
+    };
+
+    // Bytecode has been through the front end already and only runs on the VM
+    let is_bytecode: bool = bytes.starts_with(bytecode::MAGIC)
+        || matches!(&options.input, Input::Path(path)
+            if path.extension().is_some_and(|ext| ext == bytecode::EXTENSION));
+    if is_bytecode {
+        return run_bytecode(&options, &reporter, &bytes);
+    }
+
+    let Ok(source) = String::from_utf8(bytes) else {
+        reporter.failure(&format!("{}: not valid UTF-8", options.input.name()));
+        return std::process::ExitCode::from(COMPILE_ERROR);
+    };
+
+    let (tokens, _) = match lex::scan_source_with_comments(&source) {
+        Ok(scanned) => scanned,
+        Err(e) => {
+            match e.downcast::<Diagnostic>() {
+                Ok(diagnostic) => reporter.problems(&[*diagnostic], &[]),
+                Err(e) => reporter.failure(&e.to_string()),
+            }
+            return std::process::ExitCode::from(COMPILE_ERROR);
+        }
+    };
+
+    match options.command {
+        Command::Lex => {
+            let text: String = if options.json {
+                json_text(Output::Tokens(&tokens))
+            } else {
+                tokens.iter().map(|token| format!("{}\n", token)).collect()
+            };
+            emit(&options, text.as_bytes())
+        }
+        Command::Parse => match Parser::new(tokens).parse() {
+            Ok(statements) => {
+                let text: String = if options.json {
+                    json_text(Output::Ast(&statements))
+                } else {
+                    format!("{:#?}\n", statements)
+                };
+                emit(&options, text.as_bytes())
+            }
+            Err(errors) => {
+                reporter.problems(&errors, &[]);
+                std::process::ExitCode::from(COMPILE_ERROR)
+            }
+        },
+        Command::Check => match check(&reporter, &options.input, &source, options.strict) {
+            Ok(_) => std::process::ExitCode::SUCCESS,
+            Err(code) => code,
+        },
+        Command::Run => {
+            let program: Program = match check(&reporter, &options.input, &source, options.strict) {
+                Ok(program) => program,
+                Err(code) => return code,
+            };
+
+            if options.vm {
+                return report(&reporter, vm(options.trace).interpret_program(&program));
+            }
+            report(&reporter, Interpreter::new().interpret_program(&program))
+        }
+        Command::Disasm => match check(&reporter, &options.input, &source, options.strict) {
+            Ok(program) => emit(&options, disassemble(&program.main().script).as_bytes()),
+            Err(code) => code,
+        },
+        Command::Build => {
+            let program: Program = match check(&reporter, &options.input, &source, options.strict) {
+                Ok(program) => program,
+                Err(code) => return code,
+            };
+            // Bytecode files stand alone, so there is nowhere to put the
+            // modules a program imports
+            if program.modules.len() > 1 {
+                reporter.failure(&format!(
+                    "{} imports other modules, so build the project holding it instead",
+                    options.input.name()
+                ));
+                return std::process::ExitCode::from(COMPILE_ERROR);
+            }
+            emit(&options, &bytecode::write(&program.main().script))
+        }
+        Command::Test | Command::Repl | Command::Lsp | Command::New => {
+            unreachable!(
+                "tests, the REPL, the language server and new don't read a program up front"
+            )
+        }
+        Command::Fmt => {
+            let formatted: String = match formatter::format(&source) {
+                Ok(formatted) => formatted,
+                Err(errors) => {
+                    reporter.problems(&errors, &[]);
+                    return std::process::ExitCode::from(COMPILE_ERROR);
+                }
+            };
+            if options.check {
+                let name: String = options.input.name();
+                let diff: String =
+                    diff::unified(&source, &formatted, &name, &format!("{} (formatted)", name));
+                if diff.is_empty() {
+                    return std::process::ExitCode::SUCCESS;
+                }
+                print!("{}", diff);
+                return std::process::ExitCode::from(UNFORMATTED);
+            }
+            // Leave formatted files untouched, modification time included
+            if formatted == source && options.output.is_none() && options.input != Input::Stdin {
+                return std::process::ExitCode::SUCCESS;
+            }
+            emit(&options, formatted.as_bytes())
+        }
+    }
+}
+
+// Prints each failing test with what went wrong, then a summary
+fn test(options: &Options) -> std::process::ExitCode {
+    let Input::Path(root) = &options.input else {
+        unreachable!("`test` always reads a directory")
+    };
+    let paths: Vec<std::path::PathBuf> = match test_runner::discover(root) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", root.display(), e);
+            return std::process::ExitCode::from(read_error(&e));
+        }
+    };
+    let mut cases: Vec<Case> = paths
+        .iter()
+        .flat_map(|path| test_runner::cases(path))
+        .collect();
+    let found: usize = cases.len();
+    if let Some(filter) = &options.filter {
+        cases.retain(|case| case.to_string().contains(filter.as_str()));
+    }
+
+    let backend: Backend = if options.vm {
+        Backend::Vm
+    } else {
+        Backend::Interpreter
+    };
+    let jobs: usize = options.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    });
+    let noun: &str = if cases.len() == 1 { "test" } else { "tests" };
+    println!("running {} {}", cases.len(), noun);
+    let outcomes: Vec<Outcome> = test_runner::run_all(&cases, backend, jobs);
+
+    let mut failed: usize = 0;
+    for outcome in outcomes.iter().filter(|outcome| !outcome.passed()) {
+        failed += 1;
+        println!("FAIL {}", outcome.case);
+        for failure in outcome.failures.iter() {
+            for line in failure.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+
+    let mut summary: String = format!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if cases.len() < found {
+        summary.push_str(&format!(", {} filtered out", found - cases.len()));
+    }
+    println!("{}", summary);
+    if failed > 0 {
+        std::process::ExitCode::from(TESTS_FAILED)
+    } else {
+        std::process::ExitCode::SUCCESS
+    }
+}
+
+fn new(options: &Options) -> std::process::ExitCode {
+    let Input::Path(dir) = &options.input else {
+        unreachable!("`new` always has a directory")
+    };
+    let name: String = dir
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    if !manifest::is_valid_name(&name) {
+        return usage_error(&manifest::invalid_name(&name));
+    }
+    match project::create(dir) {
+        Ok(()) => {
+            println!("created {}", dir.display());
+            std::process::ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("Failed to create the project: {}", message);
+            std::process::ExitCode::from(IO_ERROR)
+        }
+    }
+}
+
+// check, run and build given a project directory start at its entry point,
+// and build compiles every module into the project's target directory
+fn project_command(
+    options: &Options,
+    reporter: &Reporter,
+    dir: &std::path::Path,
+) -> std::process::ExitCode {
+    if options.command == Command::Build && options.output.is_some() {
+        return usage_error("`build` of a project writes to its target directory, not -o");
+    }
+    let project: Project = match Project::open(dir) {
+        Ok(project) => project,
+        Err(message) => {
+            reporter.failure(&message);
+            return std::process::ExitCode::from(COMPILE_ERROR);
+        }
+    };
+
+    let mut loader: Loader = project.loader();
+    loader.set_strict(options.strict);
+    if options.command == Command::Build {
+        let build: project::Build = match project.build(&mut loader) {
+            Ok(build) => build,
+            Err(error) => return build_failure(reporter, &loader, error),
+        };
+        reporter.problems(&[], loader.warnings());
+        for path in build.compiled.iter() {
+            let path: &std::path::Path = path.strip_prefix(&project.root).unwrap_or(path);
+            println!("compiled {}", path.display());
+        }
+        let manifest: &manifest::Manifest = &project.main().manifest;
+        println!(
+            "built {} {}, {} compiled, {} unchanged",
+            manifest.name,
+            manifest.version,
+            build.compiled.len(),
+            build.fresh.len()
+        );
+        return std::process::ExitCode::SUCCESS;
+    }
+
+    let program: Program = match project.load(&mut loader) {
+        Ok(program) => program,
+        Err(error) => return build_failure(reporter, &loader, error),
+    };
+    reporter.problems(&[], loader.warnings());
+    match options.command {
+        Command::Run if options.vm => {
+            report(reporter, vm(options.trace).interpret_program(&program))
+        }
+        Command::Run => report(reporter, Interpreter::new().interpret_program(&program)),
+        _ => std::process::ExitCode::SUCCESS,
+    }
+}
+
+fn build_failure(
+    reporter: &Reporter,
+    loader: &Loader,
+    error: BuildError,
+) -> std::process::ExitCode {
+    match error {
+        BuildError::Compile(errors) => {
+            reporter.problems(&errors, loader.warnings());
+            std::process::ExitCode::from(COMPILE_ERROR)
+        }
+        BuildError::Read(message) => {
+            reporter.failure(&message);
+            std::process::ExitCode::from(NO_INPUT)
+        }
+        BuildError::Write(message) => {
+            reporter.failure(&message);
+            std::process::ExitCode::from(IO_ERROR)
+        }
+    }
+}
+
+fn repl() -> std::process::ExitCode {
+    let interactive: bool = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let mut repl: Repl = Repl::new(Box::new(std::io::stdout()), Box::new(std::io::stderr()));
+    repl.prompt(interactive);
+    if let Some(path) = history_path() {
+        repl.history(path);
+    }
+    if interactive {
+        println!("miette {}, :help for help", env!("CARGO_PKG_VERSION"));
+    }
+
+    match repl.run(&mut std::io::stdin().lock()) {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("repl: {}", e);
+            std::process::ExitCode::from(IO_ERROR)
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn lsp() -> std::process::ExitCode {
+    let mut server: miette::lsp::Server = miette::lsp::Server::new();
+    match server.run(&mut std::io::stdin().lock(), &mut std::io::stdout().lock()) {
+        Ok(true) => std::process::ExitCode::SUCCESS,
+        Ok(false) => std::process::ExitCode::from(UNCLEAN_EXIT),
+        Err(e) => {
+            eprintln!("lsp: {}", e);
+            std::process::ExitCode::from(IO_ERROR)
+        }
+    }
+}
+
+#[cfg(not(feature = "json"))]
+fn lsp() -> std::process::ExitCode {
+    unreachable!("lsp is rejected when the json feature is disabled")
+}
+
+// $MIETTE_HISTORY, or ~/.miette_history. An empty $MIETTE_HISTORY turns
+// history off.
+fn history_path() -> Option<std::path::PathBuf> {
+    match std::env::var_os("MIETTE_HISTORY") {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(std::path::PathBuf::from(path)),
+        None => std::env::var_os("HOME")
+            .map(|home| std::path::PathBuf::from(home).join(".miette_history")),
+    }
+}
+
+fn usage_error(message: &str) -> std::process::ExitCode {
+    eprintln!("error: {}", message);
+    eprintln!("{}", cli::args::USAGE);
+    eprintln!("Try 'miette --help' for more information.");
+    std::process::ExitCode::from(USAGE_ERROR)
+}
+
+fn read_input(input: &Input) -> std::io::Result<Vec<u8>> {
+    match input {
+        Input::Path(path) => std::fs::read(path),
+        Input::Stdin => {
+            let mut bytes: Vec<u8> = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)?;
+            Ok(bytes)
+        }
+    }
+}
+
+fn read_failure(reporter: &Reporter, name: &str, error: &std::io::Error) -> std::process::ExitCode {
+    reporter.failure(&format!("{}: {}", name, error));
+    std::process::ExitCode::from(read_error(error))
+}
+
+// A missing input, or one that can't be opened, is told apart from other
+// failures to read it
+fn read_error(error: &std::io::Error) -> u8 {
+    match error.kind() {
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => NO_INPUT,
+        _ => IO_ERROR,
+    }
+}
+
+// Writes a command's output to -o, next to the source for build, over the
+// source for fmt, and to stdout otherwise
+fn emit(options: &Options, bytes: &[u8]) -> std::process::ExitCode {
+    let path: Option<std::path::PathBuf> = match (&options.output, &options.input) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Input::Path(path)) if options.command == Command::Build => {
+            Some(path.with_extension(bytecode::EXTENSION))
+        }
+        (None, Input::Path(path)) if options.command == Command::Fmt => Some(path.clone()),
+        _ => None,
+    };
+
+    let written: std::io::Result<()> = match &path {
+        Some(path) => std::fs::write(path, bytes),
+        None => std::io::Write::write_all(&mut std::io::stdout(), bytes),
+    };
+    match written {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            let name: String = path.map_or("stdout".to_string(), |path| path.display().to_string());
+            eprintln!("Failed to write {}: {}", name, e);
+            std::process::ExitCode::from(IO_ERROR)
+        }
+    }
+}
+
+// Loads the program and everything it imports, each parsed, resolved, type
+// checked and compiled, reporting warnings along with any errors. Compiling
+// even when the interpreter will run the program keeps `check` and both
+// backends agreeing on which programs are valid.
+fn check(
+    reporter: &Reporter,
+    input: &Input,
+    source: &str,
+    strict: bool,
+) -> Result<Program, std::process::ExitCode> {
+    // Imports in a program read from stdin are relative to the working
+    // directory
+    let path: &std::path::Path = match input {
+        Input::Path(path) => path,
+        Input::Stdin => std::path::Path::new(""),
+    };
+    // A file inside a project can import the packages it depends on
+    let mut loader: Loader = match path.parent().and_then(manifest::find) {
+        Some(root) => match Project::open(&root) {
+            Ok(project) => project.loader(),
+            Err(message) => {
+                reporter.failure(&message);
+                return Err(std::process::ExitCode::from(COMPILE_ERROR));
+            }
+        },
+        None => Loader::new(),
+    };
+    loader.set_strict(strict);
+    match loader.load(path, source) {
+        Ok(program) => {
+            reporter.problems(&[], loader.warnings());
+            Ok(program)
+        }
+        Err(errors) => {
+            reporter.problems(&errors, loader.warnings());
+            Err(std::process::ExitCode::from(COMPILE_ERROR))
+        }
+    }
+}
+
+fn run_bytecode(options: &Options, reporter: &Reporter, bytes: &[u8]) -> std::process::ExitCode {
+    if options.command == Command::Run {
+        // Imports are found beside the file, or in the current directory
+        // when it came from stdin
+        let path: std::path::PathBuf = match &options.input {
+            Input::Path(path) => path.clone(),
+            Input::Stdin => std::path::PathBuf::from(options.input.name()),
+        };
+        return match bytecode::link(&path, bytes) {
+            Ok(modules) => report(reporter, vm(options.trace).interpret_linked(&modules)),
+            Err(message) => {
+                reporter.failure(&message);
+                std::process::ExitCode::from(COMPILE_ERROR)
+            }
+        };
+    }
+
+    let script: std::rc::Rc<FunctionProto> = match bytecode::load(bytes) {
+        Ok(script) => script,
+        Err(e) => {
+            reporter.failure(&format!("{}: {}", options.input.name(), e));
+            return std::process::ExitCode::from(COMPILE_ERROR);
+        }
+    };
+
+    match options.command {
+        Command::Disasm => emit(options, disassemble(&script).as_bytes()),
+        command => usage_error(&format!(
+            "`{}` needs source code, {} is compiled bytecode",
+            command.name(),
+            options.input.name()
+        )),
+    }
+}
+
+fn vm(trace: bool) -> Vm {
+    let mut vm: Vm = Vm::new();
+    if trace {
+        vm.trace(Box::new(std::io::stderr()));
+    }
+    vm
+}
+
+fn report(
+    reporter: &Reporter,
+    result: Result<(), miette::error::RuntimeError>,
+) -> std::process::ExitCode {
+    match result {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(error) => {
+            reporter.runtime_error(&error);
+            std::process::ExitCode::from(RUNTIME_ERROR)
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+enum Output<'a> {
+    Tokens(&'a std::collections::VecDeque<lex::Token>),
+    Ast(&'a [Stmt]),
+}
+
+#[cfg(feature = "json")]
+fn json_text(output: Output) -> String {
+    let text: String = match output {
+        Output::Tokens(tokens) => miette::json::tokens_to_json(tokens),
+        Output::Ast(statements) => miette::json::ast_to_json(statements),
+    };
+    text + "\n"
+}
+
+#[cfg(not(feature = "json"))]
+fn json_text(_output: Output) -> String {
+    unreachable!("--json is rejected when the json feature is disabled")
 }
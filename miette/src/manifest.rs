@@ -0,0 +1,307 @@
+// Miette.toml, which makes a directory a project:
+//
+//     [package]
+//     name = "shapes"
+//     version = "0.1.0"
+//     entry = "src/lib.miette"
+//
+//     [dependencies]
+//     geometry = { path = "../geometry" }
+//
+// Only as much TOML as that is understood: the two tables, string values,
+// inline tables of strings and `#` comments. The entry point defaults to
+// src/main.miette, and dependency paths are relative to the manifest.
+
+pub const FILE_NAME: &str = "Miette.toml";
+pub const DEFAULT_ENTRY: &str = "src/main.miette";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub entry: std::path::PathBuf,
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub path: std::path::PathBuf,
+}
+
+// Line is 0 for problems with the manifest as a whole, like a missing key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+enum Table {
+    None,
+    Package,
+    Dependencies,
+}
+
+enum Value {
+    Text(String),
+    Inline(Vec<(String, String)>),
+}
+
+pub fn parse(text: &str) -> Result<Manifest, ManifestError> {
+    let mut name: Option<String> = None;
+    let mut version: Option<String> = None;
+    let mut entry: Option<String> = None;
+    let mut dependencies: Vec<Dependency> = Vec::new();
+    let mut seen: Vec<&str> = Vec::new();
+    let mut table: Table = Table::None;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line: usize = index + 1;
+        let error = |message: String| ManifestError { line, message };
+        let content: &str = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = content.strip_prefix('[') {
+            let Some(header) = header.strip_suffix(']') else {
+                return Err(error("expected ']' after the table name".to_string()));
+            };
+            let header: &str = header.trim();
+            table = match header {
+                "package" => Table::Package,
+                "dependencies" => Table::Dependencies,
+                other => return Err(error(format!("unknown table [{}]", other))),
+            };
+            if seen.contains(&header) {
+                return Err(error(format!("[{}] appears twice", header)));
+            }
+            seen.push(header);
+            continue;
+        }
+
+        let Some((key, value)) = content.split_once('=') else {
+            return Err(error(format!("expected 'key = value', got '{}'", content)));
+        };
+        let key: &str = key.trim();
+        if !is_bare_key(key) {
+            return Err(error(format!("'{}' isn't a valid key", key)));
+        }
+        let value: Value = parse_value(value.trim()).map_err(error)?;
+
+        match table {
+            Table::None => {
+                return Err(error(format!(
+                    "'{}' must be inside a table like [package]",
+                    key
+                )));
+            }
+            Table::Package => {
+                let slot: &mut Option<String> = match key {
+                    "name" => &mut name,
+                    "version" => &mut version,
+                    "entry" => &mut entry,
+                    other => return Err(error(format!("unknown key '{}' in [package]", other))),
+                };
+                let Value::Text(text) = value else {
+                    return Err(error(format!("'{}' must be a string", key)));
+                };
+                if slot.replace(text).is_some() {
+                    return Err(error(format!("'{}' is set twice", key)));
+                }
+            }
+            Table::Dependencies => {
+                let path: Option<String> = match value {
+                    Value::Inline(entries) if entries.len() == 1 && entries[0].0 == "path" => {
+                        entries.into_iter().next().map(|(_, path)| path)
+                    }
+                    _ => None,
+                };
+                let Some(path) = path else {
+                    return Err(error(format!(
+                        "dependency '{}' needs a path, as in {} = {{ path = \"../{}\" }}",
+                        key, key, key
+                    )));
+                };
+                if dependencies.iter().any(|dependency| dependency.name == key) {
+                    return Err(error(format!("dependency '{}' is listed twice", key)));
+                }
+                dependencies.push(Dependency {
+                    name: key.to_string(),
+                    path: std::path::PathBuf::from(path),
+                });
+            }
+        }
+    }
+
+    let missing = |key: &str| ManifestError {
+        line: 0,
+        message: format!("[package] needs a {}", key),
+    };
+    let name: String = name.ok_or_else(|| missing("name"))?;
+    let version: String = version.ok_or_else(|| missing("version"))?;
+    if !is_valid_name(&name) {
+        return Err(ManifestError {
+            line: 0,
+            message: invalid_name(&name),
+        });
+    }
+    if !is_valid_version(&version) {
+        return Err(ManifestError {
+            line: 0,
+            message: format!("version '{}' must look like 1.2.3", version),
+        });
+    }
+    Ok(Manifest {
+        name,
+        version,
+        entry: std::path::PathBuf::from(entry.as_deref().unwrap_or(DEFAULT_ENTRY)),
+        dependencies,
+    })
+}
+
+// Reads and parses the manifest in `dir`, naming the file in any error
+pub fn read(dir: &std::path::Path) -> Result<Manifest, String> {
+    let path: std::path::PathBuf = dir.join(FILE_NAME);
+    let text: String = std::fs::read_to_string(&path)
+        .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// The nearest directory from `start` up that holds a manifest
+pub fn find(start: &std::path::Path) -> Option<std::path::PathBuf> {
+    let start: &std::path::Path = if start.as_os_str().is_empty() {
+        std::path::Path::new(".")
+    } else {
+        start
+    };
+    let start: std::path::PathBuf = std::fs::canonicalize(start).ok()?;
+    start
+        .ancestors()
+        .find(|dir| dir.join(FILE_NAME).is_file())
+        .map(std::path::Path::to_path_buf)
+}
+
+// The manifest `miette new` writes
+pub fn template(name: &str) -> String {
+    format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n",
+        name
+    )
+}
+
+// Names are used in imports and under target/, so they stay simple
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub fn invalid_name(name: &str) -> String {
+    format!(
+        "package name '{}' must start with a letter and hold only letters, digits, '-' and '_'",
+        name
+    )
+}
+
+fn is_valid_version(version: &str) -> bool {
+    let parts: Vec<&str> = version.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// A `#` starts a comment unless it's inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(inner) = text.strip_prefix('{') {
+        let Some(inner) = inner.strip_suffix('}') else {
+            return Err("expected '}' after the inline table".to_string());
+        };
+        let mut entries: Vec<(String, String)> = Vec::new();
+        let mut rest: &str = inner.trim();
+        while !rest.is_empty() {
+            let Some((key, after)) = rest.split_once('=') else {
+                return Err(format!("expected 'key = value', got '{}'", rest));
+            };
+            let key: &str = key.trim();
+            if !is_bare_key(key) {
+                return Err(format!("'{}' isn't a valid key", key));
+            }
+            let (value, after) = parse_string(after.trim_start())?;
+            entries.push((key.to_string(), value));
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.is_empty() {
+                return Err(format!("expected ',' or '}}', got '{}'", rest));
+            }
+        }
+        return Ok(Value::Inline(entries));
+    }
+
+    let (value, rest) = parse_string(text)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected '{}' after the value", rest.trim()));
+    }
+    Ok(Value::Text(value))
+}
+
+// A basic string and what follows it
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let Some(body) = text.strip_prefix('"') else {
+        return Err(format!(
+            "expected a string in double quotes, got '{}'",
+            text
+        ));
+    };
+    let mut value: String = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, other)) => return Err(format!("unknown escape '\\{}'", other)),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
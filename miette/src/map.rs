@@ -0,0 +1,157 @@
+// The map object shared by every backend.
+//
+// Keys may be nil, booleans, numbers or strings. Numbers hash by value, so
+// `1` and `1.0` are the same key and so are `0` and `-0`; NaN is rejected
+// because it is never equal to itself.
+//
+// Iteration order is insertion order: keys come back in the order they were
+// first added. Assigning to an existing key keeps its position, deleting a
+// key removes it without disturbing the others, and a deleted key that is
+// added again goes to the end.
+
+use crate::error::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    // Bit pattern of a non NaN number with -0 folded into 0
+    Number(u64),
+    Text(String),
+}
+
+impl MapKey {
+    pub fn number(n: f64) -> Result<MapKey, MapError> {
+        if n.is_nan() {
+            return Err(MapError::NanKey);
+        }
+        let n: f64 = if n == 0.0 { 0.0 } else { n };
+        Ok(MapKey::Number(n.to_bits()))
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            MapKey::Number(bits) => Some(f64::from_bits(*bits)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapError {
+    NanKey,
+    // Carries the type name of the offending value
+    Unhashable(String),
+    MissingKey(String),
+}
+
+impl std::fmt::Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapError::NanKey => write!(f, "NaN can't be used as a map key."),
+            MapError::Unhashable(kind) => write!(f, "A {} can't be used as a map key.", kind),
+            MapError::MissingKey(key) => write!(f, "Key {} not found in map.", key),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+impl MapError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MapError::Unhashable(_) => ErrorKind::TypeError,
+            MapError::NanKey | MapError::MissingKey(_) => ErrorKind::KeyError,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map<V> {
+    // Insertion ordered slots, None where a key was deleted
+    entries: Vec<Option<(MapKey, V)>>,
+    index: std::collections::HashMap<MapKey, usize>,
+}
+
+impl<V> Default for Map<V> {
+    fn default() -> Self {
+        Map {
+            entries: Vec::new(),
+            index: std::collections::HashMap::new(),
+        }
+    }
+}
+
+impl<V> Map<V> {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&V> {
+        let slot: usize = *self.index.get(key)?;
+        self.entries[slot].as_ref().map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    // Returns the previous value when the key was already present
+    pub fn insert(&mut self, key: MapKey, value: V) -> Option<V> {
+        if let Some(slot) = self.index.get(&key) {
+            let entry: &mut (MapKey, V) = self.entries[*slot].as_mut().expect("live slot");
+            return Some(std::mem::replace(&mut entry.1, value));
+        }
+
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push(Some((key, value)));
+        None
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<V> {
+        let slot: usize = self.index.remove(key)?;
+        let (_, value) = self.entries[slot].take().expect("live slot");
+
+        // Compact once most slots are dead so iteration stays proportional to len
+        if self.entries.len() > 8 && self.index.len() * 2 < self.entries.len() {
+            self.compact();
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &V)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.as_ref().map(|(key, value)| (key, value)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &MapKey> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries
+            .iter_mut()
+            .filter_map(|entry| entry.as_mut().map(|(_, value)| value))
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|entry| entry.is_some());
+        for (slot, entry) in self.entries.iter().enumerate() {
+            let (key, _) = entry.as_ref().expect("retained slot");
+            self.index.insert(key.clone(), slot);
+        }
+    }
+}
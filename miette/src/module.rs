@@ -0,0 +1,416 @@
+// Loads a program along with everything it imports. An import names a file
+// relative to the importing one or, failing that, a package the project
+// depends on or a file in a directory on the search path, which starts out as
+// $MIETTE_PATH:
+//
+//     import "shapes/circle.miette" as circle;
+//     print circle.area(2);
+//
+// Each file is loaded once however many modules import it, and runs before
+// any module importing it. Modules have globals of their own, other modules
+// only see the ones declared with `export`.
+//
+// A module's hash covers its source, the modules it imports and the miette
+// loading it. A build hands the loader the bytecode it made last time by
+// hash, and modules found there are only parsed, for what they import, and
+// not checked or compiled again.
+
+use crate::ast::Stmt;
+use crate::bytecode;
+use crate::checker::Checker;
+use crate::chunk::FunctionProto;
+use crate::compiler::Compiler;
+use crate::error::Diagnostic;
+use crate::lex::{self, Comment, Span, Token, TokenKind};
+use crate::lint::{self, Warning};
+use crate::parser::Parser;
+use crate::resolver::{Locals, Resolver};
+
+pub const EXTENSION: &str = "miette";
+
+pub struct Module {
+    pub path: std::path::PathBuf,
+    // The file name without its extension, as values show it
+    pub name: String,
+    pub source: String,
+    pub statements: Vec<Stmt>,
+    // Empty for a module whose bytecode was reused
+    pub locals: Locals,
+    pub script: std::rc::Rc<FunctionProto>,
+    // Each import's path as written, and the module it loads
+    pub imports: std::collections::HashMap<String, usize>,
+    pub exports: Vec<String>,
+    pub hash: u64,
+}
+
+// Every module of a program, each after the ones it imports, so the main
+// module comes last
+pub struct Program {
+    pub modules: Vec<Module>,
+}
+
+impl Program {
+    pub fn main(&self) -> &Module {
+        self.modules.last().expect("a program has a main module")
+    }
+}
+
+#[derive(Default)]
+pub struct Loader {
+    search_path: Vec<std::path::PathBuf>,
+    // Entry points of the packages a project depends on, by name
+    packages: std::collections::HashMap<String, std::path::PathBuf>,
+    modules: Vec<Module>,
+    // Keyed by canonical path, so two ways of naming a file load it once
+    loaded: std::collections::HashMap<std::path::PathBuf, usize>,
+    failed: std::collections::HashSet<std::path::PathBuf>,
+    // The chain of imports being loaded, canonical and as named, to catch
+    // cycles
+    loading: Vec<(std::path::PathBuf, std::path::PathBuf)>,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Warning>,
+    // Type annotations required on exported functions
+    strict: bool,
+    // Bytecode from an earlier build, by module hash
+    built: std::collections::HashMap<u64, std::rc::Rc<FunctionProto>>,
+}
+
+impl Loader {
+    pub fn new() -> Loader {
+        let search_path: Vec<std::path::PathBuf> = match std::env::var_os("MIETTE_PATH") {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+        Loader {
+            search_path,
+            ..Loader::default()
+        }
+    }
+
+    // Searched in order after the importing file's directory and packages
+    pub fn add_search_path(&mut self, dir: std::path::PathBuf) {
+        self.search_path.push(dir);
+    }
+
+    // `import "name"` loads the package's entry point, and `import
+    // "name/path"` a file beside it
+    pub fn add_package(&mut self, name: &str, entry: std::path::PathBuf) {
+        self.packages.insert(name.to_string(), entry);
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Modules whose hash is among these take that bytecode as it is. Their
+    // warnings were reported when they were built.
+    pub fn reuse(&mut self, built: std::collections::HashMap<u64, std::rc::Rc<FunctionProto>>) {
+        self.built = built;
+    }
+
+    // Loads the program whose main module is `source`, read from `path`.
+    // Problems in other modules say which file they are in.
+    pub fn load(
+        &mut self,
+        path: &std::path::Path,
+        source: &str,
+    ) -> Result<Program, Vec<Diagnostic>> {
+        self.modules.clear();
+        self.loaded.clear();
+        self.failed.clear();
+        self.errors.clear();
+        self.warnings.clear();
+
+        self.load_module(path, source, true);
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(Program {
+            modules: std::mem::take(&mut self.modules),
+        })
+    }
+
+    // Warnings from the last call to load, reported whether or not it failed
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn load_module(&mut self, path: &std::path::Path, source: &str, main: bool) -> Option<usize> {
+        let key: std::path::PathBuf = canonical(path);
+        self.loading.push((key.clone(), path.to_path_buf()));
+        let module: Option<Module> = self.compile(path, source, main);
+        self.loading.pop();
+
+        let Some(module) = module else {
+            self.failed.insert(key);
+            return None;
+        };
+        self.modules.push(module);
+        self.loaded.insert(key, self.modules.len() - 1);
+        Some(self.modules.len() - 1)
+    }
+
+    // Stops at the first stage with errors, as `miette check` does. Imports
+    // are loaded once the module parses.
+    fn compile(&mut self, path: &std::path::Path, source: &str, main: bool) -> Option<Module> {
+        let file: Option<&std::path::Path> = if main { None } else { Some(path) };
+        let (tokens, comments): (std::collections::VecDeque<Token>, Vec<Comment>) =
+            match lex::scan_source_with_comments(source) {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    let error: Diagnostic = match e.downcast::<Diagnostic>() {
+                        Ok(diagnostic) => *diagnostic,
+                        Err(e) => Diagnostic {
+                            message: e.to_string(),
+                            line: 0,
+                            span: Span::default(),
+                            location: String::new(),
+                        },
+                    };
+                    self.error(file, error);
+                    return None;
+                }
+            };
+
+        let statements: Vec<Stmt> = match Parser::new(tokens).parse() {
+            Ok(statements) => statements,
+            Err(errors) => {
+                for error in errors {
+                    self.error(file, error);
+                }
+                return None;
+            }
+        };
+
+        let mut imports: std::collections::HashMap<String, usize> =
+            std::collections::HashMap::new();
+        let mut exports: Vec<String> = Vec::new();
+        let mut complete: bool = true;
+        for statement in statements.iter() {
+            match statement {
+                Stmt::Import { path: literal, .. } => match self.import(path, literal, file) {
+                    Some(index) => {
+                        imports.insert(text(literal).to_string(), index);
+                    }
+                    None => complete = false,
+                },
+                Stmt::Export { declaration, .. } => exports.extend(declared_name(declaration)),
+                _ => {}
+            }
+        }
+
+        let hash: u64 = self.hash(source, &imports);
+        let (locals, script): (Locals, std::rc::Rc<FunctionProto>) = match self.built.get(&hash) {
+            Some(script) if complete => (Locals::new(), script.clone()),
+            _ => self.compile_statements(&statements, &comments, file)?,
+        };
+
+        if !complete {
+            return None;
+        }
+        Some(Module {
+            path: path.to_path_buf(),
+            name: path
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+            source: source.to_string(),
+            statements,
+            locals,
+            script,
+            imports,
+            exports,
+            hash,
+        })
+    }
+
+    // Resolves, checks and compiles a module that parsed
+    fn compile_statements(
+        &mut self,
+        statements: &[Stmt],
+        comments: &[Comment],
+        file: Option<&std::path::Path>,
+    ) -> Option<(Locals, std::rc::Rc<FunctionProto>)> {
+        let mut resolver: Resolver = Resolver::new();
+        let resolved: Result<Locals, Vec<Diagnostic>> = resolver.resolve(statements);
+        for mut warning in lint::suppress(resolver.warnings().to_vec(), comments) {
+            warning.location = located(file, &warning.location);
+            self.warnings.push(warning);
+        }
+        let locals: Locals = match resolved {
+            Ok(locals) => locals,
+            Err(errors) => {
+                for error in errors {
+                    self.error(file, error);
+                }
+                return None;
+            }
+        };
+
+        let mut checker: Checker = Checker::new();
+        checker.set_strict(self.strict);
+        if let Err(errors) = checker.check(statements) {
+            for error in errors {
+                self.error(file, error);
+            }
+            return None;
+        }
+
+        let script: std::rc::Rc<FunctionProto> = match Compiler::new().compile(statements) {
+            Ok(script) => script,
+            Err(errors) => {
+                for error in errors {
+                    self.error(file, error);
+                }
+                return None;
+            }
+        };
+
+        Some((locals, script))
+    }
+
+    // FNV-1a, which unlike std's hasher is the same from one build of miette
+    // to the next, over the versions, the source and each import's path as
+    // written, the file it loads and that module's own hash
+    fn hash(&self, source: &str, imports: &std::collections::HashMap<String, usize>) -> u64 {
+        let mut text: String = format!(
+            "{} {} {}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            bytecode::VERSION,
+            self.strict,
+            source
+        );
+        let mut imports: Vec<(&String, &usize)> = imports.iter().collect();
+        imports.sort();
+        for (written, index) in imports {
+            let module: &Module = &self.modules[*index];
+            text.push_str(&format!(
+                "\n{} {} {:016x}",
+                written,
+                canonical(&module.path).display(),
+                module.hash
+            ));
+        }
+        text.bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte: u8| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    // The index of the module `literal` names, loading it first if need be
+    fn import(
+        &mut self,
+        importer: &std::path::Path,
+        literal: &Token,
+        file: Option<&std::path::Path>,
+    ) -> Option<usize> {
+        let Some(path) = self.find(importer, text(literal)) else {
+            let error: Diagnostic =
+                Diagnostic::at(literal, &format!("Can't find module '{}'.", text(literal)));
+            self.error(file, error);
+            return None;
+        };
+
+        let key: std::path::PathBuf = canonical(&path);
+        if let Some(&index) = self.loaded.get(&key) {
+            return Some(index);
+        }
+        if self.failed.contains(&key) {
+            return None;
+        }
+        if let Some(start) = self.loading.iter().position(|(loading, _)| *loading == key) {
+            let mut chain: String = format!("Import cycle: {}", self.loading[start].1.display());
+            for (_, named) in self.loading[start + 1..].iter() {
+                chain.push_str(&format!(" imports {}, which", named.display()));
+            }
+            chain.push_str(&format!(" imports {}.", path.display()));
+            self.error(file, Diagnostic::at(literal, &chain));
+            return None;
+        }
+
+        match std::fs::read_to_string(&path) {
+            Ok(source) => self.load_module(&path, &source, false),
+            Err(e) => {
+                let message: String = format!("Can't read module '{}': {}.", path.display(), e);
+                self.error(file, Diagnostic::at(literal, &message));
+                self.failed.insert(key);
+                None
+            }
+        }
+    }
+
+    // Next to the importing file first, then in a package, then along the
+    // search path. The extension may be left off.
+    fn find(&self, importer: &std::path::Path, literal: &str) -> Option<std::path::PathBuf> {
+        let with_extension = |path: &str| -> std::path::PathBuf {
+            let mut path: std::path::PathBuf = std::path::PathBuf::from(path);
+            if path.extension().is_none() {
+                path.set_extension(EXTENSION);
+            }
+            path
+        };
+        let relative: std::path::PathBuf = with_extension(literal);
+        let beside: std::path::PathBuf = importer
+            .parent()
+            .unwrap_or(std::path::Path::new(""))
+            .join(&relative);
+        if beside.is_file() {
+            return Some(beside);
+        }
+
+        let (package, rest): (&str, Option<&str>) = match literal.split_once('/') {
+            Some((package, rest)) => (package, Some(rest)),
+            None => (literal, None),
+        };
+        if let Some(entry) = self.packages.get(package) {
+            let path: std::path::PathBuf = match rest {
+                Some(rest) => entry
+                    .parent()
+                    .unwrap_or(std::path::Path::new(""))
+                    .join(with_extension(rest)),
+                None => entry.clone(),
+            };
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+
+        self.search_path
+            .iter()
+            .map(|dir| dir.join(&relative))
+            .find(|path| path.is_file())
+    }
+
+    fn error(&mut self, file: Option<&std::path::Path>, mut error: Diagnostic) {
+        error.location = located(file, &error.location);
+        self.errors.push(error);
+    }
+}
+
+// " at 'x'" becomes " in lib.miette at 'x'" for modules other than the main
+// one, whose problems are reported as they always were
+fn located(file: Option<&std::path::Path>, location: &str) -> String {
+    match file {
+        Some(path) => format!(" in {}{}", path.display(), location),
+        None => location.to_string(),
+    }
+}
+
+// Files that can't be canonicalized, like stdin, go by the name given
+pub(crate) fn canonical(path: &std::path::Path) -> std::path::PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn text(literal: &Token) -> &str {
+    match literal.kind() {
+        TokenKind::Text(text) => text,
+        _ => literal.lexeme(),
+    }
+}
+
+fn declared_name(declaration: &Stmt) -> Option<String> {
+    match declaration {
+        Stmt::Var { name, .. } | Stmt::Class { name, .. } => Some(name.lexeme().to_string()),
+        Stmt::Function { function } => function.name.as_ref().map(|name| name.lexeme().to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,1094 @@
+use crate::ast::{self, Catch, Expr, Field, Function, Literal, Stmt, TypeExpr};
+use crate::error::Diagnostic;
+use crate::lex::{Span, Token, TokenKind};
+
+// Calls are compiled with a one byte argument count
+const MAX_ARGUMENTS: usize = 255;
+
+// Nesting deeper than this is an error rather than a stack overflow, here or
+// in any pass that walks the tree afterwards
+const MAX_DEPTH: usize = 200;
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    errors: Vec<Diagnostic>,
+    // Statements and expressions being parsed inside one another
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: std::collections::VecDeque<Token>) -> Self {
+        let mut tokens: Vec<Token> = tokens.into_iter().collect();
+
+        // Every lookahead relies on the stream ending in EOF
+        if !matches!(
+            tokens.last().map(|token| token.kind()),
+            Some(TokenKind::EOF)
+        ) {
+            let line: usize = tokens.last().map_or(1, |token| token.line());
+            let span: Span = tokens.last().map_or(Span::default(), |token| {
+                Span::new(token.span().end, token.span().end)
+            });
+            tokens.push(Token::new(
+                tokens.len(),
+                TokenKind::EOF,
+                String::new(),
+                line,
+                span,
+            ));
+        }
+
+        Parser {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    // Parses the whole token stream. On failure every error found is returned,
+    // the parser resynchronises at statement boundaries after each one.
+    pub fn parse(mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let mut statements: Vec<Stmt> = Vec::new();
+
+        while !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    // Parses a single expression that must make up the whole input
+    pub fn parse_expression(mut self) -> Result<Expr, Vec<Diagnostic>> {
+        let expr: Result<Expr, Diagnostic> = self.expression().and_then(|expr| {
+            if self.is_at_end() {
+                Ok(expr)
+            } else {
+                Err(self.error(self.peek(), "Expect end of expression."))
+            }
+        });
+
+        match expr {
+            Ok(expr) => Ok(expr),
+            Err(error) => {
+                self.errors.push(error);
+                Err(self.errors)
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> Option<Stmt> {
+        let result: Result<Stmt, Diagnostic> = if self.match_kind(&TokenKind::Class) {
+            self.class_declaration()
+        } else if self.check(&TokenKind::Fun) && self.check_next_identifier() {
+            // `fun` without a name starts a lambda expression statement instead
+            self.advance();
+            self.function("function").map(|function| Stmt::Function {
+                function: std::rc::Rc::new(function),
+            })
+        } else if self.match_kind(&TokenKind::Var) {
+            self.var_declaration()
+        } else if self.check_contextual("test", |kind| matches!(kind, TokenKind::Text(_))) {
+            self.test_declaration()
+        } else if self.check_contextual("import", |kind| matches!(kind, TokenKind::Text(_))) {
+            self.import_declaration()
+        } else if self.check_contextual("export", |kind| {
+            matches!(kind, TokenKind::Var | TokenKind::Fun | TokenKind::Class)
+        }) {
+            self.export_declaration()
+        } else {
+            self.statement()
+        };
+
+        match result {
+            Ok(stmt) => Some(stmt),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let name: Token = self.consume_identifier("Expect class name.")?;
+
+        let superclass: Option<Expr> = if self.match_kind(&TokenKind::Less) {
+            let superclass_name: Token = self.consume_identifier("Expect superclass name.")?;
+            Some(Expr::Variable {
+                id: ast::next_id(),
+                name: superclass_name,
+            })
+        } else {
+            None
+        };
+
+        self.consume(&TokenKind::LeftBracket, "Expect '{' before class body.")?;
+
+        let mut fields: Vec<Field> = Vec::new();
+        let mut methods: Vec<std::rc::Rc<Function>> = Vec::new();
+        while !self.check(&TokenKind::RightBracket) && !self.is_at_end() {
+            if self.check_identifier() && self.check_next(&TokenKind::Colon) {
+                fields.push(self.field()?);
+            } else {
+                methods.push(std::rc::Rc::new(self.function("method")?));
+            }
+        }
+
+        self.consume(&TokenKind::RightBracket, "Expect '}' after class body.")?;
+
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            fields,
+            methods,
+        })
+    }
+
+    // `name: Type;` in a class body
+    fn field(&mut self) -> Result<Field, Diagnostic> {
+        let name: Token = self.advance().clone();
+        self.advance();
+        let annotation: TypeExpr = self.type_annotation()?;
+        self.consume(&TokenKind::SemiColon, "Expect ';' after field type.")?;
+        Ok(Field { name, annotation })
+    }
+
+    // `test`, `import` and `export` are only keywords where a name couldn't
+    // be, so they stay usable as names
+    fn check_contextual(&self, keyword: &str, next: impl Fn(&TokenKind) -> bool) -> bool {
+        matches!(self.peek().kind(), TokenKind::Identifier(name) if name == keyword)
+            && self
+                .tokens
+                .get(self.current + 1)
+                .is_some_and(|token| next(token.kind()))
+    }
+
+    fn test_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let name: Token = self.advance().clone();
+        self.consume(&TokenKind::LeftBracket, "Expect '{' after test name.")?;
+        let body: Vec<Stmt> = self.block()?;
+        Ok(Stmt::Test {
+            keyword,
+            name,
+            body,
+        })
+    }
+
+    fn import_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let path: Token = self.advance().clone();
+        if !self.check_contextual("as", |_| true) {
+            return Err(self.error(self.peek(), "Expect 'as' after import path."));
+        }
+        self.advance();
+        let name: Token = self.consume_identifier("Expect module name after 'as'.")?;
+        self.consume(&TokenKind::SemiColon, "Expect ';' after import.")?;
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            name,
+        })
+    }
+
+    fn export_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let declaration: Stmt = if self.match_kind(&TokenKind::Class) {
+            self.class_declaration()?
+        } else if self.match_kind(&TokenKind::Var) {
+            self.var_declaration()?
+        } else {
+            self.advance();
+            Stmt::Function {
+                function: std::rc::Rc::new(self.function("function")?),
+            }
+        };
+        Ok(Stmt::Export {
+            keyword,
+            declaration: Box::new(declaration),
+        })
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Function, Diagnostic> {
+        let name: Token = self.consume_identifier(&format!("Expect {} name.", kind))?;
+        self.consume(
+            &TokenKind::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let (params, param_types) = self.parameters()?;
+        let return_type: Option<TypeExpr> = self.return_type()?;
+        self.consume(
+            &TokenKind::LeftBracket,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body: Vec<Stmt> = self.block()?;
+
+        Ok(Function {
+            name: Some(name),
+            params,
+            param_types,
+            return_type,
+            body,
+        })
+    }
+
+    // `fun (a, b) { ... }` with the `fun` already consumed
+    fn lambda(&mut self, keyword: Token) -> Result<Expr, Diagnostic> {
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'fun'.")?;
+        let (params, param_types) = self.parameters()?;
+        let return_type: Option<TypeExpr> = self.return_type()?;
+        self.consume(&TokenKind::LeftBracket, "Expect '{' before lambda body.")?;
+        let body: Vec<Stmt> = self.block()?;
+
+        Ok(Expr::Lambda {
+            keyword,
+            function: std::rc::Rc::new(Function {
+                name: None,
+                params,
+                param_types,
+                return_type,
+                body,
+            }),
+        })
+    }
+
+    // `(a, b) -> expression` with the opening parenthesis not yet consumed.
+    // The body is a single expression that becomes the return value, and the
+    // parameters can't have types.
+    fn arrow_lambda(&mut self) -> Result<Expr, Diagnostic> {
+        self.advance();
+        let (params, param_types) = self.parameters()?;
+        let keyword: Token = self
+            .consume(&TokenKind::Arrow, "Expect '->' after lambda parameters.")?
+            .clone();
+        let value: Expr = self.expression()?;
+
+        Ok(Expr::Lambda {
+            keyword: keyword.clone(),
+            function: std::rc::Rc::new(Function {
+                name: None,
+                params,
+                param_types,
+                return_type: None,
+                body: vec![Stmt::Return {
+                    keyword,
+                    value: Some(value),
+                }],
+            }),
+        })
+    }
+
+    // Looks past a parenthesised identifier list for the `->` of an arrow
+    // lambda so it is not mistaken for a grouping
+    fn is_arrow_lambda(&self) -> bool {
+        let mut index: usize = self.current + 1;
+        let kind_at = |index: usize| self.tokens.get(index).map(|token| token.kind());
+
+        if !matches!(kind_at(index), Some(TokenKind::RightParen)) {
+            loop {
+                if !matches!(kind_at(index), Some(TokenKind::Identifier(_))) {
+                    return false;
+                }
+                index += 1;
+                match kind_at(index) {
+                    Some(TokenKind::Comma) => index += 1,
+                    Some(TokenKind::RightParen) => break,
+                    _ => return false,
+                }
+            }
+        }
+
+        matches!(kind_at(index + 1), Some(TokenKind::Arrow))
+    }
+
+    // Parameter list after the opening parenthesis, consumes the closing one.
+    // Each parameter may have a type, `name: Type`.
+    fn parameters(&mut self) -> Result<(Vec<Token>, Vec<Option<TypeExpr>>), Diagnostic> {
+        let mut params: Vec<Token> = Vec::new();
+        let mut param_types: Vec<Option<TypeExpr>> = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    let error: Diagnostic = self.error(
+                        self.peek(),
+                        &format!("Can't have more than {} parameters.", MAX_ARGUMENTS),
+                    );
+                    self.errors.push(error);
+                }
+                params.push(self.consume_identifier("Expect parameter name.")?);
+                param_types.push(if self.match_kind(&TokenKind::Colon) {
+                    Some(self.type_annotation()?)
+                } else {
+                    None
+                });
+                if !self.match_kind(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenKind::RightParen, "Expect ')' after parameters.")?;
+        Ok((params, param_types))
+    }
+
+    // `: Type` after a parameter list
+    fn return_type(&mut self) -> Result<Option<TypeExpr>, Diagnostic> {
+        if self.match_kind(&TokenKind::Colon) {
+            Ok(Some(self.type_annotation()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // `A | B?`, where `?` binds tighter than `|`
+    fn type_annotation(&mut self) -> Result<TypeExpr, Diagnostic> {
+        let mut types: Vec<TypeExpr> = vec![self.optional_type()?];
+        while self.match_kind(&TokenKind::Pipe) {
+            types.push(self.optional_type()?);
+        }
+
+        if types.len() == 1 {
+            Ok(types.remove(0))
+        } else {
+            Ok(TypeExpr::Union { types })
+        }
+    }
+
+    fn optional_type(&mut self) -> Result<TypeExpr, Diagnostic> {
+        let name: Token = self.consume_identifier("Expect type name.")?;
+        let annotation: TypeExpr = TypeExpr::Named { name };
+        if !self.check(&TokenKind::Question) {
+            return Ok(annotation);
+        }
+        Ok(TypeExpr::Optional {
+            inner: Box::new(annotation),
+            question: self.advance().clone(),
+        })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let name: Token = self.consume_identifier("Expect variable name.")?;
+        let annotation: Option<TypeExpr> = if self.match_kind(&TokenKind::Colon) {
+            Some(self.type_annotation()?)
+        } else {
+            None
+        };
+
+        let initializer: Option<Expr> = if self.match_kind(&TokenKind::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            &TokenKind::SemiColon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var {
+            name,
+            annotation,
+            initializer,
+        })
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Diagnostic> {
+        self.nested(Self::statement_kind)
+    }
+
+    fn statement_kind(&mut self) -> Result<Stmt, Diagnostic> {
+        match self.peek().kind() {
+            TokenKind::For => self.for_statement(),
+            TokenKind::If => self.if_statement(),
+            TokenKind::Print => self.print_statement(),
+            TokenKind::Return => self.return_statement(),
+            TokenKind::While => self.while_statement(),
+            TokenKind::Throw => self.throw_statement(),
+            TokenKind::Try => self.try_statement(),
+            TokenKind::Break => {
+                let keyword: Token = self.advance().clone();
+                self.consume(&TokenKind::SemiColon, "Expect ';' after 'break'.")?;
+                Ok(Stmt::Break { keyword })
+            }
+            TokenKind::Continue => {
+                let keyword: Token = self.advance().clone();
+                self.consume(&TokenKind::SemiColon, "Expect ';' after 'continue'.")?;
+                Ok(Stmt::Continue { keyword })
+            }
+            TokenKind::LeftBracket => {
+                self.advance();
+                Ok(Stmt::Block {
+                    statements: self.block()?,
+                })
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check_identifier() && self.check_next(&TokenKind::In) {
+            return self.for_in_statement(keyword);
+        }
+
+        let initializer: Option<Box<Stmt>> = if self.match_kind(&TokenKind::SemiColon) {
+            None
+        } else if self.match_kind(&TokenKind::Var) {
+            Some(Box::new(self.var_declaration()?))
+        } else {
+            Some(Box::new(self.expression_statement()?))
+        };
+
+        let condition: Option<Expr> = if self.check(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenKind::SemiColon, "Expect ';' after loop condition.")?;
+
+        let increment: Option<Expr> = if self.check(&TokenKind::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenKind::RightParen, "Expect ')' after for clauses.")?;
+
+        let body: Box<Stmt> = Box::new(self.statement()?);
+
+        Ok(Stmt::For {
+            keyword,
+            initializer,
+            condition,
+            increment,
+            body,
+        })
+    }
+
+    // `for (name in iterable) body` with the opening parenthesis consumed
+    fn for_in_statement(&mut self, keyword: Token) -> Result<Stmt, Diagnostic> {
+        let variable: Token = self.consume_identifier("Expect loop variable name.")?;
+        self.consume(&TokenKind::In, "Expect 'in' after loop variable.")?;
+        let iterable: Expr = self.expression()?;
+        self.consume(&TokenKind::RightParen, "Expect ')' after for-in clause.")?;
+        let body: Box<Stmt> = Box::new(self.statement()?);
+
+        Ok(Stmt::ForIn {
+            keyword,
+            variable,
+            iterable,
+            body,
+        })
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'if'.")?;
+        let condition: Expr = self.expression()?;
+        self.consume(&TokenKind::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch: Box<Stmt> = Box::new(self.statement()?);
+        let else_branch: Option<Box<Stmt>> = if self.match_kind(&TokenKind::Else) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            keyword,
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let expression: Expr = self.expression()?;
+        self.consume(&TokenKind::SemiColon, "Expect ';' after value.")?;
+        Ok(Stmt::Print {
+            keyword,
+            expression,
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let value: Option<Expr> = if self.check(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenKind::SemiColon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        let value: Expr = self.expression()?;
+        self.consume(&TokenKind::SemiColon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        self.consume(&TokenKind::LeftBracket, "Expect '{' after 'try'.")?;
+        let body: Vec<Stmt> = self.block()?;
+
+        let catch: Option<Catch> = if self.match_kind(&TokenKind::Catch) {
+            self.consume(&TokenKind::LeftParen, "Expect '(' after 'catch'.")?;
+            let name: Token = self.consume_identifier("Expect error variable name.")?;
+            self.consume(&TokenKind::RightParen, "Expect ')' after error variable.")?;
+            self.consume(&TokenKind::LeftBracket, "Expect '{' before catch body.")?;
+            Some(Catch {
+                name,
+                body: self.block()?,
+            })
+        } else {
+            None
+        };
+
+        let finally: Option<Vec<Stmt>> = if self.match_kind(&TokenKind::Finally) {
+            self.consume(&TokenKind::LeftBracket, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(self.peek(), "Expect 'catch' or 'finally' after try block."));
+        }
+
+        Ok(Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword: Token = self.advance().clone();
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'while'.")?;
+        let condition: Expr = self.expression()?;
+        self.consume(&TokenKind::RightParen, "Expect ')' after condition.")?;
+        let body: Box<Stmt> = Box::new(self.statement()?);
+
+        Ok(Stmt::While {
+            keyword,
+            condition,
+            body,
+        })
+    }
+
+    // Statements up to the closing brace, the opening one is already consumed
+    fn block(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        let mut statements: Vec<Stmt> = Vec::new();
+
+        while !self.check(&TokenKind::RightBracket) && !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+
+        self.consume(&TokenKind::RightBracket, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let expression: Expr = self.expression()?;
+        self.consume(&TokenKind::SemiColon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression { expression })
+    }
+
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, Diagnostic> {
+        let expr: Expr = self.or()?;
+
+        if self.check(&TokenKind::Equal) {
+            let equals: Token = self.advance().clone();
+            let value: Box<Expr> = Box::new(self.expression()?);
+
+            return match expr {
+                Expr::Variable { name, .. } => Ok(Expr::Assign {
+                    id: ast::next_id(),
+                    name,
+                    value,
+                }),
+                Expr::Get { object, name } => Ok(Expr::Set {
+                    object,
+                    name,
+                    value,
+                }),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Expr::IndexSet {
+                    object,
+                    bracket,
+                    index,
+                    value,
+                }),
+                _ => {
+                    // Report without unwinding, the rest of the statement is fine
+                    let error: Diagnostic = self.error(&equals, "Invalid assignment target.");
+                    self.errors.push(error);
+                    Ok(expr)
+                }
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, Diagnostic> {
+        let expr: Expr = self.and()?;
+
+        self.chain(|parser| {
+            let mut expr: Expr = expr;
+            while parser.check(&TokenKind::Or) {
+                parser.deeper()?;
+                let operator: Token = parser.advance().clone();
+                let right: Expr = parser.and()?;
+                expr = Expr::Logical {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+            Ok(expr)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, Diagnostic> {
+        let expr: Expr = self.equality()?;
+
+        self.chain(|parser| {
+            let mut expr: Expr = expr;
+            while parser.check(&TokenKind::And) {
+                parser.deeper()?;
+                let operator: Token = parser.advance().clone();
+                let right: Expr = parser.equality()?;
+                expr = Expr::Logical {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+            Ok(expr)
+        })
+    }
+
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(
+            &[TokenKind::BangEqual, TokenKind::EqualEqual],
+            Parser::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(
+            &[
+                TokenKind::Greater,
+                TokenKind::GreaterEqual,
+                TokenKind::Less,
+                TokenKind::LessEqual,
+            ],
+            Parser::range,
+        )
+    }
+
+    // `start..end` or `start..=end`, ranges do not chain
+    fn range(&mut self) -> Result<Expr, Diagnostic> {
+        let expr: Expr = self.term()?;
+
+        if self.check(&TokenKind::DotDot) || self.check(&TokenKind::DotDotEqual) {
+            let operator: Token = self.advance().clone();
+            let end: Expr = self.term()?;
+            return Ok(Expr::Range {
+                start: Box::new(expr),
+                inclusive: matches!(operator.kind(), TokenKind::DotDotEqual),
+                operator,
+                end: Box::new(end),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(&[TokenKind::Minus, TokenKind::Plus], Parser::factor)
+    }
+
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(&[TokenKind::Slash, TokenKind::Star], Parser::unary)
+    }
+
+    // Left associative binary operators that all bind tighter than `operand`
+    fn binary(
+        &mut self,
+        operators: &[TokenKind],
+        operand: fn(&mut Parser) -> Result<Expr, Diagnostic>,
+    ) -> Result<Expr, Diagnostic> {
+        let expr: Expr = operand(self)?;
+
+        self.chain(|parser| {
+            let mut expr: Expr = expr;
+            while operators.iter().any(|kind| parser.check(kind)) {
+                parser.deeper()?;
+                let operator: Token = parser.advance().clone();
+                let right: Expr = operand(parser)?;
+                expr = Expr::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            }
+            Ok(expr)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        if self.check(&TokenKind::Bang) || self.check(&TokenKind::Minus) {
+            let operator: Token = self.advance().clone();
+            let right: Expr = self.nested(Self::unary)?;
+            return Ok(Expr::Unary {
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expr, Diagnostic> {
+        let expr: Expr = self.primary()?;
+
+        self.chain(|parser| {
+            let mut expr: Expr = expr;
+            while [
+                TokenKind::LeftParen,
+                TokenKind::Dot,
+                TokenKind::LeftSBracket,
+            ]
+            .iter()
+            .any(|kind| parser.check(kind))
+            {
+                parser.deeper()?;
+                if parser.match_kind(&TokenKind::LeftParen) {
+                    expr = parser.finish_call(expr)?;
+                } else if parser.match_kind(&TokenKind::Dot) {
+                    let name: Token =
+                        parser.consume_identifier("Expect property name after '.'.")?;
+                    expr = Expr::Get {
+                        object: Box::new(expr),
+                        name,
+                    };
+                } else {
+                    let bracket: Token = parser.advance().clone();
+                    expr = parser.finish_index(expr, bracket)?;
+                }
+            }
+            Ok(expr)
+        })
+    }
+
+    // `[index]` or `[start:end]` where either slice bound may be left out
+    fn finish_index(&mut self, object: Expr, bracket: Token) -> Result<Expr, Diagnostic> {
+        let start: Option<Box<Expr>> = if self.check(&TokenKind::Colon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+
+        if self.match_kind(&TokenKind::Colon) {
+            let end: Option<Box<Expr>> = if self.check(&TokenKind::RightSBracket) {
+                None
+            } else {
+                Some(Box::new(self.expression()?))
+            };
+            self.consume(&TokenKind::RightSBracket, "Expect ']' after slice.")?;
+
+            return Ok(Expr::Slice {
+                object: Box::new(object),
+                bracket,
+                start,
+                end,
+            });
+        }
+
+        self.consume(&TokenKind::RightSBracket, "Expect ']' after index.")?;
+        Ok(Expr::Index {
+            object: Box::new(object),
+            bracket,
+            // Only None when a colon followed, which returned above
+            index: start.expect("index expression"),
+        })
+    }
+
+    // Map literal entries, a trailing comma is allowed. Only reached in
+    // expression position, a `{` that starts a statement is always a block.
+    fn map(&mut self, brace: Token) -> Result<Expr, Diagnostic> {
+        let mut entries: Vec<(Expr, Expr)> = Vec::new();
+
+        while !self.check(&TokenKind::RightBracket) {
+            let key: Expr = self.expression()?;
+            self.consume(&TokenKind::Colon, "Expect ':' after map key.")?;
+            let value: Expr = self.expression()?;
+            entries.push((key, value));
+            if !self.match_kind(&TokenKind::Comma) {
+                break;
+            }
+        }
+
+        self.consume(&TokenKind::RightBracket, "Expect '}' after map entries.")?;
+        Ok(Expr::Map { brace, entries })
+    }
+
+    // List literal elements, a trailing comma is allowed
+    fn list(&mut self, bracket: Token) -> Result<Expr, Diagnostic> {
+        let mut elements: Vec<Expr> = Vec::new();
+
+        while !self.check(&TokenKind::RightSBracket) {
+            elements.push(self.expression()?);
+            if !self.match_kind(&TokenKind::Comma) {
+                break;
+            }
+        }
+
+        self.consume(&TokenKind::RightSBracket, "Expect ']' after list elements.")?;
+        Ok(Expr::List { bracket, elements })
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, Diagnostic> {
+        let mut arguments: Vec<Expr> = Vec::new();
+
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    let error: Diagnostic = self.error(
+                        self.peek(),
+                        &format!("Can't have more than {} arguments.", MAX_ARGUMENTS),
+                    );
+                    self.errors.push(error);
+                }
+                arguments.push(self.expression()?);
+                if !self.match_kind(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+
+        let paren: Token = self
+            .consume(&TokenKind::RightParen, "Expect ')' after arguments.")?
+            .clone();
+
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        let token: Token = self.peek().clone();
+
+        let value: Literal = match token.kind() {
+            TokenKind::False => Literal::Bool(false),
+            TokenKind::True => Literal::Bool(true),
+            TokenKind::Nil => Literal::Nil,
+            TokenKind::Number(n) => Literal::Number(*n),
+            TokenKind::Text(s) => Literal::Text(s.clone()),
+            TokenKind::Identifier(_) => {
+                self.advance();
+                return Ok(Expr::Variable {
+                    id: ast::next_id(),
+                    name: token,
+                });
+            }
+            TokenKind::This => {
+                self.advance();
+                return Ok(Expr::This {
+                    id: ast::next_id(),
+                    keyword: token,
+                });
+            }
+            TokenKind::Super => {
+                self.advance();
+                self.consume(&TokenKind::Dot, "Expect '.' after 'super'.")?;
+                let method: Token = self.consume_identifier("Expect superclass method name.")?;
+                return Ok(Expr::Super {
+                    id: ast::next_id(),
+                    keyword: token,
+                    method,
+                });
+            }
+            TokenKind::Fun => {
+                self.advance();
+                return self.lambda(token);
+            }
+            TokenKind::LeftSBracket => {
+                self.advance();
+                return self.list(token);
+            }
+            TokenKind::LeftBracket => {
+                self.advance();
+                return self.map(token);
+            }
+            TokenKind::LeftParen if self.is_arrow_lambda() => {
+                return self.arrow_lambda();
+            }
+            TokenKind::LeftParen => {
+                self.advance();
+                let expression: Expr = self.expression()?;
+                self.consume(&TokenKind::RightParen, "Expect ')' after expression.")?;
+                return Ok(Expr::Grouping {
+                    expression: Box::new(expression),
+                });
+            }
+            _ => return Err(self.error(&token, "Expect expression.")),
+        };
+
+        self.advance();
+        Ok(Expr::Literal { value, token })
+    }
+
+    // Skips tokens until a likely statement boundary so one mistake does not
+    // cascade into a page of follow-on errors
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if let TokenKind::SemiColon = self.previous().kind() {
+                return;
+            }
+
+            match self.peek().kind() {
+                TokenKind::Class
+                | TokenKind::Fun
+                | TokenKind::Var
+                | TokenKind::For
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::Print
+                | TokenKind::Return
+                | TokenKind::Throw
+                | TokenKind::Try => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, kind: &TokenKind, message: &str) -> Result<&Token, Diagnostic> {
+        if self.check(kind) {
+            return Ok(self.advance());
+        }
+
+        Err(self.error(self.peek(), message))
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> Result<Token, Diagnostic> {
+        if let TokenKind::Identifier(_) = self.peek().kind() {
+            return Ok(self.advance().clone());
+        }
+
+        Err(self.error(self.peek(), message))
+    }
+
+    fn match_kind(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    // Compares only the kind of token, not any value it carries
+    fn check(&self, kind: &TokenKind) -> bool {
+        std::mem::discriminant(self.peek().kind()) == std::mem::discriminant(kind)
+    }
+
+    fn check_identifier(&self) -> bool {
+        matches!(self.peek().kind(), TokenKind::Identifier(_))
+    }
+
+    fn check_next_identifier(&self) -> bool {
+        matches!(
+            self.tokens.get(self.current + 1).map(|token| token.kind()),
+            Some(TokenKind::Identifier(_))
+        )
+    }
+
+    fn check_next(&self, kind: &TokenKind) -> bool {
+        self.tokens.get(self.current + 1).is_some_and(|token| {
+            std::mem::discriminant(token.kind()) == std::mem::discriminant(kind)
+        })
+    }
+
+    fn advance(&mut self) -> &Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        matches!(self.peek().kind(), TokenKind::EOF)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
+    }
+
+    fn error(&self, token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::at(token, message)
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, Diagnostic>,
+    ) -> Result<T, Diagnostic> {
+        self.chain(|parser| {
+            parser.deeper()?;
+            parse(parser)
+        })
+    }
+
+    // Gives back the levels `parse` went deeper by, however it ends. Loops
+    // that build left-nested trees, like `a + b + c` or `a[0][1]`, go one
+    // level deeper each time round, as each pass wraps what came before.
+    fn chain<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, Diagnostic>,
+    ) -> Result<T, Diagnostic> {
+        let depth: usize = self.depth;
+        let result: Result<T, Diagnostic> = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    fn deeper(&mut self) -> Result<(), Diagnostic> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(self.peek(), "Too deeply nested."));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+}
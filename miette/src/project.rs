@@ -0,0 +1,280 @@
+// A project is a directory with a Miette.toml, see manifest.rs. The packages
+// it depends on are projects too, and `miette build` compiles the modules of
+// all of them into its target directory:
+//
+//     target/app/main.mtc
+//     target/geometry/shapes.mtc
+//     target/.hashes
+//
+// Each module's bytecode is stored under its package's name, at its path from
+// the directory holding the package's entry point, and says where the files
+// of the modules it imports are, so `miette run target/app/main.mtc` runs the
+// whole program. .hashes records the hash of the module each file was built
+// from, see module.rs, so a rebuild only compiles the modules that changed,
+// or import one that did, and leaves the files of the others alone.
+
+use crate::bytecode;
+use crate::chunk::FunctionProto;
+use crate::error::Diagnostic;
+use crate::manifest::{self, Manifest};
+use crate::module::{self, Loader, Program};
+
+pub const TARGET: &str = "target";
+const HASHES: &str = ".hashes";
+
+pub struct Package {
+    pub manifest: Manifest,
+    pub root: std::path::PathBuf,
+}
+
+impl Package {
+    pub fn entry(&self) -> std::path::PathBuf {
+        self.root.join(&self.manifest.entry)
+    }
+
+    fn source_dir(&self) -> std::path::PathBuf {
+        let entry: std::path::PathBuf = self.entry();
+        entry.parent().unwrap_or(&self.root).to_path_buf()
+    }
+}
+
+pub struct Project {
+    pub root: std::path::PathBuf,
+    // The project's own package first, then everything it depends on, each
+    // once
+    pub packages: Vec<Package>,
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    Compile(Vec<Diagnostic>),
+    // The entry point can't be read, or target/ can't be written
+    Read(String),
+    Write(String),
+}
+
+#[derive(Debug, Default)]
+pub struct Build {
+    // Bytecode files in dependency order, those of the modules this build
+    // compiled and those left as they were
+    pub compiled: Vec<std::path::PathBuf>,
+    pub fresh: Vec<std::path::PathBuf>,
+}
+
+impl Project {
+    pub fn open(root: &std::path::Path) -> Result<Project, String> {
+        let mut project: Project = Project {
+            root: module::canonical(root),
+            packages: Vec::new(),
+        };
+        project.add(root, &mut Vec::new())?;
+        Ok(project)
+    }
+
+    // Reads the package in `dir`, then what it depends on. `chain` holds the
+    // names of the packages that led here, to catch cycles.
+    fn add(&mut self, dir: &std::path::Path, chain: &mut Vec<String>) -> Result<(), String> {
+        let manifest: Manifest = manifest::read(dir)?;
+        let root: std::path::PathBuf = module::canonical(dir);
+        if let Some(start) = chain.iter().position(|name| *name == manifest.name) {
+            let mut message: String = format!("dependency cycle: {}", chain[start]);
+            for name in chain[start + 1..].iter() {
+                message.push_str(&format!(" depends on {}, which", name));
+            }
+            message.push_str(&format!(" depends on {}", manifest.name));
+            return Err(message);
+        }
+        if let Some(existing) = self
+            .packages
+            .iter()
+            .find(|package| package.manifest.name == manifest.name)
+        {
+            if existing.root == root {
+                return Ok(());
+            }
+            return Err(format!(
+                "two packages are named '{}': {} and {}",
+                manifest.name,
+                existing.root.display(),
+                root.display()
+            ));
+        }
+
+        let dependencies: Vec<manifest::Dependency> = manifest.dependencies.clone();
+        chain.push(manifest.name.clone());
+        self.packages.push(Package {
+            manifest,
+            root: root.clone(),
+        });
+        for dependency in dependencies {
+            self.add(&root.join(&dependency.path), chain)?;
+        }
+        chain.pop();
+        Ok(())
+    }
+
+    pub fn main(&self) -> &Package {
+        &self.packages[0]
+    }
+
+    // A loader that finds the packages the project depends on by name
+    pub fn loader(&self) -> Loader {
+        let mut loader: Loader = Loader::new();
+        for package in self.packages.iter().skip(1) {
+            loader.add_package(&package.manifest.name, package.entry());
+        }
+        loader
+    }
+
+    // Loads the program starting at the project's entry point
+    pub fn load(&self, loader: &mut Loader) -> Result<Program, BuildError> {
+        let entry: std::path::PathBuf = self.main().entry();
+        let source: String = std::fs::read_to_string(&entry)
+            .map_err(|e| BuildError::Read(format!("can't read {}: {}", entry.display(), e)))?;
+        loader.load(&entry, &source).map_err(BuildError::Compile)
+    }
+
+    pub fn build(&self, loader: &mut Loader) -> Result<Build, BuildError> {
+        let target: std::path::PathBuf = self.root.join(TARGET);
+        let previous: std::collections::HashMap<std::path::PathBuf, u64> =
+            read_hashes(&target.join(HASHES));
+        // The files of the last build that can still be loaded, whose
+        // modules need no compiling if they haven't changed since
+        let mut intact: std::collections::HashSet<&std::path::PathBuf> =
+            std::collections::HashSet::new();
+        let mut built: std::collections::HashMap<u64, std::rc::Rc<FunctionProto>> =
+            std::collections::HashMap::new();
+        for (artifact, hash) in previous.iter() {
+            let Ok(bytes) = std::fs::read(target.join(artifact)) else {
+                continue;
+            };
+            if let Ok(loaded) = bytecode::load_artifact(&bytes) {
+                intact.insert(artifact);
+                built.insert(*hash, loaded.script);
+            }
+        }
+        loader.reuse(built);
+
+        let program: Program = self.load(loader)?;
+        let artifacts: Vec<std::path::PathBuf> = program
+            .modules
+            .iter()
+            .map(|module| self.artifact(&module.path))
+            .collect();
+
+        let mut build: Build = Build::default();
+        let mut hashes: Vec<(std::path::PathBuf, u64)> = Vec::new();
+        for (module, artifact) in program.modules.iter().zip(artifacts.iter()) {
+            let path: std::path::PathBuf = target.join(artifact);
+            hashes.push((artifact.clone(), module.hash));
+            if previous.get(artifact) == Some(&module.hash) && intact.contains(artifact) {
+                build.fresh.push(path);
+                continue;
+            }
+
+            let mut imports: Vec<(String, String)> = module
+                .imports
+                .iter()
+                .map(|(written, index)| (written.clone(), relative(artifact, &artifacts[*index])))
+                .collect();
+            // The same bytes from one build to the next
+            imports.sort();
+            let bytes: Vec<u8> = bytecode::write_artifact(&bytecode::Artifact {
+                script: module.script.clone(),
+                exports: module.exports.clone(),
+                imports,
+            });
+            write(&path, &bytes).map_err(BuildError::Write)?;
+            build.compiled.push(path);
+        }
+
+        let text: String = hashes
+            .iter()
+            .map(|(artifact, hash)| format!("{:016x} {}\n", hash, artifact.display()))
+            .collect();
+        write(&target.join(HASHES), text.as_bytes()).map_err(BuildError::Write)?;
+        Ok(build)
+    }
+
+    // Where a module's bytecode goes in target/. Modules from the search path
+    // belong to no package and go in target/ itself.
+    fn artifact(&self, path: &std::path::Path) -> std::path::PathBuf {
+        let path: std::path::PathBuf = module::canonical(path);
+        let owner: Option<(&Package, std::path::PathBuf)> = self
+            .packages
+            .iter()
+            .filter_map(|package| {
+                let relative: &std::path::Path = path
+                    .strip_prefix(module::canonical(&package.source_dir()))
+                    .ok()?;
+                Some((package, relative.to_path_buf()))
+            })
+            // A package nested in another's directory owns its own files
+            .min_by_key(|(_, relative)| relative.components().count());
+
+        let artifact: std::path::PathBuf = match owner {
+            Some((package, relative)) => {
+                std::path::Path::new(&package.manifest.name).join(relative)
+            }
+            None => std::path::PathBuf::from(path.file_name().unwrap_or_default()),
+        };
+        artifact.with_extension(bytecode::EXTENSION)
+    }
+}
+
+// Makes a project in `dir`, named after the directory, whose program says
+// hello
+pub fn create(dir: &std::path::Path) -> Result<(), String> {
+    let name: String = dir
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    if !manifest::is_valid_name(&name) {
+        return Err(manifest::invalid_name(&name));
+    }
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()));
+    }
+
+    let entry: std::path::PathBuf = dir.join(manifest::DEFAULT_ENTRY);
+    let files: [(std::path::PathBuf, String); 3] = [
+        (dir.join(manifest::FILE_NAME), manifest::template(&name)),
+        (entry, "print \"Hello, world!\";\n".to_string()),
+        (dir.join(".gitignore"), format!("/{}\n", TARGET)),
+    ];
+    for (path, contents) in files.iter() {
+        write(path, contents.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write(path: &std::path::Path, bytes: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("can't create {}: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, bytes).map_err(|e| format!("can't write {}: {}", path.display(), e))
+}
+
+// A missing or damaged file only means everything is compiled again
+fn read_hashes(path: &std::path::Path) -> std::collections::HashMap<std::path::PathBuf, u64> {
+    let text: String = std::fs::read_to_string(path).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| {
+            let (hash, artifact) = line.split_once(' ')?;
+            let hash: u64 = u64::from_str_radix(hash, 16).ok()?;
+            Some((std::path::PathBuf::from(artifact), hash))
+        })
+        .collect()
+}
+
+// Where the file `to` in target/ is from the directory of `from`, with `/`
+// between components whatever the platform
+fn relative(from: &std::path::Path, to: &std::path::Path) -> String {
+    let up: usize = from.components().count().saturating_sub(1);
+    let mut parts: Vec<String> = vec!["..".to_string(); up];
+    parts.extend(
+        to.components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
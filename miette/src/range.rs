@@ -0,0 +1,73 @@
+// Numeric ranges produced by `start..end` and `start..=end`, shared by every
+// backend. A range counts up from start in steps of one; a range whose end is
+// below its start is empty rather than counting down.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub inclusive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeError(pub f64);
+
+impl std::fmt::Display for RangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Range bounds must be finite numbers, got {}.", self.0)
+    }
+}
+
+impl std::error::Error for RangeError {}
+
+impl Range {
+    pub fn new(start: f64, end: f64, inclusive: bool) -> Result<Range, RangeError> {
+        if !start.is_finite() {
+            return Err(RangeError(start));
+        }
+        if !end.is_finite() {
+            return Err(RangeError(end));
+        }
+        Ok(Range {
+            start,
+            end,
+            inclusive,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        let span: f64 = self.end - self.start;
+        if span < 0.0 {
+            return 0;
+        }
+        if self.inclusive {
+            span.floor() as usize + 1
+        } else {
+            span.ceil() as usize
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<f64> {
+        if index < self.len() {
+            Some(self.start + index as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f64> + use<> {
+        let range: Range = *self;
+        (0..range.len()).map(move |index| range.start + index as f64)
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator: &str = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)
+    }
+}
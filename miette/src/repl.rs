@@ -0,0 +1,261 @@
+// Interactive sessions for `miette repl`. Each entry runs on the same
+// interpreter, so globals defined by one entry are visible to the next. An
+// entry continues over several lines while it has an unclosed bracket or
+// string, and a blank line submits it regardless.
+use crate::ast::Stmt;
+use crate::checker::Checker;
+use crate::error::Diagnostic;
+use crate::interpreter::Interpreter;
+use crate::interpreter::value::Value;
+use crate::lex;
+use crate::lint::{self, Warning};
+use crate::parser::Parser;
+use crate::resolver::{Locals, Resolver};
+use std::io::Write;
+
+pub const HELP: &str = "\
+:help           show this help
+:load <file>    run a file in this session
+:tokens <code>  show the tokens of <code>
+:ast <code>     show the syntax tree of <code>
+:reset          forget every definition
+:quit           leave, as does end of input
+
+Expressions show their value, and a lone expression needs no semicolon.";
+
+const PROMPT: &str = "> ";
+const CONTINUATION: &str = "... ";
+
+// The interpreter owns its output, and the REPL prints results to the same
+// place, so both write through one shared handle
+#[derive(Clone)]
+struct Shared(std::rc::Rc<std::cell::RefCell<Box<dyn std::io::Write>>>);
+
+impl std::io::Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+pub struct Repl {
+    interpreter: Interpreter,
+    out: Shared,
+    err: Box<dyn std::io::Write>,
+    // Entries are appended here when set
+    history: Option<std::path::PathBuf>,
+    prompt: bool,
+}
+
+impl Repl {
+    // Results and `print` go to `out`, errors and warnings to `err`
+    pub fn new(out: Box<dyn std::io::Write>, err: Box<dyn std::io::Write>) -> Repl {
+        let out: Shared = Shared(std::rc::Rc::new(std::cell::RefCell::new(out)));
+        Repl {
+            interpreter: Interpreter::with_output(Box::new(out.clone())),
+            out,
+            err,
+            history: None,
+            prompt: false,
+        }
+    }
+
+    // Appends every entry to `path`. There is no line editing to recall them
+    // with, the file is there to search through.
+    pub fn history(&mut self, path: std::path::PathBuf) {
+        self.history = Some(path);
+    }
+
+    // Prompts only help a person at a terminal, piped sessions leave them off
+    pub fn prompt(&mut self, prompt: bool) {
+        self.prompt = prompt;
+    }
+
+    // Reads entries until end of input or :quit. Only failing to read or
+    // write the session itself is an error, mistakes in entries are reported
+    // and the session carries on.
+    pub fn run(&mut self, input: &mut dyn std::io::BufRead) -> std::io::Result<()> {
+        let mut buffer: String = String::new();
+        loop {
+            if self.prompt {
+                let prompt: &str = if buffer.is_empty() {
+                    PROMPT
+                } else {
+                    CONTINUATION
+                };
+                write!(self.out, "{}", prompt)?;
+                self.out.flush()?;
+            }
+
+            let mut line: String = String::new();
+            if input.read_line(&mut line)? == 0 {
+                if !buffer.trim().is_empty() {
+                    self.submit(&buffer)?;
+                }
+                return Ok(());
+            }
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                self.record(line.trim());
+                if !self.command(line.trim())? {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            buffer.push_str(&line);
+            if !line.trim().is_empty() && lex::is_incomplete(&buffer) {
+                continue;
+            }
+            let entry: String = std::mem::take(&mut buffer);
+            if !entry.trim().is_empty() {
+                self.submit(&entry)?;
+            }
+        }
+    }
+
+    fn submit(&mut self, entry: &str) -> std::io::Result<()> {
+        self.record(entry.trim_end());
+        self.eval(entry)
+    }
+
+    // Runs one complete entry
+    pub fn eval(&mut self, source: &str) -> std::io::Result<()> {
+        let (tokens, comments) = match lex::scan_source_with_comments(source) {
+            Ok(scanned) => scanned,
+            Err(e) => return writeln!(self.err, "{}", e),
+        };
+
+        // Statements first, so `x;` is a statement. Failing that, a lone
+        // expression without its semicolon.
+        let statements: Vec<Stmt> = match Parser::new(tokens.clone()).parse() {
+            Ok(statements) => statements,
+            Err(errors) => match Parser::new(tokens).parse_expression() {
+                Ok(expression) => vec![Stmt::Expression { expression }],
+                Err(_) => {
+                    for error in errors.iter() {
+                        writeln!(self.err, "{}", error)?;
+                    }
+                    return Ok(());
+                }
+            },
+        };
+
+        let mut resolver: Resolver = Resolver::new();
+        let resolved: Result<Locals, Vec<Diagnostic>> = resolver.resolve(&statements);
+        let warnings: Vec<Warning> = lint::suppress(resolver.warnings().to_vec(), &comments);
+        for warning in warnings.iter() {
+            writeln!(self.err, "{}", warning)?;
+        }
+        let locals: Locals = match resolved {
+            Ok(locals) => locals,
+            Err(errors) => {
+                for error in errors.iter() {
+                    writeln!(self.err, "{}", error)?;
+                }
+                return Ok(());
+            }
+        };
+        if let Err(errors) = Checker::new().check(&statements) {
+            for error in errors.iter() {
+                writeln!(self.err, "{}", error)?;
+            }
+            return Ok(());
+        }
+        self.interpreter.resolve(locals);
+
+        // Expression statements show their value, unless it is nil
+        for statement in statements.iter() {
+            let result = match statement {
+                Stmt::Expression { expression } => {
+                    self.interpreter.interpret_expression(expression)
+                }
+                _ => self
+                    .interpreter
+                    .interpret(std::slice::from_ref(statement))
+                    .map(|()| Value::Nil),
+            };
+            match result {
+                Ok(Value::Nil) => {}
+                Ok(value) => writeln!(self.out, "{}", value)?,
+                // Later statements of the entry are skipped, as in a script
+                Err(error) => return writeln!(self.err, "{}", error),
+            }
+        }
+        Ok(())
+    }
+
+    // Returns false for :quit
+    fn command(&mut self, line: &str) -> std::io::Result<bool> {
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+
+        match name {
+            ":help" => writeln!(self.out, "{}", HELP)?,
+            ":quit" | ":exit" => return Ok(false),
+            ":reset" => {
+                self.interpreter = Interpreter::with_output(Box::new(self.out.clone()));
+            }
+            ":load" => match std::fs::read_to_string(argument) {
+                Ok(source) => self.eval(&source)?,
+                Err(_) => writeln!(self.err, "Failed to read the file contents")?,
+            },
+            ":tokens" => match lex::scan_source(argument) {
+                Ok(tokens) => {
+                    for token in tokens.iter() {
+                        writeln!(self.out, "{}", token)?;
+                    }
+                }
+                Err(e) => writeln!(self.err, "{}", e)?,
+            },
+            ":ast" => self.ast(argument)?,
+            _ => writeln!(
+                self.err,
+                "Unknown command '{}', :help lists the commands.",
+                name
+            )?,
+        }
+        Ok(true)
+    }
+
+    // An expression, or failing that a list of statements
+    fn ast(&mut self, source: &str) -> std::io::Result<()> {
+        let tokens = match lex::scan_source(source) {
+            Ok(tokens) => tokens,
+            Err(e) => return writeln!(self.err, "{}", e),
+        };
+        let errors = match Parser::new(tokens.clone()).parse_expression() {
+            Ok(expression) => return writeln!(self.out, "{:#?}", expression),
+            Err(errors) => errors,
+        };
+        match Parser::new(tokens).parse() {
+            Ok(statements) => writeln!(self.out, "{:#?}", statements),
+            Err(_) => {
+                for error in errors.iter() {
+                    writeln!(self.err, "{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // History is a convenience, a file that can't be written doesn't stop the
+    // session
+    fn record(&mut self, entry: &str) {
+        let Some(path) = &self.history else {
+            return;
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path);
+        if let Ok(mut file) = file {
+            let _ = writeln!(file, "{}", entry);
+        }
+    }
+}
@@ -0,0 +1,613 @@
+use crate::ast::{Expr, Function, Literal, NodeId, Stmt};
+use crate::error::Diagnostic;
+use crate::lex::Token;
+use crate::lint::{Lint, Warning};
+use crate::visit::{self, Visitor};
+
+// How many scopes out from its use each local variable was declared.
+// Variables missing from the table are globals.
+pub type Locals = std::collections::HashMap<NodeId, usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    // The name an import binds
+    Module,
+    // `this` and `super`, bound by the language rather than declared
+    Implicit,
+}
+
+// Where a name was declared and as what
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub name: Token,
+    pub kind: BindingKind,
+}
+
+// A declaration or use of a variable, for editor tooling. Uses of globals that
+// nothing in the program declares have no declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: Token,
+    pub declaration: Option<Declaration>,
+}
+
+struct Binding {
+    kind: BindingKind,
+    // None for implicit bindings
+    name: Option<Token>,
+    // False between the declaration and the end of its initializer
+    defined: bool,
+    // Whether anything reads it, assignments alone do not count
+    used: bool,
+}
+
+// Binds every variable use to its declaration ahead of execution, and reports
+// the scope errors that can be found without running the program
+pub struct Resolver {
+    // Innermost last
+    scopes: Vec<std::collections::HashMap<String, Binding>>,
+    locals: Locals,
+    function: FunctionKind,
+    class: ClassKind,
+    loop_depth: usize,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Warning>,
+    references: Vec<Reference>,
+    // The first top-level declaration of each global name
+    globals: std::collections::HashMap<String, Declaration>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            locals: Locals::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            loop_depth: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            references: Vec::new(),
+            globals: std::collections::HashMap::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Result<Locals, Vec<Diagnostic>> {
+        self.resolve_statements(statements);
+        self.warnings
+            .sort_by_key(|warning| (warning.line, warning.span.start));
+
+        // Globals bind late, so a use may come before its declaration
+        for reference in self.references.iter_mut() {
+            if reference.declaration.is_none() {
+                reference.declaration = self.globals.get(reference.name.lexeme()).cloned();
+            }
+        }
+        self.references
+            .sort_by_key(|reference| reference.name.span().start);
+
+        if self.errors.is_empty() {
+            Ok(std::mem::take(&mut self.locals))
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    // Warnings from the last call to resolve, in source order. They are
+    // reported whether or not resolution failed.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // Every declaration and use of a variable from the last call to resolve,
+    // in source order
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(std::collections::HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+
+        for binding in scope.values() {
+            let Some(name) = &binding.name else { continue };
+            if binding.used || name.lexeme().starts_with('_') {
+                continue;
+            }
+
+            let (lint, what): (Lint, &str) = match binding.kind {
+                BindingKind::Variable => (Lint::UnusedVariable, "variable"),
+                BindingKind::Parameter => (Lint::UnusedParameter, "parameter"),
+                BindingKind::Function => (Lint::UnusedFunction, "function"),
+                BindingKind::Class | BindingKind::Module | BindingKind::Implicit => continue,
+            };
+            self.warnings.push(Warning::at(
+                lint,
+                name,
+                &format!("Unused {} '{}'.", what, name.lexeme()),
+            ));
+        }
+    }
+
+    // Globals are not tracked, so only locals are checked for duplicates,
+    // shadowing and use
+    fn declare(&mut self, name: &Token, kind: BindingKind) {
+        let declaration: Declaration = Declaration {
+            name: name.clone(),
+            kind,
+        };
+        let Some((scope, enclosing)) = self.scopes.split_last_mut() else {
+            self.globals
+                .entry(name.lexeme().to_string())
+                .or_insert_with(|| declaration.clone());
+            self.references.push(Reference {
+                name: name.clone(),
+                declaration: Some(declaration),
+            });
+            return;
+        };
+
+        if scope.contains_key(name.lexeme()) {
+            self.error(name, "Already a variable with this name in this scope.");
+            return;
+        }
+        scope.insert(
+            name.lexeme().to_string(),
+            Binding {
+                kind,
+                name: Some(name.clone()),
+                defined: false,
+                used: false,
+            },
+        );
+        self.references.push(Reference {
+            name: name.clone(),
+            declaration: Some(declaration),
+        });
+
+        let shadows: bool = enclosing.iter().any(|scope| {
+            scope
+                .get(name.lexeme())
+                .is_some_and(|binding| binding.kind != BindingKind::Implicit)
+        });
+        if shadows {
+            self.warnings.push(Warning::at(
+                Lint::ShadowedName,
+                name,
+                &format!(
+                    "'{}' shadows a name from an enclosing scope.",
+                    name.lexeme()
+                ),
+            ));
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if let Some(binding) = self.scopes.last_mut().and_then(|scope| scope.get_mut(name)) {
+            binding.defined = true;
+        }
+    }
+
+    fn define_implicit(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.to_string(),
+                Binding {
+                    kind: BindingKind::Implicit,
+                    name: None,
+                    defined: true,
+                    used: true,
+                },
+            );
+        }
+    }
+
+    fn resolve_local(&mut self, id: NodeId, name: &str, read: bool) {
+        for (depth, scope) in self.scopes.iter_mut().rev().enumerate() {
+            if let Some(binding) = scope.get_mut(name) {
+                binding.used |= read;
+                self.locals.insert(id, depth);
+                return;
+            }
+        }
+    }
+
+    // Uses of globals are left without a declaration until the whole program
+    // has been seen
+    fn reference(&mut self, name: &Token) {
+        let binding: Option<&Binding> = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name.lexeme()));
+        let declaration: Option<Declaration> = match binding {
+            Some(Binding {
+                name: Some(declared),
+                kind,
+                ..
+            }) => Some(Declaration {
+                name: declared.clone(),
+                kind: *kind,
+            }),
+            Some(_) => return,
+            None => None,
+        };
+        self.references.push(Reference {
+            name: name.clone(),
+            declaration,
+        });
+    }
+
+    // Statements after a return, break or continue in the same list never run.
+    // A jump that was itself an error, like a `break` outside a loop, already
+    // has its diagnostic and isn't warned about again.
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        let mut jumped: bool = false;
+        for statement in statements {
+            if jumped && let Some(token) = statement.first_token() {
+                self.warnings.push(Warning::at(
+                    Lint::UnreachableCode,
+                    token,
+                    "Unreachable code.",
+                ));
+                jumped = false;
+            }
+            let errors: usize = self.errors.len();
+            self.visit_stmt(statement);
+            if self.errors.len() == errors
+                && matches!(
+                    statement,
+                    Stmt::Return { .. }
+                        | Stmt::Break { .. }
+                        | Stmt::Continue { .. }
+                        | Stmt::Throw { .. }
+                )
+            {
+                jumped = true;
+            }
+        }
+    }
+
+    // Warns about literal conditions. `while (true)` is left alone since it is
+    // how an endless loop is written.
+    fn check_condition(&mut self, condition: &Expr, is_loop: bool) {
+        let mut condition: &Expr = condition;
+        while let Expr::Grouping { expression } = condition {
+            condition = expression;
+        }
+
+        let Expr::Literal { value, token } = condition else {
+            return;
+        };
+        let truthy: bool = !matches!(value, Literal::Nil | Literal::Bool(false));
+        if is_loop && *value == Literal::Bool(true) {
+            return;
+        }
+
+        let message: &str = if truthy {
+            "Condition is always true."
+        } else {
+            "Condition is always false."
+        };
+        self.warnings
+            .push(Warning::at(Lint::ConstantCondition, token, message));
+    }
+
+    fn resolve_function(&mut self, function: &Function, kind: FunctionKind) {
+        let enclosing_function: FunctionKind = self.function;
+        let enclosing_loops: usize = self.loop_depth;
+        self.function = kind;
+        // A loop around the declaration does not make `break` legal inside it
+        self.loop_depth = 0;
+
+        self.begin_scope();
+        for param in function.params.iter() {
+            self.declare(param, BindingKind::Parameter);
+            self.define(param.lexeme());
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+
+        self.function = enclosing_function;
+        self.loop_depth = enclosing_loops;
+    }
+
+    fn resolve_loop_body(&mut self, body: &Stmt) {
+        self.loop_depth += 1;
+        self.visit_stmt(body);
+        self.loop_depth -= 1;
+    }
+
+    fn resolve_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[std::rc::Rc<Function>],
+    ) {
+        let enclosing_class: ClassKind = self.class;
+        self.class = ClassKind::Class;
+
+        self.declare(name, BindingKind::Class);
+        self.define(name.lexeme());
+
+        if let Some(superclass) = superclass {
+            if let Expr::Variable {
+                name: superclass_name,
+                ..
+            } = superclass
+                && superclass_name.lexeme() == name.lexeme()
+            {
+                self.error(superclass_name, "A class can't inherit from itself.");
+            }
+
+            self.class = ClassKind::Subclass;
+            self.visit_expr(superclass);
+
+            self.begin_scope();
+            self.define_implicit("super");
+        }
+
+        self.begin_scope();
+        self.define_implicit("this");
+        for method in methods {
+            let is_init: bool = method
+                .name
+                .as_ref()
+                .is_some_and(|name| name.lexeme() == "init");
+            let kind: FunctionKind = if is_init {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.resolve_function(method, kind);
+        }
+        self.end_scope();
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.class = enclosing_class;
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(Diagnostic::at(token, message));
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                self.declare(name, BindingKind::Variable);
+                if let Some(initializer) = initializer {
+                    self.visit_expr(initializer);
+                }
+                self.define(name.lexeme());
+            }
+            Stmt::Function { function } => {
+                if let Some(name) = &function.name {
+                    // Defined before the body so the function can recurse
+                    self.declare(name, BindingKind::Function);
+                    self.define(name.lexeme());
+                }
+                self.resolve_function(function, FunctionKind::Function);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => self.resolve_class(name, superclass, methods),
+            Stmt::Import { keyword, name, .. } => {
+                if !self.scopes.is_empty() {
+                    self.error(keyword, "Imports must be at the top level.");
+                }
+                self.declare(name, BindingKind::Module);
+                self.define(name.lexeme());
+            }
+            Stmt::Export {
+                keyword,
+                declaration,
+            } => {
+                if !self.scopes.is_empty() {
+                    self.error(keyword, "Exports must be at the top level.");
+                }
+                self.visit_stmt(declaration);
+            }
+            Stmt::Test { keyword, body, .. } => {
+                // Run on their own after the program, so nothing can refer to
+                // them and they can only see globals
+                if !self.scopes.is_empty() {
+                    self.error(keyword, "Tests must be declared at the top level.");
+                }
+                self.begin_scope();
+                self.resolve_statements(body);
+                self.end_scope();
+            }
+            Stmt::Return { keyword, value } => {
+                if self.function == FunctionKind::None {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer.");
+                    }
+                    self.visit_expr(value);
+                }
+            }
+            Stmt::Break { keyword } => {
+                if self.loop_depth == 0 {
+                    self.error(keyword, "Can't use 'break' outside of a loop.");
+                }
+            }
+            Stmt::Continue { keyword } => {
+                if self.loop_depth == 0 {
+                    self.error(keyword, "Can't use 'continue' outside of a loop.");
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.check_condition(condition, false);
+                self.visit_expr(condition);
+                self.visit_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.check_condition(condition, true);
+                self.visit_expr(condition);
+                self.resolve_loop_body(body);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                // The initializer's variables live in a scope around the loop
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.visit_stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.check_condition(condition, true);
+                    self.visit_expr(condition);
+                }
+                if let Some(increment) = increment {
+                    self.visit_expr(increment);
+                }
+                self.resolve_loop_body(body);
+                self.end_scope();
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(iterable);
+                self.begin_scope();
+                self.declare(variable, BindingKind::Variable);
+                self.define(variable.lexeme());
+                self.resolve_loop_body(body);
+                self.end_scope();
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_statements(body);
+                self.end_scope();
+                if let Some(catch) = catch {
+                    self.begin_scope();
+                    self.declare(&catch.name, BindingKind::Variable);
+                    self.define(catch.name.lexeme());
+                    self.resolve_statements(&catch.body);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_statements(finally);
+                    self.end_scope();
+                }
+            }
+            _ => visit::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable { id, name } => {
+                if let Some(binding) = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(name.lexeme()))
+                    && !binding.defined
+                {
+                    self.error(name, "Can't read local variable in its own initializer.");
+                }
+                self.resolve_local(*id, name.lexeme(), true);
+                self.reference(name);
+            }
+            Expr::Assign { id, name, value } => {
+                self.visit_expr(value);
+                self.resolve_local(*id, name.lexeme(), false);
+                self.reference(name);
+            }
+            Expr::This { id, keyword } => {
+                if self.class == ClassKind::None {
+                    self.error(keyword, "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_local(*id, "this", true);
+            }
+            Expr::Super { id, keyword, .. } => {
+                match self.class {
+                    ClassKind::None => {
+                        self.error(keyword, "Can't use 'super' outside of a class.");
+                        return;
+                    }
+                    ClassKind::Class => {
+                        self.error(keyword, "Can't use 'super' in a class with no superclass.");
+                        return;
+                    }
+                    ClassKind::Subclass => {}
+                }
+                self.resolve_local(*id, "super", true);
+            }
+            Expr::Lambda { function, .. } => {
+                self.resolve_function(function, FunctionKind::Function);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
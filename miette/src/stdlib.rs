@@ -0,0 +1,201 @@
+// The parts of the standard library that don't depend on how a backend
+// represents values, so the interpreter and the VM share one definition of
+// each rule and message.
+
+use crate::error::{ErrorKind, RuntimeError};
+
+// Names of every built-in function, for tools that need to know them without
+// starting a backend
+pub const NAMES: &[&str] = &[
+    "clock",
+    "len",
+    "push",
+    "pop",
+    "insert",
+    "remove",
+    "keys",
+    "values",
+    "contains",
+    "delete",
+    "str",
+    "num",
+    "type",
+    "input",
+    "sqrt",
+    "floor",
+    "pow",
+    "random",
+    "seed",
+    "split",
+    "trim",
+    "upper",
+    "lower",
+    "replace",
+    "substr",
+    "assert",
+    "assert_eq",
+    "assert_throws",
+    "error",
+];
+
+// Calls back into the script, so each backend runs it in its call path
+// rather than through its table of natives
+pub const ASSERT_THROWS: &str = "assert_throws";
+
+// What a built-in's failures are caught as
+pub fn error_kind(name: &str) -> ErrorKind {
+    match name {
+        "assert" | "assert_eq" | ASSERT_THROWS => ErrorKind::AssertionError,
+        "input" => ErrorKind::IoError,
+        _ => ErrorKind::ArgumentError,
+    }
+}
+
+// What `error(message)` makes. The trace is left empty until it is thrown.
+pub fn error(message: &str) -> RuntimeError {
+    RuntimeError {
+        kind: ErrorKind::Error,
+        message: message.to_string(),
+        line: 0,
+        span: crate::lex::Span::default(),
+        trace: Vec::new(),
+    }
+}
+
+pub fn clock() -> Result<f64, String> {
+    let elapsed: std::time::Duration = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+    Ok(elapsed.as_secs_f64())
+}
+
+// xorshift64*, small and plenty for scripts. Not for anything secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        Rng(if seed == 0 { 0x9e3779b97f4a7c15 } else { seed })
+    }
+
+    // Seeded from the clock, so each run differs unless the script seeds it
+    pub fn from_clock() -> Self {
+        let nanos: u128 = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        Rng::new(nanos as u64)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let bits: u64 = self.0.wrapping_mul(0x2545f4914f6cdd1d);
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn seed(n: f64) -> Result<Rng, String> {
+    if !n.is_finite() {
+        return Err(format!("seed() expects a finite number, got {}.", n));
+    }
+    Ok(Rng::new(n.to_bits()))
+}
+
+// Reads a line from stdin without its line ending, None at end of input
+pub fn input() -> Result<Option<String>, String> {
+    let mut line: String = String::new();
+    let read: usize = std::io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| format!("input() failed: {}.", e))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    let trimmed: usize = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+// Surrounding whitespace is ignored, anything else unparseable gives None
+pub fn parse_number(s: &str) -> Option<f64> {
+    let trimmed: &str = s.trim();
+    // Rust also accepts words like "inf" and "NaN", scripts only get digits
+    if !trimmed
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+    {
+        return None;
+    }
+    trimmed.parse::<f64>().ok()
+}
+
+pub fn sqrt(n: f64) -> Result<f64, String> {
+    if n < 0.0 {
+        return Err(format!("sqrt() of a negative number {}.", n));
+    }
+    Ok(n.sqrt())
+}
+
+// An empty separator splits into characters
+pub fn split(s: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() {
+        return s.chars().map(String::from).collect();
+    }
+    s.split(separator).map(String::from).collect()
+}
+
+pub fn replace(s: &str, from: &str, to: &str) -> Result<String, String> {
+    if from.is_empty() {
+        return Err("replace() can't replace an empty string.".to_string());
+    }
+    Ok(s.replace(from, to))
+}
+
+// Counts characters, not bytes. A negative start counts from the end and a
+// length running past the end stops there, like a slice.
+pub fn substr(s: &str, start: f64, length: f64) -> Result<String, String> {
+    if start.fract() != 0.0 {
+        return Err(format!("substr() start must be an integer, got {}.", start));
+    }
+    if length.fract() != 0.0 || length < 0.0 {
+        return Err(format!(
+            "substr() length must be a non-negative integer, got {}.",
+            length
+        ));
+    }
+
+    let count: f64 = s.chars().count() as f64;
+    let from: f64 = if start < 0.0 { count + start } else { start };
+    let from: usize = from.clamp(0.0, count) as usize;
+    Ok(s.chars()
+        .skip(from)
+        .take(length.min(count) as usize)
+        .collect())
+}
+
+pub fn expected(name: &str, kind: &str, type_name: &str) -> String {
+    format!("{}() expects {}, got a {}.", name, kind, type_name)
+}
+
+// The assertion messages take values as each backend shows them, strings
+// quoted so `"1"` and `1` tell apart
+pub fn assert_failed(value: &str) -> String {
+    format!("assert() failed, got {}.", value)
+}
+
+pub fn assert_eq_failed(actual: &str, expected: &str) -> String {
+    format!(
+        "assert_eq() failed, expected {} but got {}.",
+        expected, actual
+    )
+}
+
+pub fn assert_throws_failed(returned: &str) -> String {
+    format!(
+        "assert_throws() failed, the function returned {} without an error.",
+        returned
+    )
+}
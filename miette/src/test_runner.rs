@@ -0,0 +1,498 @@
+// `miette test`: runs every .miette file under a directory and checks it
+// against the expectations written in its comments.
+//
+//     print 1 + 2;       // expect: 3
+//     print nil + 1;     // expect runtime error: Operands must be two numbers or two strings.
+//     var a = ;          // error at ';': Expect expression.
+//     // [line 9] error at end: Expect '}' after block.
+//
+// `expect:` lines are the program's output in order. A runtime error must be
+// raised on the comment's line, or by a call made from it. Compile errors are
+// expected on the comment's line unless a `[line N]` prefix says otherwise, for
+// errors that can't share a line with a comment. A program with compile
+// errors isn't run.
+//
+// Each top-level `test "name" { ... }` block is a test of its own. It runs
+// after a fresh run of the program, seeing its globals, and passes unless it
+// raises an error, as a failed `assert` does. A file of test blocks is only
+// checked as a program too when it has expectations.
+
+use crate::ast::Stmt;
+use crate::chunk::FunctionProto;
+use crate::compiler::Compiler;
+use crate::diff;
+use crate::error::{Diagnostic, RuntimeError};
+use crate::interpreter::{self, Interpreter};
+use crate::lex::{self, Span, TokenKind};
+use crate::module::{EXTENSION, Loader, Program};
+use crate::parser::Parser;
+use crate::vm::Vm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Vm,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expectations {
+    pub output: String,
+    // Line and message
+    pub runtime_error: Option<(usize, String)>,
+    // Line and the error as it's printed, `[line N] Error at 'x': ...`
+    pub errors: Vec<(usize, String)>,
+}
+
+// A file's program, or one of its test blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub path: std::path::PathBuf,
+    // The block's position among the file's tests and its name
+    pub test: Option<(usize, String)>,
+}
+
+impl std::fmt::Display for Case {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.test {
+            Some((_, name)) => write!(f, "{}: {}", self.path.display(), name),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+// What running one case found, passing when there are no failures
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub case: Case,
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// Every .miette file under `root` in path order, or `root` itself when it's a
+// file
+pub fn discover(root: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    if root.is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+
+    let mut found: Vec<std::path::PathBuf> = Vec::new();
+    let mut pending: Vec<std::path::PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path: std::path::PathBuf = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+pub fn expectations(source: &str) -> Expectations {
+    let mut expectations: Expectations = Expectations::default();
+    for (index, text) in source.lines().enumerate() {
+        let line: usize = index + 1;
+        // Any `//` could start the comment, as strings may hold one too
+        for (start, _) in text.match_indices("// ") {
+            if expect(&mut expectations, line, &text[start + 3..]) {
+                break;
+            }
+        }
+    }
+    expectations.errors.sort_by_key(|(line, _)| *line);
+    expectations
+}
+
+// Records the expectation in a comment, false when it holds none
+fn expect(expectations: &mut Expectations, line: usize, comment: &str) -> bool {
+    if let Some(expected) = comment.strip_prefix("expect: ") {
+        expectations.output.push_str(expected);
+        expectations.output.push('\n');
+    } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+        expectations.runtime_error = Some((line, message.to_string()));
+    } else if let Some((line, rest)) = expected_error(line, comment) {
+        expectations
+            .errors
+            .push((line, format!("[line {}] Error{}", line, rest)));
+    } else {
+        return false;
+    }
+    true
+}
+
+// `error at 'x': message` or `[line N] error: message`, giving the line and
+// what follows `error`. Errors in an imported file start `error in path`.
+fn expected_error(line: usize, comment: &str) -> Option<(usize, &str)> {
+    let (line, comment): (usize, &str) = match comment.strip_prefix("[line ") {
+        Some(rest) => {
+            let (number, rest) = rest.split_once("] ")?;
+            (number.parse::<usize>().ok()?, rest)
+        }
+        None => (line, comment),
+    };
+    let rest: &str = comment.strip_prefix("error")?;
+    if rest.starts_with(": ") || rest.starts_with(" at ") || rest.starts_with(" in ") {
+        Some((line, rest))
+    } else {
+        None
+    }
+}
+
+// The cases in a file: its program unless it only holds test blocks, then
+// each of those
+pub fn cases(path: &std::path::Path) -> Vec<Case> {
+    let program: Case = Case {
+        path: path.to_path_buf(),
+        test: None,
+    };
+    // Reading it again when run reports the problem
+    let Ok(source) = std::fs::read_to_string(path) else {
+        return vec![program];
+    };
+    let names: Vec<String> = match parse(&source) {
+        Ok(statements) => tests(&statements).map(|(name, _)| name).collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut cases: Vec<Case> = Vec::new();
+    if names.is_empty() || expectations(&source) != Expectations::default() {
+        cases.push(program);
+    }
+    for (index, name) in names.into_iter().enumerate() {
+        cases.push(Case {
+            path: path.to_path_buf(),
+            test: Some((index, name)),
+        });
+    }
+    cases
+}
+
+// Runs the cases on `jobs` threads, returning their outcomes in the order
+// given
+pub fn run_all(cases: &[Case], backend: Backend, jobs: usize) -> Vec<Outcome> {
+    let next: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let outcomes: std::sync::Mutex<Vec<Option<Outcome>>> =
+        std::sync::Mutex::new(vec![None; cases.len()]);
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, cases.len().max(1)) {
+            // The stack `miette run` has, so deep recursion fails the same way
+            std::thread::Builder::new()
+                .stack_size(interpreter::STACK_SIZE)
+                .spawn_scoped(scope, || {
+                    loop {
+                        let index: usize = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(case) = cases.get(index) else {
+                            break;
+                        };
+                        let outcome: Outcome = run_case(case, backend);
+                        outcomes.lock().expect("no worker panics")[index] = Some(outcome);
+                    }
+                })
+                .expect("spawning a test thread");
+        }
+    });
+
+    outcomes
+        .into_inner()
+        .expect("no worker panics")
+        .into_iter()
+        .map(|outcome| outcome.expect("every case was run"))
+        .collect()
+}
+
+pub fn run_case(case: &Case, backend: Backend) -> Outcome {
+    let failures: Vec<String> = match std::fs::read_to_string(&case.path) {
+        Ok(source) => match &case.test {
+            Some((index, _)) => check_test_at(&case.path, &source, *index, backend),
+            None => check_at(&case.path, &source, backend),
+        },
+        Err(e) => vec![format!("can't read the file: {}", e)],
+    };
+    Outcome {
+        case: case.clone(),
+        failures,
+    }
+}
+
+// Everything about the program that differs from its expectations. Its
+// imports are relative to the working directory.
+pub fn check(source: &str, backend: Backend) -> Vec<String> {
+    check_at(std::path::Path::new(""), source, backend)
+}
+
+fn check_at(path: &std::path::Path, source: &str, backend: Backend) -> Vec<String> {
+    let expected: Expectations = expectations(source);
+    let mut failures: Vec<String> = Vec::new();
+
+    let compiled: Compiled = match compile(path, source) {
+        Ok(compiled) => compiled,
+        Err(mut errors) => {
+            errors.sort_by_key(|error| error.line);
+            let actual: Vec<(usize, String)> = errors
+                .iter()
+                .map(|error| (error.line, error.to_string()))
+                .collect();
+            if actual != expected.errors {
+                failures.push(compile_errors_differ(&expected.errors, &actual));
+            }
+            return failures;
+        }
+    };
+    if !expected.errors.is_empty() {
+        failures.push(compile_errors_differ(&expected.errors, &[]));
+    }
+
+    let capture: Capture = Capture::default();
+    let mut session: Session = Session::new(backend, &capture);
+    let error: Option<RuntimeError> = session.run_program(&compiled.program).err();
+    let output: String = capture.take();
+    if output != expected.output {
+        failures.push(format!(
+            "output differs:\n{}",
+            diff::unified(&expected.output, &output, "expected", "actual")
+        ));
+    }
+
+    match (&expected.runtime_error, &error) {
+        (None, None) => {}
+        (Some((line, message)), None) => failures.push(format!(
+            "expected runtime error on line {}: {}\nbut the program finished",
+            line, message
+        )),
+        (None, Some(error)) => failures.push(format!(
+            "unexpected runtime error: {}",
+            runtime_failure(source, error)
+        )),
+        (Some((line, message)), Some(error)) => {
+            let lines: Vec<usize> = if error.trace.is_empty() {
+                vec![error.line]
+            } else {
+                error.trace.iter().map(|frame| frame.line).collect()
+            };
+            if *message != error.message || !lines.contains(line) {
+                failures.push(format!(
+                    "expected runtime error on line {}: {}\nbut got: {}",
+                    line, message, error
+                ));
+            }
+        }
+    }
+    failures
+}
+
+// Runs the program and then its `index`th test block, which fails by raising
+// an error. What the test printed is shown with its failure.
+pub fn check_test(source: &str, index: usize, backend: Backend) -> Vec<String> {
+    check_test_at(std::path::Path::new(""), source, index, backend)
+}
+
+fn check_test_at(
+    path: &std::path::Path,
+    source: &str,
+    index: usize,
+    backend: Backend,
+) -> Vec<String> {
+    let compiled: Compiled = match compile(path, source) {
+        Ok(compiled) => compiled,
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            return vec![format!("the file doesn't compile:\n{}", errors.join("\n"))];
+        }
+    };
+    let Some(test) = compiled.tests.get(index) else {
+        return vec![format!("the file has no test number {}", index + 1)];
+    };
+
+    let capture: Capture = Capture::default();
+    let mut session: Session = Session::new(backend, &capture);
+    if let Err(error) = session.run_program(&compiled.program) {
+        return vec![format!(
+            "the program failed before the test ran: {}",
+            runtime_failure(source, &error)
+        )];
+    }
+    capture.take();
+
+    let Err(error) = session.run(&test.block, test.script.clone()) else {
+        return Vec::new();
+    };
+    let mut failure: String = runtime_failure(source, &error);
+    let output: String = capture.take();
+    if !output.is_empty() {
+        failure.push_str("\noutput:\n");
+        failure.push_str(output.trim_end_matches('\n'));
+    }
+    vec![failure]
+}
+
+// The error with the source it points at under its message, as in
+//
+//     assert_eq() failed, expected 4 but got 3.
+//       5 | assert_eq(add(1, 2), 4)
+//     [line 5] in script
+fn runtime_failure(source: &str, error: &RuntimeError) -> String {
+    let printed: String = error.to_string();
+    let Some(snippet) = snippet(source, error.span) else {
+        return printed;
+    };
+    let trace: &str = printed.strip_prefix(error.message.as_str()).unwrap_or("");
+    format!("{}\n  {} | {}{}", error.message, error.line, snippet, trace)
+}
+
+// The first line of the source in `span`, marking the rest as left out
+fn snippet(source: &str, span: Span) -> Option<String> {
+    let text: &str = source.get(span.start..span.end)?.trim();
+    match text.split_once('\n') {
+        _ if text.is_empty() => None,
+        Some((first, _)) => Some(format!("{} ...", first.trim_end())),
+        None => Some(text.to_string()),
+    }
+}
+
+fn compile_errors_differ(expected: &[(usize, String)], actual: &[(usize, String)]) -> String {
+    let lines = |errors: &[(usize, String)]| -> String {
+        errors
+            .iter()
+            .map(|(_, error)| format!("{}\n", error))
+            .collect()
+    };
+    format!(
+        "compile errors differ:\n{}",
+        diff::unified(&lines(expected), &lines(actual), "expected", "actual")
+    )
+}
+
+struct Compiled {
+    program: Program,
+    tests: Vec<Test>,
+}
+
+// A test block's body as a block statement, so it runs in a scope of its own
+// as the resolver saw it
+struct Test {
+    block: Vec<Stmt>,
+    script: std::rc::Rc<FunctionProto>,
+}
+
+// The top-level test blocks with their names
+fn tests(statements: &[Stmt]) -> impl Iterator<Item = (String, &[Stmt])> {
+    statements.iter().filter_map(|statement| match statement {
+        Stmt::Test { name, body, .. } => match name.kind() {
+            TokenKind::Text(name) => Some((name.clone(), body.as_slice())),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn parse(source: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let tokens = match lex::scan_source(source) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return Err(match e.downcast::<Diagnostic>() {
+                Ok(diagnostic) => vec![*diagnostic],
+                Err(e) => vec![Diagnostic {
+                    message: e.to_string(),
+                    line: 0,
+                    span: Span::default(),
+                    location: String::new(),
+                }],
+            });
+        }
+    };
+    Parser::new(tokens).parse()
+}
+
+// The compiler checks even interpreted programs, as `miette run` does. Test
+// blocks are compiled on their own, as the program leaves them out.
+fn compile(path: &std::path::Path, source: &str) -> Result<Compiled, Vec<Diagnostic>> {
+    let program: Program = Loader::new().load(path, source)?;
+
+    let mut compiled: Vec<Test> = Vec::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    for (_, body) in tests(&program.main().statements) {
+        let block: Vec<Stmt> = vec![Stmt::Block {
+            statements: body.to_vec(),
+        }];
+        match Compiler::new().compile(&block) {
+            Ok(script) => compiled.push(Test { block, script }),
+            Err(mut found) => errors.append(&mut found),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Compiled {
+        program,
+        tests: compiled,
+    })
+}
+
+#[derive(Clone, Default)]
+struct Capture(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl Capture {
+    // What was written since the last take
+    fn take(&self) -> String {
+        let bytes: Vec<u8> = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl std::io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A backend whose globals last from one run to the next, as a test block
+// needs the program's
+enum Session {
+    Interpreter(Interpreter),
+    Vm(Vm),
+}
+
+impl Session {
+    fn new(backend: Backend, capture: &Capture) -> Session {
+        match backend {
+            Backend::Interpreter => {
+                Session::Interpreter(Interpreter::with_output(Box::new(capture.clone())))
+            }
+            Backend::Vm => Session::Vm(Vm::with_output(Box::new(capture.clone()))),
+        }
+    }
+
+    fn run_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        match self {
+            Session::Interpreter(interpreter) => interpreter.interpret_program(program),
+            Session::Vm(vm) => vm.interpret_program(program),
+        }
+    }
+
+    // Each backend takes the form of the code it runs
+    fn run(
+        &mut self,
+        statements: &[Stmt],
+        script: std::rc::Rc<FunctionProto>,
+    ) -> Result<(), RuntimeError> {
+        match self {
+            Session::Interpreter(interpreter) => interpreter.interpret(statements),
+            Session::Vm(vm) => vm.interpret(script),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use crate::ast::{Expr, Function, Stmt};
+
+// Read-only traversal. Passes override the hooks they care about and call the
+// matching walk_* function to keep descending into the children.
+pub trait Visitor: Sized {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function);
+    }
+}
+
+pub fn walk_stmts<V: Visitor>(visitor: &mut V, stmts: &[Stmt]) {
+    for stmt in stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expression { expression } => visitor.visit_expr(expression),
+        Stmt::Print { expression, .. } => visitor.visit_expr(expression),
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr(initializer);
+            }
+        }
+        Stmt::Block { statements } => walk_stmts(visitor, statements),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_stmt(initializer);
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expr(condition);
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment);
+            }
+            visitor.visit_stmt(body);
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            visitor.visit_expr(iterable);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Function { function } => visitor.visit_function(function),
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr(superclass);
+            }
+            for method in methods {
+                visitor.visit_function(method);
+            }
+        }
+        Stmt::Test { body, .. } => walk_stmts(visitor, body),
+        Stmt::Import { .. } => {}
+        Stmt::Export { declaration, .. } => visitor.visit_stmt(declaration),
+        Stmt::Throw { value, .. } => visitor.visit_expr(value),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            walk_stmts(visitor, body);
+            if let Some(catch) = catch {
+                walk_stmts(visitor, &catch.body);
+            }
+            if let Some(finally) = finally {
+                walk_stmts(visitor, finally);
+            }
+        }
+    }
+}
+
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Literal { .. } => {}
+        Expr::Grouping { expression } => visitor.visit_expr(expression),
+        Expr::Unary { right, .. } => visitor.visit_expr(right),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Variable { .. } => {}
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(callee);
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+        Expr::Get { object, .. } => visitor.visit_expr(object),
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(value);
+        }
+        Expr::This { .. } | Expr::Super { .. } => {}
+        Expr::List { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Index { object, index, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(index);
+        }
+        Expr::IndexSet {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(index);
+            visitor.visit_expr(value);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            visitor.visit_expr(object);
+            if let Some(start) = start {
+                visitor.visit_expr(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_expr(end);
+            }
+        }
+        Expr::Range { start, end, .. } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+        }
+        Expr::Lambda { function, .. } => visitor.visit_function(function),
+    }
+}
+
+pub fn walk_function<V: Visitor>(visitor: &mut V, function: &Function) {
+    walk_stmts(visitor, &function.body);
+}
+
+// Same traversal with mutable access, for passes that rewrite the tree in
+// place (desugaring, constant folding, renaming).
+pub trait VisitorMut: Sized {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function);
+    }
+}
+
+pub fn walk_stmts_mut<V: VisitorMut>(visitor: &mut V, stmts: &mut [Stmt]) {
+    for stmt in stmts {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression { expression } => visitor.visit_expr_mut(expression),
+        Stmt::Print { expression, .. } => visitor.visit_expr_mut(expression),
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr_mut(initializer);
+            }
+        }
+        Stmt::Block { statements } => walk_stmts_mut(visitor, statements),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt_mut(else_branch);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_stmt_mut(initializer);
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expr_mut(condition);
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expr_mut(increment);
+            }
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            visitor.visit_expr_mut(iterable);
+            visitor.visit_stmt_mut(body);
+        }
+        // Clones the declaration only if a runtime closure still shares it
+        Stmt::Function { function } => visitor.visit_function_mut(std::rc::Rc::make_mut(function)),
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr_mut(superclass);
+            }
+            for method in methods {
+                visitor.visit_function_mut(std::rc::Rc::make_mut(method));
+            }
+        }
+        Stmt::Test { body, .. } => walk_stmts_mut(visitor, body),
+        Stmt::Import { .. } => {}
+        Stmt::Export { declaration, .. } => visitor.visit_stmt_mut(declaration),
+        Stmt::Throw { value, .. } => visitor.visit_expr_mut(value),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            walk_stmts_mut(visitor, body);
+            if let Some(catch) = catch {
+                walk_stmts_mut(visitor, &mut catch.body);
+            }
+            if let Some(finally) = finally {
+                walk_stmts_mut(visitor, finally);
+            }
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Literal { .. } => {}
+        Expr::Grouping { expression } => visitor.visit_expr_mut(expression),
+        Expr::Unary { right, .. } => visitor.visit_expr_mut(right),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Variable { .. } => {}
+        Expr::Assign { value, .. } => visitor.visit_expr_mut(value),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr_mut(callee);
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+        Expr::Get { object, .. } => visitor.visit_expr_mut(object),
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::This { .. } | Expr::Super { .. } => {}
+        Expr::List { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr_mut(element);
+            }
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                visitor.visit_expr_mut(key);
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Expr::Index { object, index, .. } => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_expr_mut(index);
+        }
+        Expr::IndexSet {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_expr_mut(index);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            visitor.visit_expr_mut(object);
+            if let Some(start) = start {
+                visitor.visit_expr_mut(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_expr_mut(end);
+            }
+        }
+        Expr::Range { start, end, .. } => {
+            visitor.visit_expr_mut(start);
+            visitor.visit_expr_mut(end);
+        }
+        Expr::Lambda { function, .. } => {
+            visitor.visit_function_mut(std::rc::Rc::make_mut(function))
+        }
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut>(visitor: &mut V, function: &mut Function) {
+    walk_stmts_mut(visitor, &mut function.body);
+}
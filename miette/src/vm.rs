@@ -0,0 +1,1379 @@
+pub mod heap;
+pub mod natives;
+pub mod value;
+
+use crate::bytecode::Linked;
+use crate::chunk::{Constant, FunctionProto, OpCode};
+use crate::disassembler::disassemble_instruction;
+use crate::error::{ErrorKind, RuntimeError, TraceFrame};
+use crate::list::List;
+use crate::map::{Map, MapError};
+use crate::module::Program;
+use crate::range::Range;
+use crate::stdlib;
+use heap::Heap;
+use value::{
+    BoundMethod, Class, Closure, Instance, IterState, Module, NativeFunction, ObjRef, Object,
+    Upvalue, Value,
+};
+
+// Same limit as the interpreter, not counting the script itself
+const MAX_FRAMES: usize = 4096;
+
+struct CallFrame {
+    closure: ObjRef,
+    // Shared with the closure so instructions can be read without the heap
+    function: std::rc::Rc<FunctionProto>,
+    ip: usize,
+    // Stack index of slot zero
+    base: usize,
+    // Index of the globals the function reads and writes
+    module: usize,
+}
+
+// A `try` block being run, where to go and what to unwind to if something
+// inside it throws
+struct Handler {
+    // Index of the frame running the try
+    frame: usize,
+    // Stack height when the try started
+    stack: usize,
+    // Start of the catch or finally code
+    ip: usize,
+}
+
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    // One set per module, the first for the main one
+    globals: Vec<std::collections::HashMap<String, Value>>,
+    // Built-ins, so each module's globals can start out with them
+    natives: Vec<(String, Value)>,
+    // The modules of the running program loaded so far, and those the
+    // running module imports by path as written
+    modules: Vec<Value>,
+    imports: std::collections::HashMap<String, Value>,
+    // Upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<ObjRef>,
+    // Innermost last
+    handlers: Vec<Handler>,
+    // The value a `throw` is unwinding with, next to the error carrying it up
+    // the Rust stack, so a catch can hand back what was thrown
+    thrown: Option<(Value, RuntimeError)>,
+    out: Box<dyn std::io::Write>,
+    // Receives the stack and each instruction before it runs
+    trace: Option<Box<dyn std::io::Write>>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::with_output(Box::new(std::io::stdout()))
+    }
+
+    // `print` writes to `out` instead of stdout
+    pub fn with_output(out: Box<dyn std::io::Write>) -> Self {
+        let mut vm: Vm = Vm {
+            heap: Heap::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: vec![std::collections::HashMap::new()],
+            natives: Vec::new(),
+            modules: Vec::new(),
+            imports: std::collections::HashMap::new(),
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            thrown: None,
+            out,
+            trace: None,
+        };
+
+        for (name, arity, function) in natives::natives() {
+            vm.define_native(name, arity, move |heap: &mut Heap, args: &[Value]| {
+                function(heap, args)
+            });
+        }
+        vm
+    }
+
+    // Makes `function` callable from scripts as a global named `name`
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Heap, &[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let native: ObjRef = self.heap.alloc(Object::Native(NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: std::rc::Rc::new(function),
+        }));
+        self.globals[0].insert(name.to_string(), Value::Object(native));
+        self.natives.push((name.to_string(), Value::Object(native)));
+    }
+
+    pub fn trace(&mut self, out: Box<dyn std::io::Write>) {
+        self.trace = Some(out);
+    }
+
+    // Collects as often as possible, for flushing out missing roots in tests
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    // Returns the number of objects freed
+    pub fn collect_garbage(&mut self) -> usize {
+        let mut roots: Vec<Value> = self.stack.clone();
+        for globals in self.globals.iter() {
+            roots.extend(globals.values().cloned());
+        }
+        roots.extend(self.natives.iter().map(|(_, native)| native.clone()));
+        roots.extend(self.modules.iter().cloned());
+        roots.extend(self.imports.values().cloned());
+        roots.extend(self.frames.iter().map(|frame| Value::Object(frame.closure)));
+        roots.extend(
+            self.open_upvalues
+                .iter()
+                .map(|upvalue| Value::Object(*upvalue)),
+        );
+        roots.extend(self.thrown.iter().map(|(value, _)| value.clone()));
+        self.heap.collect(&roots)
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn interpret(&mut self, script: std::rc::Rc<FunctionProto>) -> Result<(), RuntimeError> {
+        self.run_script(script, 0)?;
+        self.flush()
+    }
+
+    // Runs each module of a program in turn, the main one in the VM's own
+    // globals and the others in globals of their own
+    pub fn interpret_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let modules: Vec<Linked> = program
+            .modules
+            .iter()
+            .map(|module| Linked {
+                name: module.name.clone(),
+                script: module.script.clone(),
+                imports: module.imports.clone(),
+                exports: module.exports.clone(),
+            })
+            .collect();
+        self.interpret_linked(&modules)
+    }
+
+    // The same for a program read from .mtc files
+    pub fn interpret_linked(&mut self, modules: &[Linked]) -> Result<(), RuntimeError> {
+        let result: Result<(), RuntimeError> = self.run_modules(modules);
+        self.modules.clear();
+        self.imports.clear();
+        result?;
+        self.flush()
+    }
+
+    fn run_modules(&mut self, modules: &[Linked]) -> Result<(), RuntimeError> {
+        for (index, module) in modules.iter().enumerate() {
+            let globals: usize = if index + 1 == modules.len() {
+                0
+            } else {
+                self.globals.push(self.natives.iter().cloned().collect());
+                self.globals.len() - 1
+            };
+            self.imports = module
+                .imports
+                .iter()
+                .map(|(path, index)| (path.clone(), self.modules[*index].clone()))
+                .collect();
+            self.run_script(module.script.clone(), globals)?;
+
+            let handle: ObjRef = self.heap.alloc(Object::Module(Module {
+                name: module.name.clone(),
+                globals,
+                exports: module.exports.clone(),
+            }));
+            self.modules.push(Value::Object(handle));
+        }
+        Ok(())
+    }
+
+    fn run_script(
+        &mut self,
+        script: std::rc::Rc<FunctionProto>,
+        module: usize,
+    ) -> Result<(), RuntimeError> {
+        let closure: ObjRef = self.heap.alloc(Object::Closure(Closure {
+            function: script,
+            upvalues: Vec::new(),
+            module,
+        }));
+        self.stack.push(Value::Object(closure));
+
+        let result: Result<(), RuntimeError> =
+            self.call_closure(closure, 0).and_then(|()| self.run(0));
+
+        // Globals survive for the next script, the rest is per run
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.thrown = None;
+        result
+    }
+
+    fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.out.flush().map_err(|e| RuntimeError {
+            kind: ErrorKind::IoError,
+            message: format!("Failed to write output: {}.", e),
+            line: 0,
+            span: crate::lex::Span::default(),
+            trace: Vec::new(),
+        })
+    }
+
+    // Executes until the frame count drops back to `depth`, catching what is
+    // thrown in the frames above it
+    fn run(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        loop {
+            match self.execute(depth) {
+                Ok(()) => return Ok(()),
+                Err(error) => self.catch(error, depth)?,
+            }
+        }
+    }
+
+    // Unwinds to the innermost try and carries on at its catch or finally
+    // code with the thrown value on the stack. Tries in frames below `depth`
+    // belong to an outer run, which gets the error instead.
+    fn catch(&mut self, error: RuntimeError, depth: usize) -> Result<(), RuntimeError> {
+        if self
+            .handlers
+            .last()
+            .is_none_or(|handler| handler.frame < depth)
+        {
+            return Err(error);
+        }
+        let handler: Handler = self.handlers.pop().expect("handler");
+
+        let value: Value = self.caught(error);
+        self.frames.truncate(handler.frame + 1);
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.stack.push(value);
+        self.frame_mut().ip = handler.ip;
+        Ok(())
+    }
+
+    // The value a catch gets: what was thrown, or the runtime error itself
+    fn caught(&mut self, error: RuntimeError) -> Value {
+        match self.thrown.take() {
+            Some((value, thrown)) if thrown == error => value,
+            _ => Value::Object(self.heap.alloc(Object::Error(error))),
+        }
+    }
+
+    // Starts unwinding with `value`. An error made by `error()` takes on the
+    // place it is first thrown from, anything else is reported as uncaught
+    // if nothing catches it.
+    fn throw(&mut self, value: Value) -> RuntimeError {
+        let error: RuntimeError = match self.error_object(&value).cloned() {
+            Some(error) if error.trace.is_empty() => {
+                let raised: RuntimeError = self.error(error.kind, &error.message);
+                if let Value::Object(handle) = value {
+                    *self.heap.get_mut(handle) = Object::Error(raised.clone());
+                }
+                raised
+            }
+            Some(error) => error,
+            None => {
+                let message: String = format!("Uncaught {}.", self.heap.repr(&value));
+                self.error(ErrorKind::Error, &message)
+            }
+        };
+        self.thrown = Some((value, error.clone()));
+        error
+    }
+
+    // The body of `run`, stopping at the first error
+    fn execute(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        loop {
+            // Between instructions every live value is reachable from a root
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+
+            if self.trace.is_some() {
+                self.trace_instruction();
+            }
+
+            let byte: u8 = self.read_byte();
+            let Some(op) = OpCode::from_byte(byte) else {
+                return Err(self.error(ErrorKind::Error, &format!("Unknown opcode {}.", byte)));
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let value: Value = match self.read_constant() {
+                        Constant::Number(n) => Value::Number(n),
+                        Constant::Text(s) => Value::Text(s),
+                        Constant::Function(_) => Value::Nil,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot: usize = self.read_byte() as usize;
+                    let value: Value = self.stack[self.frame().base + slot].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot: usize = self.read_byte() as usize;
+                    let base: usize = self.frame().base;
+                    self.stack[base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    match self.globals[self.frame().module].get(&*name) {
+                        Some(value) => {
+                            let value: Value = value.clone();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            return Err(self.error(
+                                ErrorKind::NameError,
+                                &format!("Undefined variable '{}'.", name),
+                            ));
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let value: Value = self.pop();
+                    let module: usize = self.frame().module;
+                    self.globals[module].insert(name.to_string(), value);
+                }
+                OpCode::SetGlobal => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let value: Value = self.peek(0).clone();
+                    let module: usize = self.frame().module;
+                    match self.globals[module].get_mut(&*name) {
+                        Some(slot) => *slot = value,
+                        None => {
+                            return Err(self.error(
+                                ErrorKind::NameError,
+                                &format!("Undefined variable '{}'.", name),
+                            ));
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index: usize = self.read_byte() as usize;
+                    let upvalue: ObjRef = self.frame_upvalue(index);
+                    let value: Value = match self.heap.get(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => self.stack[*slot].clone(),
+                        Object::Upvalue(Upvalue::Closed(value)) => value.clone(),
+                        _ => Value::Nil,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index: usize = self.read_byte() as usize;
+                    let upvalue: ObjRef = self.frame_upvalue(index);
+                    let value: Value = self.peek(0).clone();
+                    match self.heap.get_mut(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => {
+                            let slot: usize = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Object::Upvalue(Upvalue::Closed(closed)) => *closed = value,
+                        _ => {}
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let object: Value = self.pop();
+                    let value: Value = self.get_property(&object, &name)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let value: Value = self.pop();
+                    let object: Value = self.pop();
+                    let instance: Option<&mut Instance> = match object {
+                        Value::Object(handle) => match self.heap.get_mut(handle) {
+                            Object::Instance(instance) => Some(instance),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let Some(instance) = instance else {
+                        return Err(self.error(ErrorKind::TypeError, "Only instances have fields."));
+                    };
+                    instance.fields.insert(name.to_string(), value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let superclass: Value = self.pop();
+                    let instance: Value = self.pop();
+                    let Value::Object(superclass) = superclass else {
+                        return Err(
+                            self.error(ErrorKind::Error, "Can't use 'super' outside of a class.")
+                        );
+                    };
+                    let Object::Class(class) = self.heap.get(superclass) else {
+                        return Err(
+                            self.error(ErrorKind::Error, "Can't use 'super' outside of a class.")
+                        );
+                    };
+                    match class.methods.get(&*name).copied() {
+                        Some(method) => {
+                            let bound: ObjRef = self.heap.alloc(Object::BoundMethod(BoundMethod {
+                                receiver: instance,
+                                method,
+                            }));
+                            self.stack.push(Value::Object(bound));
+                        }
+                        None => {
+                            let message: String = format!(
+                                "Undefined property '{}' on superclass {}.",
+                                name, class.name
+                            );
+                            return Err(self.error(ErrorKind::PropertyError, &message));
+                        }
+                    }
+                }
+                OpCode::Equal => {
+                    let b: Value = self.pop();
+                    let a: Value = self.pop();
+                    self.stack.push(Value::Bool(a.equals(&b)));
+                }
+                OpCode::NotEqual => {
+                    let b: Value = self.pop();
+                    let a: Value = self.pop();
+                    self.stack.push(Value::Bool(!a.equals(&b)));
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let (a, b) = self.number_operands()?;
+                    let value: Value = match op {
+                        OpCode::Greater => Value::Bool(a > b),
+                        OpCode::GreaterEqual => Value::Bool(a >= b),
+                        OpCode::Less => Value::Bool(a < b),
+                        OpCode::LessEqual => Value::Bool(a <= b),
+                        OpCode::Subtract => Value::Number(a - b),
+                        OpCode::Multiply => Value::Number(a * b),
+                        _ => {
+                            if b == 0.0 {
+                                return Err(
+                                    self.error(ErrorKind::ZeroDivisionError, "Division by zero.")
+                                );
+                            }
+                            Value::Number(a / b)
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Add => {
+                    let b: Value = self.pop();
+                    let a: Value = self.pop();
+                    let value: Value = match (a, b) {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::Text(a), Value::Text(b)) => Value::text(&format!("{}{}", a, b)),
+                        _ => {
+                            return Err(self.error(
+                                ErrorKind::TypeError,
+                                "Operands must be two numbers or two strings.",
+                            ));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value: Value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(self.error(ErrorKind::TypeError, "Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value: Value = self.pop();
+                    let text: String = self.heap.display(&value);
+                    if let Err(e) = writeln!(self.out, "{}", text) {
+                        return Err(
+                            self.error(ErrorKind::IoError, &format!("Failed to print: {}.", e))
+                        );
+                    }
+                }
+                OpCode::Jump => {
+                    let offset: usize = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset: usize = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset: usize = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let count: usize = self.read_byte() as usize;
+                    let callee: Value = self.peek(count).clone();
+                    self.call_value(callee, count)?;
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        return Err(
+                            self.error(ErrorKind::Error, "Closure operand is not a function.")
+                        );
+                    };
+                    let mut upvalues: Vec<ObjRef> = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local: bool = self.read_byte() == 1;
+                        let index: usize = self.read_byte() as usize;
+                        if is_local {
+                            let slot: usize = self.frame().base + index;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(self.frame_upvalue(index));
+                        }
+                    }
+                    let closure: ObjRef = self.heap.alloc(Object::Closure(Closure {
+                        function,
+                        upvalues,
+                        module: self.frame().module,
+                    }));
+                    self.stack.push(Value::Object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result: Value = self.pop();
+                    let frame: CallFrame = self.frames.pop().expect("call frame");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    self.stack.push(result);
+                    if self.frames.len() == depth {
+                        return Ok(());
+                    }
+                }
+                OpCode::Class => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let class: ObjRef = self.heap.alloc(Object::Class(Class {
+                        name: name.to_string(),
+                        methods: std::collections::HashMap::new(),
+                    }));
+                    self.stack.push(Value::Object(class));
+                }
+                OpCode::Inherit => {
+                    let methods: Option<std::collections::HashMap<String, ObjRef>> =
+                        match self.peek(1) {
+                            Value::Object(handle) => match self.heap.get(*handle) {
+                                Object::Class(superclass) => Some(superclass.methods.clone()),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                    let Some(methods) = methods else {
+                        return Err(self.error(ErrorKind::TypeError, "Superclass must be a class."));
+                    };
+                    if let Value::Object(handle) = self.pop()
+                        && let Object::Class(subclass) = self.heap.get_mut(handle)
+                    {
+                        subclass.methods = methods;
+                    }
+                }
+                OpCode::Method => {
+                    let name: std::rc::Rc<str> = self.read_name();
+                    let method: Value = self.pop();
+                    if let (Value::Object(method), Value::Object(class)) = (method, self.peek(0))
+                        && let Object::Class(class) = self.heap.get_mut(*class)
+                    {
+                        class.methods.insert(name.to_string(), method);
+                    }
+                }
+                OpCode::BuildList => {
+                    let count: usize = self.read_u16() as usize;
+                    let items: Vec<Value> = self.stack.split_off(self.stack.len() - count);
+                    let list: ObjRef = self.heap.alloc(Object::List(List::new(items)));
+                    self.stack.push(Value::Object(list));
+                }
+                OpCode::BuildMap => {
+                    let map: ObjRef = self.heap.alloc(Object::Map(Map::new()));
+                    self.stack.push(Value::Object(map));
+                }
+                OpCode::MapInsert => {
+                    let value: Value = self.pop();
+                    let key: Value = self.pop();
+                    let key = key
+                        .to_key(&self.heap)
+                        .map_err(|e| self.error(e.kind(), &e.to_string()))?;
+                    if let Value::Object(handle) = self.peek(0)
+                        && let Object::Map(map) = self.heap.get_mut(*handle)
+                    {
+                        map.insert(key, value);
+                    }
+                }
+                OpCode::Index => {
+                    let index: Value = self.pop();
+                    let object: Value = self.pop();
+                    let value: Value = self.index(&object, &index)?;
+                    self.stack.push(value);
+                }
+                OpCode::IndexSet => {
+                    let value: Value = self.pop();
+                    let index: Value = self.pop();
+                    let object: Value = self.pop();
+                    self.index_set(&object, &index, value.clone())?;
+                    self.stack.push(value);
+                }
+                OpCode::Slice => {
+                    let bounds: u8 = self.read_byte();
+                    let end: Option<Value> = if bounds & 2 != 0 {
+                        Some(self.pop())
+                    } else {
+                        None
+                    };
+                    let start: Option<Value> = if bounds & 1 != 0 {
+                        Some(self.pop())
+                    } else {
+                        None
+                    };
+                    let object: Value = self.pop();
+                    let start: Option<f64> = match start {
+                        Some(start) => Some(self.expect_index(&start)?),
+                        None => None,
+                    };
+                    let end: Option<f64> = match end {
+                        Some(end) => Some(self.expect_index(&end)?),
+                        None => None,
+                    };
+                    let value: Value = self.slice(&object, start, end)?;
+                    self.stack.push(value);
+                }
+                OpCode::Range => {
+                    let inclusive: bool = self.read_byte() == 1;
+                    let end: Value = self.pop();
+                    let start: Value = self.pop();
+                    let (Value::Number(start), Value::Number(end)) = (start, end) else {
+                        return Err(
+                            self.error(ErrorKind::TypeError, "Range bounds must be numbers.")
+                        );
+                    };
+                    let range: Range = Range::new(start, end, inclusive)
+                        .map_err(|e| self.error(ErrorKind::TypeError, &e.to_string()))?;
+                    self.stack.push(Value::Range(range));
+                }
+                OpCode::IterInit => {
+                    let iterable: Value = self.pop();
+                    let state: IterState = self.iterator(iterable)?;
+                    let iterator: ObjRef = self.heap.alloc(Object::Iterator(state));
+                    self.stack.push(Value::Object(iterator));
+                }
+                OpCode::IterNext => {
+                    let slot: usize = self.read_byte() as usize;
+                    let offset: usize = self.read_u16() as usize;
+                    let iterator: Value = self.stack[self.frame().base + slot].clone();
+                    match self.next_item(&iterator)? {
+                        Some(item) => self.stack.push(item),
+                        None => self.frame_mut().ip += offset,
+                    }
+                }
+                OpCode::Try => {
+                    let offset: usize = self.read_u16() as usize;
+                    let handler: Handler = Handler {
+                        frame: self.frames.len() - 1,
+                        stack: self.stack.len(),
+                        ip: self.frame().ip + offset,
+                    };
+                    self.handlers.push(handler);
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value: Value = self.pop();
+                    return Err(self.throw(value));
+                }
+                OpCode::Import => {
+                    let path: std::rc::Rc<str> = self.read_name();
+                    match self.imports.get(&*path) {
+                        Some(module) => {
+                            let module: Value = module.clone();
+                            self.stack.push(module);
+                        }
+                        None => {
+                            return Err(self.error(
+                                ErrorKind::ImportError,
+                                "Can only import modules in a program run from a file.",
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn trace_instruction(&mut self) {
+        let mut stack: String = "          ".to_string();
+        for value in self.stack.iter() {
+            stack.push_str(&format!("[ {} ]", self.heap.display(value)));
+        }
+
+        let frame: &CallFrame = self.frame();
+        let (instruction, _) = disassemble_instruction(&frame.function.chunk, frame.ip);
+        if let Some(trace) = self.trace.as_mut() {
+            // Tracing is best effort, a failed write must not stop the program
+            let _ = writeln!(trace, "{}\n{}", stack, instruction);
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, count: usize) -> Result<(), RuntimeError> {
+        let Value::Object(handle) = callee else {
+            return Err(self.not_callable(&callee));
+        };
+
+        match self.heap.get(handle) {
+            Object::Closure(_) => self.call_closure(handle, count),
+            Object::BoundMethod(bound) => {
+                let method: ObjRef = bound.method;
+                let receiver_slot: usize = self.stack.len() - count - 1;
+                self.stack[receiver_slot] = bound.receiver.clone();
+                self.call_closure(method, count)
+            }
+            Object::Class(class) => {
+                let init: Option<ObjRef> = class.methods.get("init").copied();
+                let instance: ObjRef = self.heap.alloc(Object::Instance(Instance {
+                    class: handle,
+                    fields: std::collections::HashMap::new(),
+                }));
+                let receiver_slot: usize = self.stack.len() - count - 1;
+                self.stack[receiver_slot] = Value::Object(instance);
+
+                match init {
+                    Some(init) => self.call_closure(init, count),
+                    None if count != 0 => Err(self.arity_error(0, count)),
+                    None => Ok(()),
+                }
+            }
+            Object::Native(native) => {
+                let arity: usize = native.arity;
+                let function: value::NativeFn = native.function.clone();
+                let throws: bool = native.name == stdlib::ASSERT_THROWS;
+                let kind: ErrorKind = stdlib::error_kind(&native.name);
+                if arity != count {
+                    return Err(self.arity_error(arity, count));
+                }
+
+                let arguments: Vec<Value> = self.stack.split_off(self.stack.len() - count);
+                self.pop();
+                let result: Value = if throws {
+                    self.assert_throws(arguments[0].clone())?
+                } else {
+                    function(&mut self.heap, &arguments)
+                        .map_err(|message| self.error(kind, &message))?
+                };
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.not_callable(&callee)),
+        }
+    }
+
+    fn call_closure(&mut self, closure: ObjRef, count: usize) -> Result<(), RuntimeError> {
+        let Object::Closure(closure_object) = self.heap.get(closure) else {
+            return Err(self.error(ErrorKind::TypeError, "Can only call functions and classes."));
+        };
+        let function: std::rc::Rc<FunctionProto> = closure_object.function.clone();
+        let module: usize = closure_object.module;
+
+        if function.arity != count {
+            return Err(self.arity_error(function.arity, count));
+        }
+        if self.frames.len() > MAX_FRAMES {
+            return Err(self.error(ErrorKind::StackOverflowError, "Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            base: self.stack.len() - count - 1,
+            module,
+        });
+        Ok(())
+    }
+
+    // Calls from inside an instruction, running the callee to completion
+    fn call_now(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let count: usize = arguments.len();
+        let depth: usize = self.frames.len();
+        self.stack.push(callee.clone());
+        self.stack.extend(arguments);
+
+        self.call_value(callee, count)?;
+        if self.frames.len() > depth {
+            self.run(depth)?;
+        }
+        Ok(self.pop())
+    }
+
+    // Calls `function` expecting it to fail, giving back the error's message.
+    // The frames and stack slots the error left behind are dropped.
+    fn assert_throws(&mut self, function: Value) -> Result<Value, RuntimeError> {
+        let arity: Option<usize> = match &function {
+            Value::Object(handle) => match self.heap.get(*handle) {
+                Object::Closure(closure) => Some(closure.function.arity),
+                Object::Native(native) => Some(native.arity),
+                Object::BoundMethod(bound) => match self.heap.get(bound.method) {
+                    Object::Closure(closure) => Some(closure.function.arity),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(arity) = arity else {
+            let type_name: &str = self.heap.type_name(&function);
+            return Err(self.error(
+                stdlib::error_kind(stdlib::ASSERT_THROWS),
+                &stdlib::expected(stdlib::ASSERT_THROWS, "a function", type_name),
+            ));
+        };
+        // Otherwise the arity error would pass for the one expected
+        if arity != 0 {
+            return Err(self.arity_error(arity, 0));
+        }
+
+        let depth: usize = self.frames.len();
+        let height: usize = self.stack.len();
+        match self.call_now(function, Vec::new()) {
+            Ok(value) => Err(self.error(
+                ErrorKind::AssertionError,
+                &stdlib::assert_throws_failed(&self.heap.repr(&value)),
+            )),
+            Err(error) => {
+                self.frames.truncate(depth);
+                self.handlers.retain(|handler| handler.frame < depth);
+                self.close_upvalues(height);
+                self.stack.truncate(height);
+                let thrown: Value = self.caught(error);
+                Ok(match self.error_object(&thrown) {
+                    Some(error) => Value::text(&error.message),
+                    None => thrown,
+                })
+            }
+        }
+    }
+
+    fn error_object(&self, value: &Value) -> Option<&RuntimeError> {
+        match value {
+            Value::Object(handle) => match self.heap.get(*handle) {
+                Object::Error(error) => Some(error),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn not_callable(&self, callee: &Value) -> RuntimeError {
+        self.error(
+            ErrorKind::TypeError,
+            &format!(
+                "Can only call functions and classes, got a {}.",
+                self.heap.type_name(callee)
+            ),
+        )
+    }
+
+    fn arity_error(&self, arity: usize, count: usize) -> RuntimeError {
+        self.error(
+            ErrorKind::ArityError,
+            &format!("Expected {} arguments but got {}.", arity, count),
+        )
+    }
+
+    // Fields shadow methods, methods come back bound to the instance
+    fn get_property(&mut self, object: &Value, name: &str) -> Result<Value, RuntimeError> {
+        if let Value::Object(handle) = object
+            && let Object::Module(module) = self.heap.get(*handle)
+        {
+            let value: Option<&Value> = if module.exports.iter().any(|export| export == name) {
+                self.globals[module.globals].get(name)
+            } else {
+                None
+            };
+            return match value {
+                Some(value) => Ok(value.clone()),
+                None => Err(self.error(
+                    ErrorKind::PropertyError,
+                    &format!("Module '{}' has no export '{}'.", module.name, name),
+                )),
+            };
+        }
+
+        if let Value::Object(handle) = object
+            && let Object::Error(error) = self.heap.get(*handle)
+        {
+            return match name {
+                "message" => Ok(Value::text(&error.message)),
+                "kind" => Ok(Value::text(&error.kind.to_string())),
+                "trace" => {
+                    let frames: Vec<Value> = error
+                        .trace
+                        .iter()
+                        .map(|frame| Value::text(&frame.to_string()))
+                        .collect();
+                    Ok(Value::Object(
+                        self.heap.alloc(Object::List(List::new(frames))),
+                    ))
+                }
+                _ => Err(self.error(
+                    ErrorKind::PropertyError,
+                    &format!("Undefined property '{}' on error.", name),
+                )),
+            };
+        }
+
+        let instance: Option<&Instance> = match object {
+            Value::Object(handle) => match self.heap.get(*handle) {
+                Object::Instance(instance) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(instance) = instance else {
+            return Err(self.error(
+                ErrorKind::TypeError,
+                &format!(
+                    "Only instances have properties, got a {}.",
+                    self.heap.type_name(object)
+                ),
+            ));
+        };
+
+        if let Some(value) = instance.fields.get(name) {
+            return Ok(value.clone());
+        }
+
+        let Object::Class(class) = self.heap.get(instance.class) else {
+            return Err(self.error(ErrorKind::Error, "Instance without a class."));
+        };
+        match class.methods.get(name).copied() {
+            Some(method) => {
+                let bound: ObjRef = self.heap.alloc(Object::BoundMethod(BoundMethod {
+                    receiver: object.clone(),
+                    method,
+                }));
+                Ok(Value::Object(bound))
+            }
+            None => Err(self.error(
+                ErrorKind::PropertyError,
+                &format!("Undefined property '{}' on {} instance.", name, class.name),
+            )),
+        }
+    }
+
+    fn invoke(&mut self, object: &Value, name: &str) -> Result<Value, RuntimeError> {
+        let method: Value = self.get_property(object, name)?;
+        self.call_now(method, Vec::new())
+    }
+
+    fn has_method(&self, instance: ObjRef, name: &str) -> bool {
+        let Object::Instance(instance) = self.heap.get(instance) else {
+            return false;
+        };
+        match self.heap.get(instance.class) {
+            Object::Class(class) => class.methods.contains_key(name),
+            _ => false,
+        }
+    }
+
+    fn iterator(&mut self, iterable: Value) -> Result<IterState, RuntimeError> {
+        let handle: ObjRef = match &iterable {
+            Value::Text(text) => {
+                let items: Vec<Value> = text
+                    .chars()
+                    .map(|c| Value::text(c.encode_utf8(&mut [0; 4])))
+                    .collect();
+                return Ok(IterState::Values { items, index: 0 });
+            }
+            Value::Range(range) => {
+                return Ok(IterState::Range {
+                    range: *range,
+                    index: 0,
+                });
+            }
+            Value::Object(handle) => *handle,
+            other => {
+                return Err(self.error(
+                    ErrorKind::TypeError,
+                    &format!("Can't iterate over a {}.", self.heap.type_name(other)),
+                ));
+            }
+        };
+
+        match self.heap.get(handle) {
+            Object::List(_) => Ok(IterState::List {
+                list: handle,
+                index: 0,
+            }),
+            Object::Map(map) => Ok(IterState::Values {
+                items: map.keys().map(Value::from_key).collect(),
+                index: 0,
+            }),
+            Object::Instance(instance) => {
+                // An iterator is used directly, an iterable hands one out
+                if self.has_method(handle, "next") {
+                    return Ok(IterState::Protocol(iterable));
+                }
+                if self.has_method(handle, "iter") {
+                    let iterator: Value = self.invoke(&iterable, "iter")?;
+                    return self.iterator(iterator);
+                }
+                let class_name: String = match self.heap.get(instance.class) {
+                    Object::Class(class) => class.name.clone(),
+                    _ => String::new(),
+                };
+                Err(self.error(
+                    ErrorKind::TypeError,
+                    &format!(
+                        "{} instance is not iterable, it needs an iter() or next() method.",
+                        class_name
+                    ),
+                ))
+            }
+            _ => Err(self.error(
+                ErrorKind::TypeError,
+                &format!("Can't iterate over a {}.", self.heap.type_name(&iterable)),
+            )),
+        }
+    }
+
+    fn next_item(&mut self, iterator: &Value) -> Result<Option<Value>, RuntimeError> {
+        let Value::Object(handle) = iterator else {
+            return Ok(None);
+        };
+
+        let protocol: Option<Value> = match self.heap.get(*handle) {
+            Object::Iterator(IterState::Protocol(object)) => Some(object.clone()),
+            _ => None,
+        };
+        if let Some(object) = protocol {
+            let item: Value = self.invoke(&object, "next")?;
+            return Ok(match item {
+                Value::Nil => None,
+                item => Some(item),
+            });
+        }
+
+        let list_items: Option<(ObjRef, usize)> = match self.heap.get(*handle) {
+            Object::Iterator(IterState::List { list, index }) => Some((*list, *index)),
+            _ => None,
+        };
+        if let Some((list, index)) = list_items {
+            let item: Option<Value> = match self.heap.get(list) {
+                Object::List(list) => list.items().get(index).cloned(),
+                _ => None,
+            };
+            if let Object::Iterator(IterState::List { index, .. }) = self.heap.get_mut(*handle) {
+                *index += 1;
+            }
+            return Ok(item);
+        }
+
+        match self.heap.get_mut(*handle) {
+            Object::Iterator(IterState::Values { items, index }) => {
+                let item: Option<Value> = items.get(*index).cloned();
+                *index += 1;
+                Ok(item)
+            }
+            Object::Iterator(IterState::Range { range, index }) => {
+                let item: Option<Value> = range.get(*index).map(Value::Number);
+                *index += 1;
+                Ok(item)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn index(&mut self, object: &Value, index: &Value) -> Result<Value, RuntimeError> {
+        if let Value::Text(text) = object {
+            let position: f64 = self.expect_index(index)?;
+            let chars: List<char> = List::new(text.chars().collect());
+            return match chars.get(position) {
+                Ok(c) => Ok(Value::text(c.encode_utf8(&mut [0; 4]))),
+                Err(e) => Err(self.error(
+                    ErrorKind::IndexError,
+                    &e.to_string()
+                        .replace("List", "String")
+                        .replace("list", "string"),
+                )),
+            };
+        }
+
+        if let Value::Object(handle) = object {
+            match self.heap.get(*handle) {
+                Object::List(_) => {
+                    let position: f64 = self.expect_index(index)?;
+                    let Object::List(list) = self.heap.get(*handle) else {
+                        unreachable!("checked above");
+                    };
+                    return list
+                        .get(position)
+                        .cloned()
+                        .map_err(|e| self.error(ErrorKind::IndexError, &e.to_string()));
+                }
+                Object::Map(map) => {
+                    let key = index
+                        .to_key(&self.heap)
+                        .map_err(|e| self.error(e.kind(), &e.to_string()))?;
+                    return match map.get(&key) {
+                        Some(value) => Ok(value.clone()),
+                        None => {
+                            let missing: String = self.heap.repr(index);
+                            Err(self.error(
+                                ErrorKind::KeyError,
+                                &MapError::MissingKey(missing).to_string(),
+                            ))
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        Err(self.error(
+            ErrorKind::TypeError,
+            &format!("Can't index into a {}.", self.heap.type_name(object)),
+        ))
+    }
+
+    fn index_set(
+        &mut self,
+        object: &Value,
+        index: &Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if let Value::Object(handle) = object {
+            match self.heap.get(*handle) {
+                Object::List(_) => {
+                    let position: f64 = self.expect_index(index)?;
+                    let result = match self.heap.get_mut(*handle) {
+                        Object::List(list) => list.set(position, value),
+                        _ => Ok(()),
+                    };
+                    return result.map_err(|e| self.error(ErrorKind::IndexError, &e.to_string()));
+                }
+                Object::Map(_) => {
+                    let key = index
+                        .to_key(&self.heap)
+                        .map_err(|e| self.error(e.kind(), &e.to_string()))?;
+                    if let Object::Map(map) = self.heap.get_mut(*handle) {
+                        map.insert(key, value);
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        Err(self.error(
+            ErrorKind::TypeError,
+            &format!("Can't assign into a {}.", self.heap.type_name(object)),
+        ))
+    }
+
+    fn slice(
+        &mut self,
+        object: &Value,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Value, RuntimeError> {
+        if let Value::Text(text) = object {
+            let chars: List<char> = List::new(text.chars().collect());
+            return match chars.slice(start, end) {
+                Ok(sliced) => Ok(Value::text(&sliced.items().iter().collect::<String>())),
+                Err(e) => Err(self.error(ErrorKind::IndexError, &e.to_string())),
+            };
+        }
+
+        if let Value::Object(handle) = object
+            && let Object::List(list) = self.heap.get(*handle)
+        {
+            let sliced: List<Value> = list
+                .slice(start, end)
+                .map_err(|e| self.error(ErrorKind::IndexError, &e.to_string()))?;
+            let list: ObjRef = self.heap.alloc(Object::List(sliced));
+            return Ok(Value::Object(list));
+        }
+
+        Err(self.error(
+            ErrorKind::TypeError,
+            &format!("Can't slice a {}.", self.heap.type_name(object)),
+        ))
+    }
+
+    fn expect_index(&self, index: &Value) -> Result<f64, RuntimeError> {
+        match index {
+            Value::Number(n) => Ok(*n),
+            other => Err(self.error(
+                ErrorKind::TypeError,
+                &format!(
+                    "Index must be a number, got a {}.",
+                    self.heap.type_name(other)
+                ),
+            )),
+        }
+    }
+
+    fn number_operands(&mut self) -> Result<(f64, f64), RuntimeError> {
+        let b: Value = self.pop();
+        let a: Value = self.pop();
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok((a, b)),
+            _ => Err(self.error(ErrorKind::TypeError, "Operands must be numbers.")),
+        }
+    }
+
+    // Reuses the upvalue already open for a slot so closures share it
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let mut position: usize = self.open_upvalues.len();
+        for (i, upvalue) in self.open_upvalues.iter().enumerate() {
+            if let Object::Upvalue(Upvalue::Open(open)) = self.heap.get(*upvalue) {
+                if *open == slot {
+                    return *upvalue;
+                }
+                if *open > slot {
+                    position = i;
+                    break;
+                }
+            }
+        }
+
+        let upvalue: ObjRef = self.heap.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    // Moves every open upvalue at or above `from` off the stack
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            let Object::Upvalue(Upvalue::Open(slot)) = self.heap.get(upvalue) else {
+                self.open_upvalues.pop();
+                continue;
+            };
+            if *slot < from {
+                break;
+            }
+            let value: Value = self.stack[*slot].clone();
+            *self.heap.get_mut(upvalue) = Object::Upvalue(Upvalue::Closed(value));
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("call frame")
+    }
+
+    fn frame_upvalue(&self, index: usize) -> ObjRef {
+        match self.heap.get(self.frame().closure) {
+            Object::Closure(closure) => closure.upvalues[index],
+            _ => unreachable!("frames always run closures"),
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame: &mut CallFrame = self.frame_mut();
+        let byte: u8 = frame.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame: &mut CallFrame = self.frame_mut();
+        let value: u16 = frame.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index: usize = self.read_u16() as usize;
+        self.frame().function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> std::rc::Rc<str> {
+        match self.read_constant() {
+            Constant::Text(name) => name,
+            _ => std::rc::Rc::from(""),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    // Builds an error at the current instruction with the call stack attached
+    fn error(&self, kind: ErrorKind, message: &str) -> RuntimeError {
+        let mut trace: Vec<TraceFrame> = Vec::new();
+        for frame in self.frames.iter().rev() {
+            let offset: usize = frame.ip.saturating_sub(1);
+            trace.push(TraceFrame {
+                function: frame.function.name.clone(),
+                line: frame.function.chunk.lines.get(offset).copied().unwrap_or(0),
+            });
+        }
+
+        let (line, span) = match self.frames.last() {
+            Some(frame) => {
+                let offset: usize = frame.ip.saturating_sub(1);
+                let chunk = &frame.function.chunk;
+                (
+                    chunk.lines.get(offset).copied().unwrap_or(0),
+                    chunk.spans.get(offset).copied().unwrap_or_default(),
+                )
+            }
+            None => (0, Default::default()),
+        };
+
+        RuntimeError {
+            kind,
+            message: message.to_string(),
+            line,
+            span,
+            trace,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use super::value::{IterState, ObjRef, Object, Upvalue, Value};
+
+// Collections start once this many bytes are live, and after each one the
+// threshold grows to a multiple of what survived
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+const GROWTH_FACTOR: usize = 2;
+
+// Every object the VM allocates. Handles are indexes, so objects never move;
+// a freed slot is reused by a later allocation.
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    // Estimated size of everything allocated, see `object_size`
+    bytes_allocated: usize,
+    next_gc: usize,
+    allocations_since_gc: usize,
+    // Collect at every opportunity instead of waiting for the threshold
+    stress: bool,
+    gray: Vec<ObjRef>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_THRESHOLD,
+            allocations_since_gc: 0,
+            stress: false,
+            gray: Vec::new(),
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap::default()
+    }
+
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        self.bytes_allocated += object_size(&object);
+        self.allocations_since_gc += 1;
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(object);
+                ObjRef(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marks.push(false);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, handle: ObjRef) -> &Object {
+        self.objects[handle.0]
+            .as_ref()
+            .expect("object used after it was freed")
+    }
+
+    pub fn get_mut(&mut self, handle: ObjRef) -> &mut Object {
+        self.objects[handle.0]
+            .as_mut()
+            .expect("object used after it was freed")
+    }
+
+    // Number of live objects
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        if self.stress {
+            return self.allocations_since_gc > 0;
+        }
+        self.bytes_allocated > self.next_gc
+    }
+
+    // Frees everything not reachable from `roots` and returns how many
+    // objects went
+    pub fn collect(&mut self, roots: &[Value]) -> usize {
+        for root in roots {
+            self.mark_value(root);
+        }
+        while let Some(handle) = self.gray.pop() {
+            self.blacken(handle);
+        }
+
+        let freed: usize = self.sweep();
+        self.next_gc = (self.bytes_allocated * GROWTH_FACTOR).max(INITIAL_THRESHOLD);
+        self.allocations_since_gc = 0;
+        freed
+    }
+
+    fn mark_value(&mut self, value: &Value) {
+        if let Value::Object(handle) = value {
+            self.mark_object(*handle);
+        }
+    }
+
+    // Marked objects wait on the gray stack until their references are traced
+    fn mark_object(&mut self, handle: ObjRef) {
+        if self.marks[handle.0] {
+            return;
+        }
+        self.marks[handle.0] = true;
+        self.gray.push(handle);
+    }
+
+    fn blacken(&mut self, handle: ObjRef) {
+        let mut values: Vec<Value> = Vec::new();
+        let mut handles: Vec<ObjRef> = Vec::new();
+        match self.get(handle) {
+            Object::List(list) => values.extend(list.items().iter().cloned()),
+            Object::Map(map) => values.extend(map.values().cloned()),
+            Object::Closure(closure) => handles.extend(closure.upvalues.iter().copied()),
+            Object::Upvalue(Upvalue::Closed(value)) => values.push(value.clone()),
+            Object::Upvalue(Upvalue::Open(_))
+            | Object::Native(_)
+            | Object::Module(_)
+            | Object::Error(_) => {}
+            Object::Class(class) => handles.extend(class.methods.values().copied()),
+            Object::Instance(instance) => {
+                handles.push(instance.class);
+                values.extend(instance.fields.values().cloned());
+            }
+            Object::BoundMethod(bound) => {
+                values.push(bound.receiver.clone());
+                handles.push(bound.method);
+            }
+            Object::Iterator(state) => match state {
+                IterState::List { list, .. } => handles.push(*list),
+                IterState::Values { items, .. } => values.extend(items.iter().cloned()),
+                IterState::Range { .. } => {}
+                IterState::Protocol(object) => values.push(object.clone()),
+            },
+        }
+
+        for value in values.iter() {
+            self.mark_value(value);
+        }
+        for handle in handles {
+            self.mark_object(handle);
+        }
+    }
+
+    // Recounts the survivors as it goes, lists and maps may have grown
+    // since they were allocated
+    fn sweep(&mut self) -> usize {
+        let mut freed: usize = 0;
+        let mut live_bytes: usize = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let Some(object) = slot else {
+                continue;
+            };
+            if self.marks[index] {
+                self.marks[index] = false;
+                live_bytes += object_size(object);
+            } else {
+                *slot = None;
+                self.free.push(index);
+                freed += 1;
+            }
+        }
+        self.bytes_allocated = live_bytes;
+        freed
+    }
+
+    pub fn type_name(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Text(_) => "string",
+            Value::Range(_) => "range",
+            Value::Object(handle) => match self.get(*handle) {
+                Object::List(_) => "list",
+                Object::Map(_) => "map",
+                Object::Closure(_) | Object::Native(_) | Object::BoundMethod(_) => "function",
+                Object::Class(_) => "class",
+                Object::Instance(_) => "instance",
+                Object::Upvalue(_) => "upvalue",
+                Object::Iterator(_) => "iterator",
+                Object::Module(_) => "module",
+                Object::Error(_) => "error",
+            },
+        }
+    }
+
+    // Formats a value the way `print` shows it
+    pub fn display(&self, value: &Value) -> String {
+        let mut out: String = String::new();
+        // Writing into a String cannot fail
+        let _ = self.write(value, false, &mut Vec::new(), &mut out);
+        out
+    }
+
+    // Like `display` but with strings quoted, for values quoted in messages
+    pub fn repr(&self, value: &Value) -> String {
+        let mut out: String = String::new();
+        let _ = self.write(value, true, &mut Vec::new(), &mut out);
+        out
+    }
+
+    // Strings nested in containers are quoted so `["1"]` and `[1]` differ
+    fn write(
+        &self,
+        value: &Value,
+        nested: bool,
+        seen: &mut Vec<ObjRef>,
+        out: &mut String,
+    ) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        let handle: ObjRef = match value {
+            Value::Nil => return write!(out, "nil"),
+            Value::Bool(b) => return write!(out, "{}", b),
+            Value::Number(n) => return write!(out, "{}", n),
+            Value::Text(s) if nested => return write!(out, "{:?}", s),
+            Value::Text(s) => return write!(out, "{}", s),
+            Value::Range(range) => return write!(out, "{}", range),
+            Value::Object(handle) => *handle,
+        };
+
+        match self.get(handle) {
+            Object::List(list) => {
+                if seen.contains(&handle) {
+                    return write!(out, "[...]");
+                }
+                seen.push(handle);
+                write!(out, "[")?;
+                for (i, item) in list.items().iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ", ")?;
+                    }
+                    self.write(item, true, seen, out)?;
+                }
+                seen.pop();
+                write!(out, "]")
+            }
+            Object::Map(map) => {
+                if seen.contains(&handle) {
+                    return write!(out, "{{...}}");
+                }
+                seen.push(handle);
+                write!(out, "{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ", ")?;
+                    }
+                    self.write(&Value::from_key(key), true, seen, out)?;
+                    write!(out, ": ")?;
+                    self.write(value, true, seen, out)?;
+                }
+                seen.pop();
+                write!(out, "}}")
+            }
+            Object::Closure(closure) => write!(out, "<fn {}>", closure.function.name),
+            Object::BoundMethod(bound) => {
+                self.write(&Value::Object(bound.method), nested, seen, out)
+            }
+            Object::Native(native) => write!(out, "<native fn {}>", native.name),
+            Object::Class(class) => write!(out, "{}", class.name),
+            Object::Instance(instance) => match self.get(instance.class) {
+                Object::Class(class) => write!(out, "{} instance", class.name),
+                _ => write!(out, "instance"),
+            },
+            Object::Upvalue(_) => write!(out, "<upvalue>"),
+            Object::Iterator(_) => write!(out, "<iterator>"),
+            Object::Module(module) => write!(out, "<module {}>", module.name),
+            Object::Error(error) => write!(out, "{}: {}", error.kind, error.message),
+        }
+    }
+}
+
+// A rough size for deciding when to collect, counting the object and the
+// values it holds directly
+fn object_size(object: &Object) -> usize {
+    let value: usize = std::mem::size_of::<Value>();
+    let contents: usize = match object {
+        Object::List(list) => list.len() * value,
+        Object::Map(map) => map.len() * (value + std::mem::size_of::<crate::map::MapKey>()),
+        Object::Closure(closure) => closure.upvalues.len() * std::mem::size_of::<ObjRef>(),
+        Object::Class(class) => class.methods.len() * std::mem::size_of::<(String, ObjRef)>(),
+        Object::Instance(instance) => {
+            instance.fields.len() * (value + std::mem::size_of::<String>())
+        }
+        Object::Iterator(IterState::Values { items, .. }) => items.len() * value,
+        _ => 0,
+    };
+    std::mem::size_of::<Object>() + contents
+}
@@ -0,0 +1,361 @@
+use super::heap::Heap;
+use super::value::{NativeFn, ObjRef, Object, Value};
+use crate::list::List;
+use crate::map::Map;
+use crate::stdlib::{self, Rng};
+
+type Builtin = fn(&mut Heap, &[Value]) -> Result<Value, String>;
+
+// Same built-ins as the interpreter, with the same messages
+const NATIVES: &[(&str, usize, Builtin)] = &[
+    ("clock", 0, clock),
+    ("len", 1, len),
+    ("push", 2, push),
+    ("pop", 1, pop),
+    ("insert", 3, insert),
+    ("remove", 2, remove),
+    ("keys", 1, keys),
+    ("values", 1, values),
+    ("contains", 2, contains),
+    ("delete", 2, delete),
+    ("str", 1, str),
+    ("num", 1, num),
+    ("type", 1, type_of),
+    ("input", 0, input),
+    ("sqrt", 1, sqrt),
+    ("floor", 1, floor),
+    ("pow", 2, pow),
+    ("split", 2, split),
+    ("trim", 1, trim),
+    ("upper", 1, upper),
+    ("lower", 1, lower),
+    ("replace", 3, replace),
+    ("substr", 3, substr),
+    ("assert", 1, assert),
+    ("assert_eq", 2, assert_eq),
+    (stdlib::ASSERT_THROWS, 1, assert_throws),
+    ("error", 1, error),
+];
+
+// The table above plus `random` and `seed`, which share a generator
+pub fn natives() -> Vec<(&'static str, usize, NativeFn)> {
+    let mut natives: Vec<(&'static str, usize, NativeFn)> = NATIVES
+        .iter()
+        .map(|(name, arity, function)| (*name, *arity, std::rc::Rc::new(*function) as NativeFn))
+        .collect();
+
+    let rng: std::rc::Rc<std::cell::Cell<Rng>> =
+        std::rc::Rc::new(std::cell::Cell::new(Rng::from_clock()));
+    let state: std::rc::Rc<std::cell::Cell<Rng>> = rng.clone();
+    natives.push((
+        "random",
+        0,
+        std::rc::Rc::new(move |_heap: &mut Heap, _args: &[Value]| {
+            let mut generator: Rng = state.get();
+            let n: f64 = generator.next_f64();
+            state.set(generator);
+            Ok(Value::Number(n))
+        }),
+    ));
+    natives.push((
+        "seed",
+        1,
+        std::rc::Rc::new(move |heap: &mut Heap, args: &[Value]| {
+            rng.set(stdlib::seed(expect_number(heap, &args[0], "seed")?)?);
+            Ok(Value::Nil)
+        }),
+    ));
+    natives
+}
+
+fn clock(_heap: &mut Heap, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(stdlib::clock()?))
+}
+
+fn len(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let len: usize = match &args[0] {
+        Value::Text(s) => s.chars().count(),
+        Value::Range(range) => range.len(),
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::List(list) => list.len(),
+            Object::Map(map) => map.len(),
+            _ => return Err(length_error(heap, &args[0])),
+        },
+        other => return Err(length_error(heap, other)),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+fn length_error(heap: &Heap, value: &Value) -> String {
+    format!("Can't take the length of a {}.", heap.type_name(value))
+}
+
+fn push(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    expect_list(heap, &args[0], "push")?.push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+fn pop(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    expect_list(heap, &args[0], "pop")?
+        .pop()
+        .map_err(|e| e.to_string())
+}
+
+fn insert(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let index_type: &str = heap.type_name(&args[1]);
+    let list: &mut List<Value> = expect_list(heap, &args[0], "insert")?;
+    let index: f64 = expect_index(&args[1], index_type, "insert")?;
+    list.insert(index, args[2].clone())
+        .map_err(|e| e.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn remove(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let index_type: &str = heap.type_name(&args[1]);
+    let list: &mut List<Value> = expect_list(heap, &args[0], "remove")?;
+    let index: f64 = expect_index(&args[1], index_type, "remove")?;
+    list.remove(index).map_err(|e| e.to_string())
+}
+
+fn keys(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let keys: Vec<Value> = expect_map(heap, &args[0], "keys")?
+        .keys()
+        .map(Value::from_key)
+        .collect();
+    Ok(Value::Object(heap.alloc(Object::List(List::new(keys)))))
+}
+
+fn values(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let values: Vec<Value> = expect_map(heap, &args[0], "values")?
+        .values()
+        .cloned()
+        .collect();
+    Ok(Value::Object(heap.alloc(Object::List(List::new(values)))))
+}
+
+// Substrings of a string, elements of a list or keys of a map
+fn contains(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    if let Value::Text(s) = &args[0] {
+        let needle: std::rc::Rc<str> = expect_text(heap, &args[1], "contains")?;
+        return Ok(Value::Bool(s.contains(&*needle)));
+    }
+
+    if let Value::Object(handle) = &args[0] {
+        match heap.get(*handle) {
+            Object::List(list) => {
+                let found: bool = list.items().iter().any(|item| item.equals(&args[1]));
+                return Ok(Value::Bool(found));
+            }
+            Object::Map(map) => {
+                let key = args[1].to_key(heap).map_err(|e| e.to_string())?;
+                return Ok(Value::Bool(map.contains(&key)));
+            }
+            _ => {}
+        }
+    }
+
+    Err(stdlib::expected(
+        "contains",
+        "a string, list or map",
+        heap.type_name(&args[0]),
+    ))
+}
+
+// Returns the removed value, or nil when the key was not there
+fn delete(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let key = args[1].to_key(heap);
+    let map: &mut Map<Value> = expect_map(heap, &args[0], "delete")?;
+    let removed: Option<Value> = map.remove(&key.map_err(|e| e.to_string())?);
+    Ok(removed.unwrap_or(Value::Nil))
+}
+
+fn str(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(&heap.display(&args[0])))
+}
+
+// nil when the string isn't a number
+fn num(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(n) => Ok(Value::Number(*n)),
+        Value::Text(s) => Ok(stdlib::parse_number(s).map_or(Value::Nil, Value::Number)),
+        other => Err(stdlib::expected(
+            "num",
+            "a string or number",
+            heap.type_name(other),
+        )),
+    }
+}
+
+fn type_of(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(heap.type_name(&args[0])))
+}
+
+fn input(_heap: &mut Heap, _args: &[Value]) -> Result<Value, String> {
+    Ok(stdlib::input()?.map_or(Value::Nil, |line| Value::text(&line)))
+}
+
+fn sqrt(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(stdlib::sqrt(expect_number(
+        heap, &args[0], "sqrt",
+    )?)?))
+}
+
+fn floor(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(
+        expect_number(heap, &args[0], "floor")?.floor(),
+    ))
+}
+
+fn pow(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let base: f64 = expect_number(heap, &args[0], "pow")?;
+    let exponent: f64 = expect_number(heap, &args[1], "pow")?;
+    Ok(Value::Number(base.powf(exponent)))
+}
+
+fn split(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let s: std::rc::Rc<str> = expect_text(heap, &args[0], "split")?;
+    let separator: std::rc::Rc<str> = expect_text(heap, &args[1], "split")?;
+    let parts: Vec<Value> = stdlib::split(&s, &separator)
+        .iter()
+        .map(|part| Value::text(part))
+        .collect();
+    Ok(Value::Object(heap.alloc(Object::List(List::new(parts)))))
+}
+
+fn trim(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(expect_text(heap, &args[0], "trim")?.trim()))
+}
+
+fn upper(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(
+        &expect_text(heap, &args[0], "upper")?.to_uppercase(),
+    ))
+}
+
+fn lower(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::text(
+        &expect_text(heap, &args[0], "lower")?.to_lowercase(),
+    ))
+}
+
+fn replace(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let s: std::rc::Rc<str> = expect_text(heap, &args[0], "replace")?;
+    let from: std::rc::Rc<str> = expect_text(heap, &args[1], "replace")?;
+    let to: std::rc::Rc<str> = expect_text(heap, &args[2], "replace")?;
+    Ok(Value::text(&stdlib::replace(&s, &from, &to)?))
+}
+
+fn substr(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let s: std::rc::Rc<str> = expect_text(heap, &args[0], "substr")?;
+    let start: f64 = expect_number(heap, &args[1], "substr")?;
+    let length: f64 = expect_number(heap, &args[2], "substr")?;
+    Ok(Value::text(&stdlib::substr(&s, start, length)?))
+}
+
+fn assert(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    if !args[0].is_truthy() {
+        return Err(stdlib::assert_failed(&heap.repr(&args[0])));
+    }
+    Ok(Value::Nil)
+}
+
+fn assert_eq(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let (actual, expected) = (&args[0], &args[1]);
+    if !same(heap, actual, expected, &mut Vec::new()) {
+        return Err(stdlib::assert_eq_failed(
+            &heap.repr(actual),
+            &heap.repr(expected),
+        ));
+    }
+    Ok(Value::Nil)
+}
+
+fn assert_throws(_heap: &mut Heap, _args: &[Value]) -> Result<Value, String> {
+    unreachable!("the VM runs assert_throws() in its call path")
+}
+
+fn error(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let message: std::rc::Rc<str> = expect_text(heap, &args[0], "error")?;
+    Ok(Value::Object(
+        heap.alloc(Object::Error(stdlib::error(&message))),
+    ))
+}
+
+// Equality that looks inside lists and maps, where `==` compares identity.
+// Pairs already being compared count as equal, so cycles end.
+fn same(heap: &Heap, a: &Value, b: &Value, comparing: &mut Vec<(ObjRef, ObjRef)>) -> bool {
+    let (Value::Object(left), Value::Object(right)) = (a, b) else {
+        return a.equals(b);
+    };
+    if left == right || comparing.contains(&(*left, *right)) {
+        return true;
+    }
+    comparing.push((*left, *right));
+    let equal: bool = match (heap.get(*left), heap.get(*right)) {
+        (Object::List(a), Object::List(b)) => {
+            a.len() == b.len()
+                && a.items()
+                    .iter()
+                    .zip(b.items())
+                    .all(|(a, b)| same(heap, a, b, comparing))
+        }
+        (Object::Map(a), Object::Map(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same(heap, a, b, comparing)))
+        }
+        _ => false,
+    };
+    comparing.pop();
+    equal
+}
+
+fn expect_list<'a>(
+    heap: &'a mut Heap,
+    value: &Value,
+    name: &str,
+) -> Result<&'a mut List<Value>, String> {
+    let type_name: &str = heap.type_name(value);
+    if let Value::Object(handle) = value
+        && let Object::List(list) = heap.get_mut(*handle)
+    {
+        return Ok(list);
+    }
+    Err(stdlib::expected(name, "a list", type_name))
+}
+
+fn expect_map<'a>(
+    heap: &'a mut Heap,
+    value: &Value,
+    name: &str,
+) -> Result<&'a mut Map<Value>, String> {
+    let type_name: &str = heap.type_name(value);
+    if let Value::Object(handle) = value
+        && let Object::Map(map) = heap.get_mut(*handle)
+    {
+        return Ok(map);
+    }
+    Err(stdlib::expected(name, "a map", type_name))
+}
+
+fn expect_text(heap: &Heap, value: &Value, name: &str) -> Result<std::rc::Rc<str>, String> {
+    match value {
+        Value::Text(s) => Ok(s.clone()),
+        other => Err(stdlib::expected(name, "a string", heap.type_name(other))),
+    }
+}
+
+fn expect_number(heap: &Heap, value: &Value, name: &str) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        other => Err(stdlib::expected(name, "a number", heap.type_name(other))),
+    }
+}
+
+// Takes the type name up front since the list being indexed holds the heap
+fn expect_index(value: &Value, type_name: &str, name: &str) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => Err(stdlib::expected(name, "a number index", type_name)),
+    }
+}
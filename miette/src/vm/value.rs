@@ -0,0 +1,138 @@
+use super::heap::Heap;
+use crate::chunk::FunctionProto;
+use crate::error::RuntimeError;
+use crate::list::List;
+use crate::map::{Map, MapError, MapKey};
+use crate::range::Range;
+
+// Handle to an object on the VM heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub usize);
+
+// Strings are immutable and cannot form cycles, so they live outside the heap
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Text(std::rc::Rc<str>),
+    Range(Range),
+    Object(ObjRef),
+}
+
+impl Value {
+    pub fn text(s: &str) -> Value {
+        Value::Text(std::rc::Rc::from(s))
+    }
+
+    // nil and false are falsey, everything else is truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn to_key(&self, heap: &Heap) -> Result<MapKey, MapError> {
+        match self {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Number(n) => MapKey::number(*n),
+            Value::Text(s) => Ok(MapKey::Text(s.to_string())),
+            other => Err(MapError::Unhashable(heap.type_name(other).to_string())),
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> Value {
+        match key {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Number(_) => Value::Number(key.as_number().unwrap_or_default()),
+            MapKey::Text(s) => Value::text(s),
+        }
+    }
+
+    // Value types compare by value, objects by identity
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Text(a), Value::Text(b)) => a == b,
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+pub enum Object {
+    List(List<Value>),
+    Map(Map<Value>),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Native(NativeFunction),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    Iterator(IterState),
+    Module(Module),
+    // A caught runtime error or one made by `error()`, which `throw` fills
+    // in with where it was thrown
+    Error(RuntimeError),
+}
+
+pub struct Closure {
+    pub function: std::rc::Rc<FunctionProto>,
+    pub upvalues: Vec<ObjRef>,
+    // Index of the globals of the module that declared the function
+    pub module: usize,
+}
+
+// A captured variable, on the stack while its scope is live and moved into
+// the upvalue once the scope ends
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+// Any Rust closure can be a native, so built-ins can keep state between calls
+pub type NativeFn = std::rc::Rc<dyn Fn(&mut Heap, &[Value]) -> Result<Value, String>>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+// Inherited methods are copied in when the class is declared
+pub struct Class {
+    pub name: String,
+    pub methods: std::collections::HashMap<String, ObjRef>,
+}
+
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: std::collections::HashMap<String, Value>,
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// Where a for-in loop is up to
+pub enum IterState {
+    // Indexed each step so the body may grow or shrink the list
+    List { list: ObjRef, index: usize },
+    // Map keys and string characters, taken when the loop starts
+    Values { items: Vec<Value>, index: usize },
+    Range { range: Range, index: usize },
+    // An instance with a `next()` method
+    Protocol(Value),
+}
+
+// An imported file. Its globals stay with the VM, exports are read from them
+// when used.
+pub struct Module {
+    pub name: String,
+    pub globals: usize,
+    pub exports: Vec<String>,
+}
@@ -0,0 +1,364 @@
+use miette::bytecode::{self, LoadError};
+use miette::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use miette::compiler::Compiler;
+use miette::lex::{self, Span};
+use miette::parser::Parser;
+
+fn compile(source: &str) -> std::rc::Rc<FunctionProto> {
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    Compiler::new().compile(&statements).unwrap()
+}
+
+// Deterministic xorshift so failures can be reproduced from the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// The file's checksum, so tests can corrupt a file without it showing
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+// Builds a function whose code is valid for the loader, with `depth` levels
+// of nested functions in its constant pool
+fn random_function(rng: &mut Rng, depth: usize) -> FunctionProto {
+    let mut chunk: Chunk = Chunk::new();
+    let names: [&str; 5] = ["a", "b", "ünï", "", "x y"];
+    let numbers: [f64; 6] = [0.0, -0.0, 1.5, -7.0, f64::INFINITY, 1e300];
+    for _ in 0..rng.below(6) + 1 {
+        chunk.constants.push(Constant::Text(std::rc::Rc::from(
+            names[rng.below(names.len())],
+        )));
+    }
+    for _ in 0..rng.below(4) {
+        chunk
+            .constants
+            .push(Constant::Number(numbers[rng.below(numbers.len())]));
+    }
+    let upvalue_count: usize = rng.below(3);
+    if depth > 0 {
+        for _ in 0..rng.below(3) {
+            let inner: FunctionProto = random_function(rng, depth - 1);
+            chunk
+                .constants
+                .push(Constant::Function(std::rc::Rc::new(inner)));
+        }
+    }
+
+    let arity: usize = rng.below(256);
+    // Slot 0 and the arguments, kept above zero so there is always a value
+    // for the jumps and the final return
+    let mut depth: usize = 1 + arity;
+    let mut jumps: Vec<usize> = Vec::new();
+    for _ in 0..rng.below(20) {
+        let line: usize = rng.below(4) + 1;
+        let span: Span = Span::new(rng.below(50), rng.below(50));
+        let index: usize = rng.below(chunk.constants.len());
+        match (rng.below(5), chunk.constants[index].clone()) {
+            (0, Constant::Text(_)) => {
+                chunk.write(OpCode::GetGlobal as u8, line, span);
+                chunk.write(0, line, span);
+                chunk.write(index as u8, line, span);
+                depth += 1;
+            }
+            (1, Constant::Function(function)) => {
+                chunk.write(OpCode::Closure as u8, line, span);
+                chunk.write(0, line, span);
+                chunk.write(index as u8, line, span);
+                for _ in 0..function.upvalue_count {
+                    chunk.write(1, line, span);
+                    chunk.write(rng.below(depth.min(256)) as u8, line, span);
+                }
+                depth += 1;
+            }
+            (2, _) => {
+                jumps.push(chunk.code.len());
+                chunk.write(OpCode::JumpIfFalse as u8, line, span);
+                chunk.write(0, line, span);
+                chunk.write(0, line, span);
+            }
+            (3, Constant::Number(_)) => {
+                chunk.write(OpCode::Constant as u8, line, span);
+                chunk.write(0, line, span);
+                chunk.write(index as u8, line, span);
+                depth += 1;
+            }
+            _ if depth > 1 => {
+                chunk.write(OpCode::Pop as u8, line, span);
+                depth -= 1;
+            }
+            _ => {
+                chunk.write(OpCode::Nil as u8, line, span);
+                depth += 1;
+            }
+        }
+    }
+
+    // Every forward jump lands on the final return
+    let end: usize = chunk.code.len();
+    for jump in jumps {
+        let distance: [u8; 2] = ((end - jump - 3) as u16).to_be_bytes();
+        chunk.code[jump + 1] = distance[0];
+        chunk.code[jump + 2] = distance[1];
+    }
+    chunk.write(OpCode::Return as u8, 9, Span::default());
+
+    FunctionProto {
+        name: names[rng.below(names.len())].to_string(),
+        arity,
+        upvalue_count,
+        chunk,
+    }
+}
+
+#[test]
+fn every_test_program_round_trips() {
+    let mut count: usize = 0;
+    for entry in std::fs::read_dir("tests/programs").unwrap() {
+        let path: std::path::PathBuf = entry.unwrap().path();
+        let script = compile(&std::fs::read_to_string(&path).unwrap());
+        let loaded = bytecode::load(&bytecode::write(&script)).unwrap();
+        assert_eq!(loaded, script, "{}", path.display());
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn random_functions_round_trip() {
+    for seed in 1..200 {
+        let mut rng: Rng = Rng(seed);
+        let function: FunctionProto = random_function(&mut rng, 2);
+        let bytes: Vec<u8> = bytecode::write(&function);
+        let loaded = bytecode::load(&bytes).unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+        assert_eq!(*loaded, function, "seed {}", seed);
+        assert_eq!(bytecode::write(&loaded), bytes, "seed {}", seed);
+    }
+}
+
+#[test]
+fn exports_and_imports_round_trip() {
+    let artifact: bytecode::Artifact = bytecode::Artifact {
+        script: compile("export var a = 1;\n"),
+        exports: vec!["a".to_string()],
+        imports: vec![("geo".to_string(), "../geo/main.mtc".to_string())],
+    };
+    let bytes: Vec<u8> = bytecode::write_artifact(&artifact);
+    assert_eq!(bytecode::load_artifact(&bytes), Ok(artifact.clone()));
+    assert_eq!(bytecode::write_artifact(&artifact), bytes);
+
+    // Files without the tables, like those of single-file builds, have none
+    let plain: Vec<u8> = bytecode::write(&artifact.script);
+    let loaded: bytecode::Artifact = bytecode::load_artifact(&plain).unwrap();
+    assert!(loaded.exports.is_empty() && loaded.imports.is_empty());
+    for length in 0..bytes.len() {
+        assert!(
+            bytecode::load_artifact(&bytes[..length]).is_err(),
+            "length {}",
+            length
+        );
+    }
+}
+
+#[test]
+fn import_table_indexes_are_checked() {
+    let artifact: bytecode::Artifact = bytecode::Artifact {
+        script: compile("print 1;\n"),
+        exports: Vec::new(),
+        imports: vec![("geo".to_string(), "geo.mtc".to_string())],
+    };
+    let mut bytes: Vec<u8> = bytecode::write_artifact(&artifact);
+    // The file index is the last thing before the checksum
+    let at: usize = bytes.len() - 8;
+    bytes[at..at + 4].copy_from_slice(&99u32.to_be_bytes());
+    let body: usize = bytes.len() - 4;
+    let checksum: u32 = fnv1a(&bytes[..body]);
+    bytes[body..].copy_from_slice(&checksum.to_be_bytes());
+
+    assert_eq!(
+        bytecode::load_artifact(&bytes).unwrap_err().to_string(),
+        format!(
+            "String index 99 out of range for {} strings.",
+            u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]])
+        )
+    );
+}
+
+#[test]
+fn every_truncation_is_rejected() {
+    let bytes: Vec<u8> = bytecode::write(&compile(
+        "fun f(a) { return fun() { return a; }; }\nprint f(\"x\")();\n",
+    ));
+    for length in 0..bytes.len() {
+        assert!(
+            bytecode::load(&bytes[..length]).is_err(),
+            "length {}",
+            length
+        );
+    }
+}
+
+#[test]
+fn every_corrupted_byte_is_rejected() {
+    let bytes: Vec<u8> = bytecode::write(&compile("var a = [1, 2];\nfor (x in a) print x;\n"));
+    for position in 0..bytes.len() {
+        for flip in [0x01, 0x80, 0xff] {
+            let mut corrupt: Vec<u8> = bytes.clone();
+            corrupt[position] ^= flip;
+            assert!(
+                bytecode::load(&corrupt).is_err(),
+                "byte {} flipped by {:#x}",
+                position,
+                flip
+            );
+        }
+    }
+}
+
+#[test]
+fn header_errors_are_precise() {
+    let bytes: Vec<u8> = bytecode::write(&compile("print 1;"));
+
+    assert_eq!(bytecode::load(b"MIET"), Err(LoadError::BadMagic));
+
+    let mut future: Vec<u8> = bytes.clone();
+    future[5] = 9;
+    assert_eq!(
+        bytecode::load(&future),
+        Err(LoadError::UnsupportedVersion(9))
+    );
+
+    assert_eq!(
+        bytecode::load(&bytes[..8]).unwrap_err().to_string(),
+        "File ends at byte 8 while reading the string count."
+    );
+
+    let mut trailing: Vec<u8> = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        bytecode::load(&trailing),
+        Err(LoadError::TrailingBytes {
+            offset: bytes.len()
+        })
+    );
+}
+
+#[test]
+fn corrupt_checksum_is_reported() {
+    let mut bytes: Vec<u8> = bytecode::write(&compile("print 1;"));
+    let last: usize = bytes.len() - 1;
+    bytes[last] ^= 1;
+    assert!(matches!(
+        bytecode::load(&bytes),
+        Err(LoadError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn invalid_code_is_rejected_even_with_a_valid_checksum() {
+    let mut chunk: Chunk = Chunk::new();
+    chunk.write(OpCode::Constant as u8, 1, Span::default());
+    chunk.write(0, 1, Span::default());
+    chunk.write(3, 1, Span::default());
+    chunk.write(OpCode::Return as u8, 1, Span::default());
+    let function: FunctionProto = FunctionProto {
+        name: "script".to_string(),
+        arity: 0,
+        upvalue_count: 0,
+        chunk,
+    };
+
+    assert_eq!(
+        bytecode::load(&bytecode::write(&function))
+            .unwrap_err()
+            .to_string(),
+        "Invalid code in script at 0000: no value constant 3."
+    );
+}
+
+#[test]
+fn stack_misuse_is_rejected_even_with_a_valid_checksum() {
+    let script = |code: &[u8]| -> FunctionProto {
+        let mut chunk: Chunk = Chunk::new();
+        for byte in code {
+            chunk.write(*byte, 1, Span::default());
+        }
+        FunctionProto {
+            name: "script".to_string(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk,
+        }
+    };
+
+    let local: FunctionProto = script(&[OpCode::GetLocal as u8, 200, OpCode::Return as u8]);
+    assert_eq!(
+        bytecode::load(&bytecode::write(&local))
+            .unwrap_err()
+            .to_string(),
+        "Invalid code in script at 0000: local slot 200 but the stack holds 1."
+    );
+
+    let pop = OpCode::Pop as u8;
+    let underflow: FunctionProto = script(&[pop, pop, pop, pop, OpCode::Return as u8]);
+    assert_eq!(
+        bytecode::load(&bytecode::write(&underflow))
+            .unwrap_err()
+            .to_string(),
+        "Invalid code in script at 0001: Pop needs 1 on the stack, which holds 0."
+    );
+
+    // A jump target is reached with the smaller of the depths leading to it
+    let join: FunctionProto = script(&[
+        OpCode::True as u8,
+        OpCode::JumpIfFalse as u8,
+        0,
+        1,
+        OpCode::Nil as u8,
+        OpCode::GetLocal as u8,
+        2,
+        OpCode::Return as u8,
+    ]);
+    assert_eq!(
+        bytecode::load(&bytecode::write(&join))
+            .unwrap_err()
+            .to_string(),
+        "Invalid code in script at 0005: local slot 2 but the stack holds 2."
+    );
+}
+
+#[test]
+fn missing_return_is_rejected() {
+    let mut chunk: Chunk = Chunk::new();
+    chunk.write(OpCode::Nil as u8, 1, Span::default());
+    let function: FunctionProto = FunctionProto {
+        name: "script".to_string(),
+        arity: 0,
+        upvalue_count: 0,
+        chunk,
+    };
+
+    assert_eq!(
+        bytecode::load(&bytecode::write(&function))
+            .unwrap_err()
+            .to_string(),
+        "Invalid code in script at 0001: code does not end with a return."
+    );
+}
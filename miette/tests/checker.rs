@@ -0,0 +1,271 @@
+use miette::ast::Stmt;
+use miette::checker::Checker;
+use miette::lex;
+use miette::parser::Parser;
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = lex::scan_source(source).unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn check_errors(source: &str) -> Vec<String> {
+    match Checker::new().check(&parse(source)) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    }
+}
+
+fn strict_errors(source: &str) -> Vec<String> {
+    let mut checker: Checker = Checker::new();
+    checker.set_strict(true);
+    match checker.check(&parse(source)) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    }
+}
+
+#[test]
+fn unannotated_code_is_never_reported() {
+    let source: &str = "var a = 1; a = \"one\"; fun f(x) { return x + 1; } f(\"two\");";
+    assert!(check_errors(source).is_empty());
+}
+
+#[test]
+fn locals_take_the_type_of_their_initializer() {
+    assert_eq!(
+        check_errors("var a = \"one\"; var n: Number = a;"),
+        vec!["[line 1] Error at 'a': Expected Number for 'n', found String."]
+    );
+}
+
+#[test]
+fn assignments_update_the_inferred_type() {
+    assert_eq!(
+        check_errors("var a = 1; var b: Number = a; a = \"one\"; var c: Number = a;"),
+        vec!["[line 1] Error at 'a': Expected Number for 'c', found String."]
+    );
+}
+
+#[test]
+fn nil_checks_narrow_optional_types() {
+    let source: &str = "
+        fun f(s: String?) {
+            if (s == nil) return;
+            var t: String = s;
+        }
+        fun g(s: String?) {
+            if (s != nil and len(s) > 0) { var t: String = s; }
+            var u: String = s;
+        }";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 8] Error at 's': Expected String for 'u', found String?."]
+    );
+}
+
+#[test]
+fn branches_merge_into_a_union() {
+    assert_eq!(
+        check_errors("var a = 1; if (clock() > 0) a = \"one\"; var n: Number = a;"),
+        vec!["[line 1] Error at 'a': Expected Number for 'n', found Number | String."]
+    );
+}
+
+#[test]
+fn loops_forget_what_their_body_assigns() {
+    let source: &str = "
+        var a: Number? = 1;
+        while (a != nil) { a = nil; }
+        var b: Number? = 1;
+        for (var i = 0; i < 3; i = i + 1) { var n: Number = b; b = nil; }";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 5] Error at 'b': Expected Number for 'n', found Number?."]
+    );
+}
+
+#[test]
+fn functions_see_outer_variables_as_declared() {
+    let source: &str = "
+        var a: Number? = 1;
+        fun f() { var n: Number = a; }
+        var b = 1;
+        fun g() { var n: Number = b; }";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 3] Error at 'a': Expected Number for 'n', found Number?."]
+    );
+}
+
+#[test]
+fn fields_are_narrowed_until_a_call() {
+    let source: &str = "
+        class Node {
+            next: Node?;
+            value: Number;
+            last(): Number {
+                if (this.next != nil) return this.next.last();
+                return this.value;
+            }
+        }
+        fun f(node: Node) {
+            if (node.next != nil) {
+                var next: Node = node.next;
+                print next;
+                clock();
+                var again: Node = node.next;
+            }
+        }";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 15] Error at 'node': Expected Node for 'again', found Node?."]
+    );
+}
+
+#[test]
+fn subclasses_are_accepted_for_their_superclass() {
+    let source: &str = "
+        class Shape {}
+        class Circle < Shape {}
+        var s: Shape = Circle();
+        var c: Circle = Shape();";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 5] Error at 'Shape': Expected Circle for 'c', found Shape."]
+    );
+}
+
+#[test]
+fn methods_and_initializers_check_their_arguments() {
+    let source: &str = "
+        class Point {
+            init(x: Number, y: Number) {}
+            scale(by: Number): Point { return this; }
+        }
+        var p = Point(1, \"two\");
+        p.scale(nil);";
+    assert_eq!(
+        check_errors(source),
+        vec![
+            "[line 6] Error at '\"two\"': Expected Number for parameter 'y' of 'init', found String.",
+            "[line 7] Error at 'nil': Expected Number for parameter 'by' of 'scale', found Nil.",
+        ]
+    );
+}
+
+#[test]
+fn operators_check_their_operands() {
+    let source: &str = "
+        var x: Number = 1;
+        var y: String = \"a\";
+        var z: Number? = nil;
+        print x - y;
+        print -y;
+        print z * 2;
+        print x + y;
+        print y + y;
+        print x < 2;
+        if (z != nil) print z * 2;";
+    assert_eq!(
+        check_errors(source),
+        vec![
+            "[line 5] Error at 'y': Expected Number for operand of '-', found String.",
+            "[line 6] Error at 'y': Expected Number for operand of '-', found String.",
+            "[line 7] Error at 'z': Expected Number for operand of '*', found Nil.",
+            "[line 8] Error at 'y': Expected Number for operand of '+', found String.",
+        ]
+    );
+    assert_eq!(
+        check_errors("fun f(n: Number?) { return n + 1; }"),
+        vec!["[line 1] Error at 'n': Expected Number for operand of '+', found Number?."]
+    );
+}
+
+#[test]
+fn calls_check_arity_and_callee() {
+    let source: &str = "
+        fun f(a: Number): String { return \"\"; }
+        class Pair { init(a: Number, b: Number) {} }
+        class Child < Pair {}
+        fun g(a) {}
+        var x: Number = 1;
+        f(1, 2);
+        f();
+        g(1, 2);
+        Child(1);
+        x();
+        len(1, 2, 3);";
+    assert_eq!(
+        check_errors(source),
+        vec![
+            "[line 7] Error at 'f': Expected 1 arguments to 'f' but got 2.",
+            "[line 8] Error at 'f': Expected 1 arguments to 'f' but got 0.",
+            "[line 10] Error at 'Child': Expected 2 arguments to 'init' but got 1.",
+            "[line 11] Error at 'x': Can only call functions and classes, found Number.",
+        ]
+    );
+}
+
+#[test]
+fn plain_code_stays_dynamic() {
+    let source: &str = "
+        var n = num(\"3\");
+        print n + 1;
+        print \"a\" + 1;
+        var text = \"text\";
+        text();
+        fun f(a) {}
+        f(1, 2);
+        class Pair { init(a, b) {} }
+        Pair(1);";
+    assert_eq!(check_errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn returning_on_every_path_is_required_unless_nil_is_allowed() {
+    let source: &str = "
+        fun a(n: Number): Number { if (n > 0) { return 1; } else { return 2; } }
+        fun b(n: Number): Number { while (true) { return n; } }
+        fun c(n: Number): Number? { if (n > 0) return 1; }
+        fun d(n: Number): Number { if (n > 0) return 1; }";
+    assert_eq!(
+        check_errors(source),
+        vec!["[line 5] Error at 'Number': 'd' can reach its end without returning Number."]
+    );
+}
+
+#[test]
+fn mismatches_span_the_whole_expression() {
+    let errors = Checker::new()
+        .check(&parse("var n: Number = \"a\" + \"b\";"))
+        .unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].span.start, errors[0].span.end), (16, 25));
+}
+
+#[test]
+fn unions_and_optionals_print_as_written() {
+    assert_eq!(
+        check_errors("var a: Number | String? = true;"),
+        vec!["[line 1] Error at 'true': Expected Number | String | Nil for 'a', found Bool."]
+    );
+}
+
+#[test]
+fn strict_mode_requires_annotations_on_exports() {
+    let source: &str = "
+        export fun area(w: Number, h): Number { return w * h; }
+        export fun name(s: String) { return s; }
+        export class Box { init(size) {} grow(): Nil {} }
+        fun private(x) { return x; }";
+    assert_eq!(
+        strict_errors(source),
+        vec![
+            "[line 2] Error at 'h': Parameter 'h' of exported function 'area' needs a type.",
+            "[line 3] Error at 'name': Exported function 'name' needs a return type.",
+            "[line 4] Error at 'size': Parameter 'size' of exported method 'init' needs a type.",
+        ]
+    );
+    assert!(check_errors(source).is_empty());
+}
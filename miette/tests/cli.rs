@@ -0,0 +1,495 @@
+mod common;
+
+use common::Scratch;
+
+// Runs the built binary the way a user would
+fn miette(args: &[&str]) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn run_prints_program_output_once() {
+    let scratch: Scratch = Scratch::new("cli-run");
+    let path = scratch.file("once.miette", "print \"a\";\nprint 1 + 1;\n");
+    for args in [vec!["run"], vec!["run", "--vm"]] {
+        let mut args: Vec<&str> = args;
+        args.push(path.to_str().unwrap());
+        let output = miette(&args);
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "a\n2\n",
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn exit_codes_follow_sysexits() {
+    let scratch: Scratch = Scratch::new("cli-exit-codes");
+    assert_eq!(miette(&[]).status.code(), Some(64));
+    let compile_error = scratch.file("compile_error.miette", "print ;");
+    assert_eq!(
+        miette(&["run", compile_error.to_str().unwrap()])
+            .status
+            .code(),
+        Some(65)
+    );
+    let runtime_error = scratch.file("runtime_error.miette", "print 1 / 0;");
+    assert_eq!(
+        miette(&["run", runtime_error.to_str().unwrap()])
+            .status
+            .code(),
+        Some(70)
+    );
+
+    let missing = scratch.path().join("no_such_file.miette");
+    let output = miette(&["run", missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(66));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("{}: ", missing.display())),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn built_bytecode_runs_without_the_source() {
+    let scratch: Scratch = Scratch::new("cli-built");
+    let path = scratch.file(
+        "built.miette",
+        "fun twice(x) { return x * 2; }\nprint twice(21);\n",
+    );
+    assert!(miette(&["build", path.to_str().unwrap()]).status.success());
+
+    let compiled: std::path::PathBuf = path.with_extension("mtc");
+    std::fs::remove_file(&path).unwrap();
+    let output = miette(&["run", compiled.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+}
+
+// Runs the binary with `stdin` piped in
+fn miette_with_stdin(args: &[&str], stdin: &str) -> std::process::Output {
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn dash_reads_the_program_from_stdin() {
+    let output = miette_with_stdin(&["run", "-"], "print 6 * 7;\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+
+    let output = miette_with_stdin(&["check", "-"], "print ;\n");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1] Error at ';': Expect expression.\n"
+    );
+}
+
+#[test]
+fn long_chains_are_errors_not_crashes() {
+    let sum = |terms: usize| -> String { format!("print {};\n", vec!["1"; terms].join(" + ")) };
+    for args in [vec!["run", "-"], vec!["run", "--vm", "-"]] {
+        let output = miette_with_stdin(&args, &sum(150));
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "150\n");
+    }
+
+    let index: String = format!("var x = [1];\nprint x{};\n", "[0]".repeat(1000));
+    for (args, source) in [
+        (["run", "-"], sum(1000)),
+        (["check", "-"], sum(3000)),
+        (["run", "-"], index),
+    ] {
+        let output = miette_with_stdin(&args, &source);
+        assert_eq!(output.status.code(), Some(65), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Too deeply nested."), "{}", stderr);
+    }
+}
+
+#[test]
+fn check_reports_errors_without_running() {
+    let scratch: Scratch = Scratch::new("cli-check");
+    let path = scratch.file("check.miette", "print \"ran\";\n");
+    let output = miette(&["check", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // Unresolvable code fails the check even though it would never run
+    let path = scratch.file("check_error.miette", "fun f() { return; }\nreturn 1;\n");
+    assert_eq!(
+        miette(&["check", path.to_str().unwrap()]).status.code(),
+        Some(65)
+    );
+}
+
+#[test]
+fn strict_requires_annotated_exports() {
+    let scratch: Scratch = Scratch::new("cli-strict");
+    let path = scratch.file(
+        "strict.miette",
+        "export fun f(a) { return a; }\nprint f(1);\n",
+    );
+    let output = miette(&["run", path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+
+    let output = miette(&["check", "--strict", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1] Error at 'f': Exported function 'f' needs a return type.\n\
+         [line 1] Error at 'a': Parameter 'a' of exported function 'f' needs a type.\n"
+    );
+    assert_eq!(
+        miette(&["fmt", "--strict", "x.miette"]).status.code(),
+        Some(64)
+    );
+}
+
+#[test]
+fn usage_errors_explain_themselves() {
+    for args in [
+        vec!["frobnicate", "x.miette"],
+        vec!["run"],
+        vec!["run", "--color=sometimes", "x.miette"],
+        vec!["run", "--max-errors", "0", "x.miette"],
+        vec!["run", "--bogus", "x.miette"],
+        vec!["run", "-o", "out", "x.miette"],
+        vec!["build", "-"],
+    ] {
+        let output = miette(&args);
+        assert_eq!(output.status.code(), Some(64), "{:?}", args);
+        let stderr: String = String::from_utf8_lossy(&output.stderr).to_string();
+        assert!(stderr.starts_with("error: "), "{:?}: {}", args, stderr);
+        assert!(stderr.contains("usage: miette"), "{:?}: {}", args, stderr);
+    }
+
+    let help = miette(&["--help"]);
+    assert!(help.status.success());
+    assert!(String::from_utf8_lossy(&help.stdout).contains("exit codes:"));
+}
+
+#[test]
+fn max_errors_limits_the_report() {
+    let scratch: Scratch = Scratch::new("cli-max-errors");
+    let path = scratch.file("many_errors.miette", "print ;\nprint ;\nprint ;\n");
+    let output = miette(&["check", "--max-errors", "1", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1] Error at ';': Expect expression.\n... and 2 more errors\n"
+    );
+}
+
+#[test]
+fn color_marks_errors_only_when_asked() {
+    let scratch: Scratch = Scratch::new("cli-color");
+    let path = scratch.file("color.miette", "print ;\n");
+    let plain = miette(&["check", "--color=never", path.to_str().unwrap()]);
+    assert!(!String::from_utf8_lossy(&plain.stderr).contains('\x1b'));
+    let colored = miette(&["check", "--color", "always", path.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&colored.stderr).contains("\x1b[1;31mError\x1b[0m"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_error_format_is_one_document() {
+    let scratch: Scratch = Scratch::new("cli-json");
+    let path = scratch.file("json_errors.miette", "var a = 1;\nprint ;\nprint ;\n");
+    let output = miette(&[
+        "check",
+        "--error-format=json",
+        "--max-errors=1",
+        path.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(65));
+    let document: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(document["kind"], "diagnostics");
+    assert_eq!(document["diagnostics"].as_array().unwrap().len(), 1);
+    assert_eq!(document["diagnostics"][0]["line"], 2);
+    assert_eq!(document["omitted"], 1);
+
+    let path = scratch.file("json_runtime.miette", "print 1 / 0;\n");
+    let output = miette(&["run", "--error-format", "json", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(70));
+    let document: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(document["kind"], "runtime_error");
+    assert_eq!(document["error"]["line"], 1);
+}
+
+#[test]
+fn output_flag_redirects_to_a_file() {
+    let scratch: Scratch = Scratch::new("cli-output");
+    let path = scratch.file("redirect.miette", "print 1;\n");
+    let listing: std::path::PathBuf = path.with_extension("txt");
+    let output = miette(&[
+        "disasm",
+        path.to_str().unwrap(),
+        "-o",
+        listing.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(
+        std::fs::read_to_string(&listing)
+            .unwrap()
+            .starts_with("== script ==")
+    );
+
+    // Bytecode piped in is recognised by its header
+    let compiled: std::path::PathBuf = path.with_extension("bin");
+    assert!(
+        miette(&[
+            "build",
+            "-o",
+            compiled.to_str().unwrap(),
+            path.to_str().unwrap()
+        ])
+        .status
+        .success()
+    );
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .args(["run", "-"])
+        .stdin(std::fs::File::open(&compiled).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+}
+
+#[test]
+fn fmt_rewrites_in_place_and_check_shows_a_diff() {
+    let scratch: Scratch = Scratch::new("cli-fmt");
+    let path = scratch.file("unformatted.miette", "print 1+2;\n");
+    let output = miette(&["fmt", "--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout: String = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(
+        stdout.contains("-print 1+2;\n+print 1 + 2;\n"),
+        "{}",
+        stdout
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "print 1+2;\n");
+
+    assert!(miette(&["fmt", path.to_str().unwrap()]).status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "print 1 + 2;\n");
+    let output = miette(&["fmt", "--check", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = miette_with_stdin(&["fmt", "-"], "var  a=1 ;");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "var a = 1;\n");
+    assert_eq!(
+        miette(&["run", "--check", "x.miette"]).status.code(),
+        Some(64)
+    );
+}
+
+#[test]
+fn test_reports_failures_and_filters() {
+    let scratch: Scratch = Scratch::new("cli-suite");
+    scratch.file("good.miette", "print 1; // expect: 1\n");
+    scratch.file("nested/bad.miette", "print 1; // expect: 2\n");
+    scratch.file("notes.txt", "print 1; // expect: 2\n");
+    let dir: &str = scratch.path().to_str().unwrap();
+
+    let output = miette(&["test", dir, "-j", "2"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout: String = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.starts_with("running 2 tests\nFAIL "), "{}", stdout);
+    assert!(
+        stdout.contains("bad.miette\n    output differs:\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.ends_with("    +1\n1 passed, 1 failed\n"),
+        "{}",
+        stdout
+    );
+
+    let output = miette(&["test", "--vm", "--filter=good", dir]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "running 1 test\n1 passed, 0 failed, 1 filtered out\n"
+    );
+
+    assert_eq!(miette(&["test", "-"]).status.code(), Some(64));
+    assert_eq!(miette(&["run", "--jobs", "2", dir]).status.code(), Some(64));
+}
+
+#[test]
+fn test_runs_test_blocks_by_name() {
+    let scratch: Scratch = Scratch::new("cli-blocks");
+    scratch.file(
+        "lib.miette",
+        "fun double(n) { return n * 2; }\n\
+         test \"doubles\" { assert_eq(double(2), 4); }\n\
+         test \"breaks\" { assert_eq(double(2), 5); }\n",
+    );
+    let dir: &str = scratch.path().to_str().unwrap();
+
+    let output = miette(&["test", "--vm", dir]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout: String = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.starts_with("running 2 tests\nFAIL "), "{}", stdout);
+    assert!(
+        stdout.contains(
+            "lib.miette: breaks\n    \
+             assert_eq() failed, expected 5 but got 4.\n      \
+             3 | assert_eq(double(2), 5)\n"
+        ),
+        "{}",
+        stdout
+    );
+    assert!(stdout.ends_with("1 passed, 1 failed\n"), "{}", stdout);
+
+    let output = miette(&["test", "--filter", "doubles", dir]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "running 1 test\n1 passed, 0 failed, 1 filtered out\n"
+    );
+
+    // Skipped by a normal run
+    let output = miette(&["run", &format!("{}/lib.miette", dir)]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn run_loads_imports_and_build_refuses_them() {
+    let scratch: Scratch = Scratch::new("cli-imports");
+    scratch.file(
+        "vendor/greet.miette",
+        "export fun hello(name) { return \"hello \" + name; }\n",
+    );
+    let vendor: std::path::PathBuf = scratch.path().join("vendor");
+    let main: std::path::PathBuf = scratch.file(
+        "main.miette",
+        "import \"greet\" as greet;\nprint greet.hello(\"there\");\n",
+    );
+    let main: &str = main.to_str().unwrap();
+
+    let output = miette(&["run", main]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1] Error at '\"greet\"': Can't find module 'greet'.\n"
+    );
+
+    for args in [vec!["run"], vec!["run", "--vm"]] {
+        let mut args: Vec<&str> = args;
+        args.push(main);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+            .args(&args)
+            .env("MIETTE_PATH", &vendor)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello there\n");
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .args(["build", main])
+        .env("MIETTE_PATH", &vendor)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn new_makes_a_project_that_builds_and_runs() {
+    let scratch: Scratch = Scratch::new("cli-projects");
+    let dir: &std::path::Path = scratch.path();
+    let app: std::path::PathBuf = dir.join("hello");
+    let app_arg: &str = app.to_str().unwrap();
+
+    let output = miette(&["new", app_arg]);
+    assert!(output.status.success());
+    assert_eq!(miette(&["new", app_arg]).status.code(), Some(74));
+    let bad_name: std::path::PathBuf = dir.join("1st");
+    assert_eq!(
+        miette(&["new", bad_name.to_str().unwrap()]).status.code(),
+        Some(64)
+    );
+
+    let output = miette(&["build", app_arg]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "compiled target/hello/main.mtc\nbuilt hello 0.1.0, 1 compiled, 0 unchanged\n"
+    );
+    let output = miette(&["build", app_arg]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "built hello 0.1.0, 0 compiled, 1 unchanged\n"
+    );
+    assert_eq!(
+        miette(&["build", "-o", "out", app_arg]).status.code(),
+        Some(64)
+    );
+
+    for args in [vec!["run", app_arg], vec!["run", "--vm", app_arg]] {
+        let output = miette(&args);
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, world!\n");
+    }
+    let artifact: std::path::PathBuf = app.join("target/hello/main.mtc");
+    let output = miette(&["run", artifact.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, world!\n");
+
+    // The files a build writes find the modules they import beside them
+    std::fs::write(
+        app.join("src/util.miette"),
+        "export fun shout(s) { return s + \"!\"; }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        app.join("src/main.miette"),
+        "import \"util.miette\" as u;\nprint u.shout(\"Hello\");\n",
+    )
+    .unwrap();
+    let output = miette(&["build", app_arg]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "compiled target/hello/util.mtc\ncompiled target/hello/main.mtc\nbuilt hello 0.1.0, 2 compiled, 0 unchanged\n"
+    );
+    let output = miette(&["run", artifact.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello!\n");
+    std::fs::remove_file(app.join("target/hello/util.mtc")).unwrap();
+    let output = miette(&["run", artifact.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("util.mtc"));
+
+    std::fs::write(app.join("Miette.toml"), "[package]\nname = \"hello\"\n").unwrap();
+    let output = miette(&["check", app_arg]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("[package] needs a version"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
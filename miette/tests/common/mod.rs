@@ -0,0 +1,60 @@
+// Helpers shared by the integration tests. Each test file compiles this
+// module on its own and uses only some of it.
+#![allow(dead_code)]
+
+// Collects what the interpreter, the VM or the REPL writes, for the test to
+// read back
+#[derive(Clone, Default)]
+pub struct Capture(pub std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl std::io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A directory of one test's own under the system's temporary one. The name
+// carries the process id so runs going at once keep apart, and the directory
+// is removed again when the test drops it.
+pub struct Scratch(std::path::PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        let dir: std::path::PathBuf =
+            std::env::temp_dir().join(format!("miette-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    // Writes `source` to `name` inside the directory, returning its path
+    pub fn file(&self, name: &str, source: &str) -> std::path::PathBuf {
+        let path: std::path::PathBuf = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,90 @@
+use miette::ast::Stmt;
+use miette::chunk::{Constant, FunctionProto, OpCode};
+use miette::compiler::Compiler;
+use miette::lex;
+use miette::parser::Parser;
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = lex::scan_source(source).unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn compile(source: &str) -> std::rc::Rc<FunctionProto> {
+    Compiler::new().compile(&parse(source)).unwrap()
+}
+
+fn compile_errors(source: &str) -> Vec<String> {
+    Compiler::new()
+        .compile(&parse(source))
+        .unwrap_err()
+        .iter()
+        .map(|error| error.to_string())
+        .collect()
+}
+
+#[test]
+fn script_ends_with_an_implicit_return() {
+    let script = compile("print 1;");
+    let code: &[u8] = &script.chunk.code;
+    assert_eq!(script.name, "script");
+    assert_eq!(code[code.len() - 2], OpCode::Nil as u8);
+    assert_eq!(code[code.len() - 1], OpCode::Return as u8);
+    assert_eq!(script.chunk.lines.len(), code.len());
+}
+
+#[test]
+fn repeated_constants_share_a_slot() {
+    let script = compile("print \"a\"; print \"a\"; print 2; print 2;");
+    assert_eq!(script.chunk.constants.len(), 2);
+}
+
+#[test]
+fn functions_are_compiled_into_the_constant_pool() {
+    let script = compile("fun add(a, b) { var c = 1; return a + b + c; }");
+    let function = script
+        .chunk
+        .constants
+        .iter()
+        .find_map(|constant| match constant {
+            Constant::Function(function) => Some(function.clone()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(function.name, "add");
+    assert_eq!(function.arity, 2);
+    assert_eq!(function.upvalue_count, 0);
+}
+
+#[test]
+fn closures_count_their_upvalues() {
+    let script = compile("fun outer() { var a = 1; var b = 2; fun inner() { return a + b; } }");
+    let Some(Constant::Function(outer)) = script
+        .chunk
+        .constants
+        .iter()
+        .find(|constant| matches!(constant, Constant::Function(_)))
+    else {
+        panic!("outer was not compiled");
+    };
+    let Some(Constant::Function(inner)) = outer
+        .chunk
+        .constants
+        .iter()
+        .find(|constant| matches!(constant, Constant::Function(_)))
+    else {
+        panic!("inner was not compiled");
+    };
+    assert_eq!(inner.upvalue_count, 2);
+}
+
+#[test]
+fn too_many_locals_is_a_compile_error() {
+    let declarations: String = (0..300).map(|i| format!("var v{} = {};", i, i)).collect();
+    let errors: Vec<String> = compile_errors(&format!("{{ {} }}", declarations));
+    // Slot zero is reserved, so 255 locals fit and each one after that fails
+    assert_eq!(errors.len(), 300 - 255);
+    assert_eq!(
+        errors[0],
+        "[line 1] Error at 'v255': Too many local variables in function."
+    );
+}
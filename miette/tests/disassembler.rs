@@ -0,0 +1,89 @@
+mod common;
+
+use common::Capture;
+use miette::chunk::{Chunk, OpCode};
+use miette::compiler::Compiler;
+use miette::disassembler::{disassemble, disassemble_instruction};
+use miette::lex;
+use miette::parser::Parser;
+use miette::vm::Vm;
+
+fn disassemble_source(source: &str) -> String {
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    disassemble(&Compiler::new().compile(&statements).unwrap())
+}
+
+#[test]
+fn instructions_show_line_opcode_and_constant() {
+    let listing: String = disassemble_source("print 1 + 2;\nprint \"a\";\n");
+    assert_eq!(
+        listing,
+        "== script ==\n\
+         0000    1 Constant            0 '1'\n\
+         0003    | Constant            1 '2'\n\
+         0006    | Add\n\
+         0007    | Print\n\
+         0008    2 Constant            2 \"a\"\n\
+         0011    | Print\n\
+         0012    | Nil\n\
+         0013    | Return\n"
+    );
+}
+
+#[test]
+fn jumps_show_their_target() {
+    let listing: String = disassemble_source("if (true) print 1;");
+    assert!(
+        listing.contains("0001    | JumpIfFalse      -> 0012\n"),
+        "{}",
+        listing
+    );
+    assert!(
+        listing.contains("0009    | Jump             -> 0013\n"),
+        "{}",
+        listing
+    );
+}
+
+#[test]
+fn nested_functions_follow_the_script() {
+    let listing: String =
+        disassemble_source("fun outer() {\n  var a = 1;\n  fun inner() { return a; }\n}\n");
+    let outer: usize = listing.find("== outer ==").unwrap();
+    let inner: usize = listing.find("== inner ==").unwrap();
+    assert!(outer < inner);
+    assert!(
+        listing.contains("Closure             1 <fn inner>\n0006    |   local             1\n")
+    );
+    assert!(listing.contains("GetUpvalue          0"));
+}
+
+#[test]
+fn truncated_instruction_is_reported() {
+    let mut chunk: Chunk = Chunk::new();
+    chunk.write(OpCode::Constant as u8, 1, Default::default());
+    chunk.write(0, 1, Default::default());
+    let (text, next) = disassemble_instruction(&chunk, 0);
+    assert_eq!(text, "0000    1 Constant <truncated>");
+    assert_eq!(next, 2);
+}
+
+#[test]
+fn trace_prints_the_stack_before_each_instruction() {
+    let tokens = lex::scan_source("print 1 + 2;").unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let script = Compiler::new().compile(&statements).unwrap();
+
+    let trace: Capture = Capture::default();
+    let mut vm: Vm = Vm::with_output(Box::new(Capture::default()));
+    vm.trace(Box::new(trace.clone()));
+    vm.interpret(script).unwrap();
+
+    let text: String = String::from_utf8(trace.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "          [ <fn script> ]");
+    assert_eq!(lines[1], "0000    1 Constant            0 '1'");
+    assert_eq!(lines[4], "          [ <fn script> ][ 1 ][ 2 ]");
+    assert_eq!(lines[5], "0006    | Add");
+}
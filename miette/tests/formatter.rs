@@ -0,0 +1,270 @@
+mod common;
+
+use common::Capture;
+use miette::diff;
+use miette::formatter::{self, WIDTH};
+use miette::interpreter::{self, Interpreter};
+use miette::lex;
+use miette::parser::Parser;
+use miette::resolver::Resolver;
+
+// What a program prints, followed by its runtime error if any
+fn run(source: &str) -> String {
+    // With the stack `miette run` has, which the deepest recursion needs
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || run_here(source))
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
+
+fn run_here(source: &str) -> String {
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let locals = Resolver::new().resolve(&statements).unwrap();
+
+    let out: Capture = Capture::default();
+    let mut interpreter: Interpreter = Interpreter::with_output(Box::new(out.clone()));
+    interpreter.resolve(locals);
+    let error: String = match interpreter.interpret(&statements) {
+        Ok(()) => String::new(),
+        Err(error) => error.to_string(),
+    };
+    String::from_utf8(out.0.borrow().clone()).unwrap() + &error
+}
+
+const MESSY: &str = "\
+// header
+import   \"lib/util\"as util ;
+
+
+var a=[1,2,3];   // trailing
+fun f(a,b){
+  // inside
+  if(a<b){return a;}else if (a==b) return 0; else {return b;}
+
+
+  // end of body
+}
+class A < B { init(x) { this.x = x; } // after init
+
+  get() { return super.get()[1:]; }
+}
+for (;;) {}
+var g = (x) -> (y) -> x + y;
+if (x) print -a[0]..=3;
+else print !true;
+test   \"adds\"{assert_eq(1+1,2);}
+export   fun  h(){}
+try{throw error(\"x\");}catch(e){print e;}finally{print 1;}
+var n :Number?=nil;
+fun k(a:Number,b : String|Nil):Bool{return true;}
+class P{x:Number ; y(){}}
+";
+
+const TIDY: &str = "\
+// header
+import \"lib/util\" as util;
+
+var a = [1, 2, 3]; // trailing
+fun f(a, b) {
+    // inside
+    if (a < b) {
+        return a;
+    } else if (a == b) return 0;
+    else {
+        return b;
+    }
+
+    // end of body
+}
+class A < B {
+    init(x) {
+        this.x = x;
+    } // after init
+
+    get() {
+        return super.get()[1:];
+    }
+}
+for (;;) {}
+var g = (x) -> (y) -> x + y;
+if (x) print -a[0]..=3;
+else print !true;
+test \"adds\" {
+    assert_eq(1 + 1, 2);
+}
+export fun h() {}
+try {
+    throw error(\"x\");
+} catch (e) {
+    print e;
+} finally {
+    print 1;
+}
+var n: Number? = nil;
+fun k(a: Number, b: String | Nil): Bool {
+    return true;
+}
+class P {
+    x: Number;
+    y() {}
+}
+";
+
+#[test]
+fn layout_is_canonical() {
+    assert_eq!(formatter::format(MESSY).unwrap(), TIDY);
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let mut sources: Vec<(String, String)> = vec![("messy".to_string(), MESSY.to_string())];
+    for entry in std::fs::read_dir("tests/programs").unwrap() {
+        let path: std::path::PathBuf = entry.unwrap().path();
+        sources.push((
+            path.display().to_string(),
+            std::fs::read_to_string(&path).unwrap(),
+        ));
+    }
+
+    for (name, source) in sources.iter() {
+        let once: String = formatter::format(source).unwrap();
+        let twice: String = formatter::format(&once).unwrap();
+        assert_eq!(
+            once,
+            twice,
+            "{}\n{}",
+            name,
+            diff::unified(&once, &twice, "once", "twice")
+        );
+    }
+}
+
+#[test]
+fn formatting_keeps_every_comment_and_behaviour() {
+    for entry in std::fs::read_dir("tests/programs").unwrap() {
+        let path: std::path::PathBuf = entry.unwrap().path();
+        let source: String = std::fs::read_to_string(&path).unwrap();
+        let formatted: String = formatter::format(&source).unwrap();
+
+        let comments = |source: &str| -> Vec<String> {
+            let (_, comments) = lex::scan_source_with_comments(source).unwrap();
+            comments
+                .into_iter()
+                .map(|comment| comment.text.trim_end().to_string())
+                .collect()
+        };
+        assert_eq!(
+            comments(&source),
+            comments(&formatted),
+            "{}",
+            path.display()
+        );
+
+        // Errors point at lines, which formatting may move
+        let expected: String = run(&source);
+        let actual: String = run(&formatted);
+        let without_lines = |output: &str| -> Vec<String> {
+            output
+                .lines()
+                .filter(|line| !line.starts_with("[line "))
+                .map(String::from)
+                .collect()
+        };
+        assert_eq!(
+            without_lines(&expected),
+            without_lines(&actual),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn long_calls_and_lists_wrap_one_item_per_line() {
+    // Three of these don't fit on a line, one does
+    let item: String = "a".repeat(WIDTH / 3);
+    let source: String = format!(
+        "print call({0}, {0}, {0});\nvar xs = [{0}, {0}, {0}];\nprint call({0});\n",
+        item
+    );
+    assert_eq!(
+        formatter::format(&source).unwrap(),
+        format!(
+            "print call(\n    {0},\n    {0},\n    {0}\n);\n\
+             var xs = [\n    {0},\n    {0},\n    {0},\n];\n\
+             print call({0});\n",
+            item
+        )
+    );
+}
+
+#[test]
+fn a_trailing_lambda_stays_on_the_call_line() {
+    assert_eq!(
+        formatter::format("for (i in 0..3) push(fs, fun () { return i; });").unwrap(),
+        "for (i in 0..3) push(fs, fun () {\n    return i;\n});\n"
+    );
+}
+
+#[test]
+fn comments_stay_inside_their_block() {
+    assert_eq!(
+        formatter::format("fun f() { // why\n  g();\n  // later\n}\n{\n// only\n}\n").unwrap(),
+        "fun f() { // why\n    g();\n    // later\n}\n{\n    // only\n}\n"
+    );
+}
+
+#[test]
+fn comments_stay_with_list_and_call_items() {
+    assert_eq!(
+        formatter::format("var xs = [ // open\n 1, 2, // two\n 3\n];\n").unwrap(),
+        "var xs = [ // open\n    1,\n    2, // two\n    3,\n];\n"
+    );
+    assert_eq!(
+        formatter::format("f(1, // first\n 2);\n").unwrap(),
+        "f(\n    1, // first\n    2\n);\n"
+    );
+    assert_eq!(
+        formatter::format("var m = {\n// lead\n\"a\": 1, // one\n\"b\": 2\n// end\n};\n").unwrap(),
+        "var m = {\n    // lead\n    \"a\": 1, // one\n    \"b\": 2,\n    // end\n};\n"
+    );
+    assert_eq!(
+        formatter::format("fun g(a, // the a\n b) {}\nvar e = [\n// none yet\n];\n").unwrap(),
+        "fun g(\n    a, // the a\n    b\n) {}\nvar e = [\n    // none yet\n];\n"
+    );
+}
+
+#[test]
+fn unparseable_programs_are_not_formatted() {
+    let errors = formatter::format("print (1;").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "[line 1] Error at ';': Expect ')' after expression."
+    );
+    let errors = formatter::format("print \"open").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "[line 1] Error: Unterminated string."
+    );
+}
+
+#[test]
+fn unified_diff_marks_changes_with_context() {
+    let old: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+    let new: &str = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10";
+    assert_eq!(
+        diff::unified(old, new, "old", "new"),
+        "--- old\n+++ new\n\
+         @@ -2,9 +2,9 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n 9\n-10\n+10\n\\ No newline at end of file\n"
+    );
+    assert_eq!(diff::unified(old, old, "old", "new"), "");
+    assert_eq!(
+        diff::unified("", "a\n", "old", "new"),
+        "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+a\n"
+    );
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::Capture;
+use miette::compiler::Compiler;
+use miette::lex;
+use miette::parser::Parser;
+use miette::vm::Vm;
+
+// Runs `source` on a fresh VM and hands it back along with what it printed
+fn run(source: &str, stress: bool) -> (Vm, String) {
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let script = Compiler::new().compile(&statements).unwrap();
+
+    let capture: Capture = Capture::default();
+    let mut vm: Vm = Vm::with_output(Box::new(capture.clone()));
+    vm.set_gc_stress(stress);
+    vm.interpret(script).unwrap();
+    let output: String = String::from_utf8(capture.0.borrow().clone()).unwrap();
+    (vm, output)
+}
+
+#[test]
+fn unreachable_objects_are_freed() {
+    let (mut vm, _) = run(
+        "for (i in 0..100) { var xs = [i, [i]]; var m = {\"k\": xs}; }",
+        false,
+    );
+    let before: usize = vm.heap().len();
+    let freed: usize = vm.collect_garbage();
+    assert!(freed >= 300, "freed {}", freed);
+    assert_eq!(vm.heap().len(), before - freed);
+}
+
+#[test]
+fn objects_reachable_from_globals_survive() {
+    let source: &str = "
+        class Node { init(value) { this.value = value; this.next = nil; } }
+        var head = Node(1);
+        head.next = Node(2);
+        var keep = {\"list\": [head, fun() { return head; }]};
+        for (i in 0..50) { var junk = [i]; }
+        print keep[\"list\"][1]().next.value;
+    ";
+    let (mut vm, output) = run(source, true);
+    assert_eq!(output, "2\n");
+
+    vm.collect_garbage();
+    let live: usize = vm.heap().len();
+    assert_eq!(vm.collect_garbage(), 0);
+    assert_eq!(vm.heap().len(), live);
+}
+
+#[test]
+fn cycles_are_collected() {
+    let source: &str = "
+        class Pair {}
+        fun make() {
+            var a = Pair();
+            var b = Pair();
+            a.other = b;
+            b.other = a;
+            var xs = [];
+            push(xs, xs);
+        }
+        make();
+    ";
+    let (mut vm, _) = run(source, false);
+    vm.collect_garbage();
+    let baseline: usize = vm.heap().len();
+
+    let tokens = lex::scan_source("make(); make(); make();").unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let script = Compiler::new().compile(&statements).unwrap();
+    vm.interpret(script).unwrap();
+    vm.collect_garbage();
+    assert_eq!(vm.heap().len(), baseline);
+}
+
+#[test]
+fn closed_upvalues_keep_their_values_alive() {
+    let source: &str = "
+        fun make() {
+            var items = [\"a\", [\"b\"]];
+            fun get() { return items; }
+            return get;
+        }
+        var get = make();
+        var junk = [];
+        for (i in 0..50) push(junk, [i]);
+        junk = nil;
+        print get();
+    ";
+    let (_, output) = run(source, true);
+    assert_eq!(output, "[\"a\", [\"b\"]]\n");
+}
+
+#[test]
+fn stress_mode_matches_normal_runs() {
+    let source: &str = "
+        class Counter {
+            init() { this.n = 0; }
+            iter() { return this; }
+            next() {
+                this.n = this.n + 1;
+                if (this.n > 3) return nil;
+                return [this.n, {\"square\": this.n * this.n}];
+            }
+        }
+        var total = [];
+        for (pair in Counter()) push(total, pair[1][\"square\"]);
+        print total;
+        print \"ab\"[0:1] + \"c\";
+    ";
+    let (_, normal) = run(source, false);
+    let (_, stressed) = run(source, true);
+    assert_eq!(normal, "[1, 4, 9]\nac\n");
+    assert_eq!(stressed, normal);
+}
+
+#[test]
+fn threshold_tracks_live_bytes() {
+    let (mut vm, _) = run("var big = []; for (i in 0..1000) push(big, i);", false);
+    vm.collect_garbage();
+    let live: usize = vm.heap().bytes_allocated();
+    assert!(live >= 1000 * std::mem::size_of::<f64>(), "{} bytes", live);
+
+    let tokens = lex::scan_source("big = nil;").unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    vm.interpret(Compiler::new().compile(&statements).unwrap())
+        .unwrap();
+    vm.collect_garbage();
+    assert!(vm.heap().bytes_allocated() < live);
+}
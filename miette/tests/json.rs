@@ -0,0 +1,72 @@
+#![cfg(feature = "json")]
+
+use miette::json;
+use miette::lex;
+use miette::parser::Parser;
+
+fn parse_json(text: &str) -> serde_json::Value {
+    serde_json::from_str(text).expect("output is valid JSON")
+}
+
+#[test]
+fn tokens_document_has_version_kinds_and_spans() {
+    let tokens = lex::scan_source("var x = \"hi\";\nprint x;").unwrap();
+    let document: serde_json::Value = parse_json(&json::tokens_to_json(&tokens));
+
+    assert_eq!(document["schema_version"], json::SCHEMA_VERSION);
+    assert_eq!(document["kind"], "tokens");
+
+    let tokens: &Vec<serde_json::Value> = document["tokens"].as_array().unwrap();
+    assert_eq!(tokens.len(), 9);
+
+    assert_eq!(tokens[0]["kind"]["type"], "Var");
+    assert_eq!(tokens[1]["kind"]["type"], "Identifier");
+    assert_eq!(tokens[1]["kind"]["value"], "x");
+    assert_eq!(tokens[3]["kind"]["type"], "Text");
+    assert_eq!(tokens[3]["kind"]["value"], "hi");
+    assert_eq!(tokens[3]["lexeme"], "\"hi\"");
+    assert_eq!(tokens[3]["span"]["start"], 8);
+    assert_eq!(tokens[3]["span"]["end"], 12);
+    assert_eq!(tokens[5]["line"], 2);
+    assert_eq!(tokens[8]["kind"]["type"], "EOF");
+}
+
+#[test]
+fn ast_document_tags_nodes_by_type() {
+    let tokens = lex::scan_source("fun f(a) { return a + 1; }\nprint f(2);").unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let document: serde_json::Value = parse_json(&json::ast_to_json(&statements));
+
+    assert_eq!(document["schema_version"], json::SCHEMA_VERSION);
+    assert_eq!(document["kind"], "ast");
+
+    let function: &serde_json::Value = &document["statements"][0];
+    assert_eq!(function["type"], "Function");
+    assert_eq!(function["function"]["name"]["lexeme"], "f");
+    assert_eq!(function["function"]["params"][0]["lexeme"], "a");
+
+    let returned: &serde_json::Value = &function["function"]["body"][0]["value"];
+    assert_eq!(returned["type"], "Binary");
+    assert_eq!(returned["operator"]["lexeme"], "+");
+    assert_eq!(returned["right"]["value"]["type"], "Number");
+    assert_eq!(returned["right"]["value"]["value"], 1.0);
+
+    let print: &serde_json::Value = &document["statements"][1];
+    assert_eq!(print["type"], "Print");
+    assert_eq!(print["expression"]["type"], "Call");
+}
+
+#[test]
+fn parse_errors_serialize_as_diagnostics() {
+    let tokens = lex::scan_source("var = 1;").unwrap();
+    let errors = Parser::new(tokens).parse().unwrap_err();
+    let document: serde_json::Value = parse_json(&json::diagnostics_to_json(&errors));
+
+    assert_eq!(document["kind"], "diagnostics");
+    assert_eq!(
+        document["diagnostics"][0]["message"],
+        "Expect variable name."
+    );
+    assert_eq!(document["diagnostics"][0]["line"], 1);
+    assert_eq!(document["diagnostics"][0]["location"], " at '='");
+}
@@ -0,0 +1,129 @@
+use miette::lex;
+use miette::lint::{self, Lint};
+use miette::parser::Parser;
+use miette::resolver::Resolver;
+
+// Warnings left after suppression comments, formatted for comparison
+fn warnings(source: &str) -> Vec<String> {
+    let (tokens, comments) = lex::scan_source_with_comments(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let mut resolver: Resolver = Resolver::new();
+    resolver.resolve(&statements).unwrap();
+    lint::suppress(resolver.warnings().to_vec(), &comments)
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect()
+}
+
+#[test]
+fn lint_codes_round_trip() {
+    for lint in lint::ALL_LINTS {
+        assert_eq!(Lint::from_code(lint.code()), Some(*lint));
+    }
+    assert_eq!(Lint::from_code("unused"), None);
+}
+
+#[test]
+fn unused_locals_parameters_and_functions() {
+    assert_eq!(
+        warnings("fun f(a, _b) {\n  var c = 1;\n  fun g() {}\n}\nf(1, 2);"),
+        vec![
+            "[line 1] Warning at 'a': Unused parameter 'a'. [unused_parameter]",
+            "[line 2] Warning at 'c': Unused variable 'c'. [unused_variable]",
+            "[line 3] Warning at 'g': Unused function 'g'. [unused_function]",
+        ]
+    );
+}
+
+#[test]
+fn assignment_alone_is_not_a_use() {
+    assert_eq!(
+        warnings("{ var a = 1; a = 2; }"),
+        vec!["[line 1] Warning at 'a': Unused variable 'a'. [unused_variable]"]
+    );
+}
+
+#[test]
+fn globals_are_not_checked_for_use() {
+    assert!(warnings("var a = 1; fun f(x) { return x; }").is_empty());
+}
+
+#[test]
+fn code_after_jumps_is_unreachable() {
+    assert_eq!(
+        warnings(
+            "fun f() {\n  return 1;\n  print 2;\n  print 3;\n}\nwhile (true) {\n  break;\n  f();\n}\ntry {\n  throw 1;\n  f();\n} catch (e) {}"
+        ),
+        vec![
+            "[line 3] Warning at 'print': Unreachable code. [unreachable_code]",
+            "[line 8] Warning at 'f': Unreachable code. [unreachable_code]",
+            "[line 12] Warning at 'f': Unreachable code. [unreachable_code]",
+            "[line 13] Warning at 'e': Unused variable 'e'. [unused_variable]",
+        ]
+    );
+}
+
+#[test]
+fn jumps_that_are_errors_leave_what_follows_alone() {
+    let tokens = lex::scan_source("break;\nprint 1;\nreturn 2;\nprint 3;").unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let mut resolver: Resolver = Resolver::new();
+    let errors: Vec<String> = resolver
+        .resolve(&statements)
+        .unwrap_err()
+        .iter()
+        .map(|error| error.to_string())
+        .collect();
+
+    assert_eq!(errors.len(), 2);
+    assert!(resolver.warnings().is_empty());
+}
+
+#[test]
+fn shadowed_names() {
+    assert_eq!(
+        warnings("fun f(a) {\n  { var a = 2; print a; }\n  return a;\n}\nf(1);"),
+        vec![
+            "[line 2] Warning at 'a': 'a' shadows a name from an enclosing scope. [shadowed_name]"
+        ]
+    );
+}
+
+#[test]
+fn constant_conditions() {
+    assert_eq!(
+        warnings(
+            "if (true) print 1;\nwhile ((nil)) print 2;\nwhile (true) break;\nif (0) print 3;"
+        ),
+        vec![
+            "[line 1] Warning at 'true': Condition is always true. [constant_condition]",
+            "[line 2] Warning at 'nil': Condition is always false. [constant_condition]",
+            "[line 4] Warning at '0': Condition is always true. [constant_condition]",
+        ]
+    );
+}
+
+#[test]
+fn allow_comment_on_the_same_line() {
+    assert!(warnings("if (false) print 1; // miette: allow(constant_condition)").is_empty());
+}
+
+#[test]
+fn allow_comment_covers_the_next_line() {
+    let source: &str = "fun f() {\n  // miette: allow(unused_variable, shadowed_name)\n  var a = 1;\n  var b = 2;\n}\nf();";
+
+    assert_eq!(
+        warnings(source),
+        vec!["[line 4] Warning at 'b': Unused variable 'b'. [unused_variable]"]
+    );
+}
+
+#[test]
+fn allow_comment_only_silences_named_lints() {
+    assert_eq!(
+        warnings("if (false) print 1; // miette: allow(unused_variable)"),
+        vec!["[line 1] Warning at 'false': Condition is always false. [constant_condition]"]
+    );
+}
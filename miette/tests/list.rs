@@ -0,0 +1,76 @@
+use miette::list::{List, ListError};
+
+fn numbers() -> List<i32> {
+    List::new(vec![10, 20, 30, 40])
+}
+
+#[test]
+fn negative_indices_count_from_the_end() {
+    let list: List<i32> = numbers();
+
+    assert_eq!(list.get(0.0), Ok(&10));
+    assert_eq!(list.get(-1.0), Ok(&40));
+    assert_eq!(list.get(-4.0), Ok(&10));
+}
+
+#[test]
+fn reads_and_writes_are_bounds_checked() {
+    let mut list: List<i32> = numbers();
+
+    assert_eq!(
+        list.get(4.0),
+        Err(ListError::OutOfRange { index: 4.0, len: 4 })
+    );
+    assert_eq!(
+        list.get(-5.0),
+        Err(ListError::OutOfRange {
+            index: -5.0,
+            len: 4
+        })
+    );
+    assert_eq!(list.set(1.5, 0), Err(ListError::NotAnInteger(1.5)));
+
+    list.set(-2.0, 99).unwrap();
+    assert_eq!(list.items(), &[10, 20, 99, 40]);
+    assert_eq!(
+        list.get(7.0).unwrap_err().to_string(),
+        "List index 7 out of range for list of length 4."
+    );
+}
+
+#[test]
+fn slices_clamp_their_bounds() {
+    let list: List<i32> = numbers();
+
+    assert_eq!(list.slice(Some(1.0), Some(3.0)).unwrap().items(), &[20, 30]);
+    assert_eq!(list.slice(None, Some(-1.0)).unwrap().items(), &[10, 20, 30]);
+    assert_eq!(list.slice(Some(-2.0), None).unwrap().items(), &[30, 40]);
+    assert_eq!(
+        list.slice(Some(1.0), Some(100.0)).unwrap().items(),
+        &[20, 30, 40]
+    );
+    assert!(list.slice(Some(3.0), Some(1.0)).unwrap().is_empty());
+    assert_eq!(
+        list.slice(Some(0.5), None),
+        Err(ListError::NotAnInteger(0.5))
+    );
+}
+
+#[test]
+fn push_pop_insert_remove() {
+    let mut list: List<i32> = numbers();
+
+    list.push(50);
+    assert_eq!(list.pop(), Ok(50));
+    list.insert(0.0, 5).unwrap();
+    list.insert(5.0, 45).unwrap();
+    list.insert(-1.0, 42).unwrap();
+    assert_eq!(list.items(), &[5, 10, 20, 30, 40, 42, 45]);
+    assert_eq!(list.remove(1.0), Ok(10));
+    assert_eq!(list.remove(-1.0), Ok(45));
+    assert_eq!(list.items(), &[5, 20, 30, 40, 42]);
+    assert!(list.insert(9.0, 0).is_err());
+
+    let mut empty: List<i32> = List::new(Vec::new());
+    assert_eq!(empty.pop(), Err(ListError::Empty));
+}
@@ -0,0 +1,491 @@
+#![cfg(feature = "json")]
+
+use miette::lsp::{Server, rpc};
+use serde_json::{Value, json};
+
+const URI: &str = "file:///test.miette";
+
+// Writes a session's messages up front, then plays them to a server and
+// collects what it sent back
+struct Client {
+    script: Vec<u8>,
+    next_id: i64,
+}
+
+impl Client {
+    // Already through the initialize handshake
+    fn new() -> Client {
+        let mut client: Client = Client {
+            script: Vec::new(),
+            next_id: 0,
+        };
+        client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        rpc::write_message(&mut self.script, &message).unwrap();
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> i64 {
+        self.next_id += 1;
+        let id: i64 = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        id
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    fn open(&mut self, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": URI, "languageId": "miette", "version": 1, "text": text },
+            }),
+        );
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> i64 {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            }),
+        )
+    }
+
+    // Whether the server saw a clean shutdown, and its messages
+    fn finish(self) -> (bool, Session) {
+        let mut output: Vec<u8> = Vec::new();
+        let clean: bool = Server::new()
+            .run(&mut self.script.as_slice(), &mut output)
+            .unwrap();
+
+        let mut messages: Vec<Value> = Vec::new();
+        let mut output: &[u8] = &output;
+        while let Some(body) = rpc::read_message(&mut output).unwrap() {
+            messages.push(serde_json::from_slice(&body).unwrap());
+        }
+        (clean, Session { messages })
+    }
+}
+
+struct Session {
+    messages: Vec<Value>,
+}
+
+impl Session {
+    fn response(&self, id: i64) -> &Value {
+        self.messages
+            .iter()
+            .find(|message| message["id"] == id)
+            .unwrap_or_else(|| panic!("no response to {}", id))
+    }
+
+    fn result(&self, id: i64) -> &Value {
+        &self.response(id)["result"]
+    }
+
+    fn diagnostics(&self) -> Vec<&Value> {
+        self.messages
+            .iter()
+            .filter(|message| message["method"] == "textDocument/publishDiagnostics")
+            .map(|message| &message["params"]["diagnostics"])
+            .collect()
+    }
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 },
+    })
+}
+
+fn location(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({ "uri": URI, "range": range(start, end) })
+}
+
+#[test]
+fn framing() {
+    let mut input: &[u8] =
+        b"Content-Length: 2\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n{}content-length:4\r\n\r\nnull";
+    assert_eq!(rpc::read_message(&mut input).unwrap().unwrap(), b"{}");
+    assert_eq!(rpc::read_message(&mut input).unwrap().unwrap(), b"null");
+    assert!(rpc::read_message(&mut input).unwrap().is_none());
+
+    let mut input: &[u8] = b"Content-Type: text\r\n\r\n{}";
+    assert!(rpc::read_message(&mut input).is_err());
+
+    let mut output: Vec<u8> = Vec::new();
+    rpc::write_message(&mut output, &json!({ "a": "é" })).unwrap();
+    assert_eq!(output, "Content-Length: 10\r\n\r\n{\"a\":\"é\"}".as_bytes());
+}
+
+#[test]
+fn lifecycle() {
+    let mut client: Client = Client {
+        script: Vec::new(),
+        next_id: 0,
+    };
+    let early: i64 = client.request("textDocument/hover", json!({}));
+    let initialize: i64 = client.request("initialize", json!({ "capabilities": {} }));
+    let unknown: i64 = client.request("workspace/symbol", json!({}));
+    client
+        .script
+        .extend_from_slice(b"Content-Length: 1\r\n\r\n{");
+    let shutdown: i64 = client.request("shutdown", Value::Null);
+    let late: i64 = client.request("textDocument/hover", json!({}));
+    client.notify("exit", Value::Null);
+    let (clean, session) = client.finish();
+
+    assert!(clean);
+    assert_eq!(
+        session.response(early)["error"]["code"],
+        rpc::SERVER_NOT_INITIALIZED
+    );
+    let capabilities: &Value = &session.result(initialize)["capabilities"];
+    assert_eq!(capabilities["textDocumentSync"]["change"], 1);
+    for provider in [
+        "definitionProvider",
+        "referencesProvider",
+        "hoverProvider",
+        "documentSymbolProvider",
+        "documentFormattingProvider",
+    ] {
+        assert_eq!(capabilities[provider], true, "{}", provider);
+    }
+    assert!(capabilities["semanticTokensProvider"]["legend"]["tokenTypes"].is_array());
+    assert_eq!(
+        session.response(unknown)["error"]["code"],
+        rpc::METHOD_NOT_FOUND
+    );
+    assert!(session.messages.iter().any(|message| {
+        message["id"].is_null() && message["error"]["code"] == rpc::PARSE_ERROR
+    }));
+    assert_eq!(session.response(shutdown)["result"], Value::Null);
+    assert_eq!(
+        session.response(late)["error"]["code"],
+        rpc::INVALID_REQUEST
+    );
+
+    // Exiting without a shutdown, or losing the client, is not clean
+    let mut client: Client = Client::new();
+    client.notify("exit", Value::Null);
+    assert!(!client.finish().0);
+    assert!(!Client::new().finish().0);
+}
+
+#[test]
+fn diagnostics_follow_changes() {
+    let mut client: Client = Client::new();
+    client.open("var a = 1;\nprint a +;\n");
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "{\n  var unused = 1;\n}\n" }],
+        }),
+    );
+    client.notify(
+        "textDocument/didClose",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let (_, session) = client.finish();
+
+    let published: Vec<&Value> = session.diagnostics();
+    assert_eq!(published.len(), 3);
+    assert_eq!(
+        published[0],
+        &json!([{
+            "range": range((1, 9), (1, 10)),
+            "severity": 1,
+            "source": "miette",
+            "message": "Expect expression.",
+        }])
+    );
+    assert_eq!(
+        published[1],
+        &json!([{
+            "range": range((1, 6), (1, 12)),
+            "severity": 2,
+            "source": "miette",
+            "message": "Unused variable 'unused'.",
+            "code": "unused_variable",
+        }])
+    );
+    assert_eq!(published[2], &json!([]));
+}
+
+const PROGRAM: &str = "\
+var total = 0;
+fun add(n) {
+  total = total + n;
+  return total;
+}
+add(2);
+print clock();
+";
+
+#[test]
+fn definitions_and_references() {
+    let mut client: Client = Client::new();
+    client.open(PROGRAM);
+    let global: i64 = client.at("textDocument/definition", 2, 12);
+    let parameter: i64 = client.at("textDocument/definition", 2, 18);
+    // Just after the name still counts as on it
+    let function: i64 = client.at("textDocument/definition", 5, 3);
+    let native: i64 = client.at("textDocument/definition", 6, 8);
+    let nothing: i64 = client.at("textDocument/definition", 6, 13);
+    let references: i64 = client.at("textDocument/references", 3, 10);
+    let uses: i64 = client.request(
+        "textDocument/references",
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 0, "character": 4 },
+            "context": { "includeDeclaration": false },
+        }),
+    );
+    let (_, session) = client.finish();
+
+    assert_eq!(session.result(global), &location((0, 4), (0, 9)));
+    assert_eq!(session.result(parameter), &location((1, 8), (1, 9)));
+    assert_eq!(session.result(function), &location((1, 4), (1, 7)));
+    assert_eq!(session.result(native), &Value::Null);
+    assert_eq!(session.result(nothing), &Value::Null);
+    assert_eq!(
+        session.result(references),
+        &json!([
+            location((0, 4), (0, 9)),
+            location((2, 2), (2, 7)),
+            location((2, 10), (2, 15)),
+            location((3, 9), (3, 14)),
+        ])
+    );
+    assert_eq!(session.result(uses).as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn local_names_resolve_to_their_own_scope() {
+    let mut client: Client = Client::new();
+    client.open("var x = 1;\n{\n  var x = 2;\n  print x;\n}\nprint x;\n");
+    let inner: i64 = client.at("textDocument/definition", 3, 8);
+    let outer: i64 = client.at("textDocument/definition", 5, 6);
+    let (_, session) = client.finish();
+
+    assert_eq!(session.result(inner), &location((2, 6), (2, 7)));
+    assert_eq!(session.result(outer), &location((0, 4), (0, 5)));
+}
+
+#[test]
+fn columns_count_utf16_code_units() {
+    let mut client: Client = Client::new();
+    client.open("var s = \"é😀\"; print s;\n");
+    let definition: i64 = client.at("textDocument/definition", 0, 21);
+    let references: i64 = client.at("textDocument/references", 0, 4);
+    let (_, session) = client.finish();
+
+    assert_eq!(session.result(definition), &location((0, 4), (0, 5)));
+    assert_eq!(
+        session.result(references),
+        &json!([location((0, 4), (0, 5)), location((0, 21), (0, 22))])
+    );
+}
+
+#[test]
+fn hover_shows_the_declaration() {
+    let mut client: Client = Client::new();
+    client.open(PROGRAM);
+    let function: i64 = client.at("textDocument/hover", 5, 1);
+    let parameter: i64 = client.at("textDocument/hover", 2, 18);
+    let native: i64 = client.at("textDocument/hover", 6, 7);
+    let keyword: i64 = client.at("textDocument/hover", 6, 2);
+    let (_, session) = client.finish();
+
+    assert_eq!(
+        session.result(function),
+        &json!({
+            "contents": {
+                "kind": "markdown",
+                "value": "```miette\nfun add(n) {\n```\nfunction declared on line 2",
+            },
+            "range": range((5, 0), (5, 3)),
+        })
+    );
+    assert_eq!(
+        session.result(parameter)["contents"]["value"],
+        "```miette\nfun add(n) {\n```\nparameter declared on line 2"
+    );
+    assert_eq!(
+        session.result(native)["contents"]["value"],
+        "built-in function `clock`"
+    );
+    assert_eq!(session.result(keyword), &Value::Null);
+}
+
+#[test]
+fn document_symbols_nest_methods_and_inner_functions() {
+    let mut client: Client = Client::new();
+    client.open(
+        "class Point {\n  init(x) { this.x = x; }\n  norm() { return this.x; }\n}\n\
+         fun main() {\n  fun helper() {}\n}\nvar x = 1;\n",
+    );
+    let symbols: i64 = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let (_, session) = client.finish();
+
+    let method = |name: &str, line: u32, end: u32| {
+        json!({
+            "name": name,
+            "kind": 6,
+            "range": range((line, 2), (line, end)),
+            "selectionRange": range((line, 2), (line, 2 + name.len() as u32)),
+            "children": [],
+        })
+    };
+    assert_eq!(
+        session.result(symbols),
+        &json!([
+            {
+                "name": "Point",
+                "kind": 5,
+                "range": range((0, 0), (3, 1)),
+                "selectionRange": range((0, 6), (0, 11)),
+                "children": [method("init", 1, 25), method("norm", 2, 27)],
+            },
+            {
+                "name": "main",
+                "kind": 12,
+                "range": range((4, 0), (6, 1)),
+                "selectionRange": range((4, 4), (4, 8)),
+                "children": [{
+                    "name": "helper",
+                    "kind": 12,
+                    "range": range((5, 2), (5, 17)),
+                    "selectionRange": range((5, 6), (5, 12)),
+                    "children": [],
+                }],
+            },
+        ])
+    );
+}
+
+#[test]
+fn semantic_tokens_are_classified_and_delta_encoded() {
+    let mut client: Client = Client::new();
+    client.open("fun f(a) { return a.b(len); } // done\nprint \"x\ny\" + 1;\n");
+    let tokens: i64 = client.request(
+        "textDocument/semanticTokens/full",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let (_, session) = client.finish();
+
+    // [line delta, start delta, length, type, modifiers], types indexed
+    // into the legend
+    let (keyword, string, number, operator, comment) = (0, 1, 2, 3, 4);
+    let (parameter, function, method) = (6, 7, 9);
+    let (declaration, library) = (1, 2);
+    #[rustfmt::skip]
+    let expected: Vec<u32> = vec![
+        0, 0, 3, keyword, 0,
+        0, 4, 1, function, declaration,
+        0, 2, 1, parameter, declaration,
+        0, 5, 6, keyword, 0,
+        0, 7, 1, parameter, 0,
+        0, 2, 1, method, 0,
+        0, 2, 3, function, library,
+        0, 8, 7, comment, 0,
+        1, 0, 5, keyword, 0,
+        0, 6, 2, string, 0,
+        1, 0, 2, string, 0,
+        0, 3, 1, operator, 0,
+        0, 2, 1, number, 0,
+    ];
+    assert_eq!(session.result(tokens), &json!({ "data": expected }));
+}
+
+#[test]
+fn imported_modules_are_namespaces() {
+    let mut client: Client = Client::new();
+    client.open("import \"m\" as m;\nexport var a = m;\n");
+    let tokens: i64 = client.request(
+        "textDocument/semanticTokens/full",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let (_, session) = client.finish();
+
+    let (keyword, string, operator, variable, namespace) = (0, 1, 3, 5, 11);
+    let declaration = 1;
+    #[rustfmt::skip]
+    let expected: Vec<u32> = vec![
+        0, 0, 6, keyword, 0,
+        0, 7, 3, string, 0,
+        0, 4, 2, keyword, 0,
+        0, 3, 1, namespace, declaration,
+        1, 0, 6, keyword, 0,
+        0, 7, 3, keyword, 0,
+        0, 4, 1, variable, declaration,
+        0, 2, 1, operator, 0,
+        0, 2, 1, namespace, 0,
+    ];
+    assert_eq!(session.result(tokens), &json!({ "data": expected }));
+}
+
+#[test]
+fn formatting_replaces_the_whole_document() {
+    let mut client: Client = Client::new();
+    client.open("var a=1;\nprint a;");
+    let messy: i64 = client.request(
+        "textDocument/formatting",
+        json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 4, "insertSpaces": true } }),
+    );
+    client.open("var a = 1;\n");
+    let tidy: i64 = client.request(
+        "textDocument/formatting",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    client.open("var a = ;\n");
+    let broken: i64 = client.request(
+        "textDocument/formatting",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let (_, session) = client.finish();
+
+    assert_eq!(
+        session.result(messy),
+        &json!([{ "range": range((0, 0), (1, 8)), "newText": "var a = 1;\nprint a;\n" }])
+    );
+    assert_eq!(session.result(tidy), &json!([]));
+    assert_eq!(session.result(broken), &Value::Null);
+}
+
+#[test]
+fn the_binary_serves_stdio() {
+    let mut client: Client = Client::new();
+    let shutdown: i64 = client.request("shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .arg("lsp")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), &client.script).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let mut stdout: &[u8] = &output.stdout;
+    let mut ids: Vec<Value> = Vec::new();
+    while let Some(body) = rpc::read_message(&mut stdout).unwrap() {
+        ids.push(serde_json::from_slice::<Value>(&body).unwrap()["id"].clone());
+    }
+    assert_eq!(ids, vec![json!(1), json!(shutdown)]);
+}
@@ -0,0 +1,105 @@
+use miette::manifest::{self, Dependency, Manifest, ManifestError};
+
+#[test]
+fn parses_packages_and_their_dependencies() {
+    let text: &str = "\
+# A library for drawing
+[package]
+name = \"shapes\"   # used in imports
+version = \"0.2.10\"
+entry = \"src/lib.miette\"
+
+[dependencies]
+geometry = { path = \"../geometry\" }
+colors = {path=\"vendor/#colors\"}
+";
+    assert_eq!(
+        manifest::parse(text),
+        Ok(Manifest {
+            name: "shapes".to_string(),
+            version: "0.2.10".to_string(),
+            entry: std::path::PathBuf::from("src/lib.miette"),
+            dependencies: vec![
+                Dependency {
+                    name: "geometry".to_string(),
+                    path: std::path::PathBuf::from("../geometry"),
+                },
+                Dependency {
+                    name: "colors".to_string(),
+                    path: std::path::PathBuf::from("vendor/#colors"),
+                },
+            ],
+        })
+    );
+
+    let minimal: Manifest =
+        manifest::parse("[package]\nname = \"app\"\nversion = \"0.1.0\"\n").unwrap();
+    assert_eq!(
+        minimal.entry,
+        std::path::PathBuf::from(manifest::DEFAULT_ENTRY)
+    );
+    assert!(minimal.dependencies.is_empty());
+    assert_eq!(manifest::parse(&manifest::template("app")), Ok(minimal));
+}
+
+#[test]
+fn errors_name_the_line() {
+    let error = |text: &str| manifest::parse(text).unwrap_err().to_string();
+    let package: &str = "[package]\nname = \"app\"\nversion = \"1.0.0\"\n";
+
+    assert_eq!(error("[workspace]\n"), "line 1: unknown table [workspace]");
+    assert_eq!(
+        error(&format!("{}[package]\n", package)),
+        "line 4: [package] appears twice"
+    );
+    assert_eq!(
+        error("name = \"app\"\n"),
+        "line 1: 'name' must be inside a table like [package]"
+    );
+    assert_eq!(
+        error("[package]\nauthors = \"me\"\n"),
+        "line 2: unknown key 'authors' in [package]"
+    );
+    assert_eq!(
+        error("[package]\nname = \"a\"\nname = \"b\"\n"),
+        "line 3: 'name' is set twice"
+    );
+    assert_eq!(
+        error("[package]\nname = \"app\n"),
+        "line 2: unterminated string"
+    );
+    assert_eq!(
+        error(&format!("{}[dependencies]\ngeo = \"1.0\"\n", package)),
+        "line 5: dependency 'geo' needs a path, as in geo = { path = \"../geo\" }"
+    );
+    assert_eq!(
+        error(&format!(
+            "{}[dependencies]\ngeo = {{ path = \"a\" }}\ngeo = {{ path = \"b\" }}\n",
+            package
+        )),
+        "line 6: dependency 'geo' is listed twice"
+    );
+}
+
+#[test]
+fn names_and_versions_are_checked() {
+    assert_eq!(
+        manifest::parse("[package]\nversion = \"1.0.0\"\n"),
+        Err(ManifestError {
+            line: 0,
+            message: "[package] needs a name".to_string(),
+        })
+    );
+    assert_eq!(
+        manifest::parse("[package]\nname = \"app\"\nversion = \"1.0\"\n")
+            .unwrap_err()
+            .to_string(),
+        "version '1.0' must look like 1.2.3"
+    );
+    for name in ["app", "my-app", "app_2"] {
+        assert!(manifest::is_valid_name(name), "{}", name);
+    }
+    for name in ["", "2app", "-app", "my app", "a/b"] {
+        assert!(!manifest::is_valid_name(name), "{}", name);
+    }
+}
@@ -0,0 +1,80 @@
+use miette::map::{Map, MapError, MapKey};
+
+fn text(s: &str) -> MapKey {
+    MapKey::Text(s.to_string())
+}
+
+fn number(n: f64) -> MapKey {
+    MapKey::number(n).unwrap()
+}
+
+fn keys(map: &Map<i32>) -> Vec<MapKey> {
+    map.keys().cloned().collect()
+}
+
+#[test]
+fn keys_of_every_hashable_kind() {
+    let mut map: Map<i32> = Map::new();
+    map.insert(MapKey::Nil, 1);
+    map.insert(MapKey::Bool(true), 2);
+    map.insert(number(3.0), 3);
+    map.insert(text("3"), 4);
+
+    assert_eq!(map.len(), 4);
+    assert_eq!(map.get(&MapKey::Nil), Some(&1));
+    assert_eq!(map.get(&MapKey::Bool(true)), Some(&2));
+    assert_eq!(map.get(&MapKey::Bool(false)), None);
+    assert_eq!(map.get(&number(3.0)), Some(&3));
+    assert_eq!(map.get(&text("3")), Some(&4));
+}
+
+#[test]
+fn numbers_hash_by_value() {
+    let mut map: Map<i32> = Map::new();
+    map.insert(number(0.0), 1);
+
+    assert_eq!(map.get(&number(-0.0)), Some(&1));
+    assert_eq!(number(2.5).as_number(), Some(2.5));
+    assert_eq!(MapKey::number(f64::NAN), Err(MapError::NanKey));
+}
+
+#[test]
+fn iteration_follows_insertion_order() {
+    let mut map: Map<i32> = Map::new();
+    for (i, key) in ["b", "a", "c", "d"].iter().enumerate() {
+        map.insert(text(key), i as i32);
+    }
+
+    // Updating keeps the original position
+    assert_eq!(map.insert(text("a"), 10), Some(1));
+    assert_eq!(keys(&map), vec![text("b"), text("a"), text("c"), text("d")]);
+    assert_eq!(
+        map.values().copied().collect::<Vec<i32>>(),
+        vec![0, 10, 2, 3]
+    );
+
+    // Deleting leaves the rest in place, re-adding goes to the end
+    assert_eq!(map.remove(&text("b")), Some(0));
+    assert_eq!(map.remove(&text("b")), None);
+    map.insert(text("b"), 20);
+    assert_eq!(keys(&map), vec![text("a"), text("c"), text("d"), text("b")]);
+}
+
+#[test]
+fn order_survives_compaction() {
+    let mut map: Map<i32> = Map::new();
+    for i in 0..100 {
+        map.insert(number(i as f64), i);
+    }
+    for i in 0..90 {
+        map.remove(&number(i as f64));
+    }
+
+    assert_eq!(map.len(), 10);
+    assert_eq!(
+        map.values().copied().collect::<Vec<i32>>(),
+        (90..100).collect::<Vec<i32>>()
+    );
+    assert!(map.contains(&number(95.0)));
+    assert!(!map.contains(&number(5.0)));
+}
@@ -0,0 +1,214 @@
+mod common;
+
+use common::{Capture, Scratch};
+use miette::error::Diagnostic;
+use miette::interpreter::Interpreter;
+use miette::module::{Loader, Program};
+use miette::vm::Vm;
+
+// Writes the files into a directory of their own, removed again when the
+// returned guard is dropped
+fn project(name: &str, files: &[(&str, &str)]) -> Scratch {
+    let scratch: Scratch = Scratch::new(&format!("modules-{}", name));
+    for (path, source) in files {
+        scratch.file(path, source);
+    }
+    scratch
+}
+
+fn load(loader: &mut Loader, path: &std::path::Path) -> Result<Program, Vec<Diagnostic>> {
+    let source: String = std::fs::read_to_string(path).unwrap();
+    loader.load(path, &source)
+}
+
+fn errors(loader: &mut Loader, path: &std::path::Path) -> Vec<String> {
+    match load(loader, path) {
+        Ok(_) => panic!("{} loaded", path.display()),
+        Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    }
+}
+
+#[test]
+fn imports_are_found_beside_the_importer_then_on_the_search_path() {
+    let scratch: Scratch = project(
+        "search",
+        &[
+            (
+                "app/main.miette",
+                "import \"util\" as util;\nimport \"local\" as local;\n",
+            ),
+            ("app/local.miette", ""),
+            ("vendor/util.miette", "export var version = 2;\n"),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let main: std::path::PathBuf = dir.join("app/main.miette");
+
+    assert_eq!(
+        errors(&mut Loader::new(), &main),
+        vec!["[line 1] Error at '\"util\"': Can't find module 'util'."]
+    );
+
+    let mut loader: Loader = Loader::new();
+    loader.add_search_path(dir.join("vendor"));
+    let program: Program = load(&mut loader, &main).unwrap();
+    let names: Vec<&str> = program
+        .modules
+        .iter()
+        .map(|module| module.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["util", "local", "main"]);
+    assert_eq!(program.modules[0].exports, vec!["version"]);
+    assert_eq!(program.main().imports["util"], 0);
+    assert_eq!(program.main().imports["local"], 1);
+}
+
+#[test]
+fn each_file_is_loaded_once() {
+    let scratch: Scratch = project(
+        "once",
+        &[
+            ("main.miette", "import \"b\" as b;\nimport \"c\" as c;\n"),
+            ("b.miette", "import \"shared\" as shared;\n"),
+            ("c.miette", "import \"./shared.miette\" as shared;\n"),
+            ("shared.miette", ""),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+
+    let program: Program = load(&mut Loader::new(), &dir.join("main.miette")).unwrap();
+    assert_eq!(program.modules.len(), 4);
+    assert_eq!(program.modules[0].name, "shared");
+    assert_eq!(program.modules[1].imports["shared"], 0);
+    assert_eq!(program.modules[2].imports["./shared.miette"], 0);
+}
+
+#[test]
+fn import_cycles_show_the_chain() {
+    let scratch: Scratch = project(
+        "cycle",
+        &[
+            ("a.miette", "import \"b\" as b;\n"),
+            ("b.miette", "import \"c\" as c;\n"),
+            ("c.miette", "\nimport \"a.miette\" as a;\n"),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let path = |name: &str| dir.join(name).display().to_string();
+
+    assert_eq!(
+        errors(&mut Loader::new(), &dir.join("a.miette")),
+        vec![format!(
+            "[line 2] Error in {} at '\"a.miette\"': Import cycle: {} imports {}, which imports {}, which imports {}.",
+            path("c.miette"),
+            path("a.miette"),
+            path("b.miette"),
+            path("c.miette"),
+            path("a.miette"),
+        )]
+    );
+}
+
+#[test]
+fn problems_in_imported_files_say_which_file() {
+    let scratch: Scratch = project(
+        "located",
+        &[
+            ("main.miette", "import \"lib\" as lib;\n"),
+            (
+                "lib.miette",
+                "fun f(unused) { return 1; }\nexport var x = ;\n",
+            ),
+            ("warned.miette", "import \"quiet\" as quiet;\n"),
+            ("quiet.miette", "fun f(unused) { return 1; }\n"),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let lib: String = dir.join("lib.miette").display().to_string();
+
+    let mut loader: Loader = Loader::new();
+    assert_eq!(
+        errors(&mut loader, &dir.join("main.miette")),
+        vec![format!(
+            "[line 2] Error in {} at ';': Expect expression.",
+            lib
+        )]
+    );
+
+    assert!(load(&mut loader, &dir.join("warned.miette")).is_ok());
+    let warnings: Vec<String> = loader
+        .warnings()
+        .iter()
+        .map(|warning| warning.location.clone())
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![format!(
+            " in {} at 'unused'",
+            dir.join("quiet.miette").display()
+        )]
+    );
+}
+
+const SHAPES: &str = "\
+var made = 0;
+export var sides = [];
+export class Shape {
+    init(n) {
+        made = made + 1;
+        push(sides, n);
+        this.n = n;
+    }
+}
+export fun count() {
+    return made;
+}
+";
+
+const MAIN: &str = "\
+import \"shapes\" as shapes;
+var made = \"main's own\";
+for (i in 0..20) {
+    shapes.Shape(i);
+}
+print shapes.count();
+print len(shapes.sides);
+print made;
+";
+
+#[test]
+fn modules_keep_their_globals_on_both_backends() {
+    let scratch: Scratch = project(
+        "globals",
+        &[("main.miette", MAIN), ("shapes.miette", SHAPES)],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let program: Program = load(&mut Loader::new(), &dir.join("main.miette")).unwrap();
+    let expected: &str = "20\n20\nmain's own\n";
+
+    let capture: Capture = Capture::default();
+    Interpreter::with_output(Box::new(capture.clone()))
+        .interpret_program(&program)
+        .unwrap();
+    assert_eq!(String::from_utf8(capture.0.take()).unwrap(), expected);
+
+    // Collecting at every allocation checks other modules' globals are roots
+    let mut vm: Vm = Vm::with_output(Box::new(capture.clone()));
+    vm.set_gc_stress(true);
+    vm.interpret_program(&program).unwrap();
+    assert_eq!(String::from_utf8(capture.0.take()).unwrap(), expected);
+}
+
+#[test]
+fn imports_need_a_program_from_a_file() {
+    let tokens = miette::lex::scan_source("import \"x\" as x;").unwrap();
+    let statements = miette::parser::Parser::new(tokens).parse().unwrap();
+    let error: String = Interpreter::with_output(Box::new(Capture::default()))
+        .interpret(&statements)
+        .unwrap_err()
+        .message;
+    assert_eq!(
+        error,
+        "Can only import modules in a program run from a file."
+    );
+}
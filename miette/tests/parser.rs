@@ -0,0 +1,510 @@
+use miette::ast::{Expr, Stmt, TypeExpr};
+use miette::lex;
+use miette::parser::Parser;
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = lex::scan_source(source).unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn parse_errors(source: &str) -> Vec<String> {
+    let tokens = lex::scan_source(source).unwrap();
+    Parser::new(tokens)
+        .parse()
+        .unwrap_err()
+        .iter()
+        .map(|error| error.to_string())
+        .collect()
+}
+
+fn param_names(expr: &Expr) -> Vec<String> {
+    match expr {
+        Expr::Lambda { function, .. } => function
+            .params
+            .iter()
+            .map(|param| param.lexeme().to_string())
+            .collect(),
+        other => panic!("expected a lambda, got {:?}", other),
+    }
+}
+
+#[test]
+fn lambda_passed_as_argument() {
+    let program: Vec<Stmt> = parse("apply(fun (a, b) { return a + b; }, 1, 2);");
+
+    let Stmt::Expression {
+        expression: Expr::Call { arguments, .. },
+    } = &program[0]
+    else {
+        panic!("expected a call statement");
+    };
+
+    assert_eq!(arguments.len(), 3);
+    assert_eq!(param_names(&arguments[0]), vec!["a", "b"]);
+}
+
+#[test]
+fn lambda_returned_from_function() {
+    let program: Vec<Stmt> = parse("fun adder(n) { return fun (x) { return x + n; }; }");
+
+    let Stmt::Function { function } = &program[0] else {
+        panic!("expected a function declaration");
+    };
+    assert_eq!(function.name.as_ref().unwrap().lexeme(), "adder");
+
+    let Stmt::Return {
+        value: Some(value), ..
+    } = &function.body[0]
+    else {
+        panic!("expected a return");
+    };
+    assert_eq!(param_names(value), vec!["x"]);
+}
+
+#[test]
+fn arrow_lambda_body_is_a_return() {
+    let program: Vec<Stmt> = parse("var inc = (a) -> a + 1;\nvar zero = () -> 0;");
+
+    let Stmt::Var {
+        initializer: Some(initializer),
+        ..
+    } = &program[0]
+    else {
+        panic!("expected a var");
+    };
+    assert_eq!(param_names(initializer), vec!["a"]);
+
+    let Expr::Lambda { keyword, function } = initializer else {
+        unreachable!();
+    };
+    assert_eq!(keyword.lexeme(), "->");
+    assert!(function.name.is_none());
+    assert!(matches!(
+        &function.body[..],
+        [Stmt::Return {
+            value: Some(Expr::Binary { .. }),
+            ..
+        }]
+    ));
+
+    let Stmt::Var {
+        initializer: Some(initializer),
+        ..
+    } = &program[1]
+    else {
+        panic!("expected a var");
+    };
+    assert!(param_names(initializer).is_empty());
+}
+
+#[test]
+fn arrow_lambdas_nest_and_pass_as_arguments() {
+    let program: Vec<Stmt> = parse("map(xs, (x) -> (y) -> x * y);");
+
+    let Stmt::Expression {
+        expression: Expr::Call { arguments, .. },
+    } = &program[0]
+    else {
+        panic!("expected a call statement");
+    };
+    assert_eq!(param_names(&arguments[1]), vec!["x"]);
+}
+
+#[test]
+fn parenthesised_expressions_are_still_groupings() {
+    let program: Vec<Stmt> = parse("print (a);\nprint (a) - 1;");
+
+    assert!(matches!(
+        &program[0],
+        Stmt::Print {
+            expression: Expr::Grouping { .. },
+            ..
+        }
+    ));
+    assert!(matches!(
+        &program[1],
+        Stmt::Print {
+            expression: Expr::Binary { .. },
+            ..
+        }
+    ));
+}
+
+#[test]
+fn anonymous_fun_can_start_a_statement() {
+    let program: Vec<Stmt> = parse("fun () { print 1; }();");
+
+    assert!(matches!(
+        &program[0],
+        Stmt::Expression {
+            expression: Expr::Call { .. }
+        }
+    ));
+}
+
+#[test]
+fn arrow_requires_identifier_parameters() {
+    assert_eq!(
+        parse_errors("var f = (a, 1) -> a;"),
+        vec!["[line 1] Error at ',': Expect ')' after expression."]
+    );
+}
+
+#[test]
+fn list_literals_allow_trailing_commas() {
+    let program: Vec<Stmt> = parse("var xs = [1, [2, 3], ];\nvar empty = [];");
+
+    let Stmt::Var {
+        initializer: Some(Expr::List { elements, .. }),
+        ..
+    } = &program[0]
+    else {
+        panic!("expected a list");
+    };
+    assert_eq!(elements.len(), 2);
+    assert!(matches!(&elements[1], Expr::List { elements, .. } if elements.len() == 2));
+
+    assert!(matches!(
+        &program[1],
+        Stmt::Var {
+            initializer: Some(Expr::List { elements, .. }),
+            ..
+        } if elements.is_empty()
+    ));
+}
+
+#[test]
+fn index_assignment_and_slices() {
+    let program: Vec<Stmt> =
+        parse("xs[-1] = xs[0];\nprint xs[1:3];\nprint xs[:2][0];\nprint xs[1:];");
+
+    assert!(matches!(
+        &program[0],
+        Stmt::Expression {
+            expression: Expr::IndexSet { value, .. }
+        } if matches!(**value, Expr::Index { .. })
+    ));
+    assert!(matches!(
+        &program[1],
+        Stmt::Print {
+            expression: Expr::Slice {
+                start: Some(_),
+                end: Some(_),
+                ..
+            },
+            ..
+        }
+    ));
+    assert!(matches!(
+        &program[2],
+        Stmt::Print {
+            expression: Expr::Index { object, .. },
+            ..
+        } if matches!(**object, Expr::Slice { start: None, end: Some(_), .. })
+    ));
+    assert!(matches!(
+        &program[3],
+        Stmt::Print {
+            expression: Expr::Slice {
+                start: Some(_),
+                end: None,
+                ..
+            },
+            ..
+        }
+    ));
+}
+
+#[test]
+fn slices_are_not_assignable() {
+    assert_eq!(
+        parse_errors("xs[1:2] = ys;"),
+        vec!["[line 1] Error at '=': Invalid assignment target."]
+    );
+}
+
+#[test]
+fn braces_in_expression_position_are_maps() {
+    let program: Vec<Stmt> = parse("var m = {\"a\": 1, key: [2], };\nprint {};");
+
+    let Stmt::Var {
+        initializer: Some(Expr::Map { entries, .. }),
+        ..
+    } = &program[0]
+    else {
+        panic!("expected a map");
+    };
+    assert_eq!(entries.len(), 2);
+    assert!(matches!(
+        entries[1],
+        (Expr::Variable { .. }, Expr::List { .. })
+    ));
+
+    assert!(matches!(
+        &program[1],
+        Stmt::Print {
+            expression: Expr::Map { entries, .. },
+            ..
+        } if entries.is_empty()
+    ));
+}
+
+#[test]
+fn braces_starting_a_statement_are_blocks() {
+    let program: Vec<Stmt> = parse("{}\n{ print 1; }\n({\"a\": 1})[\"a\"];");
+
+    assert!(matches!(&program[0], Stmt::Block { statements } if statements.is_empty()));
+    assert!(matches!(&program[1], Stmt::Block { statements } if statements.len() == 1));
+    assert!(matches!(
+        &program[2],
+        Stmt::Expression {
+            expression: Expr::Index { .. }
+        }
+    ));
+}
+
+#[test]
+fn map_entries_need_a_colon() {
+    assert_eq!(
+        parse_errors("var m = {\"a\" 1};"),
+        vec!["[line 1] Error at '1': Expect ':' after map key."]
+    );
+}
+
+#[test]
+fn for_in_over_a_range() {
+    let program: Vec<Stmt> = parse("for (i in 0..n + 1) { if (i == 3) continue; print i; }");
+
+    let Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        ..
+    } = &program[0]
+    else {
+        panic!("expected a for-in loop");
+    };
+    assert_eq!(variable.lexeme(), "i");
+    assert!(matches!(body.as_ref(), Stmt::Block { .. }));
+
+    // The end bound takes the whole `n + 1`
+    let Expr::Range {
+        start,
+        end,
+        inclusive,
+        ..
+    } = iterable
+    else {
+        panic!("expected a range");
+    };
+    assert!(!inclusive);
+    assert!(matches!(start.as_ref(), Expr::Literal { .. }));
+    assert!(matches!(end.as_ref(), Expr::Binary { .. }));
+}
+
+#[test]
+fn inclusive_ranges_and_comparisons() {
+    let program: Vec<Stmt> = parse("var r = 1..=10;\nprint 0..2 == r;");
+
+    assert!(matches!(
+        &program[0],
+        Stmt::Var {
+            initializer: Some(Expr::Range {
+                inclusive: true,
+                ..
+            }),
+            ..
+        }
+    ));
+    assert!(matches!(
+        &program[1],
+        Stmt::Print {
+            expression: Expr::Binary { left, .. },
+            ..
+        } if matches!(left.as_ref(), Expr::Range { .. })
+    ));
+}
+
+#[test]
+fn c_style_for_still_parses() {
+    let program: Vec<Stmt> =
+        parse("for (i = 0; i < 3; i = i + 1) print i;\nfor (x in xs) print x;");
+
+    assert!(matches!(&program[0], Stmt::For { .. }));
+    assert!(matches!(&program[1], Stmt::ForIn { .. }));
+}
+
+#[test]
+fn for_in_needs_an_iterable() {
+    assert_eq!(
+        parse_errors("for (x in) print x;"),
+        vec!["[line 1] Error at ')': Expect expression."]
+    );
+}
+
+#[test]
+fn test_is_only_a_keyword_before_a_string() {
+    let program: Vec<Stmt> = parse("test \"adds\" { print 1; } var test = 1; test(\"x\");");
+    let Stmt::Test { name, body, .. } = &program[0] else {
+        panic!("expected a test block, got {:?}", program[0]);
+    };
+    assert_eq!(name.lexeme(), "\"adds\"");
+    assert_eq!(body.len(), 1);
+    assert!(matches!(program[1], Stmt::Var { .. }));
+    assert!(matches!(program[2], Stmt::Expression { .. }));
+
+    assert_eq!(
+        parse_errors("test \"adds\" print 1;"),
+        vec!["[line 1] Error at 'print': Expect '{' after test name."]
+    );
+}
+
+#[test]
+fn import_and_export_are_only_keywords_where_they_start_one() {
+    let program: Vec<Stmt> =
+        parse("import \"lib/util\" as util; export var a = 1; var import = 1; export(a);");
+    let Stmt::Import { path, name, .. } = &program[0] else {
+        panic!("expected an import, got {:?}", program[0]);
+    };
+    assert_eq!(path.lexeme(), "\"lib/util\"");
+    assert_eq!(name.lexeme(), "util");
+    let Stmt::Export { declaration, .. } = &program[1] else {
+        panic!("expected an export, got {:?}", program[1]);
+    };
+    assert!(matches!(**declaration, Stmt::Var { .. }));
+    assert!(matches!(program[2], Stmt::Var { .. }));
+    assert!(matches!(program[3], Stmt::Expression { .. }));
+
+    assert_eq!(
+        parse_errors("import \"util\" util;"),
+        vec!["[line 1] Error at 'util': Expect 'as' after import path."]
+    );
+    assert_eq!(
+        parse_errors("import \"util\" as;"),
+        vec!["[line 1] Error at ';': Expect module name after 'as'."]
+    );
+    assert_eq!(
+        parse_errors("import \"util\" as util print 1;"),
+        vec!["[line 1] Error at 'print': Expect ';' after import."]
+    );
+}
+
+#[test]
+fn try_needs_a_catch_or_finally() {
+    let program: Vec<Stmt> = parse("try { throw 1; } catch (e) { print e; } finally { print 2; }");
+    let Stmt::Try {
+        body,
+        catch,
+        finally,
+        ..
+    } = &program[0]
+    else {
+        panic!("expected a try, got {:?}", program[0]);
+    };
+    assert!(matches!(body[0], Stmt::Throw { .. }));
+    assert_eq!(catch.as_ref().unwrap().name.lexeme(), "e");
+    assert_eq!(finally.as_ref().unwrap().len(), 1);
+
+    assert_eq!(
+        parse_errors("try { print 1; } print 2;"),
+        vec!["[line 1] Error at 'print': Expect 'catch' or 'finally' after try block."]
+    );
+    assert_eq!(
+        parse_errors("try {} catch e {}"),
+        vec!["[line 1] Error at 'e': Expect '(' after 'catch'."]
+    );
+}
+
+#[test]
+fn type_annotations_are_optional() {
+    let program: Vec<Stmt> =
+        parse("var a: Number? = 1; fun f(x: String, y): Number | Nil {} class P { x: Number; }");
+    let Stmt::Var { annotation, .. } = &program[0] else {
+        panic!("expected a var, got {:?}", program[0]);
+    };
+    assert!(matches!(annotation, Some(TypeExpr::Optional { .. })));
+    let Stmt::Function { function } = &program[1] else {
+        panic!("expected a function, got {:?}", program[1]);
+    };
+    assert!(matches!(
+        function.param_types[..],
+        [Some(TypeExpr::Named { .. }), None]
+    ));
+    let Some(TypeExpr::Union { types }) = &function.return_type else {
+        panic!("expected a union, got {:?}", function.return_type);
+    };
+    assert_eq!(types.len(), 2);
+    let Stmt::Class {
+        fields, methods, ..
+    } = &program[2]
+    else {
+        panic!("expected a class, got {:?}", program[2]);
+    };
+    assert_eq!(fields[0].name.lexeme(), "x");
+    assert!(methods.is_empty());
+
+    assert_eq!(
+        parse_errors("var a: = 1;"),
+        vec!["[line 1] Error at '=': Expect type name."]
+    );
+    assert_eq!(
+        parse_errors("class P { x: Number }"),
+        vec!["[line 1] Error at '}': Expect ';' after field type."]
+    );
+}
+
+#[test]
+fn long_chains_count_as_nesting() {
+    // Each operator, call, property or index wraps what came before it
+    let chain = |start: &str, link: &str, length: usize| -> String {
+        format!("print {}{};", start, link.repeat(length))
+    };
+    parse(&chain("1", " + 1", 150));
+    parse(&chain("x", "[0]", 150));
+
+    for (start, link, at) in [
+        ("1", " + 1", "+"),
+        ("1", " * 1", "*"),
+        ("true", " or true", "or"),
+        ("f", "()", "("),
+        ("x", ".y", "."),
+    ] {
+        let errors: Vec<String> = parse_errors(&chain(start, link, 1000));
+        assert_eq!(
+            errors[0],
+            format!("[line 1] Error at '{}': Too deeply nested.", at)
+        );
+    }
+    let errors: Vec<String> = parse_errors(&chain("x", "[0]", 1000));
+    assert!(errors[0].ends_with("Too deeply nested."), "{:?}", errors);
+}
+
+#[test]
+fn deep_nesting_is_an_error_not_a_crash() {
+    // On a thread the size of the main one, as under `miette run`, since
+    // the test harness's threads are too small for the deepest code allowed
+    std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(|| {
+            let nested = |open: &str, close: &str, depth: usize| -> String {
+                format!("print {}1{};", open.repeat(depth), close.repeat(depth))
+            };
+            parse(&nested("(", ")", 100));
+
+            for (open, close) in [("(", ")"), ("[", "]"), ("-", "")] {
+                let errors: Vec<String> = parse_errors(&nested(open, close, 1000));
+                assert_eq!(
+                    errors[0],
+                    format!("[line 1] Error at '{}': Too deeply nested.", open)
+                );
+            }
+            let blocks: String = format!("{}print 1;{}", "{".repeat(1000), "}".repeat(1000));
+            assert_eq!(
+                parse_errors(&blocks)[0],
+                "[line 1] Error at '{': Too deeply nested."
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use common::Capture;
+use miette::checker::Checker;
+use miette::compiler::Compiler;
+use miette::interpreter::{self, Interpreter};
+use miette::lex;
+use miette::parser::Parser;
+use miette::resolver::Resolver;
+use miette::vm::Vm;
+
+// Collects everything the interpreter prints so it can be compared afterwards
+#[derive(Clone, Copy, Debug)]
+enum Backend {
+    Interpreter,
+    Vm,
+    // The VM collecting garbage after every allocation
+    VmStress,
+}
+
+// Runs a program, returning what it printed and the runtime error, if any
+fn run(source: &str, backend: Backend) -> (String, Option<String>) {
+    // With the stack `miette run` has, which the harness's threads fall far
+    // short of for the deepest recursion allowed
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || run_here(source, backend))
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
+
+fn run_here(source: &str, backend: Backend) -> (String, Option<String>) {
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let locals = Resolver::new().resolve(&statements).unwrap();
+    Checker::new().check(&statements).unwrap();
+
+    let capture: Capture = Capture::default();
+    let result = match backend {
+        Backend::Interpreter => {
+            let mut interpreter: Interpreter = Interpreter::with_output(Box::new(capture.clone()));
+            interpreter.resolve(locals);
+            interpreter.interpret(&statements)
+        }
+        Backend::Vm | Backend::VmStress => {
+            let script = Compiler::new().compile(&statements).unwrap();
+            let mut vm: Vm = Vm::with_output(Box::new(capture.clone()));
+            vm.set_gc_stress(matches!(backend, Backend::VmStress));
+            vm.interpret(script)
+        }
+    };
+    let output: String = String::from_utf8(capture.0.borrow().clone()).unwrap();
+    (output, result.err().map(|error| error.message))
+}
+
+// Each program states what it should do in `// expect: <line>` and
+// `// expect runtime error: <message>` comments
+#[test]
+fn programs() {
+    let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir("tests/programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "miette"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let source: String = std::fs::read_to_string(&path).unwrap();
+
+        let mut expected_output: String = String::new();
+        let mut expected_error: Option<String> = None;
+        for line in source.lines() {
+            if let Some((_, expected)) = line.split_once("// expect: ") {
+                expected_output.push_str(expected);
+                expected_output.push('\n');
+            } else if let Some((_, expected)) = line.split_once("// expect runtime error: ") {
+                expected_error = Some(expected.to_string());
+            }
+        }
+
+        for backend in [Backend::Interpreter, Backend::Vm, Backend::VmStress] {
+            let (output, error) = run(&source, backend);
+            let name: String = format!("{} on {:?}", path.display(), backend);
+            assert_eq!(output, expected_output, "output of {}", name);
+            assert_eq!(error, expected_error, "error from {}", name);
+        }
+    }
+}
+
+#[test]
+fn runtime_error_carries_stack_trace() {
+    let source: &str =
+        "fun inner() {\n  return 1 / 0;\n}\nfun outer() {\n  inner();\n}\nouter();\n";
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let locals = Resolver::new().resolve(&statements).unwrap();
+
+    let mut interpreter: Interpreter = Interpreter::with_output(Box::new(Capture::default()));
+    interpreter.resolve(locals);
+    let error = interpreter.interpret(&statements).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Division by zero.\n[line 2] in inner()\n[line 5] in outer()\n[line 7] in script"
+    );
+}
+
+#[test]
+fn vm_runtime_error_carries_stack_trace() {
+    let source: &str =
+        "fun inner() {\n  return 1 / 0;\n}\nfun outer() {\n  inner();\n}\nouter();\n";
+    let tokens = lex::scan_source(source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let script = Compiler::new().compile(&statements).unwrap();
+    let error = Vm::with_output(Box::new(Capture::default()))
+        .interpret(script)
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Division by zero.\n[line 2] in inner()\n[line 5] in outer()\n[line 7] in script"
+    );
+}
+
+#[test]
+fn stack_overflow_trace_shows_the_repeated_frame_once() {
+    let source: &str = "fun f() {\n  f();\n}\nf();\n";
+    let (interpreted, compiled) = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let tokens = lex::scan_source(source).unwrap();
+                let statements = Parser::new(tokens).parse().unwrap();
+                let locals = Resolver::new().resolve(&statements).unwrap();
+
+                let mut interpreter: Interpreter =
+                    Interpreter::with_output(Box::new(Capture::default()));
+                interpreter.resolve(locals);
+                let interpreted = interpreter.interpret(&statements).unwrap_err();
+
+                let script = Compiler::new().compile(&statements).unwrap();
+                let compiled = Vm::with_output(Box::new(Capture::default()))
+                    .interpret(script)
+                    .unwrap_err();
+                (interpreted.to_string(), compiled.to_string())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    });
+
+    assert_eq!(
+        interpreted,
+        "Stack overflow.\n[line 2] in f()\n[previous line repeated 4095 more times]\n[line 4] in script"
+    );
+    assert_eq!(compiled, interpreted);
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use common::Scratch;
+use miette::module::{Loader, Program};
+use miette::project::{self, Build, BuildError, Project};
+
+// Writes the files into a directory of their own, removed again when the
+// returned guard is dropped
+fn workspace(name: &str, files: &[(&str, &str)]) -> Scratch {
+    let scratch: Scratch = Scratch::new(&format!("project-{}", name));
+    for (path, source) in files {
+        scratch.file(path, source);
+    }
+    scratch
+}
+
+fn manifest(name: &str, dependencies: &[&str]) -> String {
+    let mut text: String = format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name);
+    text.push_str("\n[dependencies]\n");
+    for dependency in dependencies {
+        text.push_str(&format!(
+            "{} = {{ path = \"../{}\" }}\n",
+            dependency, dependency
+        ));
+    }
+    text
+}
+
+fn build(project: &Project) -> Build {
+    project.build(&mut project.loader()).unwrap()
+}
+
+// Paths under target/, for comparing
+fn names(root: &std::path::Path, paths: &[std::path::PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| {
+            path.strip_prefix(root.join(project::TARGET))
+                .unwrap()
+                .display()
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn dependencies_are_found_once_and_cycles_are_refused() {
+    let scratch: Scratch = workspace(
+        "open",
+        &[
+            ("app/Miette.toml", &manifest("app", &["geo", "draw"])),
+            ("draw/Miette.toml", &manifest("draw", &["geo"])),
+            ("geo/Miette.toml", &manifest("geo", &[])),
+            ("a/Miette.toml", &manifest("a", &["b"])),
+            ("b/Miette.toml", &manifest("b", &["a"])),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+
+    let project: Project = Project::open(&dir.join("app")).unwrap();
+    let names: Vec<&str> = project
+        .packages
+        .iter()
+        .map(|package| package.manifest.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["app", "geo", "draw"]);
+    assert_eq!(project.main().entry(), project.root.join("src/main.miette"));
+
+    assert_eq!(
+        Project::open(&dir.join("a")).err().unwrap(),
+        "dependency cycle: a depends on b, which depends on a"
+    );
+}
+
+#[test]
+fn builds_compile_only_what_changed() {
+    let scratch: Scratch = workspace(
+        "incremental",
+        &[
+            ("app/Miette.toml", &manifest("app", &["geo"])),
+            (
+                "app/src/main.miette",
+                "import \"geo\" as geo;\nimport \"util/text\" as text;\nprint text.shout(geo.name);\n",
+            ),
+            (
+                "app/src/util/text.miette",
+                "export fun shout(s) { var unused = 1; return s + \"!\"; }\n",
+            ),
+            ("geo/Miette.toml", &manifest("geo", &[])),
+            ("geo/src/main.miette", "export var name = \"geo\";\n"),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let root: std::path::PathBuf = dir.join("app");
+    let project: Project = Project::open(&root).unwrap();
+
+    let mut loader: Loader = project.loader();
+    let first: Build = project.build(&mut loader).unwrap();
+    assert_eq!(
+        names(&project.root, &first.compiled),
+        vec!["geo/main.mtc", "app/util/text.mtc", "app/main.mtc"]
+    );
+    assert!(first.fresh.is_empty());
+    assert_eq!(loader.warnings().len(), 1);
+
+    // Nothing is checked again, so there is nothing to warn about either
+    let mut loader: Loader = project.loader();
+    let second: Build = project.build(&mut loader).unwrap();
+    assert!(second.compiled.is_empty());
+    assert_eq!(second.fresh, first.compiled);
+    assert!(loader.warnings().is_empty());
+
+    // A module importing one that changed is compiled again too
+    std::fs::write(
+        dir.join("geo/src/main.miette"),
+        "// renamed\nexport var name = \"geo\";\n",
+    )
+    .unwrap();
+    let third: Build = build(&project);
+    assert_eq!(
+        names(&project.root, &third.compiled),
+        vec!["geo/main.mtc", "app/main.mtc"]
+    );
+    assert_eq!(
+        names(&project.root, &third.fresh),
+        vec!["app/util/text.mtc"]
+    );
+
+    // A missing or damaged file is made again, and only that one
+    std::fs::remove_file(project.root.join("target/app/util/text.mtc")).unwrap();
+    std::fs::write(project.root.join("target/geo/main.mtc"), "MTC").unwrap();
+    let fourth: Build = build(&project);
+    assert_eq!(
+        names(&project.root, &fourth.compiled),
+        vec!["geo/main.mtc", "app/util/text.mtc"]
+    );
+    assert_eq!(names(&project.root, &fourth.fresh), vec!["app/main.mtc"]);
+
+    for path in fourth.compiled.iter().chain(fourth.fresh.iter()) {
+        assert!(miette::bytecode::load(&std::fs::read(path).unwrap()).is_ok());
+    }
+
+    // The entry point's file brings in the rest, dependencies included
+    let main: std::path::PathBuf = project.root.join("target/app/main.mtc");
+    let modules = miette::bytecode::link(&main, &std::fs::read(&main).unwrap()).unwrap();
+    let linked: Vec<&str> = modules.iter().map(|module| module.name.as_str()).collect();
+    assert_eq!(linked, vec!["main", "text", "main"]);
+    assert_eq!(modules[1].exports, vec!["shout"]);
+}
+
+#[test]
+fn errors_in_any_package_stop_the_build() {
+    let scratch: Scratch = workspace(
+        "broken",
+        &[
+            ("app/Miette.toml", &manifest("app", &["geo"])),
+            ("app/src/main.miette", "import \"geo\" as geo;\n"),
+            ("geo/Miette.toml", &manifest("geo", &[])),
+            ("geo/src/main.miette", "export var name = ;\n"),
+        ],
+    );
+    let dir: &std::path::Path = scratch.path();
+    let project: Project = Project::open(&dir.join("app")).unwrap();
+    let mut loader: Loader = project.loader();
+    match project.build(&mut loader) {
+        Err(BuildError::Compile(errors)) => assert_eq!(
+            errors[0].to_string(),
+            format!(
+                "[line 1] Error in {} at ';': Expect expression.",
+                project.packages[1].entry().display()
+            )
+        ),
+        _ => panic!("the build succeeded"),
+    }
+    assert!(!project.root.join(project::TARGET).exists());
+
+    std::fs::remove_file(dir.join("app/src/main.miette")).unwrap();
+    assert!(matches!(
+        project.load(&mut project.loader()),
+        Err(BuildError::Read(_))
+    ));
+}
+
+#[test]
+fn new_projects_load() {
+    let scratch: Scratch = workspace("new", &[]);
+    let dir: std::path::PathBuf = scratch.path().join("fresh");
+    project::create(&dir).unwrap();
+    assert!(project::create(&dir).is_err());
+
+    let project: Project = Project::open(&dir).unwrap();
+    assert_eq!(project.main().manifest.name, "fresh");
+    let program: Program = project.load(&mut project.loader()).unwrap();
+    assert_eq!(program.modules.len(), 1);
+}
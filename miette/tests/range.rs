@@ -0,0 +1,42 @@
+use miette::range::{Range, RangeError};
+
+fn values(range: Range) -> Vec<f64> {
+    range.iter().collect()
+}
+
+#[test]
+fn exclusive_and_inclusive_ends() {
+    let exclusive: Range = Range::new(0.0, 3.0, false).unwrap();
+    let inclusive: Range = Range::new(0.0, 3.0, true).unwrap();
+
+    assert_eq!(values(exclusive), vec![0.0, 1.0, 2.0]);
+    assert_eq!(values(inclusive), vec![0.0, 1.0, 2.0, 3.0]);
+    assert_eq!(exclusive.len(), 3);
+    assert_eq!(inclusive.get(3), Some(3.0));
+    assert_eq!(inclusive.get(4), None);
+    assert_eq!(exclusive.to_string(), "0..3");
+    assert_eq!(inclusive.to_string(), "0..=3");
+}
+
+#[test]
+fn backwards_and_empty_ranges() {
+    assert!(Range::new(5.0, 1.0, false).unwrap().is_empty());
+    assert!(Range::new(2.0, 2.0, false).unwrap().is_empty());
+    assert_eq!(values(Range::new(2.0, 2.0, true).unwrap()), vec![2.0]);
+}
+
+#[test]
+fn fractional_starts_step_by_one() {
+    assert_eq!(
+        values(Range::new(0.5, 3.0, false).unwrap()),
+        vec![0.5, 1.5, 2.5]
+    );
+}
+
+#[test]
+fn bounds_must_be_finite() {
+    assert_eq!(
+        Range::new(0.0, f64::INFINITY, false),
+        Err(RangeError(f64::INFINITY))
+    );
+}
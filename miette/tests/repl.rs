@@ -0,0 +1,119 @@
+mod common;
+
+use common::{Capture, Scratch};
+use miette::lex;
+use miette::repl::Repl;
+
+// Feeds `input` to a fresh session, returning what it wrote to out and err
+fn session(input: &str) -> (String, String) {
+    let out: Capture = Capture::default();
+    let err: Capture = Capture::default();
+    let mut repl: Repl = Repl::new(Box::new(out.clone()), Box::new(err.clone()));
+    repl.run(&mut input.as_bytes()).unwrap();
+    (out.text(), err.text())
+}
+
+#[test]
+fn incomplete_input_is_detected() {
+    for source in [
+        "fun f() {",
+        "print (1 +",
+        "var a = [1,",
+        "print \"abc",
+        "\"a\\",
+    ] {
+        assert!(lex::is_incomplete(source), "{}", source);
+    }
+    for source in ["print 1;", "print )", "var a = [1];", "print 1 +", "@"] {
+        assert!(!lex::is_incomplete(source), "{}", source);
+    }
+}
+
+#[test]
+fn globals_persist_between_entries() {
+    let (out, err) = session("var a = 40;\nfun add(x) {\n  return x + a;\n}\nprint add(2);\n");
+    assert_eq!(out, "42\n");
+    assert_eq!(err, "");
+}
+
+#[test]
+fn expressions_show_their_value() {
+    let (out, _) = session("1 + 2\n\"a\" + \"b\";\nnil\nvar x;\nx = 5;\n");
+    assert_eq!(out, "3\nab\n5\n");
+}
+
+#[test]
+fn open_strings_continue_on_the_next_line() {
+    let (out, _) = session("print \"one\ntwo\";\n");
+    assert_eq!(out, "one\ntwo\n");
+}
+
+#[test]
+fn errors_leave_the_session_running() {
+    let (out, err) = session("print ;\nprint missing;\nvar ok = 1;\nprint ok;\n");
+    assert_eq!(out, "1\n");
+    assert_eq!(
+        err,
+        "[line 1] Error at ';': Expect expression.\n\
+         Undefined variable 'missing'.\n[line 1] in script\n"
+    );
+}
+
+#[test]
+fn a_blank_line_submits_an_unfinished_entry() {
+    let (out, err) = session("print (1\n\nprint 2;\n");
+    assert_eq!(out, "2\n");
+    assert_eq!(err, "[line 3] Error at end: Expect ')' after expression.\n");
+}
+
+#[test]
+fn commands() {
+    let scratch: Scratch = Scratch::new("repl-commands");
+    let library: std::path::PathBuf =
+        scratch.file("library.miette", "fun square(x) { return x * x; }\n");
+
+    let (out, err) = session(&format!(
+        ":load {}\nprint square(3);\n:reset\nprint square;\n:tokens 1 +\n:nope\n:quit\nprint 1;\n",
+        library.display()
+    ));
+    assert!(out.starts_with("9\n0 "), "{}", out);
+    assert_eq!(out.lines().count(), 4, "{}", out);
+    assert_eq!(
+        err,
+        "Undefined variable 'square'.\n[line 1] in script\n\
+         Unknown command ':nope', :help lists the commands.\n"
+    );
+
+    let (out, _) = session(":ast -x\n:help\n");
+    assert!(out.starts_with("Unary {"), "{}", out);
+    assert!(out.contains(":reset"));
+}
+
+#[test]
+fn piped_sessions_keep_history() {
+    let scratch: Scratch = Scratch::new("repl-history");
+    let history: std::path::PathBuf = scratch.path().join("history");
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_miette"))
+        .arg("repl")
+        .env("MIETTE_HISTORY", &history)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(
+        &mut child.stdin.take().unwrap(),
+        b"var greeting = \"hi\";\nfun shout(s) {\n  return upper(s);\n}\nshout(greeting)\n",
+    )
+    .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    // No prompts or banner when stdin isn't a terminal
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "HI\n");
+    assert_eq!(
+        std::fs::read_to_string(&history).unwrap(),
+        "var greeting = \"hi\";\nfun shout(s) {\n  return upper(s);\n}\nshout(greeting)\n"
+    );
+}
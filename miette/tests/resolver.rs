@@ -0,0 +1,191 @@
+use miette::ast::Stmt;
+use miette::lex;
+use miette::parser::Parser;
+use miette::resolver::{BindingKind, Locals, Resolver};
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = lex::scan_source(source).unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn resolve(source: &str) -> Locals {
+    Resolver::new().resolve(&parse(source)).unwrap()
+}
+
+fn resolve_errors(source: &str) -> Vec<String> {
+    Resolver::new()
+        .resolve(&parse(source))
+        .unwrap_err()
+        .iter()
+        .map(|error| error.to_string())
+        .collect()
+}
+
+#[test]
+fn globals_are_left_unresolved() {
+    assert!(resolve("var a = 1; print a; a = 2;").is_empty());
+}
+
+#[test]
+fn locals_record_their_scope_distance() {
+    let mut depths: Vec<usize> = resolve("{ var a = 1; { print a; } a = 2; }")
+        .into_values()
+        .collect();
+    depths.sort();
+
+    assert_eq!(depths, vec![0, 1]);
+}
+
+#[test]
+fn closures_resolve_through_function_scopes() {
+    let depths: Vec<usize> = resolve("fun outer() { var x = 1; return fun () { return x; }; }")
+        .into_values()
+        .collect();
+
+    assert_eq!(depths, vec![1]);
+}
+
+#[test]
+fn local_read_in_own_initializer() {
+    assert_eq!(
+        resolve_errors("{ var a = a; }"),
+        vec!["[line 1] Error at 'a': Can't read local variable in its own initializer."]
+    );
+}
+
+#[test]
+fn duplicate_local_declaration() {
+    assert_eq!(
+        resolve_errors("fun f(a) { var a = 1; }"),
+        vec!["[line 1] Error at 'a': Already a variable with this name in this scope."]
+    );
+}
+
+#[test]
+fn global_redeclaration_is_allowed() {
+    resolve("var a = 1; var a = 2;");
+}
+
+#[test]
+fn return_at_top_level() {
+    assert_eq!(
+        resolve_errors("return 1;"),
+        vec!["[line 1] Error at 'return': Can't return from top-level code."]
+    );
+}
+
+#[test]
+fn this_outside_class() {
+    assert_eq!(
+        resolve_errors("fun f() { return this; }"),
+        vec!["[line 1] Error at 'this': Can't use 'this' outside of a class."]
+    );
+}
+
+#[test]
+fn super_without_superclass() {
+    assert_eq!(
+        resolve_errors("class A { f() { super.f(); } }\nsuper.g();"),
+        vec![
+            "[line 1] Error at 'super': Can't use 'super' in a class with no superclass.",
+            "[line 2] Error at 'super': Can't use 'super' outside of a class.",
+        ]
+    );
+}
+
+#[test]
+fn value_returned_from_initializer() {
+    assert_eq!(
+        resolve_errors("class A { init() { return 1; } f() { return 2; } }"),
+        vec!["[line 1] Error at 'return': Can't return a value from an initializer."]
+    );
+}
+
+#[test]
+fn bare_return_in_initializer() {
+    resolve("class A { init() { return; } }");
+}
+
+#[test]
+fn class_inheriting_from_itself() {
+    assert_eq!(
+        resolve_errors("class A < A {}"),
+        vec!["[line 1] Error at 'A': A class can't inherit from itself."]
+    );
+}
+
+#[test]
+fn break_and_continue_outside_loops() {
+    assert_eq!(
+        resolve_errors("break;\nwhile (true) { fun f() { continue; } }"),
+        vec![
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+            "[line 2] Error at 'continue': Can't use 'continue' outside of a loop.",
+        ]
+    );
+}
+
+#[test]
+fn break_inside_loops() {
+    resolve("while (true) { if (true) break; }\nfor (x in [1]) { continue; }");
+}
+
+#[test]
+fn references_point_at_their_declarations() {
+    let statements: Vec<Stmt> = parse("fun f(a) { return a + g; }\nvar g = 1;\nprint h;");
+    let mut resolver: Resolver = Resolver::new();
+    resolver.resolve(&statements).unwrap();
+
+    // Name, and the line and kind of its declaration
+    let references: Vec<(String, Option<(usize, BindingKind)>)> = resolver
+        .references()
+        .iter()
+        .map(|reference| {
+            (
+                reference.name.lexeme().to_string(),
+                reference
+                    .declaration
+                    .as_ref()
+                    .map(|declaration| (declaration.name.line(), declaration.kind)),
+            )
+        })
+        .collect();
+    assert_eq!(
+        references,
+        vec![
+            ("f".to_string(), Some((1, BindingKind::Function))),
+            ("a".to_string(), Some((1, BindingKind::Parameter))),
+            ("a".to_string(), Some((1, BindingKind::Parameter))),
+            // Globals may be used before they are declared
+            ("g".to_string(), Some((2, BindingKind::Variable))),
+            ("g".to_string(), Some((2, BindingKind::Variable))),
+            ("h".to_string(), None),
+        ]
+    );
+}
+
+#[test]
+fn tests_belong_at_the_top_level() {
+    assert_eq!(resolve("test \"t\" { var a = 1; print a; }").len(), 1);
+    assert_eq!(
+        resolve_errors("fun f() { test \"t\" { } }"),
+        vec!["[line 1] Error at 'test': Tests must be declared at the top level."]
+    );
+    assert_eq!(
+        resolve_errors("{ test \"t\" { } }"),
+        vec!["[line 1] Error at 'test': Tests must be declared at the top level."]
+    );
+}
+
+#[test]
+fn imports_and_exports_belong_at_the_top_level() {
+    assert!(resolve("import \"util\" as util; export var a = util;").is_empty());
+    assert_eq!(
+        resolve_errors("fun f() { import \"util\" as util; }"),
+        vec!["[line 1] Error at 'import': Imports must be at the top level."]
+    );
+    assert_eq!(
+        resolve_errors("{ export fun f() {} }"),
+        vec!["[line 1] Error at 'export': Exports must be at the top level."]
+    );
+}
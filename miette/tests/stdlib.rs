@@ -0,0 +1,127 @@
+mod common;
+
+use common::Capture;
+use miette::interpreter::Interpreter;
+use miette::interpreter::value::Value;
+use miette::lex;
+use miette::parser::Parser;
+use miette::resolver::Resolver;
+use miette::stdlib::{self, Rng};
+use miette::vm::Vm;
+use miette::vm::heap::Heap;
+
+#[test]
+fn seeded_generators_repeat() {
+    let mut a: Rng = stdlib::seed(7.0).unwrap();
+    let mut b: Rng = stdlib::seed(7.0).unwrap();
+    for _ in 0..1000 {
+        let n: f64 = a.next_f64();
+        assert!((0.0..1.0).contains(&n));
+        assert_eq!(n, b.next_f64());
+    }
+    assert!(stdlib::seed(f64::NAN).is_err());
+}
+
+#[test]
+fn numbers_parse_from_digits_only() {
+    assert_eq!(stdlib::parse_number(" 1e3 "), Some(1000.0));
+    assert_eq!(stdlib::parse_number("-0.5"), Some(-0.5));
+    assert_eq!(stdlib::parse_number("NaN"), None);
+    assert_eq!(stdlib::parse_number("-inf"), None);
+    assert_eq!(stdlib::parse_number(""), None);
+    assert_eq!(stdlib::parse_number("1.2.3"), None);
+}
+
+#[test]
+fn substr_counts_characters() {
+    assert_eq!(stdlib::substr("héllo", 1.0, 3.0).unwrap(), "éll");
+    assert_eq!(stdlib::substr("héllo", -3.0, 2.0).unwrap(), "ll");
+    assert_eq!(stdlib::substr("héllo", -10.0, 2.0).unwrap(), "hé");
+    assert_eq!(
+        stdlib::substr("x", 0.5, 1.0).unwrap_err(),
+        "substr() start must be an integer, got 0.5."
+    );
+    assert_eq!(
+        stdlib::substr("x", 0.0, -1.0).unwrap_err(),
+        "substr() length must be a non-negative integer, got -1."
+    );
+}
+
+const SOURCE: &str = "print twice(21); print counter(); print counter();";
+
+#[test]
+fn interpreter_accepts_rust_closures() {
+    let tokens = lex::scan_source(SOURCE).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let locals = Resolver::new().resolve(&statements).unwrap();
+
+    let capture: Capture = Capture::default();
+    let mut interpreter: Interpreter = Interpreter::with_output(Box::new(capture.clone()));
+    interpreter.define_native("twice", 1, |args: &[Value]| match &args[0] {
+        Value::Number(n) => Ok(Value::Number(n * 2.0)),
+        _ => Err("twice() expects a number.".to_string()),
+    });
+    let count: std::rc::Rc<std::cell::Cell<f64>> = std::rc::Rc::default();
+    interpreter.define_native("counter", 0, move |_args: &[Value]| {
+        count.set(count.get() + 1.0);
+        Ok(Value::Number(count.get()))
+    });
+    interpreter.resolve(locals);
+    interpreter.interpret(&statements).unwrap();
+
+    assert_eq!(&*capture.0.borrow(), b"42\n1\n2\n");
+}
+
+#[test]
+fn vm_accepts_rust_closures() {
+    use miette::vm::value::Value;
+
+    let tokens = lex::scan_source(SOURCE).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let script = miette::compiler::Compiler::new()
+        .compile(&statements)
+        .unwrap();
+
+    let capture: Capture = Capture::default();
+    let mut vm: Vm = Vm::with_output(Box::new(capture.clone()));
+    vm.define_native("twice", 1, |_heap: &mut Heap, args: &[Value]| {
+        match &args[0] {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => Err("twice() expects a number.".to_string()),
+        }
+    });
+    let count: std::rc::Rc<std::cell::Cell<f64>> = std::rc::Rc::default();
+    vm.define_native("counter", 0, move |_heap: &mut Heap, _args: &[Value]| {
+        count.set(count.get() + 1.0);
+        Ok(Value::Number(count.get()))
+    });
+    vm.interpret(script).unwrap();
+
+    assert_eq!(&*capture.0.borrow(), b"42\n1\n2\n");
+}
+
+#[test]
+fn every_listed_name_is_defined() {
+    let source: String = stdlib::NAMES
+        .iter()
+        .map(|name| format!("print type({});", name))
+        .collect();
+    let tokens = lex::scan_source(&source).unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    let capture: Capture = Capture::default();
+    let mut interpreter: Interpreter = Interpreter::with_output(Box::new(capture.clone()));
+    interpreter.interpret(&statements).unwrap();
+    let script = miette::compiler::Compiler::new()
+        .compile(&statements)
+        .unwrap();
+    Vm::with_output(Box::new(capture.clone()))
+        .interpret(script)
+        .unwrap();
+
+    let expected: String = "function\n".repeat(stdlib::NAMES.len() * 2);
+    assert_eq!(
+        String::from_utf8(capture.0.borrow().clone()).unwrap(),
+        expected
+    );
+}
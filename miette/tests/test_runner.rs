@@ -0,0 +1,188 @@
+mod common;
+
+use common::Scratch;
+use miette::test_runner::{self, Backend, Case, Expectations, Outcome};
+
+#[test]
+fn expectations_come_from_comments() {
+    let source: &str = "\
+print 1; // expect: 1
+print \"a // b\"; // expect: a // b
+var a = ; // error at ';': Expect expression.
+// errors in prose are not expectations
+f(); // expect runtime error: Boom.
+// [line 9] error at end: Expect '}' after block.
+";
+    assert_eq!(
+        test_runner::expectations(source),
+        Expectations {
+            output: "1\na // b\n".to_string(),
+            runtime_error: Some((5, "Boom.".to_string())),
+            errors: vec![
+                (3, "[line 3] Error at ';': Expect expression.".to_string()),
+                (
+                    9,
+                    "[line 9] Error at end: Expect '}' after block.".to_string()
+                ),
+            ],
+        }
+    );
+}
+
+#[test]
+fn passing_programs_have_no_failures() {
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        assert_eq!(
+            test_runner::check(
+                "fun f() { return 1 / 0; }\nprint 1; // expect: 1\nf(); // expect runtime error: Division by zero.\n",
+                backend
+            ),
+            Vec::<String>::new()
+        );
+    }
+}
+
+#[test]
+fn failures_say_what_differs() {
+    let failures: Vec<String> = test_runner::check(
+        "print 1; // expect: 2\nprint 1 / 0; // expect runtime error: Other.\n",
+        Backend::Interpreter,
+    );
+    assert_eq!(
+        failures,
+        vec![
+            "output differs:\n--- expected\n+++ actual\n@@ -1,1 +1,1 @@\n-2\n+1\n".to_string(),
+            "expected runtime error on line 2: Other.\nbut got: Division by zero.\n[line 2] in script"
+                .to_string(),
+        ]
+    );
+
+    // On the right line, or a call made from it
+    let failures: Vec<String> = test_runner::check(
+        "\nprint 1 / 0;\n// expect runtime error: Division by zero.\n",
+        Backend::Vm,
+    );
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].starts_with("expected runtime error on line 3"));
+
+    assert_eq!(
+        test_runner::check("print 1;\nvar a = ;\n", Backend::Interpreter),
+        vec![
+            "compile errors differ:\n--- expected\n+++ actual\n@@ -0,0 +1,1 @@\n\
+             +[line 2] Error at ';': Expect expression.\n"
+                .to_string()
+        ]
+    );
+}
+
+// Every program in the repository's tests is a conformance test, on both
+// backends
+#[test]
+fn the_conformance_suite_passes() {
+    let paths: Vec<std::path::PathBuf> =
+        test_runner::discover(std::path::Path::new("tests")).unwrap();
+    assert!(paths.iter().any(|path| path.starts_with("tests/programs")));
+    assert!(paths.iter().any(|path| path.starts_with("tests/errors")));
+    assert!(paths.iter().any(|path| path.starts_with("tests/modules")));
+    assert!(paths.is_sorted());
+
+    let cases: Vec<Case> = paths
+        .iter()
+        .flat_map(|path| test_runner::cases(path))
+        .collect();
+    assert!(
+        paths
+            .iter()
+            .all(|path| cases.iter().any(|case| &case.path == path))
+    );
+    assert!(cases.iter().any(|case| case.test.is_some()));
+
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let outcomes: Vec<Outcome> = test_runner::run_all(&cases, backend, 4);
+        assert_eq!(outcomes.len(), cases.len());
+        for (outcome, case) in outcomes.iter().zip(cases.iter()) {
+            assert_eq!(&outcome.case, case);
+            assert!(outcome.passed(), "{:?}", outcome);
+        }
+    }
+}
+
+const TESTS: &str = "\
+fun add(a, b) { return a + b; }
+var runs = 0;
+runs = runs + 1;
+
+test \"adds\" {
+    assert_eq(add(1, 2), 3);
+    // Each test gets a fresh run of the program
+    assert_eq(runs, 1);
+    runs = 10;
+}
+
+test \"still one run\" {
+    assert_eq(runs, 1);
+}
+
+test \"fails\" {
+    print \"checking\";
+    assert_eq(add(1, 2), 4);
+}
+";
+
+#[test]
+fn test_blocks_run_after_the_program() {
+    let scratch: Scratch = Scratch::new("test-runner-blocks");
+    let path: std::path::PathBuf = scratch.file("blocks.miette", TESTS);
+
+    // Only expectations make a file of tests a program to check too
+    let cases: Vec<Case> = test_runner::cases(&path);
+    let names: Vec<String> = cases.iter().map(|case| case.to_string()).collect();
+    assert_eq!(
+        names,
+        ["adds", "still one run", "fails"].map(|name| format!("{}: {}", path.display(), name))
+    );
+
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        assert_eq!(
+            test_runner::check_test(TESTS, 0, backend),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            test_runner::check_test(TESTS, 1, backend),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            test_runner::check_test(TESTS, 2, backend),
+            vec![
+                "assert_eq() failed, expected 4 but got 3.\n  \
+                 18 | assert_eq(add(1, 2), 4)\n\
+                 [line 18] in script\n\
+                 output:\n\
+                 checking"
+                    .to_string()
+            ]
+        );
+    }
+
+    // Running the program skips them
+    let failures: Vec<String> = test_runner::check(
+        "print 1; // expect: 1\ntest \"never\" { print 2; }\n",
+        Backend::Vm,
+    );
+    assert_eq!(failures, Vec::<String>::new());
+}
+
+#[test]
+fn a_failing_program_fails_its_tests() {
+    let source: &str = "var a = len(1);\ntest \"t\" { assert(true); }\n";
+    assert_eq!(
+        test_runner::check_test(source, 0, Backend::Interpreter),
+        vec![
+            "the program failed before the test ran: \
+             Can't take the length of a number.\n  \
+             1 | len(1)\n\
+             [line 1] in script"
+                .to_string()
+        ]
+    );
+}
@@ -0,0 +1,397 @@
+use miette::ast::{self, Catch, Expr, Function, Literal, Stmt};
+use miette::lex::{Span, Token, TokenKind};
+use miette::visit::{self, Visitor, VisitorMut};
+
+fn token(kind: TokenKind, lexeme: &str) -> Token {
+    Token::new(0, kind, lexeme.to_string(), 1, Span::default())
+}
+
+fn ident(name: &str) -> Token {
+    token(TokenKind::Identifier(name.to_string()), name)
+}
+
+fn number(n: f64) -> Expr {
+    Expr::Literal {
+        value: Literal::Number(n),
+        token: token(TokenKind::Number(n), &n.to_string()),
+    }
+}
+
+fn variable(name: &str) -> Expr {
+    Expr::Variable {
+        id: ast::next_id(),
+        name: ident(name),
+    }
+}
+
+// One program that contains every statement and expression variant at least once
+fn every_variant() -> Vec<Stmt> {
+    let method: Function = Function {
+        name: Some(ident("method")),
+        params: vec![ident("a")],
+        param_types: vec![None],
+        return_type: None,
+        body: vec![
+            Stmt::Expression {
+                expression: Expr::Set {
+                    object: Box::new(Expr::This {
+                        id: ast::next_id(),
+                        keyword: token(TokenKind::This, "this"),
+                    }),
+                    name: ident("field"),
+                    value: Box::new(Expr::Super {
+                        id: ast::next_id(),
+                        keyword: token(TokenKind::Super, "super"),
+                        method: ident("method"),
+                    }),
+                },
+            },
+            Stmt::Return {
+                keyword: token(TokenKind::Return, "return"),
+                value: Some(Expr::Get {
+                    object: Box::new(variable("a")),
+                    name: ident("field"),
+                }),
+            },
+        ],
+    };
+
+    vec![
+        Stmt::Var {
+            name: ident("x"),
+            annotation: None,
+            initializer: Some(Expr::Grouping {
+                expression: Box::new(Expr::Binary {
+                    left: Box::new(number(1.0)),
+                    operator: token(TokenKind::Plus, "+"),
+                    right: Box::new(Expr::Unary {
+                        operator: token(TokenKind::Minus, "-"),
+                        right: Box::new(number(2.0)),
+                    }),
+                }),
+            }),
+        },
+        Stmt::Class {
+            name: ident("B"),
+            superclass: Some(variable("A")),
+            fields: Vec::new(),
+            methods: vec![std::rc::Rc::new(method)],
+        },
+        Stmt::Function {
+            function: std::rc::Rc::new(Function {
+                name: Some(ident("f")),
+                params: Vec::new(),
+                param_types: Vec::new(),
+                return_type: None,
+                body: vec![Stmt::Block {
+                    statements: vec![Stmt::While {
+                        keyword: token(TokenKind::While, "while"),
+                        condition: Expr::Logical {
+                            left: Box::new(variable("x")),
+                            operator: token(TokenKind::And, "and"),
+                            right: Box::new(variable("y")),
+                        },
+                        body: Box::new(Stmt::Break {
+                            keyword: token(TokenKind::Break, "break"),
+                        }),
+                    }],
+                }],
+            }),
+        },
+        Stmt::For {
+            keyword: token(TokenKind::For, "for"),
+            initializer: None,
+            condition: None,
+            increment: Some(Expr::Assign {
+                id: ast::next_id(),
+                name: ident("x"),
+                value: Box::new(number(3.0)),
+            }),
+            body: Box::new(Stmt::Continue {
+                keyword: token(TokenKind::Continue, "continue"),
+            }),
+        },
+        Stmt::ForIn {
+            keyword: token(TokenKind::For, "for"),
+            variable: ident("i"),
+            iterable: Expr::Range {
+                start: Box::new(number(9.0)),
+                operator: token(TokenKind::DotDot, ".."),
+                end: Box::new(number(10.0)),
+                inclusive: false,
+            },
+            body: Box::new(Stmt::Block {
+                statements: Vec::new(),
+            }),
+        },
+        Stmt::If {
+            keyword: token(TokenKind::If, "if"),
+            condition: variable("x"),
+            then_branch: Box::new(Stmt::Print {
+                keyword: token(TokenKind::Print, "print"),
+                expression: Expr::Call {
+                    callee: Box::new(variable("f")),
+                    paren: token(TokenKind::RightParen, ")"),
+                    arguments: vec![
+                        Expr::IndexSet {
+                            object: Box::new(Expr::List {
+                                bracket: token(TokenKind::LeftSBracket, "["),
+                                elements: vec![number(4.0)],
+                            }),
+                            bracket: token(TokenKind::LeftSBracket, "["),
+                            index: Box::new(Expr::Index {
+                                object: Box::new(Expr::Slice {
+                                    object: Box::new(variable("xs")),
+                                    bracket: token(TokenKind::LeftSBracket, "["),
+                                    start: None,
+                                    end: Some(Box::new(number(5.0))),
+                                }),
+                                bracket: token(TokenKind::LeftSBracket, "["),
+                                index: Box::new(number(6.0)),
+                            }),
+                            value: Box::new(Expr::Map {
+                                brace: token(TokenKind::LeftBracket, "{"),
+                                entries: vec![(number(7.0), number(8.0))],
+                            }),
+                        },
+                        Expr::Lambda {
+                            keyword: token(TokenKind::Fun, "fun"),
+                            function: std::rc::Rc::new(Function {
+                                name: None,
+                                params: vec![ident("b")],
+                                param_types: vec![None],
+                                return_type: None,
+                                body: vec![Stmt::Return {
+                                    keyword: token(TokenKind::Arrow, "->"),
+                                    value: Some(variable("b")),
+                                }],
+                            }),
+                        },
+                    ],
+                },
+            }),
+            else_branch: None,
+        },
+        Stmt::Try {
+            keyword: token(TokenKind::Try, "try"),
+            body: vec![Stmt::Throw {
+                keyword: token(TokenKind::Throw, "throw"),
+                value: variable("x"),
+            }],
+            catch: Some(Catch {
+                name: ident("e"),
+                body: Vec::new(),
+            }),
+            finally: Some(Vec::new()),
+        },
+    ]
+}
+
+fn stmt_name(stmt: &Stmt) -> &'static str {
+    match stmt {
+        Stmt::Expression { .. } => "Expression",
+        Stmt::Print { .. } => "Print",
+        Stmt::Var { .. } => "Var",
+        Stmt::Block { .. } => "Block",
+        Stmt::If { .. } => "If",
+        Stmt::While { .. } => "While",
+        Stmt::For { .. } => "For",
+        Stmt::ForIn { .. } => "ForIn",
+        Stmt::Function { .. } => "Function",
+        Stmt::Return { .. } => "Return",
+        Stmt::Break { .. } => "Break",
+        Stmt::Continue { .. } => "Continue",
+        Stmt::Class { .. } => "Class",
+        Stmt::Test { .. } => "Test",
+        Stmt::Import { .. } => "Import",
+        Stmt::Export { .. } => "Export",
+        Stmt::Throw { .. } => "Throw",
+        Stmt::Try { .. } => "Try",
+    }
+}
+
+fn expr_name(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::Literal { .. } => "Literal",
+        Expr::Grouping { .. } => "Grouping",
+        Expr::Unary { .. } => "Unary",
+        Expr::Binary { .. } => "Binary",
+        Expr::Logical { .. } => "Logical",
+        Expr::Variable { .. } => "Variable",
+        Expr::Assign { .. } => "Assign",
+        Expr::Call { .. } => "Call",
+        Expr::Get { .. } => "Get",
+        Expr::Set { .. } => "Set",
+        Expr::This { .. } => "This",
+        Expr::Super { .. } => "Super",
+        Expr::List { .. } => "List",
+        Expr::Map { .. } => "Map",
+        Expr::Index { .. } => "Index",
+        Expr::IndexSet { .. } => "IndexSet",
+        Expr::Slice { .. } => "Slice",
+        Expr::Range { .. } => "Range",
+        Expr::Lambda { .. } => "Lambda",
+    }
+}
+
+const ALL_STMTS: &[&str] = &[
+    "Expression",
+    "Print",
+    "Var",
+    "Block",
+    "If",
+    "While",
+    "For",
+    "ForIn",
+    "Function",
+    "Return",
+    "Break",
+    "Continue",
+    "Class",
+    "Throw",
+    "Try",
+];
+
+const ALL_EXPRS: &[&str] = &[
+    "Literal", "Grouping", "Unary", "Binary", "Logical", "Variable", "Assign", "Call", "Get",
+    "Set", "This", "Super", "List", "Map", "Index", "IndexSet", "Slice", "Range", "Lambda",
+];
+
+#[derive(Default)]
+struct Recorder {
+    stmts: std::collections::BTreeSet<&'static str>,
+    exprs: std::collections::BTreeSet<&'static str>,
+    functions: Vec<String>,
+}
+
+impl Visitor for Recorder {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.stmts.insert(stmt_name(stmt));
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.exprs.insert(expr_name(expr));
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.functions.push(match &function.name {
+            Some(name) => name.lexeme().to_string(),
+            None => "<lambda>".to_string(),
+        });
+        visit::walk_function(self, function);
+    }
+}
+
+#[test]
+fn visitor_reaches_every_variant() {
+    let program: Vec<Stmt> = every_variant();
+    let mut recorder: Recorder = Recorder::default();
+    visit::walk_stmts(&mut recorder, &program);
+
+    assert_eq!(recorder.stmts, ALL_STMTS.iter().copied().collect());
+    assert_eq!(recorder.exprs, ALL_EXPRS.iter().copied().collect());
+    assert_eq!(recorder.functions, vec!["method", "f", "<lambda>"]);
+}
+
+// Only overrides expressions; statements fall through to the default walk
+struct VariableCounter(usize);
+
+impl Visitor for VariableCounter {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Variable { .. } = expr {
+            self.0 += 1;
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+#[test]
+fn default_walk_descends_into_unhandled_nodes() {
+    let program: Vec<Stmt> = every_variant();
+    let mut counter: VariableCounter = VariableCounter(0);
+    visit::walk_stmts(&mut counter, &program);
+
+    // A, a, x, y, x, f, xs, b, x
+    assert_eq!(counter.0, 9);
+}
+
+#[derive(Default)]
+struct RecorderMut {
+    stmts: std::collections::BTreeSet<&'static str>,
+    exprs: std::collections::BTreeSet<&'static str>,
+}
+
+impl VisitorMut for RecorderMut {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        self.stmts.insert(stmt_name(stmt));
+        visit::walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        self.exprs.insert(expr_name(expr));
+        visit::walk_expr_mut(self, expr);
+    }
+}
+
+#[test]
+fn mutable_visitor_reaches_every_variant() {
+    let mut program: Vec<Stmt> = every_variant();
+    let mut recorder: RecorderMut = RecorderMut::default();
+    visit::walk_stmts_mut(&mut recorder, &mut program);
+
+    assert_eq!(recorder.stmts, ALL_STMTS.iter().copied().collect());
+    assert_eq!(recorder.exprs, ALL_EXPRS.iter().copied().collect());
+}
+
+struct DoubleNumbers;
+
+impl VisitorMut for DoubleNumbers {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let Expr::Literal {
+            value: Literal::Number(n),
+            ..
+        } = expr
+        {
+            *n *= 2.0;
+        }
+        visit::walk_expr_mut(self, expr);
+    }
+}
+
+struct SumNumbers(f64);
+
+impl Visitor for SumNumbers {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Literal {
+            value: Literal::Number(n),
+            ..
+        } = expr
+        {
+            self.0 += n;
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+#[test]
+fn mutable_visitor_rewrites_in_place() {
+    let mut program: Vec<Stmt> = every_variant();
+    let shared: std::rc::Rc<Function> = match &program[2] {
+        Stmt::Function { function } => function.clone(),
+        _ => unreachable!(),
+    };
+
+    visit::walk_stmts_mut(&mut DoubleNumbers, &mut program);
+
+    let mut sum: SumNumbers = SumNumbers(0.0);
+    visit::walk_stmts(&mut sum, &program);
+    assert_eq!(
+        sum.0,
+        2.0 * (1.0 + 2.0 + 3.0 + 4.0 + 5.0 + 6.0 + 7.0 + 8.0 + 9.0 + 10.0)
+    );
+
+    // A declaration still held elsewhere is copied rather than changed under its owner
+    assert_eq!(std::rc::Rc::strong_count(&shared), 1);
+}